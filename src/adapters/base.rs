@@ -1,4 +1,5 @@
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 
 use mongodb::bson::oid::ObjectId;
 use serde::de::DeserializeOwned;
@@ -7,6 +8,14 @@ use std::fmt::{Debug, Display, Formatter};
 
 pub trait Model: Clone + Debug + Send + Sync + Serialize + DeserializeOwned {
     fn id(&self) -> IdType;
+    /// When the entity was marked deleted, for the models deleted in two steps
+    fn deleted_at(&self) -> Option<DateTime<Utc>> {
+        None
+    }
+    /// When the entity disappears, for the models with a lifetime
+    fn expires_at(&self) -> Option<DateTime<Utc>> {
+        None
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
@@ -25,7 +34,7 @@ impl Display for IdType {
 }
 
 #[async_trait]
-pub trait Repository<M: Model> {
+pub trait Repository<M: Model>: Send + Sync {
     async fn create(&mut self, entity: &M) -> Result<M, RepositoryError>;
     async fn update(&mut self, entity: &M) -> Result<(), RepositoryError>;
     async fn delete(&mut self, id: &IdType) -> Result<(), RepositoryError>;
@@ -35,6 +44,22 @@ pub trait Repository<M: Model> {
         skip: Option<u64>,
         limit: Option<i32>,
     ) -> Result<(i32, Vec<M>), RepositoryError>;
+    /// The name of the collection the entities are stored in, for the stores
+    /// keeping them in named collections
+    fn collection_name(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// The repositories of the models deleted in two steps: marked deleted with
+/// `deleted_at` first, then purged once the grace period has passed. `get`,
+/// `list` and the finders leave out the entities marked deleted.
+#[async_trait]
+pub trait SoftDeleteRepository<M: Model>: Repository<M> {
+    /// Returns the entity, even when it is marked deleted
+    async fn get_with_deleted(&self, id: &IdType) -> Option<M>;
+    /// Returns the entities marked deleted before the given time
+    async fn find_deleted_before(&self, before: DateTime<Utc>) -> Result<Vec<M>, RepositoryError>;
 }
 
 #[derive(Debug, Serialize, Deserialize)]
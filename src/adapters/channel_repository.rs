@@ -1,12 +1,47 @@
-use crate::adapters::{IdType, Repository, RepositoryError};
-use crate::models::Channel;
+use crate::adapters::{IdType, RepositoryError, SoftDeleteRepository};
+use crate::models::{Channel, ChannelPreferences};
 use async_trait::async_trait;
 
 #[async_trait]
-pub trait ChannelRepository: Repository<Channel> {
+pub trait ChannelRepository: SoftDeleteRepository<Channel> {
     async fn find_by_contact_id(
         &self,
         contact_id: &IdType,
     ) -> Result<Vec<Channel>, RepositoryError>;
-    async fn get_by_contact_ids(&self, contact_ids: &Vec<IdType>) -> Option<Channel>;
+    /// Returns the channels of the contact, the deleted ones waiting for the
+    /// purge included
+    async fn find_by_contact_id_with_deleted(
+        &self,
+        contact_id: &IdType,
+    ) -> Result<Vec<Channel>, RepositoryError>;
+    async fn get_by_contact_ids(&self, contact_ids: &[IdType]) -> Option<Channel>;
+    /// Replaces the channel except for the notification settings of its
+    /// members, which only `set_preferences` changes
+    async fn update_without_preferences(
+        &mut self,
+        channel: &Channel,
+    ) -> Result<(), RepositoryError>;
+    /// Replaces the notification settings of the member, or drops them for
+    /// the defaults when `None`, leaving the rest of the channel as it is
+    async fn set_preferences(
+        &mut self,
+        channel_id: &IdType,
+        contact_id: &IdType,
+        preferences: Option<&ChannelPreferences>,
+    ) -> Result<(), RepositoryError>;
+    /// Adds the message to the pins of the channel in one update, unless it
+    /// is pinned already or the channel has `MAX_PINNED_MESSAGES` pins.
+    /// Returns whether the message was pinned.
+    async fn pin_message(
+        &mut self,
+        channel_id: &IdType,
+        message_id: &IdType,
+    ) -> Result<bool, RepositoryError>;
+    /// Removes the message from the pins of the channel in one update.
+    /// Returns whether the message was pinned.
+    async fn unpin_message(
+        &mut self,
+        channel_id: &IdType,
+        message_id: &IdType,
+    ) -> Result<bool, RepositoryError>;
 }
@@ -1,8 +1,36 @@
-use crate::adapters::Repository;
+use crate::adapters::channel_repository::ChannelRepository;
+use crate::adapters::message_repository::MessageRepository;
+use crate::adapters::{IdType, RepositoryError, SoftDeleteRepository};
 use crate::models::Contact;
 use async_trait::async_trait;
 
+/// The changes made together when a contact is deleted
+#[derive(Debug, Clone)]
+pub struct ContactDeletion {
+    pub contact_id: IdType,
+    /// The groups the contact is removed from
+    pub left_channel_ids: Vec<IdType>,
+    /// The private channels kept with the placeholder in place of the contact
+    pub orphaned_channel_ids: Vec<IdType>,
+    /// The channels deleted along with their messages
+    pub deleted_channel_ids: Vec<IdType>,
+    /// Whether the references of the messages to the contact are rewritten
+    /// to the placeholder
+    pub anonymize_messages: bool,
+}
+
 #[async_trait]
-pub trait ContactRepository: Repository<Contact> {
+pub trait ContactRepository: SoftDeleteRepository<Contact> {
     async fn find_by_email(&self, email: &str) -> Option<Contact>;
+    async fn find_by_api_key_hash(&self, api_key_hash: &str) -> Option<Contact>;
+    /// Deletes the contact, unblocks it everywhere and applies the changes to
+    /// its channels and messages, all of them or none. The changes only touch
+    /// the references to the contact, so concurrent edits of the channels are
+    /// kept.
+    async fn delete_cascade(
+        &mut self,
+        deletion: &ContactDeletion,
+        channels: &mut dyn ChannelRepository,
+        messages: &mut dyn MessageRepository,
+    ) -> Result<(), RepositoryError>;
 }
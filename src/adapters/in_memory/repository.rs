@@ -1,12 +1,36 @@
 use crate::adapters::channel_repository::ChannelRepository;
-use crate::adapters::contact_repository::ContactRepository;
+use crate::adapters::contact_repository::{ContactDeletion, ContactRepository};
+use crate::adapters::incoming_webhook_repository::IncomingWebhookRepository;
 use crate::adapters::message_repository::MessageRepository;
-use crate::adapters::{IdType, Model, Repository, RepositoryError};
-use crate::models::{Channel, Contact, Message};
+use crate::adapters::notification_repository::NotificationRepository;
+use crate::adapters::report_repository::{ModerationActionRepository, ReportRepository};
+use crate::adapters::scheduled_message_repository::ScheduledMessageRepository;
+use crate::adapters::slash_command_repository::SlashCommandRepository;
+use crate::adapters::webhook_repository::{WebhookDeliveryRepository, WebhookRepository};
+use crate::adapters::{IdType, Model, Repository, RepositoryError, SoftDeleteRepository};
+use crate::models::{
+    deleted_contact_id, Channel, ChannelPreferences, Contact, IncomingWebhook, Message,
+    MessageContent, ModerationAction, Notification, NotificationStatus, Report, ReportStatus,
+    ScheduledMessage, ScheduledMessageStatus, SlashCommand, SystemEvent, Webhook, WebhookDelivery,
+    WebhookDeliveryStatus, MAX_PINNED_MESSAGES,
+};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
 
 pub struct InMemoryRepository<M> {
     pub entities: Vec<M>,
+    /// The last sequence number given out per channel, kept across deletes
+    pub sequences: BTreeMap<IdType, i64>,
+}
+
+impl<M> Default for InMemoryRepository<M> {
+    fn default() -> Self {
+        InMemoryRepository {
+            entities: vec![],
+            sequences: BTreeMap::new(),
+        }
+    }
 }
 
 #[async_trait]
@@ -17,56 +41,66 @@ impl<M: Model> Repository<M> for InMemoryRepository<M> {
     }
 
     async fn update(&mut self, entity: &M) -> Result<(), RepositoryError> {
-        let contact = match self.get(&entity.id()).await {
-            Some(c) => c,
+        let index = match self.entities.iter().position(|e| e.id() == entity.id()) {
+            Some(i) => i,
             None => {
                 return Err(RepositoryError {
                     message: "Entity not found".to_string(),
                 })
             }
         };
-        let index = self
-            .entities
-            .iter()
-            .position(|c| c.id() == contact.id())
-            .unwrap();
         self.entities[index] = entity.clone();
         Ok(())
     }
 
     async fn delete(&mut self, id: &IdType) -> Result<(), RepositoryError> {
-        let contact = match self.get(id).await {
-            Some(c) => c,
+        let index = match self.entities.iter().position(|e| &e.id() == id) {
+            Some(i) => i,
             None => {
                 return Err(RepositoryError {
                     message: "Entity not found".to_string(),
                 })
             }
         };
-        let index = self
-            .entities
-            .iter()
-            .position(|c| c.id() == contact.id())
-            .unwrap();
         self.entities.remove(index);
         Ok(())
     }
 
     async fn get(&self, _id: &IdType) -> Option<M> {
-        for entity in self.entities.iter() {
-            let id = entity.id();
-            if &id == _id {
-                return Some(entity.clone());
-            }
-        }
-        None
+        let now = Utc::now();
+        self.get_with_deleted(_id)
+            .await
+            .filter(|e| e.deleted_at().is_none())
+            .filter(|e| e.expires_at().is_none_or(|expires_at| expires_at > now))
     }
     async fn list(
         &self,
         _skip: Option<u64>,
         _limit: Option<i32>,
     ) -> Result<(i32, Vec<M>), RepositoryError> {
-        Ok((self.entities.len() as i32, self.entities.clone()))
+        let entities: Vec<M> = self
+            .entities
+            .iter()
+            .filter(|e| e.deleted_at().is_none())
+            .cloned()
+            .collect();
+        Ok((entities.len() as i32, entities))
+    }
+}
+
+#[async_trait]
+impl<M: Model> SoftDeleteRepository<M> for InMemoryRepository<M> {
+    async fn get_with_deleted(&self, id: &IdType) -> Option<M> {
+        self.entities.iter().find(|e| &e.id() == id).cloned()
+    }
+
+    async fn find_deleted_before(&self, before: DateTime<Utc>) -> Result<Vec<M>, RepositoryError> {
+        Ok(self
+            .entities
+            .iter()
+            .filter(|e| e.deleted_at().is_some_and(|d| d < before))
+            .cloned()
+            .collect())
     }
 }
 
@@ -78,33 +112,175 @@ impl ChannelRepository for InMemoryRepository<Channel> {
     ) -> Result<Vec<Channel>, RepositoryError> {
         let mut channels = Vec::new();
         for channel in self.entities.iter() {
-            if channel.contact_ids.contains(contact_id) {
+            if channel.contact_ids.contains(contact_id) && channel.deleted_at.is_none() {
                 channels.push(channel.clone());
             }
         }
         Ok(channels)
     }
 
-    async fn get_by_contact_ids(&self, contact_ids: &Vec<IdType>) -> Option<Channel> {
-        for channel in self.entities.iter() {
-            if channel.contact_ids.clone().sort() == contact_ids.clone().sort() {
+    async fn find_by_contact_id_with_deleted(
+        &self,
+        contact_id: &IdType,
+    ) -> Result<Vec<Channel>, RepositoryError> {
+        Ok(self
+            .entities
+            .iter()
+            .filter(|c| c.contact_ids.contains(contact_id))
+            .cloned()
+            .collect())
+    }
+
+    async fn get_by_contact_ids(&self, contact_ids: &[IdType]) -> Option<Channel> {
+        let mut expected = contact_ids.to_vec();
+        expected.sort();
+        for channel in self.entities.iter().filter(|c| c.deleted_at.is_none()) {
+            let mut ids = channel.contact_ids.clone();
+            ids.sort();
+            if ids == expected {
                 return Some(channel.clone());
             }
         }
         None
     }
+
+    async fn update_without_preferences(
+        &mut self,
+        channel: &Channel,
+    ) -> Result<(), RepositoryError> {
+        let stored = match self.entities.iter_mut().find(|c| c.id() == channel.id()) {
+            Some(c) => c,
+            None => {
+                return Err(RepositoryError {
+                    message: "Entity not found".to_string(),
+                })
+            }
+        };
+        let preferences = std::mem::take(&mut stored.preferences);
+        *stored = Channel {
+            preferences,
+            ..channel.clone()
+        };
+        Ok(())
+    }
+
+    async fn set_preferences(
+        &mut self,
+        channel_id: &IdType,
+        contact_id: &IdType,
+        preferences: Option<&ChannelPreferences>,
+    ) -> Result<(), RepositoryError> {
+        let channel = match self.entities.iter_mut().find(|c| &c.id() == channel_id) {
+            Some(c) => c,
+            None => {
+                return Err(RepositoryError {
+                    message: "Entity not found".to_string(),
+                })
+            }
+        };
+        channel.preferences.retain(|p| &p.contact_id != contact_id);
+        if let Some(p) = preferences {
+            channel.preferences.push(p.clone());
+        }
+        Ok(())
+    }
+
+    async fn pin_message(
+        &mut self,
+        channel_id: &IdType,
+        message_id: &IdType,
+    ) -> Result<bool, RepositoryError> {
+        let channel = match self.entities.iter_mut().find(|c| &c.id() == channel_id) {
+            Some(c) => c,
+            None => return Ok(false),
+        };
+        if channel.pinned_message_ids.contains(message_id)
+            || channel.pinned_message_ids.len() >= MAX_PINNED_MESSAGES
+        {
+            return Ok(false);
+        }
+        channel.pinned_message_ids.push(message_id.clone());
+        channel.updated_at = Utc::now();
+        Ok(true)
+    }
+
+    async fn unpin_message(
+        &mut self,
+        channel_id: &IdType,
+        message_id: &IdType,
+    ) -> Result<bool, RepositoryError> {
+        let channel = match self.entities.iter_mut().find(|c| &c.id() == channel_id) {
+            Some(c) => c,
+            None => return Ok(false),
+        };
+        if !channel.pinned_message_ids.contains(message_id) {
+            return Ok(false);
+        }
+        channel.pinned_message_ids.retain(|id| id != message_id);
+        channel.updated_at = Utc::now();
+        Ok(true)
+    }
 }
 
 #[async_trait]
 impl ContactRepository for InMemoryRepository<Contact> {
     async fn find_by_email(&self, email: &str) -> Option<Contact> {
         for contact in self.entities.iter() {
-            if contact.email == email {
+            if contact.email == email && contact.deleted_at.is_none() {
                 return Some(contact.clone());
             }
         }
         None
     }
+
+    async fn find_by_api_key_hash(&self, api_key_hash: &str) -> Option<Contact> {
+        self.entities
+            .iter()
+            .find(|c| c.api_key_hash.as_deref() == Some(api_key_hash) && c.deleted_at.is_none())
+            .cloned()
+    }
+
+    /// Applies the changes one repository after the other, which is enough for
+    /// the tests
+    async fn delete_cascade(
+        &mut self,
+        deletion: &ContactDeletion,
+        channels: &mut dyn ChannelRepository,
+        messages: &mut dyn MessageRepository,
+    ) -> Result<(), RepositoryError> {
+        let id = &deletion.contact_id;
+        self.delete(id).await?;
+        for contact in self.entities.iter_mut() {
+            contact.blocked.retain(|b| b.contact_id != *id);
+        }
+        let updated = deletion
+            .left_channel_ids
+            .iter()
+            .chain(deletion.orphaned_channel_ids.iter());
+        for channel_id in updated {
+            let mut channel = match channels.get_with_deleted(channel_id).await {
+                Some(c) => c,
+                None => continue,
+            };
+            if deletion.left_channel_ids.contains(channel_id) {
+                channel.contact_ids.retain(|c| c != id);
+            }
+            for member in channel.contact_ids.iter_mut().filter(|c| *c == id) {
+                *member = deleted_contact_id();
+            }
+            channel.preferences.retain(|p| p.contact_id != *id);
+            channel.updated_at = Utc::now();
+            channels.update(&channel).await?;
+        }
+        for channel_id in deletion.deleted_channel_ids.iter() {
+            channels.delete(channel_id).await?;
+            messages.delete_by_channel_id(channel_id).await?;
+        }
+        if deletion.anonymize_messages {
+            messages.anonymize_contact(id).await?;
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -115,29 +291,360 @@ impl MessageRepository for InMemoryRepository<Message> {
         _limit: i64,
         _offset: u64,
     ) -> Result<Vec<Message>, RepositoryError> {
+        let now = Utc::now();
         let mut messages = Vec::new();
         for message in self.entities.iter() {
-            if message.channel_id == *channel_id {
+            if message.channel_id == *channel_id && !message.is_expired(now) {
                 messages.push(message.clone());
             }
         }
         Ok(messages)
     }
+
+    async fn find_by_mention(
+        &self,
+        contact_id: &IdType,
+        limit: i64,
+        offset: u64,
+    ) -> Result<Vec<Message>, RepositoryError> {
+        let now = Utc::now();
+        Ok(self
+            .entities
+            .iter()
+            .rev()
+            .filter(|m| m.mentions.contains(contact_id) && !m.is_expired(now))
+            .skip(offset as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn find_by_sender(
+        &self,
+        from: &IdType,
+        limit: i64,
+        offset: u64,
+    ) -> Result<Vec<Message>, RepositoryError> {
+        let now = Utc::now();
+        Ok(self
+            .entities
+            .iter()
+            .filter(|m| m.from == *from && !m.is_expired(now))
+            .skip(offset as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn delete_by_channel_id(&mut self, channel_id: &IdType) -> Result<u64, RepositoryError> {
+        let before = self.entities.len();
+        self.entities.retain(|m| m.channel_id != *channel_id);
+        Ok((before - self.entities.len()) as u64)
+    }
+
+    async fn get_after_seq(
+        &self,
+        channel_id: &IdType,
+        after_seq: i64,
+        limit: i64,
+    ) -> Result<Vec<Message>, RepositoryError> {
+        let now = Utc::now();
+        let mut messages: Vec<Message> = self
+            .entities
+            .iter()
+            .filter(|m| m.channel_id == *channel_id && m.seq > after_seq && !m.is_expired(now))
+            .cloned()
+            .collect();
+        messages.sort_by_key(|m| m.seq);
+        messages.truncate(limit as usize);
+        Ok(messages)
+    }
+
+    async fn next_seq(&mut self, channel_id: &IdType) -> Result<i64, RepositoryError> {
+        let seq = self.sequences.entry(channel_id.clone()).or_insert(0);
+        *seq += 1;
+        Ok(*seq)
+    }
+
+    async fn create_in_sequence(&mut self, message: &Message) -> Result<Message, RepositoryError> {
+        let mut message = message.clone();
+        message.seq = self.next_seq(&message.channel_id).await?;
+        self.create(&message).await
+    }
+
+    async fn anonymize_contact(&mut self, contact_id: &IdType) -> Result<u64, RepositoryError> {
+        let mut count = 0;
+        for message in self.entities.iter_mut() {
+            let mut changed = false;
+            for reference in [&mut message.from, &mut message.to] {
+                if reference == contact_id {
+                    *reference = deleted_contact_id();
+                    changed = true;
+                }
+            }
+            if let MessageContent::System {
+                event:
+                    SystemEvent::MemberJoined { contact_id: member }
+                    | SystemEvent::MemberLeft { contact_id: member },
+            } = &mut message.content
+            {
+                if member == contact_id {
+                    *member = deleted_contact_id();
+                    changed = true;
+                }
+            }
+            let mentions = message.mentions.len();
+            message.mentions.retain(|m| m != contact_id);
+            changed |= message.mentions.len() != mentions;
+            count += u64::from(changed);
+        }
+        Ok(count)
+    }
+
+    async fn find_by_client_message_id(
+        &self,
+        from: &IdType,
+        client_message_id: &str,
+    ) -> Option<Message> {
+        self.entities
+            .iter()
+            .find(|m| m.from == *from && m.client_message_id.as_deref() == Some(client_message_id))
+            .cloned()
+    }
+
+    async fn purge_expired(&mut self, now: DateTime<Utc>) -> Result<u64, RepositoryError> {
+        let count = self.entities.len();
+        self.entities.retain(|m| !m.is_expired(now));
+        Ok((count - self.entities.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl ScheduledMessageRepository for InMemoryRepository<ScheduledMessage> {
+    async fn find_due(&self, now: DateTime<Utc>) -> Result<Vec<ScheduledMessage>, RepositoryError> {
+        Ok(self
+            .entities
+            .iter()
+            .filter(|m| m.status == ScheduledMessageStatus::Pending && m.deliver_at <= now)
+            .cloned()
+            .collect())
+    }
+
+    async fn find_pending_by_sender(
+        &self,
+        contact_id: &IdType,
+    ) -> Result<Vec<ScheduledMessage>, RepositoryError> {
+        let mut messages: Vec<ScheduledMessage> = self
+            .entities
+            .iter()
+            .filter(|m| m.status == ScheduledMessageStatus::Pending && m.from == *contact_id)
+            .cloned()
+            .collect();
+        messages.sort_by_key(|m| m.deliver_at);
+        Ok(messages)
+    }
+
+    async fn update_if_status(
+        &mut self,
+        scheduled: &ScheduledMessage,
+        status: &ScheduledMessageStatus,
+    ) -> Result<bool, RepositoryError> {
+        match self
+            .entities
+            .iter_mut()
+            .find(|m| m.id() == scheduled.id() && &m.status == status)
+        {
+            Some(m) => {
+                *m = scheduled.clone();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for InMemoryRepository<Webhook> {
+    async fn find_by_channel_id(
+        &self,
+        channel_id: &IdType,
+    ) -> Result<Vec<Webhook>, RepositoryError> {
+        Ok(self
+            .entities
+            .iter()
+            .filter(|w| w.channel_id.as_ref().is_none_or(|id| id == channel_id))
+            .cloned()
+            .collect())
+    }
+}
+
+#[async_trait]
+impl WebhookDeliveryRepository for InMemoryRepository<WebhookDelivery> {
+    async fn find_due(&self, now: DateTime<Utc>) -> Result<Vec<WebhookDelivery>, RepositoryError> {
+        Ok(self
+            .entities
+            .iter()
+            .filter(|d| d.status == WebhookDeliveryStatus::Pending && d.next_attempt_at <= now)
+            .cloned()
+            .collect())
+    }
+
+    async fn find_by_webhook_id(
+        &self,
+        webhook_id: &IdType,
+        limit: i64,
+        offset: u64,
+    ) -> Result<Vec<WebhookDelivery>, RepositoryError> {
+        let mut deliveries: Vec<WebhookDelivery> = self
+            .entities
+            .iter()
+            .filter(|d| d.webhook_id == *webhook_id)
+            .cloned()
+            .collect();
+        deliveries.sort_by_key(|d| std::cmp::Reverse(d.created_at));
+        Ok(deliveries
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect())
+    }
+}
+
+#[async_trait]
+impl NotificationRepository for InMemoryRepository<Notification> {
+    async fn find_pending(&self) -> Result<Vec<Notification>, RepositoryError> {
+        let mut notifications: Vec<Notification> = self
+            .entities
+            .iter()
+            .filter(|n| n.status == NotificationStatus::Pending)
+            .cloned()
+            .collect();
+        notifications.sort_by_key(|n| n.created_at);
+        Ok(notifications)
+    }
+}
+
+#[async_trait]
+impl ReportRepository for InMemoryRepository<Report> {
+    async fn find_by_status(
+        &self,
+        status: &ReportStatus,
+        limit: i64,
+        offset: u64,
+    ) -> Result<Vec<Report>, RepositoryError> {
+        let mut reports: Vec<Report> = self
+            .entities
+            .iter()
+            .filter(|r| &r.status == status)
+            .cloned()
+            .collect();
+        reports.sort_by_key(|r| r.created_at);
+        Ok(reports
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect())
+    }
+}
+
+#[async_trait]
+impl ModerationActionRepository for InMemoryRepository<ModerationAction> {
+    async fn find_by_report_id(
+        &self,
+        report_id: &IdType,
+    ) -> Result<Vec<ModerationAction>, RepositoryError> {
+        Ok(self
+            .entities
+            .iter()
+            .filter(|a| &a.report_id == report_id)
+            .cloned()
+            .collect())
+    }
+}
+
+#[async_trait]
+impl SlashCommandRepository for InMemoryRepository<SlashCommand> {
+    async fn find_by_name(&self, name: &str) -> Option<SlashCommand> {
+        self.entities.iter().find(|c| c.name == name).cloned()
+    }
+
+    async fn find_by_bot_id(&self, bot_id: &IdType) -> Result<Vec<SlashCommand>, RepositoryError> {
+        Ok(self
+            .entities
+            .iter()
+            .filter(|c| &c.bot_id == bot_id)
+            .cloned()
+            .collect())
+    }
+}
+
+#[async_trait]
+impl IncomingWebhookRepository for InMemoryRepository<IncomingWebhook> {
+    async fn find_by_token_hash(&self, token_hash: &str) -> Option<IncomingWebhook> {
+        self.entities
+            .iter()
+            .find(|w| w.token_hash.as_deref() == Some(token_hash))
+            .cloned()
+    }
+
+    async fn find_by_channel_id(
+        &self,
+        channel_id: &IdType,
+    ) -> Result<Vec<IncomingWebhook>, RepositoryError> {
+        Ok(self
+            .entities
+            .iter()
+            .filter(|w| w.channel_id == *channel_id)
+            .cloned()
+            .collect())
+    }
 }
 
 pub fn mock_message_repo() -> InMemoryRepository<Message> {
-    InMemoryRepository { entities: vec![] }
+    InMemoryRepository::default()
 }
 
 pub fn mock_channel_repo() -> InMemoryRepository<Channel> {
-    InMemoryRepository { entities: vec![] }
+    InMemoryRepository::default()
 }
 
 /// Creates an in-memory repository with base methods implemented
 pub fn mock_repo<M: Model>() -> impl Repository<M> {
-    InMemoryRepository { entities: vec![] }
+    InMemoryRepository::default()
 }
 
 pub fn mock_contact_repo() -> InMemoryRepository<Contact> {
-    InMemoryRepository { entities: vec![] }
+    InMemoryRepository::default()
+}
+
+pub fn mock_scheduled_message_repo() -> InMemoryRepository<ScheduledMessage> {
+    InMemoryRepository::default()
+}
+
+pub fn mock_webhook_repo() -> InMemoryRepository<Webhook> {
+    InMemoryRepository::default()
+}
+
+pub fn mock_webhook_delivery_repo() -> InMemoryRepository<WebhookDelivery> {
+    InMemoryRepository::default()
+}
+
+pub fn mock_notification_repo() -> InMemoryRepository<Notification> {
+    InMemoryRepository::default()
+}
+
+pub fn mock_report_repo() -> InMemoryRepository<Report> {
+    InMemoryRepository::default()
+}
+
+pub fn mock_moderation_action_repo() -> InMemoryRepository<ModerationAction> {
+    InMemoryRepository::default()
+}
+
+pub fn mock_slash_command_repo() -> InMemoryRepository<SlashCommand> {
+    InMemoryRepository::default()
+}
+
+pub fn mock_incoming_webhook_repo() -> InMemoryRepository<IncomingWebhook> {
+    InMemoryRepository::default()
 }
@@ -0,0 +1,12 @@
+use crate::adapters::{IdType, Repository, RepositoryError};
+use crate::models::IncomingWebhook;
+use async_trait::async_trait;
+
+#[async_trait]
+pub trait IncomingWebhookRepository: Repository<IncomingWebhook> {
+    async fn find_by_token_hash(&self, token_hash: &str) -> Option<IncomingWebhook>;
+    async fn find_by_channel_id(
+        &self,
+        channel_id: &IdType,
+    ) -> Result<Vec<IncomingWebhook>, RepositoryError>;
+}
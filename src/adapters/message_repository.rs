@@ -1,7 +1,9 @@
 use crate::adapters::{IdType, Repository, RepositoryError};
 use crate::models::Message;
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 
+/// History queries never return expired messages
 #[async_trait]
 pub trait MessageRepository: Repository<Message> {
     async fn get_by_channel_id(
@@ -10,4 +12,49 @@ pub trait MessageRepository: Repository<Message> {
         limit: i64,
         offset: u64,
     ) -> Result<Vec<Message>, RepositoryError>;
+    /// Returns the messages that mention the given contact, newest first
+    async fn find_by_mention(
+        &self,
+        contact_id: &IdType,
+        limit: i64,
+        offset: u64,
+    ) -> Result<Vec<Message>, RepositoryError>;
+    /// Returns the messages the contact sent, oldest first
+    async fn find_by_sender(
+        &self,
+        from: &IdType,
+        limit: i64,
+        offset: u64,
+    ) -> Result<Vec<Message>, RepositoryError>;
+    /// Returns the messages of the channel after the given sequence number,
+    /// in sequence order
+    async fn get_after_seq(
+        &self,
+        channel_id: &IdType,
+        after_seq: i64,
+        limit: i64,
+    ) -> Result<Vec<Message>, RepositoryError>;
+    /// Atomically reserves the next sequence number of the channel.
+    /// Numbers always increase but can skip values when a write fails. A
+    /// reserved number can be stored after higher ones, so only channels no
+    /// client reads yet, like imports, should be filled this way.
+    async fn next_seq(&mut self, channel_id: &IdType) -> Result<i64, RepositoryError>;
+    /// Stores the message under the next sequence number of its channel,
+    /// reserved with a single atomic increment before the insert. A failed
+    /// insert leaves a gap, and a concurrent send can be stored before a
+    /// lower number it raced with.
+    async fn create_in_sequence(&mut self, message: &Message) -> Result<Message, RepositoryError>;
+    /// Rewrites the sender, recipient and system events of the messages
+    /// referencing the contact to the placeholder, and drops its mentions
+    async fn anonymize_contact(&mut self, contact_id: &IdType) -> Result<u64, RepositoryError>;
+    /// Returns the message the sender stored with the given idempotency key
+    async fn find_by_client_message_id(
+        &self,
+        from: &IdType,
+        client_message_id: &str,
+    ) -> Option<Message>;
+    /// Deletes the messages of the channel and returns how many were deleted
+    async fn delete_by_channel_id(&mut self, channel_id: &IdType) -> Result<u64, RepositoryError>;
+    /// Deletes the messages expired by the given time and returns how many were deleted
+    async fn purge_expired(&mut self, now: DateTime<Utc>) -> Result<u64, RepositoryError>;
 }
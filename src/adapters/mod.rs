@@ -1,15 +1,24 @@
 mod base;
-pub use base::{IdType, Model, Repository, RepositoryError};
+pub use base::{IdType, Model, Repository, RepositoryError, SoftDeleteRepository};
 
 pub mod channel_repository;
 pub mod contact_repository;
+pub mod incoming_webhook_repository;
 pub mod mongo;
 
 #[cfg(test)]
 mod in_memory;
 pub mod message_repository;
+pub mod notification_repository;
+pub mod report_repository;
+pub mod scheduled_message_repository;
+pub mod slash_command_repository;
+pub mod webhook_repository;
 
 #[cfg(test)]
 pub use in_memory::repository::{
-    mock_channel_repo, mock_contact_repo, mock_message_repo, mock_repo, InMemoryRepository,
+    mock_channel_repo, mock_contact_repo, mock_incoming_webhook_repo, mock_message_repo,
+    mock_moderation_action_repo, mock_notification_repo, mock_repo, mock_report_repo,
+    mock_scheduled_message_repo, mock_slash_command_repo, mock_webhook_delivery_repo,
+    mock_webhook_repo, InMemoryRepository,
 };
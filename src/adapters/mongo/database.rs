@@ -1,12 +1,137 @@
-use mongodb::options::ClientOptions;
-use mongodb::Database;
+use mongodb::bson::{doc, Document};
+use mongodb::error::Error;
+use mongodb::options::{ClientOptions, IndexOptions};
+use mongodb::{ClientSession, Database, IndexModel};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// The client `init` connected with, which transactions start their sessions from
+static CLIENT: OnceLock<mongodb::Client> = OnceLock::new();
 
 pub async fn init(db_name: &str) -> Database {
+    #[allow(non_snake_case)]
     let MONGO_URL = std::env::var("MONGO_URL").unwrap_or("mongodb://localhost:27017".to_string());
     let mut options = ClientOptions::parse(&MONGO_URL).await.unwrap();
     options.app_name = Some(db_name.to_string());
     let client = mongodb::Client::with_options(options).unwrap();
-    client.database(db_name)
+    let database = client.database(db_name);
+    let _ = CLIENT.set(client);
+    database
+}
+
+/// Starts a session of the client `init` connected with
+pub async fn start_session() -> Result<ClientSession, Error> {
+    let client = CLIENT.get().expect("The database is not initialized");
+    client.start_session(None).await
+}
+
+/// Creates the indexes the repositories rely on
+pub async fn create_indexes(db: &Database) -> Result<(), Error> {
+    // Mongo deletes the messages once their expiry date has passed
+    let expires_at = IndexModel::builder()
+        .keys(doc! { "expires_at": 1 })
+        .options(
+            IndexOptions::builder()
+                .expire_after(Duration::from_secs(0))
+                .build(),
+        )
+        .build();
+    // A sender cannot store two messages with the same idempotency key
+    let client_message_id = IndexModel::builder()
+        .keys(doc! { "from": 1, "client_message_id": 1 })
+        .options(
+            IndexOptions::builder()
+                .unique(true)
+                .partial_filter_expression(doc! { "client_message_id": { "$exists": true } })
+                .build(),
+        )
+        .build();
+    let seq = IndexModel::builder()
+        .keys(doc! { "channel_id": 1, "seq": 1 })
+        .options(
+            IndexOptions::builder()
+                .unique(true)
+                .partial_filter_expression(doc! { "seq": { "$gt": 0 } })
+                .build(),
+        )
+        .build();
+    let sender = IndexModel::builder()
+        .keys(doc! { "from": 1, "created_at": 1 })
+        .build();
+    db.collection::<Document>("messages")
+        .create_indexes([expires_at, client_message_id, seq, sender], None)
+        .await?;
+    let due = IndexModel::builder()
+        .keys(doc! { "status": 1, "next_attempt_at": 1 })
+        .build();
+    let webhook_id = IndexModel::builder()
+        .keys(doc! { "webhook_id": 1, "created_at": -1 })
+        .build();
+    db.collection::<Document>("webhook_deliveries")
+        .create_indexes([due, webhook_id], None)
+        .await?;
+    let pending = IndexModel::builder()
+        .keys(doc! { "status": 1, "created_at": 1 })
+        .build();
+    db.collection::<Document>("notifications")
+        .create_index(pending, None)
+        .await?;
+    let queue = IndexModel::builder()
+        .keys(doc! { "status": 1, "created_at": 1 })
+        .build();
+    db.collection::<Document>("reports")
+        .create_index(queue, None)
+        .await?;
+    let report_id = IndexModel::builder()
+        .keys(doc! { "report_id": 1, "created_at": 1 })
+        .build();
+    db.collection::<Document>("moderation_actions")
+        .create_index(report_id, None)
+        .await?;
+    // Incoming webhooks are looked up by the hash of their token
+    let token_hash = IndexModel::builder()
+        .keys(doc! { "token_hash": 1 })
+        .options(
+            IndexOptions::builder()
+                .unique(true)
+                .partial_filter_expression(doc! { "token_hash": { "$exists": true } })
+                .build(),
+        )
+        .build();
+    db.collection::<Document>("incoming_webhooks")
+        .create_index(token_hash, None)
+        .await?;
+    // Bots are looked up by the hash of their API key
+    let api_key_hash = IndexModel::builder()
+        .keys(doc! { "api_key_hash": 1 })
+        .options(
+            IndexOptions::builder()
+                .unique(true)
+                .partial_filter_expression(doc! { "api_key_hash": { "$exists": true } })
+                .build(),
+        )
+        .build();
+    // The purge looks up what was deleted before the grace period
+    let deleted_at = || {
+        IndexModel::builder()
+            .keys(doc! { "deleted_at": 1 })
+            .options(IndexOptions::builder().sparse(true).build())
+            .build()
+    };
+    db.collection::<Document>("contacts")
+        .create_indexes([api_key_hash, deleted_at()], None)
+        .await?;
+    db.collection::<Document>("channels")
+        .create_index(deleted_at(), None)
+        .await?;
+    let name = IndexModel::builder()
+        .keys(doc! { "name": 1 })
+        .options(IndexOptions::builder().unique(true).build())
+        .build();
+    db.collection::<Document>("slash_commands")
+        .create_index(name, None)
+        .await?;
+    Ok(())
 }
 
 #[cfg(test)]
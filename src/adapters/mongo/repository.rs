@@ -1,22 +1,38 @@
 use crate::adapters::channel_repository::ChannelRepository;
-use crate::adapters::contact_repository::ContactRepository;
+use crate::adapters::contact_repository::{ContactDeletion, ContactRepository};
+use crate::adapters::incoming_webhook_repository::IncomingWebhookRepository;
 use crate::adapters::message_repository::MessageRepository;
-use crate::adapters::{IdType, Model, Repository, RepositoryError};
-use crate::models::{Channel, Contact, Message};
+use crate::adapters::mongo::database::start_session;
+use crate::adapters::notification_repository::NotificationRepository;
+use crate::adapters::report_repository::{ModerationActionRepository, ReportRepository};
+use crate::adapters::scheduled_message_repository::ScheduledMessageRepository;
+use crate::adapters::slash_command_repository::SlashCommandRepository;
+use crate::adapters::webhook_repository::{WebhookDeliveryRepository, WebhookRepository};
+use crate::adapters::{IdType, Model, Repository, RepositoryError, SoftDeleteRepository};
+use crate::models::{
+    deleted_contact_id, Channel, ChannelPreferences, Contact, IncomingWebhook, Message,
+    ModerationAction, Notification, Report, ReportStatus, ScheduledMessage, ScheduledMessageStatus,
+    SlashCommand, Webhook, WebhookDelivery, MAX_PINNED_MESSAGES,
+};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use futures::TryStreamExt;
 
+use mongodb::bson::oid::ObjectId;
 use mongodb::bson::{doc, Document};
 use serde::de::DeserializeOwned;
 
 pub struct MongoRepository<M> {
     pub collection: mongodb::Collection<M>,
+    /// The database of the collection, for the documents kept next to the models
+    database: mongodb::Database,
 }
 
 impl<M: Model> MongoRepository<M> {
     pub fn new(db: &mongodb::Database, collection_name: &str) -> Self {
         MongoRepository {
             collection: db.collection(collection_name),
+            database: db.clone(),
         }
     }
 }
@@ -52,14 +68,14 @@ where
 
     async fn delete(&mut self, id: &IdType) -> Result<(), RepositoryError> {
         let object_id = match id {
-            IdType::String(s) => {
-                match mongodb::bson::oid::ObjectId::parse_str(s) {
-                    Ok(o) => o,
-                    Err(_) => return Err(RepositoryError {
+            IdType::String(s) => match mongodb::bson::oid::ObjectId::parse_str(s) {
+                Ok(o) => o,
+                Err(_) => {
+                    return Err(RepositoryError {
                         message: "Invalid id".to_string(),
-                    }),
+                    })
                 }
-            }
+            },
             IdType::ObjectId(o) => *o,
         };
         let doc = doc! { "_id": object_id };
@@ -75,18 +91,18 @@ where
 
     async fn get(&self, _id: &IdType) -> Option<M> {
         let object_id = match _id {
-            IdType::String(s) => {
-                
-                match mongodb::bson::oid::ObjectId::parse_str(s) {
-                    Ok(o) => o,
-                    Err(_) => return None,
-                }
-            }
+            IdType::String(s) => match mongodb::bson::oid::ObjectId::parse_str(s) {
+                Ok(o) => o,
+                Err(_) => return None,
+            },
             IdType::ObjectId(o) => *o,
         };
 
         self.collection
-            .find_one(Some(doc! { "_id": object_id }), None)
+            .find_one(
+                Some(doc! { "_id": object_id, "deleted_at": null, "$or": not_expired() }),
+                None,
+            )
             .await
             .unwrap()
     }
@@ -100,8 +116,17 @@ where
             .skip(skip.unwrap_or(0))
             .limit(limit.unwrap_or(100) as i64)
             .build();
-        let mut cursor = self.collection.find(None, options).await.unwrap();
-        let count = self.collection.count_documents(None, None).await.unwrap();
+        let filter = doc! { "deleted_at": null };
+        let mut cursor = self
+            .collection
+            .find(Some(filter.clone()), options)
+            .await
+            .unwrap();
+        let count = self
+            .collection
+            .count_documents(Some(filter), None)
+            .await
+            .unwrap();
         let mut models = Vec::new();
 
         while let Some(result) = cursor.try_next().await.unwrap() {
@@ -110,18 +135,245 @@ where
 
         Ok((count as i32, models))
     }
+
+    fn collection_name(&self) -> Option<&str> {
+        Some(self.collection.name())
+    }
+}
+
+#[async_trait]
+impl<M> SoftDeleteRepository<M> for MongoRepository<M>
+where
+    M: Model + DeserializeOwned + Unpin + Send + Sync,
+{
+    async fn get_with_deleted(&self, id: &IdType) -> Option<M> {
+        let object_id = match id {
+            IdType::String(s) => match mongodb::bson::oid::ObjectId::parse_str(s) {
+                Ok(o) => o,
+                Err(_) => return None,
+            },
+            IdType::ObjectId(o) => *o,
+        };
+        self.collection
+            .find_one(Some(doc! { "_id": object_id }), None)
+            .await
+            .unwrap()
+    }
+
+    async fn find_deleted_before(&self, before: DateTime<Utc>) -> Result<Vec<M>, RepositoryError> {
+        let mut cursor = match self
+            .collection
+            .find(
+                Some(doc! { "deleted_at": { "$lt": before.timestamp() } }),
+                None,
+            )
+            .await
+        {
+            Ok(c) => c,
+            Err(e) => {
+                return Err(RepositoryError {
+                    message: e.to_string(),
+                })
+            }
+        };
+        let mut models = Vec::new();
+        while let Some(result) = cursor.try_next().await.unwrap() {
+            models.push(result);
+        }
+        Ok(models)
+    }
 }
 
 #[async_trait]
 impl ContactRepository for MongoRepository<Contact> {
     async fn find_by_email(&self, email: &str) -> Option<Contact> {
-        
-        self
-            .collection
-            .find_one(Some(doc! { "email": email }), None)
+        self.collection
+            .find_one(Some(doc! { "email": email, "deleted_at": null }), None)
             .await
             .unwrap()
     }
+
+    async fn find_by_api_key_hash(&self, api_key_hash: &str) -> Option<Contact> {
+        self.collection
+            .find_one(
+                Some(doc! { "api_key_hash": api_key_hash, "deleted_at": null }),
+                None,
+            )
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Runs the cascade in a transaction on the collections of the given
+    /// repositories, which needs Mongo to run as a replica set
+    async fn delete_cascade(
+        &mut self,
+        deletion: &ContactDeletion,
+        channels: &mut dyn ChannelRepository,
+        messages: &mut dyn MessageRepository,
+    ) -> Result<(), RepositoryError> {
+        let object_id = match &deletion.contact_id {
+            IdType::String(s) => match ObjectId::parse_str(s) {
+                Ok(o) => o,
+                Err(_) => {
+                    return Err(RepositoryError {
+                        message: "Invalid id".to_string(),
+                    })
+                }
+            },
+            IdType::ObjectId(o) => *o,
+        };
+        let (channels, messages) = match (channels.collection_name(), messages.collection_name()) {
+            (Some(c), Some(m)) => (
+                self.database.collection::<Document>(c),
+                self.database.collection::<Document>(m),
+            ),
+            _ => {
+                return Err(RepositoryError {
+                    message: "The channels and messages must be stored in Mongo".to_string(),
+                })
+            }
+        };
+        let collections = CascadeCollections {
+            contacts: self.collection.clone_with_type(),
+            channels,
+            messages,
+        };
+        let result = async {
+            let mut session = start_session().await?;
+            session.start_transaction(None).await?;
+            match cascade(&collections, object_id, deletion, &mut session).await {
+                Ok(_) => session.commit_transaction().await,
+                Err(e) => {
+                    let _ = session.abort_transaction().await;
+                    Err(e)
+                }
+            }
+        }
+        .await;
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => Err(RepositoryError {
+                message: e.to_string(),
+            }),
+        }
+    }
+}
+
+/// The collections a contact deletion cascades through
+struct CascadeCollections {
+    contacts: mongodb::Collection<Document>,
+    channels: mongodb::Collection<Document>,
+    messages: mongodb::Collection<Document>,
+}
+
+async fn cascade(
+    collections: &CascadeCollections,
+    object_id: ObjectId,
+    deletion: &ContactDeletion,
+    session: &mut mongodb::ClientSession,
+) -> Result<(), mongodb::error::Error> {
+    let CascadeCollections {
+        contacts,
+        channels,
+        messages,
+    } = collections;
+    contacts
+        .delete_one_with_session(doc! { "_id": object_id }, None, session)
+        .await?;
+    contacts
+        .update_many_with_session(
+            doc! { "blocked.contact_id": { "ObjectId": object_id } },
+            doc! { "$pull": { "blocked": { "contact_id": { "ObjectId": object_id } } } },
+            None,
+            session,
+        )
+        .await?;
+
+    let member = doc! { "ObjectId": object_id };
+    let placeholder = mongodb::bson::to_bson(&deleted_contact_id()).unwrap();
+    let now = Utc::now().timestamp();
+    channels
+        .update_many_with_session(
+            doc! { "_id": { "$in": object_ids(&deletion.left_channel_ids) } },
+            doc! {
+                "$pull": { "contact_ids": member.clone(), "preferences": { "contact_id": member.clone() } },
+                "$set": { "updated_at": now },
+            },
+            None,
+            session,
+        )
+        .await?;
+    channels
+        .update_many_with_session(
+            doc! {
+                "_id": { "$in": object_ids(&deletion.orphaned_channel_ids) },
+                "contact_ids": member.clone(),
+            },
+            doc! {
+                "$set": { "contact_ids.$": placeholder, "updated_at": now },
+                "$pull": { "preferences": { "contact_id": member } },
+            },
+            None,
+            session,
+        )
+        .await?;
+    if !deletion.deleted_channel_ids.is_empty() {
+        let ids: Vec<_> = deletion
+            .deleted_channel_ids
+            .iter()
+            .map(|id| mongodb::bson::to_bson(id).unwrap())
+            .collect();
+        channels
+            .delete_many_with_session(
+                doc! { "_id": { "$in": object_ids(&deletion.deleted_channel_ids) } },
+                None,
+                session,
+            )
+            .await?;
+        messages
+            .delete_many_with_session(doc! { "channel_id": { "$in": ids } }, None, session)
+            .await?;
+    }
+
+    if deletion.anonymize_messages {
+        for (filter, update) in anonymize_updates(object_id) {
+            messages
+                .update_many_with_session(filter, update, None, session)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// The ids stored as object ids among the given ones
+fn object_ids(ids: &[IdType]) -> Vec<ObjectId> {
+    ids.iter()
+        .filter_map(|id| match id {
+            IdType::ObjectId(o) => Some(*o),
+            IdType::String(_) => None,
+        })
+        .collect()
+}
+
+/// The updates rewriting the references of the messages to the contact to
+/// the placeholder, as filters and changes
+fn anonymize_updates(object_id: ObjectId) -> Vec<(Document, Document)> {
+    let placeholder = mongodb::bson::to_bson(&deleted_contact_id()).unwrap();
+    let mut updates: Vec<(Document, Document)> = ["from", "to", "content.event.contact_id"]
+        .into_iter()
+        .map(|field| {
+            (
+                doc! { field: { "ObjectId": object_id } },
+                doc! { "$set": { field: placeholder.clone() } },
+            )
+        })
+        .collect();
+    updates.push((
+        doc! { "mentions": { "ObjectId": object_id } },
+        doc! { "$pull": { "mentions": { "ObjectId": object_id } } },
+    ));
+    updates
 }
 
 #[async_trait]
@@ -138,7 +390,8 @@ impl ChannelRepository for MongoRepository<Channel> {
             .collection
             .find(
                 Some(doc! {
-                    "contact_ids": {"ObjectId": object_id}
+                    "contact_ids": {"ObjectId": object_id},
+                    "deleted_at": null,
                 }),
                 None,
             )
@@ -151,7 +404,38 @@ impl ChannelRepository for MongoRepository<Channel> {
         Ok(channels)
     }
 
-    async fn get_by_contact_ids(&self, contact_ids: &Vec<IdType>) -> Option<Channel> {
+    async fn find_by_contact_id_with_deleted(
+        &self,
+        contact_id: &IdType,
+    ) -> Result<Vec<Channel>, RepositoryError> {
+        let object_id = match contact_id {
+            IdType::String(s) => match ObjectId::parse_str(s) {
+                Ok(o) => o,
+                Err(_) => return Ok(vec![]),
+            },
+            IdType::ObjectId(o) => *o,
+        };
+        let cursor = match self
+            .collection
+            .find(doc! { "contact_ids": { "ObjectId": object_id } }, None)
+            .await
+        {
+            Ok(c) => c,
+            Err(e) => {
+                return Err(RepositoryError {
+                    message: e.to_string(),
+                })
+            }
+        };
+        match cursor.try_collect().await {
+            Ok(channels) => Ok(channels),
+            Err(e) => Err(RepositoryError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    async fn get_by_contact_ids(&self, contact_ids: &[IdType]) -> Option<Channel> {
         let ids = contact_ids
             .iter()
             .map(|id| match id {
@@ -170,13 +454,175 @@ impl ChannelRepository for MongoRepository<Channel> {
                 Some(doc! {
                     "contact_ids": {
                         "$all": ids
-                        }
+                        },
+                    "deleted_at": null,
                 }),
                 None,
             )
             .await
             .unwrap()
     }
+
+    async fn update_without_preferences(
+        &mut self,
+        channel: &Channel,
+    ) -> Result<(), RepositoryError> {
+        let mut replacement = match mongodb::bson::to_document(channel) {
+            Ok(d) => d,
+            Err(e) => {
+                return Err(RepositoryError {
+                    message: e.to_string(),
+                })
+            }
+        };
+        replacement.remove("preferences");
+        // The stored settings are carried over in the same update, so a
+        // concurrent `set_preferences` is kept
+        let update = vec![doc! {
+            "$replaceWith": {
+                "$mergeObjects": [
+                    { "$literal": replacement },
+                    { "preferences": { "$ifNull": ["$preferences", []] } },
+                ]
+            }
+        }];
+        let filter = match channel.id() {
+            IdType::String(s) => doc! { "id": s },
+            IdType::ObjectId(o) => doc! { "_id": o },
+        };
+        match self.collection.update_one(filter, update, None).await {
+            Ok(r) if r.matched_count == 0 => Err(RepositoryError {
+                message: "Entity not found".to_string(),
+            }),
+            Ok(_) => Ok(()),
+            Err(e) => Err(RepositoryError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    async fn set_preferences(
+        &mut self,
+        channel_id: &IdType,
+        contact_id: &IdType,
+        preferences: Option<&ChannelPreferences>,
+    ) -> Result<(), RepositoryError> {
+        let object_id = match channel_id {
+            IdType::String(s) => match ObjectId::parse_str(s) {
+                Ok(o) => o,
+                Err(_) => {
+                    return Err(RepositoryError {
+                        message: "Invalid id".to_string(),
+                    })
+                }
+            },
+            IdType::ObjectId(o) => *o,
+        };
+        let (member, preferences) = match (
+            mongodb::bson::to_bson(contact_id),
+            preferences.map(mongodb::bson::to_bson).transpose(),
+        ) {
+            (Ok(m), Ok(p)) => (m, p),
+            (Err(e), _) | (_, Err(e)) => {
+                return Err(RepositoryError {
+                    message: e.to_string(),
+                })
+            }
+        };
+        // Swapped in one pipeline update, so concurrent changes of the other
+        // members' settings or of the channel are kept
+        let mut kept = doc! {
+            "$filter": {
+                "input": { "$ifNull": ["$preferences", []] },
+                "cond": { "$ne": ["$$this.contact_id", member] },
+            }
+        };
+        if let Some(p) = preferences {
+            kept = doc! { "$concatArrays": [kept, [{ "$literal": p }]] };
+        }
+        let update = vec![doc! { "$set": { "preferences": kept } }];
+        match self
+            .collection
+            .update_one(doc! { "_id": object_id }, update, None)
+            .await
+        {
+            Ok(r) if r.matched_count == 0 => Err(RepositoryError {
+                message: "Entity not found".to_string(),
+            }),
+            Ok(_) => Ok(()),
+            Err(e) => Err(RepositoryError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    async fn pin_message(
+        &mut self,
+        channel_id: &IdType,
+        message_id: &IdType,
+    ) -> Result<bool, RepositoryError> {
+        let (object_id, pinned) = pin_ids(channel_id, message_id)?;
+        // The pin past the cap is missing while the channel has room left
+        let last_pin = format!("pinned_message_ids.{}", MAX_PINNED_MESSAGES - 1);
+        let filter = doc! {
+            "_id": object_id,
+            "pinned_message_ids": { "$ne": pinned.clone() },
+            last_pin: { "$exists": false },
+        };
+        let update = doc! {
+            "$addToSet": { "pinned_message_ids": pinned },
+            "$set": { "updated_at": Utc::now().timestamp() },
+        };
+        match self.collection.update_one(filter, update, None).await {
+            Ok(r) => Ok(r.matched_count == 1),
+            Err(e) => Err(RepositoryError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    async fn unpin_message(
+        &mut self,
+        channel_id: &IdType,
+        message_id: &IdType,
+    ) -> Result<bool, RepositoryError> {
+        let (object_id, pinned) = pin_ids(channel_id, message_id)?;
+        let filter = doc! { "_id": object_id, "pinned_message_ids": pinned.clone() };
+        let update = doc! {
+            "$pull": { "pinned_message_ids": pinned },
+            "$set": { "updated_at": Utc::now().timestamp() },
+        };
+        match self.collection.update_one(filter, update, None).await {
+            Ok(r) => Ok(r.matched_count == 1),
+            Err(e) => Err(RepositoryError {
+                message: e.to_string(),
+            }),
+        }
+    }
+}
+
+/// The object id of the channel and the stored form of the pinned message id
+fn pin_ids(
+    channel_id: &IdType,
+    message_id: &IdType,
+) -> Result<(ObjectId, mongodb::bson::Bson), RepositoryError> {
+    let object_id = match channel_id {
+        IdType::String(s) => match ObjectId::parse_str(s) {
+            Ok(o) => o,
+            Err(_) => {
+                return Err(RepositoryError {
+                    message: "Invalid id".to_string(),
+                })
+            }
+        },
+        IdType::ObjectId(o) => *o,
+    };
+    match mongodb::bson::to_bson(message_id) {
+        Ok(pinned) => Ok((object_id, pinned)),
+        Err(e) => Err(RepositoryError {
+            message: e.to_string(),
+        }),
+    }
 }
 
 #[async_trait]
@@ -191,12 +637,143 @@ impl MessageRepository for MongoRepository<Message> {
             IdType::String(s) => mongodb::bson::oid::ObjectId::parse_str(s).unwrap(),
             IdType::ObjectId(o) => *o,
         };
+        let options = mongodb::options::FindOptions::builder()
+            .limit(Some(limit))
+            .skip(Some(offset))
+            .sort(Some(doc! { "seq": -1, "created_at": -1 }))
+            .build();
+
+        let mut cursor = self
+            .collection
+            .find(
+                Some(doc! {
+                    "channel_id": {
+                        "ObjectId": object_id
+                    },
+                    "$or": not_expired(),
+                }),
+                options,
+            )
+            .await
+            .unwrap();
+        let mut messages = Vec::new();
+        while let Some(result) = cursor.try_next().await.unwrap() {
+            messages.push(result);
+        }
+        Ok(messages)
+    }
+
+    async fn find_by_mention(
+        &self,
+        contact_id: &IdType,
+        limit: i64,
+        offset: u64,
+    ) -> Result<Vec<Message>, RepositoryError> {
+        let object_id = match contact_id {
+            IdType::String(s) => match mongodb::bson::oid::ObjectId::parse_str(s) {
+                Ok(o) => o,
+                Err(_) => return Ok(vec![]),
+            },
+            IdType::ObjectId(o) => *o,
+        };
         let options = mongodb::options::FindOptions::builder()
             .limit(Some(limit))
             .skip(Some(offset))
             .sort(Some(doc! { "created_at": -1 }))
             .build();
 
+        let mut cursor = self
+            .collection
+            .find(
+                Some(doc! {
+                    "mentions": {
+                        "ObjectId": object_id
+                    },
+                    "$or": not_expired(),
+                }),
+                options,
+            )
+            .await
+            .unwrap();
+        let mut messages = Vec::new();
+        while let Some(result) = cursor.try_next().await.unwrap() {
+            messages.push(result);
+        }
+        Ok(messages)
+    }
+
+    async fn find_by_sender(
+        &self,
+        from: &IdType,
+        limit: i64,
+        offset: u64,
+    ) -> Result<Vec<Message>, RepositoryError> {
+        let object_id = match from {
+            IdType::String(s) => mongodb::bson::oid::ObjectId::parse_str(s).unwrap(),
+            IdType::ObjectId(o) => *o,
+        };
+        let options = mongodb::options::FindOptions::builder()
+            .limit(Some(limit))
+            .skip(Some(offset))
+            .sort(Some(doc! { "created_at": 1, "_id": 1 }))
+            .build();
+
+        let mut cursor = self
+            .collection
+            .find(
+                Some(doc! {
+                    "from": {
+                        "ObjectId": object_id
+                    },
+                    "$or": not_expired(),
+                }),
+                options,
+            )
+            .await
+            .unwrap();
+        let mut messages = Vec::new();
+        while let Some(result) = cursor.try_next().await.unwrap() {
+            messages.push(result);
+        }
+        Ok(messages)
+    }
+
+    async fn find_by_client_message_id(
+        &self,
+        from: &IdType,
+        client_message_id: &str,
+    ) -> Option<Message> {
+        let object_id = match from {
+            IdType::String(s) => mongodb::bson::oid::ObjectId::parse_str(s).ok()?,
+            IdType::ObjectId(o) => *o,
+        };
+        self.collection
+            .find_one(
+                Some(doc! {
+                    "from": { "ObjectId": object_id },
+                    "client_message_id": client_message_id,
+                }),
+                None,
+            )
+            .await
+            .unwrap()
+    }
+
+    async fn get_after_seq(
+        &self,
+        channel_id: &IdType,
+        after_seq: i64,
+        limit: i64,
+    ) -> Result<Vec<Message>, RepositoryError> {
+        let object_id = match channel_id {
+            IdType::String(s) => mongodb::bson::oid::ObjectId::parse_str(s).unwrap(),
+            IdType::ObjectId(o) => *o,
+        };
+        let options = mongodb::options::FindOptions::builder()
+            .limit(Some(limit))
+            .sort(Some(doc! { "seq": 1 }))
+            .build();
+
         let mut cursor = self
             .collection
             .find(
@@ -204,6 +781,8 @@ impl MessageRepository for MongoRepository<Message> {
                     "channel_id": {
                         "ObjectId": object_id
                     },
+                    "seq": { "$gt": after_seq },
+                    "$or": not_expired(),
                 }),
                 options,
             )
@@ -215,4 +794,484 @@ impl MessageRepository for MongoRepository<Message> {
         }
         Ok(messages)
     }
+
+    async fn next_seq(&mut self, channel_id: &IdType) -> Result<i64, RepositoryError> {
+        let object_id = match channel_id {
+            IdType::String(s) => match mongodb::bson::oid::ObjectId::parse_str(s) {
+                Ok(o) => o,
+                Err(_) => {
+                    return Err(RepositoryError {
+                        message: "Invalid id".to_string(),
+                    })
+                }
+            },
+            IdType::ObjectId(o) => *o,
+        };
+        let options = mongodb::options::FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(mongodb::options::ReturnDocument::After)
+            .build();
+        let result = self
+            .database
+            .collection::<Document>("channel_sequences")
+            .find_one_and_update(
+                doc! { "_id": object_id },
+                doc! { "$inc": { "seq": 1_i64 } },
+                options,
+            )
+            .await;
+        match result {
+            Ok(Some(d)) => match d.get_i64("seq") {
+                Ok(seq) => Ok(seq),
+                Err(e) => Err(RepositoryError {
+                    message: e.to_string(),
+                }),
+            },
+            Ok(None) => Err(RepositoryError {
+                message: format!("Could not increment the sequence of channel {channel_id}"),
+            }),
+            Err(e) => Err(RepositoryError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    /// Increments the counter, then inserts the message, without a
+    /// transaction so that a standalone Mongo server is enough
+    async fn create_in_sequence(&mut self, message: &Message) -> Result<Message, RepositoryError> {
+        let mut message = message.clone();
+        message.seq = self.next_seq(&message.channel_id).await?;
+        self.create(&message).await
+    }
+
+    async fn anonymize_contact(&mut self, contact_id: &IdType) -> Result<u64, RepositoryError> {
+        let object_id = match contact_id {
+            IdType::String(s) => match ObjectId::parse_str(s) {
+                Ok(o) => o,
+                Err(_) => return Ok(0),
+            },
+            IdType::ObjectId(o) => *o,
+        };
+        let mut count = 0;
+        for (filter, update) in anonymize_updates(object_id) {
+            match self.collection.update_many(filter, update, None).await {
+                Ok(r) => count += r.modified_count,
+                Err(e) => {
+                    return Err(RepositoryError {
+                        message: e.to_string(),
+                    })
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    async fn delete_by_channel_id(&mut self, channel_id: &IdType) -> Result<u64, RepositoryError> {
+        let object_id = match channel_id {
+            IdType::String(s) => mongodb::bson::oid::ObjectId::parse_str(s).unwrap(),
+            IdType::ObjectId(o) => *o,
+        };
+        let result = self
+            .collection
+            .delete_many(doc! { "channel_id": { "ObjectId": object_id } }, None)
+            .await;
+        match result {
+            Ok(r) => Ok(r.deleted_count),
+            Err(e) => Err(RepositoryError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    async fn purge_expired(&mut self, now: DateTime<Utc>) -> Result<u64, RepositoryError> {
+        let expires_at = mongodb::bson::DateTime::from_millis(now.timestamp_millis());
+        let result = self
+            .collection
+            .delete_many(doc! { "expires_at": { "$lte": expires_at } }, None)
+            .await;
+        match result {
+            Ok(r) => Ok(r.deleted_count),
+            Err(e) => Err(RepositoryError {
+                message: e.to_string(),
+            }),
+        }
+    }
+}
+
+/// Matches the messages without an expiry or expiring in the future
+fn not_expired() -> Vec<Document> {
+    vec![
+        doc! { "expires_at": { "$exists": false } },
+        doc! { "expires_at": { "$gt": mongodb::bson::DateTime::now() } },
+    ]
+}
+
+#[async_trait]
+impl ScheduledMessageRepository for MongoRepository<ScheduledMessage> {
+    async fn find_due(&self, now: DateTime<Utc>) -> Result<Vec<ScheduledMessage>, RepositoryError> {
+        let options = mongodb::options::FindOptions::builder()
+            .sort(Some(doc! { "deliver_at": 1 }))
+            .build();
+        let mut cursor = match self
+            .collection
+            .find(
+                Some(doc! {
+                    "status": "Pending",
+                    "deliver_at": { "$lte": now.timestamp() },
+                }),
+                options,
+            )
+            .await
+        {
+            Ok(c) => c,
+            Err(e) => {
+                return Err(RepositoryError {
+                    message: e.to_string(),
+                })
+            }
+        };
+        let mut messages = Vec::new();
+        while let Some(result) = cursor.try_next().await.unwrap() {
+            messages.push(result);
+        }
+        Ok(messages)
+    }
+
+    async fn find_pending_by_sender(
+        &self,
+        contact_id: &IdType,
+    ) -> Result<Vec<ScheduledMessage>, RepositoryError> {
+        // Matches the sender as stored, so string ids like the deleted
+        // contact placeholder find their messages instead of failing to parse
+        let sender = match mongodb::bson::to_bson(contact_id) {
+            Ok(s) => s,
+            Err(e) => {
+                return Err(RepositoryError {
+                    message: e.to_string(),
+                })
+            }
+        };
+        let options = mongodb::options::FindOptions::builder()
+            .sort(Some(doc! { "deliver_at": 1 }))
+            .build();
+        let cursor = match self
+            .collection
+            .find(
+                Some(doc! {
+                    "status": "Pending",
+                    "from": sender,
+                }),
+                options,
+            )
+            .await
+        {
+            Ok(c) => c,
+            Err(e) => {
+                return Err(RepositoryError {
+                    message: e.to_string(),
+                })
+            }
+        };
+        match cursor.try_collect().await {
+            Ok(messages) => Ok(messages),
+            Err(e) => Err(RepositoryError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    async fn update_if_status(
+        &mut self,
+        scheduled: &ScheduledMessage,
+        status: &ScheduledMessageStatus,
+    ) -> Result<bool, RepositoryError> {
+        let status = match mongodb::bson::to_bson(status) {
+            Ok(s) => s,
+            Err(e) => {
+                return Err(RepositoryError {
+                    message: e.to_string(),
+                })
+            }
+        };
+        let filter = match scheduled.id() {
+            IdType::String(s) => doc! { "id": s, "status": status },
+            IdType::ObjectId(o) => doc! { "_id": o, "status": status },
+        };
+        match self.collection.replace_one(filter, scheduled, None).await {
+            Ok(r) => Ok(r.matched_count == 1),
+            Err(e) => Err(RepositoryError {
+                message: e.to_string(),
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for MongoRepository<Webhook> {
+    async fn find_by_channel_id(
+        &self,
+        channel_id: &IdType,
+    ) -> Result<Vec<Webhook>, RepositoryError> {
+        let object_id = match channel_id {
+            IdType::String(s) => mongodb::bson::oid::ObjectId::parse_str(s).unwrap(),
+            IdType::ObjectId(o) => *o,
+        };
+        let filter = doc! {
+            "$or": [
+                { "channel_id": { "ObjectId": object_id } },
+                { "channel_id": null },
+            ]
+        };
+        let mut cursor = match self.collection.find(Some(filter), None).await {
+            Ok(c) => c,
+            Err(e) => {
+                return Err(RepositoryError {
+                    message: e.to_string(),
+                })
+            }
+        };
+        let mut webhooks = Vec::new();
+        while let Some(result) = cursor.try_next().await.unwrap() {
+            webhooks.push(result);
+        }
+        Ok(webhooks)
+    }
+}
+
+#[async_trait]
+impl WebhookDeliveryRepository for MongoRepository<WebhookDelivery> {
+    async fn find_due(&self, now: DateTime<Utc>) -> Result<Vec<WebhookDelivery>, RepositoryError> {
+        let options = mongodb::options::FindOptions::builder()
+            .sort(Some(doc! { "next_attempt_at": 1 }))
+            .build();
+        let mut cursor = match self
+            .collection
+            .find(
+                Some(doc! {
+                    "status": "Pending",
+                    "next_attempt_at": { "$lte": now.timestamp() },
+                }),
+                options,
+            )
+            .await
+        {
+            Ok(c) => c,
+            Err(e) => {
+                return Err(RepositoryError {
+                    message: e.to_string(),
+                })
+            }
+        };
+        let mut deliveries = Vec::new();
+        while let Some(result) = cursor.try_next().await.unwrap() {
+            deliveries.push(result);
+        }
+        Ok(deliveries)
+    }
+
+    async fn find_by_webhook_id(
+        &self,
+        webhook_id: &IdType,
+        limit: i64,
+        offset: u64,
+    ) -> Result<Vec<WebhookDelivery>, RepositoryError> {
+        let object_id = match webhook_id {
+            IdType::String(s) => mongodb::bson::oid::ObjectId::parse_str(s).unwrap(),
+            IdType::ObjectId(o) => *o,
+        };
+        let options = mongodb::options::FindOptions::builder()
+            .limit(Some(limit))
+            .skip(Some(offset))
+            .sort(Some(doc! { "created_at": -1 }))
+            .build();
+        let mut cursor = self
+            .collection
+            .find(
+                Some(doc! { "webhook_id": { "ObjectId": object_id } }),
+                options,
+            )
+            .await
+            .unwrap();
+        let mut deliveries = Vec::new();
+        while let Some(result) = cursor.try_next().await.unwrap() {
+            deliveries.push(result);
+        }
+        Ok(deliveries)
+    }
+}
+
+#[async_trait]
+impl IncomingWebhookRepository for MongoRepository<IncomingWebhook> {
+    async fn find_by_token_hash(&self, token_hash: &str) -> Option<IncomingWebhook> {
+        self.collection
+            .find_one(Some(doc! { "token_hash": token_hash }), None)
+            .await
+            .ok()
+            .flatten()
+    }
+
+    async fn find_by_channel_id(
+        &self,
+        channel_id: &IdType,
+    ) -> Result<Vec<IncomingWebhook>, RepositoryError> {
+        let object_id = match channel_id {
+            IdType::String(s) => mongodb::bson::oid::ObjectId::parse_str(s).unwrap(),
+            IdType::ObjectId(o) => *o,
+        };
+        let mut cursor = match self
+            .collection
+            .find(Some(doc! { "channel_id": { "ObjectId": object_id } }), None)
+            .await
+        {
+            Ok(c) => c,
+            Err(e) => {
+                return Err(RepositoryError {
+                    message: e.to_string(),
+                })
+            }
+        };
+        let mut webhooks = Vec::new();
+        while let Some(result) = cursor.try_next().await.unwrap() {
+            webhooks.push(result);
+        }
+        Ok(webhooks)
+    }
+}
+
+#[async_trait]
+impl SlashCommandRepository for MongoRepository<SlashCommand> {
+    async fn find_by_name(&self, name: &str) -> Option<SlashCommand> {
+        self.collection
+            .find_one(Some(doc! { "name": name }), None)
+            .await
+            .ok()
+            .flatten()
+    }
+
+    async fn find_by_bot_id(&self, bot_id: &IdType) -> Result<Vec<SlashCommand>, RepositoryError> {
+        let object_id = match bot_id {
+            IdType::String(s) => mongodb::bson::oid::ObjectId::parse_str(s).unwrap(),
+            IdType::ObjectId(o) => *o,
+        };
+        let mut cursor = match self
+            .collection
+            .find(Some(doc! { "bot_id": { "ObjectId": object_id } }), None)
+            .await
+        {
+            Ok(c) => c,
+            Err(e) => {
+                return Err(RepositoryError {
+                    message: e.to_string(),
+                })
+            }
+        };
+        let mut commands = Vec::new();
+        while let Some(result) = cursor.try_next().await.unwrap() {
+            commands.push(result);
+        }
+        Ok(commands)
+    }
+}
+
+#[async_trait]
+impl NotificationRepository for MongoRepository<Notification> {
+    async fn find_pending(&self) -> Result<Vec<Notification>, RepositoryError> {
+        let options = mongodb::options::FindOptions::builder()
+            .sort(Some(doc! { "created_at": 1 }))
+            .build();
+        let mut cursor = match self
+            .collection
+            .find(Some(doc! { "status": "Pending" }), options)
+            .await
+        {
+            Ok(c) => c,
+            Err(e) => {
+                return Err(RepositoryError {
+                    message: e.to_string(),
+                })
+            }
+        };
+        let mut notifications = Vec::new();
+        while let Some(result) = cursor.try_next().await.unwrap() {
+            notifications.push(result);
+        }
+        Ok(notifications)
+    }
+}
+
+#[async_trait]
+impl ReportRepository for MongoRepository<Report> {
+    async fn find_by_status(
+        &self,
+        status: &ReportStatus,
+        limit: i64,
+        offset: u64,
+    ) -> Result<Vec<Report>, RepositoryError> {
+        let status = match mongodb::bson::to_bson(status) {
+            Ok(s) => s,
+            Err(e) => {
+                return Err(RepositoryError {
+                    message: e.to_string(),
+                })
+            }
+        };
+        let options = mongodb::options::FindOptions::builder()
+            .limit(Some(limit))
+            .skip(Some(offset))
+            .sort(Some(doc! { "created_at": 1 }))
+            .build();
+        let mut cursor = match self
+            .collection
+            .find(Some(doc! { "status": status }), options)
+            .await
+        {
+            Ok(c) => c,
+            Err(e) => {
+                return Err(RepositoryError {
+                    message: e.to_string(),
+                })
+            }
+        };
+        let mut reports = Vec::new();
+        while let Some(result) = cursor.try_next().await.unwrap() {
+            reports.push(result);
+        }
+        Ok(reports)
+    }
+}
+
+#[async_trait]
+impl ModerationActionRepository for MongoRepository<ModerationAction> {
+    async fn find_by_report_id(
+        &self,
+        report_id: &IdType,
+    ) -> Result<Vec<ModerationAction>, RepositoryError> {
+        let object_id = match report_id {
+            IdType::String(s) => mongodb::bson::oid::ObjectId::parse_str(s).unwrap(),
+            IdType::ObjectId(o) => *o,
+        };
+        let options = mongodb::options::FindOptions::builder()
+            .sort(Some(doc! { "created_at": 1 }))
+            .build();
+        let mut cursor = match self
+            .collection
+            .find(
+                Some(doc! { "report_id": { "ObjectId": object_id } }),
+                options,
+            )
+            .await
+        {
+            Ok(c) => c,
+            Err(e) => {
+                return Err(RepositoryError {
+                    message: e.to_string(),
+                })
+            }
+        };
+        let mut actions = Vec::new();
+        while let Some(result) = cursor.try_next().await.unwrap() {
+            actions.push(result);
+        }
+        Ok(actions)
+    }
 }
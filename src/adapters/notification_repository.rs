@@ -0,0 +1,9 @@
+use crate::adapters::{Repository, RepositoryError};
+use crate::models::Notification;
+use async_trait::async_trait;
+
+#[async_trait]
+pub trait NotificationRepository: Repository<Notification> {
+    /// Returns the notifications still to be sent, oldest first
+    async fn find_pending(&self) -> Result<Vec<Notification>, RepositoryError>;
+}
@@ -0,0 +1,23 @@
+use crate::adapters::{IdType, Repository, RepositoryError};
+use crate::models::{ModerationAction, Report, ReportStatus};
+use async_trait::async_trait;
+
+#[async_trait]
+pub trait ReportRepository: Repository<Report> {
+    /// Returns the reports in the given status, oldest first
+    async fn find_by_status(
+        &self,
+        status: &ReportStatus,
+        limit: i64,
+        offset: u64,
+    ) -> Result<Vec<Report>, RepositoryError>;
+}
+
+#[async_trait]
+pub trait ModerationActionRepository: Repository<ModerationAction> {
+    /// Returns the actions taken on the report, oldest first
+    async fn find_by_report_id(
+        &self,
+        report_id: &IdType,
+    ) -> Result<Vec<ModerationAction>, RepositoryError>;
+}
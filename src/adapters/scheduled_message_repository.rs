@@ -0,0 +1,23 @@
+use crate::adapters::{IdType, Repository, RepositoryError};
+use crate::models::{ScheduledMessage, ScheduledMessageStatus};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+#[async_trait]
+pub trait ScheduledMessageRepository: Repository<ScheduledMessage> {
+    /// Returns the pending messages that should have been delivered by the given time
+    async fn find_due(&self, now: DateTime<Utc>) -> Result<Vec<ScheduledMessage>, RepositoryError>;
+    /// Returns the pending messages of the sender, soonest first
+    async fn find_pending_by_sender(
+        &self,
+        contact_id: &IdType,
+    ) -> Result<Vec<ScheduledMessage>, RepositoryError>;
+    /// Replaces the message only while its stored status is the given one,
+    /// so that concurrent changes of the status are not overwritten.
+    /// Returns whether the message was replaced.
+    async fn update_if_status(
+        &mut self,
+        scheduled: &ScheduledMessage,
+        status: &ScheduledMessageStatus,
+    ) -> Result<bool, RepositoryError>;
+}
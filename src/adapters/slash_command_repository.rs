@@ -0,0 +1,9 @@
+use crate::adapters::{IdType, Repository, RepositoryError};
+use crate::models::SlashCommand;
+use async_trait::async_trait;
+
+#[async_trait]
+pub trait SlashCommandRepository: Repository<SlashCommand> {
+    async fn find_by_name(&self, name: &str) -> Option<SlashCommand>;
+    async fn find_by_bot_id(&self, bot_id: &IdType) -> Result<Vec<SlashCommand>, RepositoryError>;
+}
@@ -0,0 +1,26 @@
+use crate::adapters::{IdType, Repository, RepositoryError};
+use crate::models::{Webhook, WebhookDelivery};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+#[async_trait]
+pub trait WebhookRepository: Repository<Webhook> {
+    /// Returns the webhooks of the channel and the ones of every channel
+    async fn find_by_channel_id(
+        &self,
+        channel_id: &IdType,
+    ) -> Result<Vec<Webhook>, RepositoryError>;
+}
+
+#[async_trait]
+pub trait WebhookDeliveryRepository: Repository<WebhookDelivery> {
+    /// Returns the pending deliveries whose next attempt is due by the given time
+    async fn find_due(&self, now: DateTime<Utc>) -> Result<Vec<WebhookDelivery>, RepositoryError>;
+    /// Returns the deliveries of the webhook, newest first
+    async fn find_by_webhook_id(
+        &self,
+        webhook_id: &IdType,
+        limit: i64,
+        offset: u64,
+    ) -> Result<Vec<WebhookDelivery>, RepositoryError>;
+}
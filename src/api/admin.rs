@@ -0,0 +1,68 @@
+use crate::api::{parse_id, Repositories};
+use crate::AppState;
+use actix_web::web::Bytes;
+use actix_web::{get, web, Error, HttpResponse};
+use futures::stream::{self, StreamExt};
+use serde::Deserialize;
+use serde_json::json;
+
+pub fn get_scope() -> actix_web::Scope {
+    web::scope("/admin").service(archive_contact)
+}
+
+#[derive(Deserialize)]
+pub struct ModeratorQuery {
+    moderator_id: String,
+}
+
+/// Streams the archive of everything tied to the contact, as NDJSON, to
+/// answer privacy requests
+#[get("/contacts/{contact_id}/archive")]
+pub async fn archive_contact(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<ModeratorQuery>,
+) -> Result<HttpResponse, Error> {
+    let contact_id = parse_id(&path.into_inner())?;
+    let moderator_id = parse_id(&query.moderator_id)?;
+    let mut repos = Repositories::new(&data.db, &data.events);
+    let cursor = match repos
+        .export_service()
+        .start_archive(&moderator_id, &contact_id)
+        .await
+    {
+        Ok(c) => c,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest().json(json!({
+                "message": e.to_string()
+            })))
+        }
+    };
+    let header = Bytes::from(to_ndjson(&cursor.header()));
+    let pages = stream::unfold(Some((repos, cursor)), |state| async move {
+        let (mut repos, mut cursor) = state?;
+        match repos.export_service().next_archive_page(&mut cursor).await {
+            Ok(records) if records.is_empty() => None,
+            Ok(records) => Some((Ok(Bytes::from(to_ndjson(&records))), Some((repos, cursor)))),
+            Err(e) => Some((
+                Err(actix_web::error::ErrorInternalServerError(e.to_string())),
+                None,
+            )),
+        }
+    });
+    let body = stream::once(async move { Ok::<_, Error>(header) }).chain(pages);
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"contact-{contact_id}.ndjson\""),
+        ))
+        .streaming(body))
+}
+
+fn to_ndjson<T: serde::Serialize>(records: &[T]) -> String {
+    records
+        .iter()
+        .map(|r| serde_json::to_string(r).unwrap() + "\n")
+        .collect()
+}
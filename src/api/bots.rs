@@ -0,0 +1,197 @@
+use crate::adapters::mongo::repository::MongoRepository;
+use crate::adapters::Model;
+use crate::api::{parse_id, Repositories};
+use crate::commands::{CreateContact, RegisterSlashCommand};
+use crate::models::{Contact, MessageContent, SlashCommand};
+use crate::services::{ContactService, SlashCommandService};
+use crate::AppState;
+use actix_web::{delete, get, post, web, Error, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+
+/// The endpoints bots call, authenticated with `Authorization: Bearer <api key>`
+/// except for the creation of the bot itself
+pub fn get_scope() -> actix_web::Scope {
+    web::scope("/bots")
+        .service(create_bot)
+        .service(rotate_api_key)
+        .service(post_message)
+        .service(get_commands)
+        .service(register_command)
+        .service(delete_command)
+}
+
+#[derive(Deserialize)]
+pub struct CreateBotBody {
+    name: String,
+    email: String,
+}
+
+#[derive(Deserialize)]
+pub struct PostMessageBody {
+    channel_id: String,
+    text: String,
+    #[serde(default)]
+    markdown: bool,
+}
+
+#[derive(Deserialize)]
+pub struct RegisterCommandBody {
+    name: String,
+    callback_url: String,
+    description: Option<String>,
+}
+
+/// Creates the bot and returns it with its API key, which is not shown again
+#[post("")]
+pub async fn create_bot(
+    data: web::Data<AppState>,
+    body: web::Json<CreateBotBody>,
+) -> Result<HttpResponse, Error> {
+    let cmd = CreateContact {
+        name: body.name.clone(),
+        email: body.email.clone(),
+    };
+    let mut repo = get_contact_repository(&data.db);
+    let mut service = ContactService::new(&mut repo).with_events(data.events.clone());
+    match service.create_bot(&cmd).await {
+        Ok((bot, api_key)) => Ok(HttpResponse::Ok().json(json!({
+            "bot": bot,
+            "api_key": api_key,
+        }))),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+#[post("/api-key")]
+pub async fn rotate_api_key(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let bot = authenticate(&data, &req).await?;
+    let mut repo = get_contact_repository(&data.db);
+    let mut service = ContactService::new(&mut repo);
+    match service.rotate_api_key(&bot).await {
+        Ok((bot, api_key)) => Ok(HttpResponse::Ok().json(json!({
+            "bot": bot,
+            "api_key": api_key,
+        }))),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+/// Posts into a channel the bot is a member of
+#[post("/messages")]
+pub async fn post_message(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    body: web::Json<PostMessageBody>,
+) -> Result<HttpResponse, Error> {
+    let bot = authenticate(&data, &req).await?;
+    let channel_id = parse_id(&body.channel_id)?;
+    let text = body.text.clone();
+    let content = if body.markdown {
+        MessageContent::Markdown { text }
+    } else {
+        MessageContent::Text { text }
+    };
+    let mut repos = Repositories::new(&data.db, &data.events).with_pipeline(&data.pipeline);
+    match repos
+        .message_service()
+        .send_bot_message(&bot, &channel_id, &content)
+        .await
+    {
+        Ok(message) => Ok(HttpResponse::Ok().json(message)),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+#[get("/commands")]
+pub async fn get_commands(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let bot = authenticate(&data, &req).await?;
+    let mut repo = get_command_repository(&data.db);
+    let service = SlashCommandService::new(&mut repo);
+    match service.list_commands(&bot.id()).await {
+        Ok(commands) => Ok(HttpResponse::Ok().json(json!({ "items": commands }))),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+/// Registers a command and returns it with the secret of its signatures
+#[post("/commands")]
+pub async fn register_command(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    body: web::Json<RegisterCommandBody>,
+) -> Result<HttpResponse, Error> {
+    let bot = authenticate(&data, &req).await?;
+    let cmd = RegisterSlashCommand {
+        bot_id: bot.id(),
+        name: body.name.clone(),
+        callback_url: body.callback_url.clone(),
+        description: body.description.clone(),
+    };
+    let mut repo = get_command_repository(&data.db);
+    let mut service = SlashCommandService::new(&mut repo).with_commands(data.commands.clone());
+    match service.register_command(&cmd).await {
+        Ok(command) => Ok(HttpResponse::Ok().json(command)),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+#[delete("/commands/{name}")]
+pub async fn delete_command(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let bot = authenticate(&data, &req).await?;
+    let mut repo = get_command_repository(&data.db);
+    let mut service = SlashCommandService::new(&mut repo);
+    match service.delete_command(&bot.id(), &path.into_inner()).await {
+        Ok(_) => Ok(HttpResponse::NoContent().finish()),
+        Err(e) => Ok(HttpResponse::NotFound().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+/// Returns the bot holding the API key of the bearer token
+async fn authenticate(data: &AppState, req: &HttpRequest) -> Result<Contact, Error> {
+    let api_key = req
+        .headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+    let api_key = match api_key {
+        Some(k) => k,
+        None => return Err(actix_web::error::ErrorUnauthorized("Missing API key")),
+    };
+    let mut repo = get_contact_repository(&data.db);
+    let service = ContactService::new(&mut repo);
+    match service.authenticate_bot(api_key).await {
+        Some(bot) => Ok(bot),
+        None => Err(actix_web::error::ErrorUnauthorized("Invalid API key")),
+    }
+}
+
+fn get_contact_repository(db: &mongodb::Database) -> MongoRepository<Contact> {
+    MongoRepository::new(db, "contacts")
+}
+
+fn get_command_repository(db: &mongodb::Database) -> MongoRepository<SlashCommand> {
+    MongoRepository::new(db, "slash_commands")
+}
@@ -0,0 +1,483 @@
+use crate::api::{parse_id, Repositories};
+use crate::commands::{
+    ArchiveChannel, DeleteChannel, PinMessage, RestoreChannel, SetChannelPreferences,
+    SetMessageTtl, UnarchiveChannel, UnpinMessage,
+};
+use crate::models::{Channel, NotificationLevel};
+use crate::services::{ExportFormat, ExportRecord, MAX_SYNC_BATCH};
+use crate::AppState;
+use actix_web::web::Bytes;
+use actix_web::{delete, get, post, put, web, Error, HttpResponse};
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt};
+use serde::Deserialize;
+use serde_json::json;
+
+/// The largest export accepted by the import, in bytes
+const MAX_IMPORT_SIZE: usize = 64 * 1024 * 1024;
+
+pub fn get_scope() -> actix_web::Scope {
+    web::scope("/channels")
+        .app_data(web::PayloadConfig::new(MAX_IMPORT_SIZE))
+        .service(get_channels)
+        .service(import_channel)
+        .service(export_channel)
+        .service(get_messages)
+        .service(get_pins)
+        .service(pin_message)
+        .service(unpin_message)
+        .service(set_message_ttl)
+        .service(archive_channel)
+        .service(unarchive_channel)
+        .service(delete_channel)
+        .service(restore_channel)
+        .service(get_preferences)
+        .service(set_preferences)
+}
+
+#[derive(Deserialize)]
+pub struct GetMessagesQuery {
+    contact_id: String,
+    after_seq: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct PinMessageBody {
+    message_id: String,
+    contact_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct SetMessageTtlBody {
+    contact_id: String,
+    message_ttl: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct SetPreferencesBody {
+    contact_id: String,
+    #[serde(default)]
+    level: NotificationLevel,
+    #[serde(default)]
+    muted: bool,
+    muted_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub struct ContactQuery {
+    contact_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct GetChannelsQuery {
+    contact_id: String,
+    #[serde(default)]
+    include_archived: bool,
+}
+
+#[derive(Deserialize)]
+pub struct ModeratorQuery {
+    moderator_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    contact_id: String,
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+#[derive(Deserialize)]
+pub struct UnpinMessageQuery {
+    contact_id: String,
+}
+
+/// Returns the messages after the given sequence number, oldest first.
+/// Clients page through the history by passing the last `seq` they received.
+#[get("/{channel_id}/messages")]
+pub async fn get_messages(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<GetMessagesQuery>,
+) -> Result<HttpResponse, Error> {
+    let channel_id = parse_id(&path.into_inner())?;
+    let contact_id = parse_id(&query.contact_id)?;
+    let after_seq = query.after_seq.unwrap_or(0);
+    let limit = query.limit.unwrap_or(MAX_SYNC_BATCH);
+    let mut repos = Repositories::new(&data.db, &data.events);
+    let mut service = repos.message_service();
+    match service
+        .get_messages_after(&channel_id, &contact_id, after_seq, limit)
+        .await
+    {
+        Ok(messages) => Ok(HttpResponse::Ok().json(json!({
+            "after_seq": after_seq,
+            "items": messages,
+        }))),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+/// Returns the pinned messages of the channel to one of its members
+#[get("/{channel_id}/pins")]
+pub async fn get_pins(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<ContactQuery>,
+) -> Result<HttpResponse, Error> {
+    let channel_id = parse_id(&path.into_inner())?;
+    let contact_id = parse_id(&query.contact_id)?;
+    let mut repos = Repositories::new(&data.db, &data.events);
+    let mut service = repos.message_service();
+    match service.get_pins(&channel_id, &contact_id).await {
+        Ok(messages) => Ok(HttpResponse::Ok().json(json!({ "items": messages }))),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+#[post("/{channel_id}/pins")]
+pub async fn pin_message(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<PinMessageBody>,
+) -> Result<HttpResponse, Error> {
+    let cmd = PinMessage {
+        channel_id: parse_id(&path.into_inner())?,
+        message_id: parse_id(&body.message_id)?,
+        pinned_by: parse_id(&body.contact_id)?,
+    };
+    let mut repos = Repositories::new(&data.db, &data.events);
+    let res = repos.message_service().pin_message(&cmd).await;
+    match res {
+        Ok(message) => Ok(HttpResponse::Ok().json(message)),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+#[delete("/{channel_id}/pins/{message_id}")]
+pub async fn unpin_message(
+    data: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+    query: web::Query<UnpinMessageQuery>,
+) -> Result<HttpResponse, Error> {
+    let (channel_id, message_id) = path.into_inner();
+    let cmd = UnpinMessage {
+        channel_id: parse_id(&channel_id)?,
+        message_id: parse_id(&message_id)?,
+        unpinned_by: parse_id(&query.contact_id)?,
+    };
+    let mut repos = Repositories::new(&data.db, &data.events);
+    let res = repos.message_service().unpin_message(&cmd).await;
+    match res {
+        Ok(message) => Ok(HttpResponse::Ok().json(message)),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+#[put("/{channel_id}/message-ttl")]
+pub async fn set_message_ttl(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<SetMessageTtlBody>,
+) -> Result<HttpResponse, Error> {
+    let cmd = SetMessageTtl {
+        channel_id: parse_id(&path.into_inner())?,
+        message_ttl: body.message_ttl,
+        set_by: parse_id(&body.contact_id)?,
+    };
+    let mut repos = Repositories::new(&data.db, &data.events);
+    match repos.channel_service().set_message_ttl(&cmd).await {
+        Ok(channel) => Ok(HttpResponse::Ok().json(channel.for_member(&cmd.set_by))),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+/// Returns the channels of the contact, without the archived ones by default
+#[get("")]
+pub async fn get_channels(
+    data: web::Data<AppState>,
+    query: web::Query<GetChannelsQuery>,
+) -> Result<HttpResponse, Error> {
+    let contact_id = parse_id(&query.contact_id)?;
+    let mut repos = Repositories::new(&data.db, &data.events);
+    match repos
+        .channel_service()
+        .find_contact_channels(&contact_id, query.include_archived)
+        .await
+    {
+        Ok(channels) => {
+            let channels: Vec<Channel> =
+                channels.iter().map(|c| c.for_member(&contact_id)).collect();
+            Ok(HttpResponse::Ok().json(json!({ "items": channels })))
+        }
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+/// Makes the channel read-only until an admin unarchives it
+#[post("/{channel_id}/archive")]
+pub async fn archive_channel(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<ContactQuery>,
+) -> Result<HttpResponse, Error> {
+    let cmd = ArchiveChannel {
+        channel_id: parse_id(&path.into_inner())?,
+        archived_by: parse_id(&query.contact_id)?,
+    };
+    let mut repos = Repositories::new(&data.db, &data.events);
+    match repos.channel_service().archive_channel(&cmd).await {
+        Ok(channel) => Ok(HttpResponse::Ok().json(channel.for_member(&cmd.archived_by))),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+#[post("/{channel_id}/unarchive")]
+pub async fn unarchive_channel(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<ContactQuery>,
+) -> Result<HttpResponse, Error> {
+    let cmd = UnarchiveChannel {
+        channel_id: parse_id(&path.into_inner())?,
+        unarchived_by: parse_id(&query.contact_id)?,
+    };
+    let mut repos = Repositories::new(&data.db, &data.events);
+    match repos.channel_service().unarchive_channel(&cmd).await {
+        Ok(channel) => Ok(HttpResponse::Ok().json(channel.for_member(&cmd.unarchived_by))),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+/// Deletes the channel, which its members can restore during the grace period
+#[delete("/{channel_id}")]
+pub async fn delete_channel(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<ContactQuery>,
+) -> Result<HttpResponse, Error> {
+    let cmd = DeleteChannel {
+        channel_id: parse_id(&path.into_inner())?,
+        deleted_by: parse_id(&query.contact_id)?,
+    };
+    let mut repos = Repositories::new(&data.db, &data.events);
+    match repos.channel_service().delete_channel(&cmd).await {
+        Ok(_) => Ok(HttpResponse::NoContent().finish()),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+#[post("/{channel_id}/restore")]
+pub async fn restore_channel(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<ContactQuery>,
+) -> Result<HttpResponse, Error> {
+    let cmd = RestoreChannel {
+        channel_id: parse_id(&path.into_inner())?,
+        restored_by: parse_id(&query.contact_id)?,
+    };
+    let mut repos = Repositories::new(&data.db, &data.events);
+    match repos.channel_service().restore_channel(&cmd).await {
+        Ok(channel) => Ok(HttpResponse::Ok().json(channel.for_member(&cmd.restored_by))),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+#[get("/{channel_id}/preferences")]
+pub async fn get_preferences(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<ContactQuery>,
+) -> Result<HttpResponse, Error> {
+    let channel_id = parse_id(&path.into_inner())?;
+    let contact_id = parse_id(&query.contact_id)?;
+    let mut repos = Repositories::new(&data.db, &data.events);
+    match repos
+        .channel_service()
+        .get_preferences(&channel_id, &contact_id)
+        .await
+    {
+        Ok(preferences) => Ok(HttpResponse::Ok().json(preferences)),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+/// Mutes the channel or changes which of its messages the member is notified of
+#[put("/{channel_id}/preferences")]
+pub async fn set_preferences(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<SetPreferencesBody>,
+) -> Result<HttpResponse, Error> {
+    let cmd = SetChannelPreferences {
+        channel_id: parse_id(&path.into_inner())?,
+        contact_id: parse_id(&body.contact_id)?,
+        level: body.level.clone(),
+        muted: body.muted,
+        muted_until: body.muted_until,
+    };
+    let mut repos = Repositories::new(&data.db, &data.events);
+    match repos.channel_service().set_preferences(&cmd).await {
+        Ok(preferences) => Ok(HttpResponse::Ok().json(preferences)),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+/// Streams the history of the channel to one of its members, as NDJSON the
+/// import reads back or as an HTML transcript
+#[get("/{channel_id}/export")]
+pub async fn export_channel(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<ExportQuery>,
+) -> Result<HttpResponse, Error> {
+    let channel_id = parse_id(&path.into_inner())?;
+    let contact_id = parse_id(&query.contact_id)?;
+    let format = query.format;
+    let mut repos = Repositories::new(&data.db, &data.events);
+    let cursor = match repos
+        .export_service()
+        .start_export(&channel_id, &contact_id)
+        .await
+    {
+        Ok(c) => c,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest().json(json!({
+                "message": e.to_string()
+            })))
+        }
+    };
+    let header = Bytes::from(format.render(&cursor.header()));
+    // The pages are read as the client consumes the response, and the
+    // stream ends at the first failure
+    let pages = stream::unfold(Some((repos, cursor)), move |state| async move {
+        let (mut repos, mut cursor) = state?;
+        match repos.export_service().next_page(&mut cursor).await {
+            Ok(records) if records.is_empty() => None,
+            Ok(records) => {
+                let chunk: String = records.iter().map(|r| format.render(r)).collect();
+                Some((Ok(Bytes::from(chunk)), Some((repos, cursor))))
+            }
+            Err(e) => Some((
+                Err(actix_web::error::ErrorInternalServerError(e.to_string())),
+                None,
+            )),
+        }
+    });
+    let body = stream::once(async move { Ok::<_, Error>(header) })
+        .chain(pages)
+        .chain(stream::once(async move {
+            Ok(Bytes::from_static(format.footer().as_bytes()))
+        }));
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((
+            "Content-Disposition",
+            format!(
+                "attachment; filename=\"channel-{channel_id}.{}\"",
+                format.extension()
+            ),
+        ))
+        .streaming(body))
+}
+
+/// Recreates a channel from its NDJSON export, with the original timestamps.
+/// Only moderators can import, as the export names the members and senders.
+#[post("/import")]
+pub async fn import_channel(
+    data: web::Data<AppState>,
+    query: web::Query<ModeratorQuery>,
+    body: Bytes,
+) -> Result<HttpResponse, Error> {
+    let moderator_id = parse_id(&query.moderator_id)?;
+    let text = match std::str::from_utf8(&body) {
+        Ok(t) => t,
+        Err(_) => {
+            return Ok(HttpResponse::BadRequest().json(json!({
+                "message": "Exports must be UTF-8"
+            })))
+        }
+    };
+    let records = match ExportRecord::parse_ndjson(text) {
+        Ok(r) => r,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest().json(json!({
+                "message": e.to_string()
+            })))
+        }
+    };
+    let mut repos = Repositories::new(&data.db, &data.events).with_pipeline(&data.pipeline);
+    let mut service = repos.export_service();
+    match service.import_channel(&moderator_id, records).await {
+        Ok(channel) => Ok(HttpResponse::Ok().json(channel.without_preferences())),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+#[cfg(test)]
+mod integration_tests {
+    use crate::api::channels::get_scope;
+    use crate::events::EventBus;
+    use crate::rate_limit::RateLimiter;
+    use crate::services::{CommandRegistry, Pipeline};
+    use crate::websocket::ChatServer;
+    use crate::{adapters, AppState};
+    use actix::Actor;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App};
+
+    #[actix_web::test]
+    async fn accepts_imports_over_the_default_payload_limit() {
+        // The body is refused before the database is reached
+        let db = adapters::mongo::database::init("chatapp").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState {
+                    db,
+                    chat_server: ChatServer::default().start(),
+                    events: EventBus::default(),
+                    commands: CommandRegistry::default(),
+                    pipeline: Pipeline::default(),
+                    limiter: RateLimiter::default(),
+                }))
+                .service(get_scope()),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/channels/import?moderator_id=64b7f0c2a1e4c3d2b1a09f8e")
+            .set_payload(vec![0xff; 1024 * 1024])
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body = test::read_body(resp).await;
+        assert!(String::from_utf8_lossy(&body).contains("UTF-8"));
+    }
+}
@@ -1,12 +1,14 @@
 use crate::adapters::mongo::repository::MongoRepository;
-use crate::commands::{CreateContact, UpdateContact};
-use crate::models::Contact;
-use crate::services::ContactService;
+use crate::adapters::IdType;
+use crate::api::parse_id;
+use crate::commands::{BlockContact, CreateContact, UpdateContact};
+use crate::models::{Channel, Message};
+use crate::models::{Contact, DeletionPolicy, DoNotDisturb};
+use crate::services::{ContactService, MessageService};
 use crate::AppState;
-use actix_web::{get, post, web, Error, HttpResponse, put, delete};
+use actix_web::{delete, get, post, put, web, Error, HttpResponse};
 use serde::Deserialize;
 use serde_json::json;
-use crate::adapters::IdType;
 
 pub fn get_scope() -> actix_web::Scope {
     web::scope("/contacts")
@@ -15,6 +17,13 @@ pub fn get_scope() -> actix_web::Scope {
         .service(create_contact)
         .service(update_contact)
         .service(delete_contact)
+        .service(restore_contact)
+        .service(get_contact_mentions)
+        .service(set_do_not_disturb)
+        .service(clear_do_not_disturb)
+        .service(get_blocked)
+        .service(block_contact)
+        .service(unblock_contact)
 }
 
 #[derive(Deserialize)]
@@ -23,6 +32,13 @@ pub struct GetContactsQuery {
     per_page: Option<i32>,
 }
 
+#[derive(Deserialize)]
+pub struct BlockContactBody {
+    contact_id: String,
+    #[serde(default)]
+    hide_messages: bool,
+}
+
 #[derive(Deserialize)]
 pub struct UpdateContactBody {
     name: Option<String>,
@@ -77,7 +93,7 @@ pub async fn create_contact(
 ) -> Result<HttpResponse, Error> {
     let db = &data.db;
     let mut repo = get_repository(db);
-    let mut service = ContactService::new(&mut repo);
+    let mut service = ContactService::new(&mut repo).with_events(data.events.clone());
     let res = service.create_contact(&contact).await;
     match res {
         Ok(contact) => Ok(HttpResponse::Ok().json(contact)),
@@ -96,7 +112,7 @@ pub async fn update_contact(
     let contact_id = path.into_inner();
     let db = &data.db;
     let mut repo = get_repository(db);
-    let mut service = ContactService::new(&mut repo);
+    let mut service = ContactService::new(&mut repo).with_events(data.events.clone());
 
     let cmd = UpdateContact {
         id: IdType::String(contact_id),
@@ -111,16 +127,129 @@ pub async fn update_contact(
     }
 }
 
+/// Deletes the contact, which can be restored during the grace period. When
+/// it is purged, the policy given in the query applies, by default anonymizing
+/// its messages, leaving its groups and orphaning its private channels.
 #[delete("/{contact_id}")]
 pub async fn delete_contact(
     data: web::Data<AppState>,
     path: web::Path<String>,
+    policy: web::Query<DeletionPolicy>,
 ) -> Result<HttpResponse, Error> {
-    let contact_id = path.into_inner();
-    let db = &data.db;
-    let mut repo = get_repository(db);
+    let contact_id = parse_id(&path.into_inner())?;
+    let mut repo = get_repository(&data.db);
+    let mut service = ContactService::new(&mut repo).with_events(data.events.clone());
+    match service.delete_contact(&contact_id, &policy).await {
+        Ok(_) => Ok(HttpResponse::NoContent().finish()),
+        Err(e) => Ok(HttpResponse::BadRequest()
+            .content_type("application/json")
+            .json(e)),
+    }
+}
+
+#[post("/{contact_id}/restore")]
+pub async fn restore_contact(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let contact_id = parse_id(&path.into_inner())?;
+    let mut repo = get_repository(&data.db);
+    let mut service = ContactService::new(&mut repo).with_events(data.events.clone());
+    match service.restore_contact(&contact_id).await {
+        Ok(contact) => Ok(HttpResponse::Ok().json(contact)),
+        Err(e) => Ok(HttpResponse::BadRequest()
+            .content_type("application/json")
+            .json(e)),
+    }
+}
+
+/// Sets the daily period the contact is not notified in
+#[put("/{contact_id}/do-not-disturb")]
+pub async fn set_do_not_disturb(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<DoNotDisturb>,
+) -> Result<HttpResponse, Error> {
+    let contact_id = parse_id(&path.into_inner())?;
+    let mut repo = get_repository(&data.db);
+    let mut service = ContactService::new(&mut repo);
+    match service
+        .set_do_not_disturb(&contact_id, Some(body.into_inner()))
+        .await
+    {
+        Ok(contact) => Ok(HttpResponse::Ok().json(contact)),
+        Err(e) => Ok(HttpResponse::BadRequest()
+            .content_type("application/json")
+            .json(e)),
+    }
+}
+
+#[delete("/{contact_id}/do-not-disturb")]
+pub async fn clear_do_not_disturb(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let contact_id = parse_id(&path.into_inner())?;
+    let mut repo = get_repository(&data.db);
     let mut service = ContactService::new(&mut repo);
-    match service.delete_contact(&contact_id).await {
+    match service.set_do_not_disturb(&contact_id, None).await {
+        Ok(contact) => Ok(HttpResponse::Ok().json(contact)),
+        Err(e) => Ok(HttpResponse::BadRequest()
+            .content_type("application/json")
+            .json(e)),
+    }
+}
+
+#[get("/{contact_id}/blocked")]
+pub async fn get_blocked(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let contact_id = parse_id(&path.into_inner())?;
+    let mut repo = get_repository(&data.db);
+    let service = ContactService::new(&mut repo);
+    match service.list_blocked(&contact_id).await {
+        Ok(blocked) => Ok(HttpResponse::Ok().json(json!({ "items": blocked }))),
+        Err(e) => Ok(HttpResponse::NotFound()
+            .content_type("application/json")
+            .json(e)),
+    }
+}
+
+/// Blocks direct messages from the contact, and optionally hides the messages
+/// of the blocker from it in shared groups
+#[post("/{contact_id}/blocked")]
+pub async fn block_contact(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<BlockContactBody>,
+) -> Result<HttpResponse, Error> {
+    let cmd = BlockContact {
+        contact_id: parse_id(&path.into_inner())?,
+        blocked_id: parse_id(&body.contact_id)?,
+        hide_messages: body.hide_messages,
+    };
+    let mut repo = get_repository(&data.db);
+    let mut service = ContactService::new(&mut repo);
+    match service.block_contact(&cmd).await {
+        Ok(contact) => Ok(HttpResponse::Ok().json(json!({ "items": contact.blocked }))),
+        Err(e) => Ok(HttpResponse::BadRequest()
+            .content_type("application/json")
+            .json(e)),
+    }
+}
+
+#[delete("/{contact_id}/blocked/{blocked_id}")]
+pub async fn unblock_contact(
+    data: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, Error> {
+    let (contact_id, blocked_id) = path.into_inner();
+    let contact_id = parse_id(&contact_id)?;
+    let blocked_id = parse_id(&blocked_id)?;
+    let mut repo = get_repository(&data.db);
+    let mut service = ContactService::new(&mut repo);
+    match service.unblock_contact(&contact_id, &blocked_id).await {
         Ok(_) => Ok(HttpResponse::NoContent().finish()),
         Err(e) => Ok(HttpResponse::BadRequest()
             .content_type("application/json")
@@ -128,6 +257,36 @@ pub async fn delete_contact(
     }
 }
 
+#[get("/{contact_id}/mentions")]
+pub async fn get_contact_mentions(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<GetContactsQuery>,
+) -> Result<HttpResponse, Error> {
+    let contact_id = parse_id(&path.into_inner())?;
+    let page = query.page.unwrap_or(1);
+    let per_page = query.per_page.unwrap_or(20);
+
+    let db = &data.db;
+    let mut repo = get_repository(db);
+    let mut message_repo: MongoRepository<Message> = MongoRepository::new(db, "messages");
+    let mut channel_repo: MongoRepository<Channel> = MongoRepository::new(db, "channels");
+    let service = MessageService::new(&mut message_repo, &mut channel_repo, &mut repo);
+    match service
+        .get_mentions(&contact_id, per_page as i64, ((page - 1) * per_page) as u64)
+        .await
+    {
+        Ok(messages) => Ok(HttpResponse::Ok().json(json!({
+            "page": page,
+            "per_page": per_page,
+            "items": messages,
+        }))),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
 fn get_repository(db: &mongodb::Database) -> MongoRepository<Contact> {
     MongoRepository::new(db, "contacts")
 }
@@ -135,21 +294,33 @@ fn get_repository(db: &mongodb::Database) -> MongoRepository<Contact> {
 #[cfg(test)]
 mod integration_tests {
     use crate::api::contacts::get_scope;
+    use crate::events::EventBus;
+    use crate::rate_limit::RateLimiter;
+    use crate::services::{CommandRegistry, Pipeline};
+    use crate::websocket::ChatServer;
     use crate::{adapters, AppState};
+    use actix::Actor;
     use actix_web::{test, web, App};
 
     #[actix_web::test]
     #[ignore]
     async fn test_get_contacts() -> Result<(), actix_web::Error> {
         let db = adapters::mongo::database::init("chatapp").await;
-        let mut app = test::init_service(
+        let app = test::init_service(
             App::new()
-                .app_data(web::Data::new(AppState { db: db.to_owned() }))
+                .app_data(web::Data::new(AppState {
+                    db: db.to_owned(),
+                    chat_server: ChatServer::default().start(),
+                    events: EventBus::default(),
+                    commands: CommandRegistry::default(),
+                    pipeline: Pipeline::default(),
+                    limiter: RateLimiter::default(),
+                }))
                 .service(get_scope()),
         )
         .await;
         let req = test::TestRequest::get().uri("/contacts").to_request();
-        let resp = test::call_service(&mut app, req).await;
+        let resp = test::call_service(&app, req).await;
         assert!(resp.status().is_success());
         Ok(())
     }
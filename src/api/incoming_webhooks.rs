@@ -0,0 +1,180 @@
+use crate::adapters::mongo::repository::MongoRepository;
+use crate::api::{parse_id, Repositories};
+use crate::commands::{ChangeIncomingWebhookToken, CreateIncomingWebhook};
+use crate::models::{Channel, IncomingWebhook, MessageContent};
+use crate::services::IncomingWebhookService;
+use crate::AppState;
+use actix_web::{delete, get, post, web, Error, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+
+pub fn get_scope() -> actix_web::Scope {
+    web::scope("/incoming-webhooks")
+        .service(get_incoming_webhooks)
+        .service(create_incoming_webhook)
+        .service(rotate_token)
+        .service(revoke_token)
+}
+
+/// The secret URLs the integrations post to
+pub fn get_hooks_scope() -> actix_web::Scope {
+    web::scope("/hooks").service(post_message)
+}
+
+#[derive(Deserialize)]
+pub struct GetIncomingWebhooksQuery {
+    channel_id: String,
+    contact_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct CreateIncomingWebhookBody {
+    channel_id: String,
+    contact_id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+pub struct ContactBody {
+    contact_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct ContactQuery {
+    contact_id: String,
+}
+
+/// The payload integrations post, rendered as markdown when asked to
+#[derive(Deserialize)]
+pub struct PostMessageBody {
+    text: String,
+    #[serde(default)]
+    markdown: bool,
+}
+
+#[get("")]
+pub async fn get_incoming_webhooks(
+    data: web::Data<AppState>,
+    query: web::Query<GetIncomingWebhooksQuery>,
+) -> Result<HttpResponse, Error> {
+    let channel_id = parse_id(&query.channel_id)?;
+    let contact_id = parse_id(&query.contact_id)?;
+    let mut repo = get_repository(&data.db);
+    let mut channel_repo = get_channel_repository(&data.db);
+    let service = IncomingWebhookService::new(&mut repo, &mut channel_repo);
+    match service
+        .list_incoming_webhooks(&channel_id, &contact_id)
+        .await
+    {
+        Ok(webhooks) => Ok(HttpResponse::Ok().json(json!({ "items": webhooks }))),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+/// Creates the webhook and returns it with its token, which is not shown again
+#[post("")]
+pub async fn create_incoming_webhook(
+    data: web::Data<AppState>,
+    body: web::Json<CreateIncomingWebhookBody>,
+) -> Result<HttpResponse, Error> {
+    let cmd = CreateIncomingWebhook {
+        channel_id: parse_id(&body.channel_id)?,
+        name: body.name.clone(),
+        created_by: parse_id(&body.contact_id)?,
+    };
+    let mut repo = get_repository(&data.db);
+    let mut channel_repo = get_channel_repository(&data.db);
+    let mut service = IncomingWebhookService::new(&mut repo, &mut channel_repo);
+    match service.create_incoming_webhook(&cmd).await {
+        Ok((webhook, token)) => Ok(HttpResponse::Ok().json(json!({
+            "webhook": webhook,
+            "token": token,
+        }))),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+#[post("/{incoming_webhook_id}/token")]
+pub async fn rotate_token(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<ContactBody>,
+) -> Result<HttpResponse, Error> {
+    let cmd = ChangeIncomingWebhookToken {
+        id: parse_id(&path.into_inner())?,
+        contact_id: parse_id(&body.contact_id)?,
+    };
+    let mut repo = get_repository(&data.db);
+    let mut channel_repo = get_channel_repository(&data.db);
+    let mut service = IncomingWebhookService::new(&mut repo, &mut channel_repo);
+    match service.rotate_token(&cmd).await {
+        Ok((webhook, token)) => Ok(HttpResponse::Ok().json(json!({
+            "webhook": webhook,
+            "token": token,
+        }))),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+#[delete("/{incoming_webhook_id}/token")]
+pub async fn revoke_token(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<ContactQuery>,
+) -> Result<HttpResponse, Error> {
+    let cmd = ChangeIncomingWebhookToken {
+        id: parse_id(&path.into_inner())?,
+        contact_id: parse_id(&query.contact_id)?,
+    };
+    let mut repo = get_repository(&data.db);
+    let mut channel_repo = get_channel_repository(&data.db);
+    let mut service = IncomingWebhookService::new(&mut repo, &mut channel_repo);
+    match service.revoke_token(&cmd).await {
+        Ok(_) => Ok(HttpResponse::NoContent().finish()),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+#[post("/{token}")]
+pub async fn post_message(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<PostMessageBody>,
+) -> Result<HttpResponse, Error> {
+    let text = body.text.clone();
+    let content = if body.markdown {
+        MessageContent::Markdown { text }
+    } else {
+        MessageContent::Text { text }
+    };
+    let mut repo = get_repository(&data.db);
+    let mut channel_repo = get_channel_repository(&data.db);
+    let service = IncomingWebhookService::new(&mut repo, &mut channel_repo);
+    let mut repos = Repositories::new(&data.db, &data.events).with_pipeline(&data.pipeline);
+    let mut message_service = repos.message_service();
+    match service
+        .post(&path.into_inner(), &content, &mut message_service)
+        .await
+    {
+        Ok(message) => Ok(HttpResponse::Ok().json(message)),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+fn get_repository(db: &mongodb::Database) -> MongoRepository<IncomingWebhook> {
+    MongoRepository::new(db, "incoming_webhooks")
+}
+
+fn get_channel_repository(db: &mongodb::Database) -> MongoRepository<Channel> {
+    MongoRepository::new(db, "channels")
+}
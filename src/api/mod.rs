@@ -1 +1,95 @@
+pub mod admin;
+pub mod bots;
+pub mod channels;
 pub mod contacts;
+pub mod incoming_webhooks;
+pub mod moderation;
+pub mod scheduled_messages;
+pub mod webhooks;
+
+use crate::adapters::mongo::repository::MongoRepository;
+use crate::adapters::IdType;
+use crate::events::EventBus;
+use crate::models::{Channel, Contact, Message, ModerationAction, Report};
+use crate::services::{
+    ChannelService, CommandRegistry, ExportService, MessageService, ModerationService, Pipeline,
+};
+use mongodb::bson::oid::ObjectId;
+
+/// Parses an id received from a client into the form stored in the documents
+pub fn parse_id(id: &str) -> Result<IdType, actix_web::Error> {
+    match ObjectId::parse_str(id) {
+        Ok(o) => Ok(IdType::ObjectId(o)),
+        Err(_) => Err(actix_web::error::ErrorBadRequest(format!(
+            "Invalid id {id}"
+        ))),
+    }
+}
+
+/// The repositories the messaging services work with
+pub struct Repositories {
+    pub messages: MongoRepository<Message>,
+    pub channels: MongoRepository<Channel>,
+    pub contacts: MongoRepository<Contact>,
+    pub reports: MongoRepository<Report>,
+    pub moderation_actions: MongoRepository<ModerationAction>,
+    events: EventBus,
+    commands: CommandRegistry,
+    pipeline: Pipeline,
+}
+
+impl Repositories {
+    pub fn new(db: &mongodb::Database, events: &EventBus) -> Self {
+        Repositories {
+            messages: MongoRepository::new(db, "messages"),
+            channels: MongoRepository::new(db, "channels"),
+            contacts: MongoRepository::new(db, "contacts"),
+            reports: MongoRepository::new(db, "reports"),
+            moderation_actions: MongoRepository::new(db, "moderation_actions"),
+            events: events.clone(),
+            commands: CommandRegistry::default(),
+            pipeline: Pipeline::default(),
+        }
+    }
+
+    /// Answers the slash commands of the sent messages with the given handlers
+    pub fn with_commands(mut self, commands: &CommandRegistry) -> Self {
+        self.commands = commands.clone();
+        self
+    }
+
+    /// Runs the sent messages through the given stages before storing them
+    pub fn with_pipeline(mut self, pipeline: &Pipeline) -> Self {
+        self.pipeline = pipeline.clone();
+        self
+    }
+
+    pub fn message_service(&mut self) -> MessageService<'_> {
+        MessageService::new(&mut self.messages, &mut self.channels, &mut self.contacts)
+            .with_events(self.events.clone())
+            .with_commands(self.commands.clone())
+            .with_pipeline(self.pipeline.clone())
+    }
+
+    pub fn channel_service(&mut self) -> ChannelService<'_> {
+        ChannelService::new(&mut self.channels, &mut self.contacts, &mut self.messages)
+            .with_events(self.events.clone())
+    }
+
+    pub fn export_service(&mut self) -> ExportService<'_> {
+        ExportService::new(&mut self.messages, &mut self.channels, &mut self.contacts)
+            .with_events(self.events.clone())
+            .with_pipeline(self.pipeline.clone())
+    }
+
+    pub fn moderation_service(&mut self) -> ModerationService<'_> {
+        ModerationService::new(
+            &mut self.reports,
+            &mut self.moderation_actions,
+            &mut self.messages,
+            &mut self.channels,
+            &mut self.contacts,
+        )
+        .with_events(self.events.clone())
+    }
+}
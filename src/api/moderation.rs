@@ -0,0 +1,173 @@
+use crate::api::{parse_id, Repositories};
+use crate::commands::{ModerateReport, ReportMessage};
+use crate::models::{ModerationActionType, ReportStatus};
+use crate::AppState;
+use actix_web::{delete, get, post, put, web, Error, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+
+/// The number of reports returned per page of the queue
+const REPORTS_PER_PAGE: i64 = 50;
+
+pub fn get_scope() -> actix_web::Scope {
+    web::scope("/moderation")
+        .service(report_message)
+        .service(get_reports)
+        .service(moderate_report)
+        .service(get_actions)
+        .service(add_moderator)
+        .service(remove_moderator)
+}
+
+#[derive(Deserialize)]
+pub struct ReportMessageBody {
+    message_id: String,
+    contact_id: String,
+    reason: String,
+}
+
+#[derive(Deserialize)]
+pub struct GetReportsQuery {
+    moderator_id: String,
+    status: Option<ReportStatus>,
+    page: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct ModerateReportBody {
+    moderator_id: String,
+    action: ModerationActionType,
+    note: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ModeratorQuery {
+    moderator_id: String,
+}
+
+/// Reports a message of a channel the contact is a member of
+#[post("/reports")]
+pub async fn report_message(
+    data: web::Data<AppState>,
+    body: web::Json<ReportMessageBody>,
+) -> Result<HttpResponse, Error> {
+    let cmd = ReportMessage {
+        message_id: parse_id(&body.message_id)?,
+        reported_by: parse_id(&body.contact_id)?,
+        reason: body.reason.clone(),
+    };
+    let mut repos = Repositories::new(&data.db, &data.events);
+    let res = repos.moderation_service().report_message(&cmd).await;
+    match res {
+        Ok(report) => Ok(HttpResponse::Ok().json(report)),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+/// Returns a page of the queue, the open reports by default, oldest first
+#[get("/reports")]
+pub async fn get_reports(
+    data: web::Data<AppState>,
+    query: web::Query<GetReportsQuery>,
+) -> Result<HttpResponse, Error> {
+    let moderator_id = parse_id(&query.moderator_id)?;
+    let status = query.status.clone().unwrap_or(ReportStatus::Open);
+    let page = query.page.unwrap_or(1).max(1);
+    let offset = (page - 1) * REPORTS_PER_PAGE as u64;
+    let mut repos = Repositories::new(&data.db, &data.events);
+    let service = repos.moderation_service();
+    match service
+        .list_reports(&moderator_id, &status, REPORTS_PER_PAGE, offset)
+        .await
+    {
+        Ok(reports) => Ok(HttpResponse::Ok().json(json!({
+            "page": page,
+            "items": reports,
+        }))),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+/// Resolves, dismisses, deletes the message of or bans the author of a report
+#[post("/reports/{report_id}/actions")]
+pub async fn moderate_report(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<ModerateReportBody>,
+) -> Result<HttpResponse, Error> {
+    let cmd = ModerateReport {
+        report_id: parse_id(&path.into_inner())?,
+        moderator_id: parse_id(&body.moderator_id)?,
+        action: body.action.clone(),
+        note: body.note.clone(),
+    };
+    let mut repos = Repositories::new(&data.db, &data.events);
+    let res = repos.moderation_service().act(&cmd).await;
+    match res {
+        Ok(report) => Ok(HttpResponse::Ok().json(report)),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+#[get("/reports/{report_id}/actions")]
+pub async fn get_actions(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<ModeratorQuery>,
+) -> Result<HttpResponse, Error> {
+    let report_id = parse_id(&path.into_inner())?;
+    let moderator_id = parse_id(&query.moderator_id)?;
+    let mut repos = Repositories::new(&data.db, &data.events);
+    let service = repos.moderation_service();
+    match service.list_actions(&moderator_id, &report_id).await {
+        Ok(actions) => Ok(HttpResponse::Ok().json(json!({ "items": actions }))),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+#[put("/moderators/{contact_id}")]
+pub async fn add_moderator(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<ModeratorQuery>,
+) -> Result<HttpResponse, Error> {
+    set_moderator(&data, &path.into_inner(), &query.moderator_id, true).await
+}
+
+#[delete("/moderators/{contact_id}")]
+pub async fn remove_moderator(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<ModeratorQuery>,
+) -> Result<HttpResponse, Error> {
+    set_moderator(&data, &path.into_inner(), &query.moderator_id, false).await
+}
+
+async fn set_moderator(
+    data: &AppState,
+    contact_id: &str,
+    changed_by: &str,
+    moderator: bool,
+) -> Result<HttpResponse, Error> {
+    let contact_id = parse_id(contact_id)?;
+    let changed_by = parse_id(changed_by)?;
+    let mut repos = Repositories::new(&data.db, &data.events);
+    let mut service = repos.moderation_service();
+    match service
+        .set_moderator(&changed_by, &contact_id, moderator)
+        .await
+    {
+        Ok(contact) => Ok(HttpResponse::Ok().json(contact)),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
@@ -0,0 +1,130 @@
+use crate::adapters::mongo::repository::MongoRepository;
+use crate::api::parse_id;
+use crate::commands::{CancelScheduledMessage, EditScheduledMessage, ScheduleMessage, SendMessage};
+use crate::models::{MessageContent, ScheduledMessage};
+use crate::services::ScheduledMessageService;
+use crate::AppState;
+use actix_web::{delete, get, post, put, web, Error, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::json;
+
+pub fn get_scope() -> actix_web::Scope {
+    web::scope("/scheduled-messages")
+        .service(get_scheduled_messages)
+        .service(schedule_message)
+        .service(edit_scheduled_message)
+        .service(cancel_scheduled_message)
+}
+
+#[derive(Deserialize)]
+pub struct ContactQuery {
+    contact_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct ScheduleMessageBody {
+    channel_id: Option<String>,
+    from: String,
+    to: String,
+    content: MessageContent,
+    deliver_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct EditScheduledMessageBody {
+    contact_id: String,
+    content: Option<MessageContent>,
+    deliver_at: Option<DateTime<Utc>>,
+}
+
+#[get("")]
+pub async fn get_scheduled_messages(
+    data: web::Data<AppState>,
+    query: web::Query<ContactQuery>,
+) -> Result<HttpResponse, Error> {
+    let contact_id = parse_id(&query.contact_id)?;
+    let mut repo = get_repository(&data.db);
+    let service = ScheduledMessageService::new(&mut repo);
+    match service.list_pending(&contact_id).await {
+        Ok(messages) => Ok(HttpResponse::Ok().json(json!({ "items": messages }))),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+#[post("")]
+pub async fn schedule_message(
+    data: web::Data<AppState>,
+    body: web::Json<ScheduleMessageBody>,
+) -> Result<HttpResponse, Error> {
+    let channel_id = match &body.channel_id {
+        Some(id) => Some(parse_id(id)?),
+        None => None,
+    };
+    let cmd = ScheduleMessage {
+        message: SendMessage {
+            channel_id,
+            from: parse_id(&body.from)?,
+            to: parse_id(&body.to)?,
+            content: body.content.clone(),
+            client_message_id: None,
+        },
+        deliver_at: body.deliver_at,
+    };
+    let mut repo = get_repository(&data.db);
+    let mut service = ScheduledMessageService::new(&mut repo);
+    match service.schedule_message(&cmd).await {
+        Ok(message) => Ok(HttpResponse::Ok().json(message)),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+#[put("/{scheduled_message_id}")]
+pub async fn edit_scheduled_message(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<EditScheduledMessageBody>,
+) -> Result<HttpResponse, Error> {
+    let cmd = EditScheduledMessage {
+        id: parse_id(&path.into_inner())?,
+        contact_id: parse_id(&body.contact_id)?,
+        content: body.content.clone(),
+        deliver_at: body.deliver_at,
+    };
+    let mut repo = get_repository(&data.db);
+    let mut service = ScheduledMessageService::new(&mut repo);
+    match service.edit_scheduled_message(&cmd).await {
+        Ok(message) => Ok(HttpResponse::Ok().json(message)),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+#[delete("/{scheduled_message_id}")]
+pub async fn cancel_scheduled_message(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<ContactQuery>,
+) -> Result<HttpResponse, Error> {
+    let cmd = CancelScheduledMessage {
+        id: parse_id(&path.into_inner())?,
+        contact_id: parse_id(&query.contact_id)?,
+    };
+    let mut repo = get_repository(&data.db);
+    let mut service = ScheduledMessageService::new(&mut repo);
+    match service.cancel_scheduled_message(&cmd).await {
+        Ok(_) => Ok(HttpResponse::NoContent().finish()),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+fn get_repository(db: &mongodb::Database) -> MongoRepository<ScheduledMessage> {
+    MongoRepository::new(db, "scheduled_messages")
+}
@@ -0,0 +1,175 @@
+use crate::adapters::mongo::repository::MongoRepository;
+use crate::api::parse_id;
+use crate::commands::RegisterWebhook;
+use crate::models::{Contact, Webhook, WebhookDelivery, WebhookEventType};
+use crate::services::WebhookService;
+use crate::AppState;
+use actix_web::{delete, get, post, web, Error, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+
+pub fn get_scope() -> actix_web::Scope {
+    web::scope("/webhooks")
+        .service(get_webhooks)
+        .service(register_webhook)
+        .service(delete_webhook)
+        .service(get_deliveries)
+}
+
+#[derive(Deserialize)]
+pub struct PageQuery {
+    moderator_id: String,
+    page: Option<i32>,
+    per_page: Option<i32>,
+}
+
+#[derive(Deserialize)]
+pub struct ModeratorQuery {
+    moderator_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct RegisterWebhookBody {
+    url: String,
+    moderator_id: String,
+    channel_id: Option<String>,
+    #[serde(default)]
+    events: Vec<WebhookEventType>,
+}
+
+#[get("")]
+pub async fn get_webhooks(
+    data: web::Data<AppState>,
+    query: web::Query<PageQuery>,
+) -> Result<HttpResponse, Error> {
+    let moderator_id = parse_id(&query.moderator_id)?;
+    let page = query.page.unwrap_or(1);
+    let per_page = query.per_page.unwrap_or(20);
+    let mut repo = get_repository(&data.db);
+    let mut delivery_repo = get_delivery_repository(&data.db);
+    let mut contact_repo = get_contact_repository(&data.db);
+    let service = WebhookService::new(&mut repo, &mut delivery_repo, &mut contact_repo);
+    match service
+        .list_webhooks(
+            &moderator_id,
+            Some(((page - 1) * per_page) as u64),
+            Some(per_page),
+        )
+        .await
+    {
+        Ok((total, webhooks)) => Ok(HttpResponse::Ok().json(json!({
+            "page": page,
+            "per_page": per_page,
+            "total": total,
+            "items": webhooks.iter().map(without_secret).collect::<Vec<_>>(),
+        }))),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+/// Registers the endpoint and returns it with the secret of its signatures,
+/// which is not shown again
+#[post("")]
+pub async fn register_webhook(
+    data: web::Data<AppState>,
+    body: web::Json<RegisterWebhookBody>,
+) -> Result<HttpResponse, Error> {
+    let channel_id = match &body.channel_id {
+        Some(id) => Some(parse_id(id)?),
+        None => None,
+    };
+    let cmd = RegisterWebhook {
+        url: body.url.clone(),
+        registered_by: parse_id(&body.moderator_id)?,
+        channel_id,
+        events: body.events.clone(),
+    };
+    let mut repo = get_repository(&data.db);
+    let mut delivery_repo = get_delivery_repository(&data.db);
+    let mut contact_repo = get_contact_repository(&data.db);
+    let mut service = WebhookService::new(&mut repo, &mut delivery_repo, &mut contact_repo);
+    match service.register_webhook(&cmd).await {
+        Ok(webhook) => Ok(HttpResponse::Ok().json(webhook)),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+#[delete("/{webhook_id}")]
+pub async fn delete_webhook(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<ModeratorQuery>,
+) -> Result<HttpResponse, Error> {
+    let webhook_id = parse_id(&path.into_inner())?;
+    let moderator_id = parse_id(&query.moderator_id)?;
+    let mut repo = get_repository(&data.db);
+    let mut delivery_repo = get_delivery_repository(&data.db);
+    let mut contact_repo = get_contact_repository(&data.db);
+    let mut service = WebhookService::new(&mut repo, &mut delivery_repo, &mut contact_repo);
+    match service.delete_webhook(&moderator_id, &webhook_id).await {
+        Ok(_) => Ok(HttpResponse::NoContent().finish()),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+/// Returns the delivery log of the webhook, newest first
+#[get("/{webhook_id}/deliveries")]
+pub async fn get_deliveries(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<PageQuery>,
+) -> Result<HttpResponse, Error> {
+    let webhook_id = parse_id(&path.into_inner())?;
+    let moderator_id = parse_id(&query.moderator_id)?;
+    let page = query.page.unwrap_or(1);
+    let per_page = query.per_page.unwrap_or(20);
+    let mut repo = get_repository(&data.db);
+    let mut delivery_repo = get_delivery_repository(&data.db);
+    let mut contact_repo = get_contact_repository(&data.db);
+    let service = WebhookService::new(&mut repo, &mut delivery_repo, &mut contact_repo);
+    match service
+        .get_deliveries(
+            &moderator_id,
+            &webhook_id,
+            per_page as i64,
+            ((page - 1) * per_page) as u64,
+        )
+        .await
+    {
+        Ok(deliveries) => Ok(HttpResponse::Ok().json(json!({
+            "page": page,
+            "per_page": per_page,
+            "items": deliveries,
+        }))),
+        Err(e) => Ok(HttpResponse::BadRequest().json(json!({
+            "message": e.to_string()
+        }))),
+    }
+}
+
+/// The webhook as listed to the moderators, leaving out the signing secret
+fn without_secret(webhook: &Webhook) -> serde_json::Value {
+    let mut value = json!(webhook);
+    if let Some(fields) = value.as_object_mut() {
+        fields.remove("secret");
+    }
+    value
+}
+
+fn get_repository(db: &mongodb::Database) -> MongoRepository<Webhook> {
+    MongoRepository::new(db, "webhooks")
+}
+
+fn get_delivery_repository(db: &mongodb::Database) -> MongoRepository<WebhookDelivery> {
+    MongoRepository::new(db, "webhook_deliveries")
+}
+
+fn get_contact_repository(db: &mongodb::Database) -> MongoRepository<Contact> {
+    MongoRepository::new(db, "contacts")
+}
@@ -0,0 +1,11 @@
+//! The operators' command line, working on the database the server uses
+
+#[actix_web::main]
+async fn main() {
+    let db = messaging::adapters::mongo::database::init(messaging::DATABASE).await;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = messaging::cli::run(&db, &args).await {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
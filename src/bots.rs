@@ -0,0 +1,138 @@
+use std::time::Duration;
+
+use actix::{Actor, AsyncContext, Context, Handler, WrapFuture};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::adapters::mongo::repository::MongoRepository;
+use crate::adapters::slash_command_repository::SlashCommandRepository;
+use crate::adapters::{Model, Repository};
+use crate::api::Repositories;
+use crate::commands::PostBotMessage;
+use crate::events::{EventBus, SlashCommandInvoked};
+use crate::models::{Contact, MessageContent, SlashCommand};
+use crate::services::{sign, Pipeline};
+
+/// How long a bot has to answer a command
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The reply a bot answers a command with, rendered as markdown when asked to
+#[derive(Deserialize)]
+struct CommandReply {
+    text: String,
+    #[serde(default)]
+    markdown: bool,
+}
+
+/// Calls the bots registered for the slash commands sent in the channels and
+/// posts their replies back as the bot.
+///
+/// Commands are not retried: a bot that fails to answer gets an error reply
+/// in the channel instead.
+pub struct CommandDispatcher {
+    db: mongodb::Database,
+    events: EventBus,
+    pipeline: Pipeline,
+}
+
+impl CommandDispatcher {
+    pub fn new(db: mongodb::Database, events: EventBus, pipeline: Pipeline) -> Self {
+        CommandDispatcher {
+            db,
+            events,
+            pipeline,
+        }
+    }
+}
+
+impl Actor for CommandDispatcher {
+    type Context = Context<Self>;
+}
+
+impl Handler<SlashCommandInvoked> for CommandDispatcher {
+    type Result = ();
+
+    fn handle(&mut self, msg: SlashCommandInvoked, ctx: &mut Self::Context) -> Self::Result {
+        let dispatch = dispatch(
+            self.db.clone(),
+            self.events.clone(),
+            self.pipeline.clone(),
+            msg,
+        );
+        ctx.spawn(dispatch.into_actor(self));
+    }
+}
+
+async fn dispatch(
+    db: mongodb::Database,
+    events: EventBus,
+    pipeline: Pipeline,
+    invocation: SlashCommandInvoked,
+) {
+    let repo: MongoRepository<SlashCommand> = MongoRepository::new(&db, "slash_commands");
+    // Commands nobody registered stay plain messages
+    let command = match repo.find_by_name(&invocation.name).await {
+        Some(c) => c,
+        None => return,
+    };
+    let contacts: MongoRepository<Contact> = MongoRepository::new(&db, "contacts");
+    let bot = match contacts.get(&command.bot_id).await {
+        Some(b) => b,
+        None => return,
+    };
+    let content = match call(&command, &invocation).await {
+        Ok(Some(reply)) if reply.markdown => MessageContent::Markdown { text: reply.text },
+        Ok(Some(reply)) => MessageContent::Text { text: reply.text },
+        Ok(None) => return,
+        Err(e) => MessageContent::text(&format!("/{} failed: {e}", command.name)),
+    };
+    let cmd = PostBotMessage {
+        channel_id: invocation.channel.id(),
+        bot_id: bot.id(),
+        bot_name: bot.name,
+        content,
+    };
+    let mut repos = Repositories::new(&db, &events).with_pipeline(&pipeline);
+    if let Err(e) = repos.message_service().post_bot_message(&cmd).await {
+        eprintln!("Failed to post the reply to /{}: {e}", command.name);
+    }
+}
+
+/// Posts the signed invocation to the callback URL and returns the reply,
+/// if the bot answered with one
+async fn call(
+    command: &SlashCommand,
+    invocation: &SlashCommandInvoked,
+) -> Result<Option<CommandReply>, String> {
+    let body = json!({
+        "command": command.name,
+        "args": invocation.args,
+        "channel_id": invocation.channel.id(),
+        "message_id": invocation.message.id(),
+        "from": invocation.message.from,
+        "text": invocation.message.content.body(),
+    })
+    .to_string();
+    let client = awc::Client::builder().timeout(REQUEST_TIMEOUT).finish();
+    let mut res = client
+        .post(&command.callback_url)
+        .insert_header(("Content-Type", "application/json"))
+        .insert_header((
+            "X-Command-Signature",
+            format!("sha256={}", sign(&command.secret, &body)),
+        ))
+        .send_body(body)
+        .await
+        .map_err(|e| e.to_string())?;
+    if !res.status().is_success() {
+        return Err(format!("HTTP {}", res.status()));
+    }
+    let payload = res.body().await.map_err(|e| e.to_string())?;
+    if payload.is_empty() {
+        return Ok(None);
+    }
+    match serde_json::from_slice(&payload) {
+        Ok(reply) => Ok(Some(reply)),
+        Err(e) => Err(format!("Invalid reply: {e}")),
+    }
+}
@@ -0,0 +1,433 @@
+use std::io::{Read, Write};
+
+use actix::{Actor, Addr, Context, Handler};
+use chrono::{DateTime, Utc};
+
+use crate::adapters::mongo::database::create_indexes;
+use crate::adapters::{IdType, Model, Repository};
+use crate::api::{parse_id, Repositories};
+use crate::commands::{CreateChannel, CreateContact, SendMessage};
+use crate::events::{EventBus, MessageFlagged};
+use crate::generator::{DataGenerator, GeneratorConfig};
+use crate::models::{ChannelType, DeletionPolicy, MessageContent};
+use crate::moderation::filter_from_env;
+use crate::services::{ContactService, ExportFormat, ExportRecord, Pipeline};
+
+const USAGE: &str = "Usage: messaging-admin <command>
+
+Commands:
+  contacts list [<page>]
+  contacts create <name> <email>
+  contacts delete <contact_id>
+  contacts restore <contact_id>
+  channels create <private|group> <name> <created_by> [<contact_id>...]
+  messages post <channel_id> <from> <text>
+  migrate
+  generate <contacts> <private_channels> <group_channels> <messages_per_channel> [<seed> [<until>]]
+  export contact <contact_id> [<file>]
+  export channel <channel_id> <contact_id> [<file.ndjson|file.html>]
+  import channel <moderator_id> [<file>]";
+
+/// The number of contacts listed per page
+const CONTACTS_PER_PAGE: i32 = 50;
+
+/// Runs the operator command given on the command line. The changes go
+/// through the services, but no server is listening to their events.
+pub async fn run(db: &mongodb::Database, args: &[String]) -> std::io::Result<()> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["contacts", "list"] => list_contacts(db, 1).await,
+        ["contacts", "list", page] => match page.parse::<u64>() {
+            Ok(p) if p > 0 => list_contacts(db, p).await,
+            _ => Err(failure(format!("Invalid page {page}"))),
+        },
+        ["contacts", "create", name, email] => create_contact(db, name, email).await,
+        ["contacts", "delete", contact_id] => delete_contact(db, contact_id).await,
+        ["contacts", "restore", contact_id] => restore_contact(db, contact_id).await,
+        ["channels", "create", channel_type, name, created_by, contact_ids @ ..] => {
+            create_channel(db, channel_type, name, created_by, contact_ids).await
+        }
+        ["messages", "post", channel_id, from, text] => {
+            post_message(db, channel_id, from, text).await
+        }
+        ["migrate"] => migrate(db).await,
+        ["generate", counts @ ..] if (4..=5).contains(&counts.len()) => {
+            generate(db, counts, None).await
+        }
+        ["generate", counts @ .., until] if counts.len() == 5 => {
+            generate(db, counts, Some(until)).await
+        }
+        ["export", "contact", contact_id] => export_contact(db, contact_id, None).await,
+        ["export", "contact", contact_id, path] => export_contact(db, contact_id, Some(path)).await,
+        ["export", "channel", channel_id, contact_id] => {
+            export_channel(db, channel_id, contact_id, None).await
+        }
+        ["export", "channel", channel_id, contact_id, path] => {
+            export_channel(db, channel_id, contact_id, Some(path)).await
+        }
+        ["import", "channel", moderator_id] => import_channel(db, moderator_id, None).await,
+        ["import", "channel", moderator_id, path] => {
+            import_channel(db, moderator_id, Some(path)).await
+        }
+        _ => Err(failure(USAGE)),
+    }
+}
+
+/// Prints a page of the contacts, one JSON document per line
+async fn list_contacts(db: &mongodb::Database, page: u64) -> std::io::Result<()> {
+    let mut repos = Repositories::new(db, &EventBus::default());
+    let service = ContactService::new(&mut repos.contacts);
+    let skip = (page - 1) * CONTACTS_PER_PAGE as u64;
+    let (total, contacts) = service
+        .list(Some(skip), Some(CONTACTS_PER_PAGE))
+        .await
+        .map_err(failure)?;
+    let mut out = std::io::stdout().lock();
+    write_records(&mut out, &contacts)?;
+    eprintln!("Page {page}, {} of {total} contacts", contacts.len());
+    Ok(())
+}
+
+async fn create_contact(db: &mongodb::Database, name: &str, email: &str) -> std::io::Result<()> {
+    let cmd = CreateContact {
+        name: name.to_string(),
+        email: email.to_string(),
+    };
+    let mut repos = Repositories::new(db, &EventBus::default());
+    let mut service = ContactService::new(&mut repos.contacts);
+    let contact = service.create_contact(&cmd).await.map_err(failure)?;
+    print_record(&contact)
+}
+
+/// Deletes the contact with the default policy. It can be restored until
+/// the server purges it after the grace period.
+async fn delete_contact(db: &mongodb::Database, contact_id: &str) -> std::io::Result<()> {
+    let contact_id = parse_id(contact_id).map_err(failure)?;
+    let mut repos = Repositories::new(db, &EventBus::default());
+    let mut service = ContactService::new(&mut repos.contacts);
+    let contact = service
+        .delete_contact(&contact_id, &DeletionPolicy::default())
+        .await
+        .map_err(failure)?;
+    print_record(&contact)
+}
+
+async fn restore_contact(db: &mongodb::Database, contact_id: &str) -> std::io::Result<()> {
+    let contact_id = parse_id(contact_id).map_err(failure)?;
+    let mut repos = Repositories::new(db, &EventBus::default());
+    let mut service = ContactService::new(&mut repos.contacts);
+    let contact = service
+        .restore_contact(&contact_id)
+        .await
+        .map_err(failure)?;
+    print_record(&contact)
+}
+
+/// Creates a channel with its creator and the other given contacts as members
+async fn create_channel(
+    db: &mongodb::Database,
+    channel_type: &str,
+    name: &str,
+    created_by: &str,
+    contact_ids: &[&str],
+) -> std::io::Result<()> {
+    let channel_type = match channel_type {
+        "private" => ChannelType::Private,
+        "group" => ChannelType::Group,
+        other => return Err(failure(format!("Invalid channel type {other}"))),
+    };
+    let created_by = parse_id(created_by).map_err(failure)?;
+    let mut members = vec![created_by.clone()];
+    for id in contact_ids {
+        let id = parse_id(id).map_err(failure)?;
+        if !members.contains(&id) {
+            members.push(id);
+        }
+    }
+    let cmd = CreateChannel {
+        name: name.to_string(),
+        channel_type,
+        contact_ids: members,
+        created_by,
+    };
+    let mut repos = Repositories::new(db, &EventBus::default());
+    let channel = repos
+        .channel_service()
+        .create_channel(&cmd)
+        .await
+        .map_err(failure)?;
+    print_record(&channel)
+}
+
+/// Posts a text message to the channel on behalf of one of its members. The
+/// text goes through the standard stages and the configured content filter,
+/// whose flagged messages are reported to the moderators.
+async fn post_message(
+    db: &mongodb::Database,
+    channel_id: &str,
+    from: &str,
+    text: &str,
+) -> std::io::Result<()> {
+    let channel_id = parse_id(channel_id).map_err(failure)?;
+    let from = parse_id(from).map_err(failure)?;
+    let (events, flagged) = flag_collector();
+    let mut repos = Repositories::new(db, &events).with_pipeline(&pipeline()?);
+    let channel = match repos.channels.get(&channel_id).await {
+        Some(c) => c,
+        None => return Err(failure(format!("Channel with id {channel_id} not found"))),
+    };
+    // Channel messages are addressed to another member, or to the sender
+    // when alone in the channel
+    let to: IdType = match channel.contact_ids.iter().find(|id| **id != from) {
+        Some(id) => id.clone(),
+        None => from.clone(),
+    };
+    let cmd = SendMessage {
+        channel_id: Some(channel.id()),
+        from,
+        to,
+        content: MessageContent::text(text),
+        client_message_id: None,
+    };
+    let message = repos
+        .message_service()
+        .send_message(&cmd)
+        .await
+        .map_err(failure)?;
+    report_flagged(&mut repos, &flagged).await?;
+    print_record(&message)
+}
+
+/// Creates the indexes the repositories rely on, which the server also does
+/// when it starts
+async fn migrate(db: &mongodb::Database) -> std::io::Result<()> {
+    create_indexes(db).await.map_err(failure)?;
+    eprintln!("The indexes are up to date");
+    Ok(())
+}
+
+/// Fills the database with synthetic contacts, channels and histories
+/// spread over the year before `until`, an RFC 3339 time. The same seed and
+/// `until` generate the same data.
+async fn generate(
+    db: &mongodb::Database,
+    counts: &[&str],
+    until: Option<&str>,
+) -> std::io::Result<()> {
+    let mut numbers = Vec::with_capacity(counts.len());
+    for count in counts {
+        match count.parse::<u64>() {
+            Ok(n) => numbers.push(n),
+            Err(_) => return Err(failure(format!("Invalid count {count}"))),
+        }
+    }
+    let mut config = GeneratorConfig {
+        contacts: numbers[0] as usize,
+        private_channels: numbers[1] as usize,
+        group_channels: numbers[2] as usize,
+        messages_per_channel: numbers[3] as usize,
+        seed: numbers.get(4).copied().unwrap_or_default(),
+        ..GeneratorConfig::default()
+    };
+    if let Some(until) = until {
+        match DateTime::parse_from_rfc3339(until) {
+            Ok(t) => config.until = t.with_timezone(&Utc),
+            Err(_) => return Err(failure(format!("Invalid time {until}"))),
+        }
+    }
+    let mut repos = Repositories::new(db, &EventBus::default());
+    let mut generator = DataGenerator::new(
+        &mut repos.contacts,
+        &mut repos.channels,
+        &mut repos.messages,
+        config,
+    );
+    let generated = generator.generate().await.map_err(failure)?;
+    eprintln!(
+        "Generated {} contacts, {} channels and {} messages",
+        generated.contacts, generated.channels, generated.messages
+    );
+    Ok(())
+}
+
+/// Writes the archive of the contact's personal data to the file, or to the
+/// standard output
+async fn export_contact(
+    db: &mongodb::Database,
+    contact_id: &str,
+    path: Option<&str>,
+) -> std::io::Result<()> {
+    let contact_id = parse_id(contact_id).map_err(failure)?;
+    let mut out = open_output(path)?;
+    let mut repos = Repositories::new(db, &EventBus::default());
+    let service = repos.export_service();
+    // The operator acts on behalf of the contact
+    let mut cursor = service
+        .start_archive(&contact_id, &contact_id)
+        .await
+        .map_err(failure)?;
+    write_records(&mut out, &cursor.header())?;
+    loop {
+        let page = service
+            .next_archive_page(&mut cursor)
+            .await
+            .map_err(failure)?;
+        if page.is_empty() {
+            break;
+        }
+        write_records(&mut out, &page)?;
+    }
+    out.flush()
+}
+
+/// Writes the history of the channel, as seen by the member, to the file or
+/// the standard output. Files ending in `.html` get a transcript, the others
+/// the NDJSON the import reads back.
+async fn export_channel(
+    db: &mongodb::Database,
+    channel_id: &str,
+    contact_id: &str,
+    path: Option<&str>,
+) -> std::io::Result<()> {
+    let channel_id = parse_id(channel_id).map_err(failure)?;
+    let contact_id = parse_id(contact_id).map_err(failure)?;
+    let format = match path {
+        Some(p) if p.ends_with(".html") => ExportFormat::Html,
+        _ => ExportFormat::Ndjson,
+    };
+    let mut out = open_output(path)?;
+    let mut repos = Repositories::new(db, &EventBus::default());
+    let service = repos.export_service();
+    let mut cursor = service
+        .start_export(&channel_id, &contact_id)
+        .await
+        .map_err(failure)?;
+    write!(out, "{}", format.render(&cursor.header()))?;
+    loop {
+        let page = service.next_page(&mut cursor).await.map_err(failure)?;
+        if page.is_empty() {
+            break;
+        }
+        for record in page.iter() {
+            write!(out, "{}", format.render(record))?;
+        }
+    }
+    write!(out, "{}", format.footer())?;
+    out.flush()
+}
+
+/// Recreates a channel from the NDJSON export in the file, or on the
+/// standard input, on behalf of a moderator
+async fn import_channel(
+    db: &mongodb::Database,
+    moderator_id: &str,
+    path: Option<&str>,
+) -> std::io::Result<()> {
+    let moderator_id = parse_id(moderator_id).map_err(failure)?;
+    let text = match path {
+        Some(p) => std::fs::read_to_string(p)?,
+        None => {
+            let mut text = String::new();
+            std::io::stdin().read_to_string(&mut text)?;
+            text
+        }
+    };
+    let records = ExportRecord::parse_ndjson(&text).map_err(failure)?;
+    let (events, flagged) = flag_collector();
+    let mut repos = Repositories::new(db, &events).with_pipeline(&pipeline()?);
+    let channel = repos
+        .export_service()
+        .import_channel(&moderator_id, records)
+        .await
+        .map_err(failure)?;
+    report_flagged(&mut repos, &flagged).await?;
+    print_record(&channel)
+}
+
+/// Returns the stages the server runs the sent messages through
+fn pipeline() -> std::io::Result<Pipeline> {
+    let pipeline = Pipeline::standard();
+    pipeline.register(filter_from_env().map_err(failure)?);
+    Ok(pipeline)
+}
+
+/// Keeps the messages the content filter flagged. No moderation queue
+/// listens to the events of the command line, so it reports them itself.
+struct FlaggedMessages {
+    flagged: Vec<MessageFlagged>,
+}
+
+impl Actor for FlaggedMessages {
+    type Context = Context<Self>;
+}
+
+impl Handler<MessageFlagged> for FlaggedMessages {
+    type Result = ();
+
+    fn handle(&mut self, msg: MessageFlagged, _ctx: &mut Self::Context) -> Self::Result {
+        self.flagged.push(msg);
+    }
+}
+
+/// Hands over the flagged messages kept so far
+struct TakeFlagged;
+
+impl actix::Message for TakeFlagged {
+    type Result = Vec<MessageFlagged>;
+}
+
+impl Handler<TakeFlagged> for FlaggedMessages {
+    type Result = Vec<MessageFlagged>;
+
+    fn handle(&mut self, _msg: TakeFlagged, _ctx: &mut Self::Context) -> Self::Result {
+        std::mem::take(&mut self.flagged)
+    }
+}
+
+/// Returns a bus whose flagged messages are kept for `report_flagged`
+fn flag_collector() -> (EventBus, Addr<FlaggedMessages>) {
+    let events = EventBus::default();
+    let flagged = FlaggedMessages { flagged: vec![] }.start();
+    events.subscribe::<MessageFlagged>(flagged.clone().recipient());
+    (events, flagged)
+}
+
+/// Queues a report for each message the content filter flagged, as the
+/// server's moderation queue does
+async fn report_flagged(
+    repos: &mut Repositories,
+    flagged: &Addr<FlaggedMessages>,
+) -> std::io::Result<()> {
+    let flagged = flagged.send(TakeFlagged).await.map_err(failure)?;
+    let mut service = repos.moderation_service();
+    for msg in flagged {
+        service
+            .flag_message(&msg.message, &msg.rules)
+            .await
+            .map_err(failure)?;
+    }
+    Ok(())
+}
+
+fn open_output(path: Option<&str>) -> std::io::Result<Box<dyn Write>> {
+    Ok(match path {
+        Some(p) => Box::new(std::io::BufWriter::new(std::fs::File::create(p)?)),
+        None => Box::new(std::io::stdout().lock()),
+    })
+}
+
+fn print_record<T: serde::Serialize>(record: &T) -> std::io::Result<()> {
+    let mut out = std::io::stdout().lock();
+    write_records(&mut out, std::slice::from_ref(record))
+}
+
+fn write_records<T: serde::Serialize>(out: &mut dyn Write, records: &[T]) -> std::io::Result<()> {
+    for record in records {
+        writeln!(out, "{}", serde_json::to_string(record)?)?;
+    }
+    Ok(())
+}
+
+fn failure(e: impl std::fmt::Display) -> std::io::Error {
+    std::io::Error::other(e.to_string())
+}
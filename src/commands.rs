@@ -1,12 +1,35 @@
 use crate::adapters::IdType;
-use crate::models::ChannelType;
+use crate::models::{
+    ChannelType, MessageContent, ModerationActionType, NotificationLevel, WebhookEventType,
+};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 pub struct SendMessage {
     pub channel_id: Option<IdType>,
     pub from: IdType,
     pub to: IdType,
-    pub content: String,
+    pub content: MessageContent,
+    /// Idempotency key generated by the client, unique per sender.
+    /// Sending again with the same key returns the stored message.
+    pub client_message_id: Option<String>,
+}
+
+pub struct ScheduleMessage {
+    pub message: SendMessage,
+    pub deliver_at: DateTime<Utc>,
+}
+
+pub struct EditScheduledMessage {
+    pub id: IdType,
+    pub contact_id: IdType,
+    pub content: Option<MessageContent>,
+    pub deliver_at: Option<DateTime<Utc>>,
+}
+
+pub struct CancelScheduledMessage {
+    pub id: IdType,
+    pub contact_id: IdType,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -26,4 +49,129 @@ pub struct CreateChannel {
     pub name: String,
     pub channel_type: ChannelType,
     pub contact_ids: Vec<IdType>,
+    pub created_by: IdType,
+}
+
+pub struct RenameChannel {
+    pub channel_id: IdType,
+    pub name: String,
+    pub renamed_by: IdType,
+}
+
+pub struct JoinChannel {
+    pub channel_id: IdType,
+    pub contact_id: IdType,
+}
+
+pub struct LeaveChannel {
+    pub channel_id: IdType,
+    pub contact_id: IdType,
+}
+
+pub struct ArchiveChannel {
+    pub channel_id: IdType,
+    pub archived_by: IdType,
+}
+
+pub struct UnarchiveChannel {
+    pub channel_id: IdType,
+    pub unarchived_by: IdType,
+}
+
+pub struct DeleteChannel {
+    pub channel_id: IdType,
+    pub deleted_by: IdType,
+}
+
+pub struct RestoreChannel {
+    pub channel_id: IdType,
+    pub restored_by: IdType,
+}
+
+pub struct SetMessageTtl {
+    pub channel_id: IdType,
+    /// The message lifetime in seconds, or `None` to keep messages forever
+    pub message_ttl: Option<i64>,
+    pub set_by: IdType,
+}
+
+/// Replaces the notification settings of a member for the channel
+pub struct SetChannelPreferences {
+    pub channel_id: IdType,
+    pub contact_id: IdType,
+    pub level: NotificationLevel,
+    pub muted: bool,
+    /// When the mute ends, or `None` to stay muted until unmuted
+    pub muted_until: Option<DateTime<Utc>>,
+}
+
+pub struct PinMessage {
+    pub channel_id: IdType,
+    pub message_id: IdType,
+    pub pinned_by: IdType,
+}
+
+pub struct UnpinMessage {
+    pub channel_id: IdType,
+    pub message_id: IdType,
+    pub unpinned_by: IdType,
+}
+
+pub struct RegisterWebhook {
+    pub url: String,
+    /// The moderator registering the webhook
+    pub registered_by: IdType,
+    /// Limits the webhook to one channel, or `None` for every channel
+    pub channel_id: Option<IdType>,
+    /// The event types to send, or every type when empty
+    pub events: Vec<WebhookEventType>,
+}
+
+/// Posts a message on behalf of an integration
+pub struct PostBotMessage {
+    pub channel_id: IdType,
+    pub bot_id: IdType,
+    pub bot_name: String,
+    pub content: MessageContent,
+}
+
+pub struct CreateIncomingWebhook {
+    pub channel_id: IdType,
+    /// The name the posted messages are shown with
+    pub name: String,
+    pub created_by: IdType,
+}
+
+/// Revokes the token of the incoming webhook, or replaces it when rotating
+pub struct ChangeIncomingWebhookToken {
+    pub id: IdType,
+    pub contact_id: IdType,
+}
+
+pub struct RegisterSlashCommand {
+    pub bot_id: IdType,
+    /// The command name, without the leading slash
+    pub name: String,
+    pub callback_url: String,
+    pub description: Option<String>,
+}
+
+pub struct BlockContact {
+    pub contact_id: IdType,
+    pub blocked_id: IdType,
+    /// Also hides the messages of the blocker in shared groups
+    pub hide_messages: bool,
+}
+
+pub struct ReportMessage {
+    pub message_id: IdType,
+    pub reported_by: IdType,
+    pub reason: String,
+}
+
+pub struct ModerateReport {
+    pub report_id: IdType,
+    pub moderator_id: IdType,
+    pub action: ModerationActionType,
+    pub note: Option<String>,
 }
@@ -0,0 +1,221 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use actix::Recipient;
+
+use crate::adapters::IdType;
+use crate::models::{Channel, Contact, Message};
+
+/// An event published by the services after a successful write
+pub trait DomainEvent: actix::Message<Result = ()> + Clone + Send + Unpin + 'static {}
+
+#[derive(Clone, Debug, actix::Message)]
+#[rtype(result = "()")]
+pub struct ContactCreated {
+    pub contact: Contact,
+}
+
+#[derive(Clone, Debug, actix::Message)]
+#[rtype(result = "()")]
+pub struct ContactUpdated {
+    pub contact: Contact,
+}
+
+#[derive(Clone, Debug, actix::Message)]
+#[rtype(result = "()")]
+pub struct ContactDeleted {
+    pub contact_id: IdType,
+}
+
+#[derive(Clone, Debug, actix::Message)]
+#[rtype(result = "()")]
+pub struct ChannelCreated {
+    pub channel: Channel,
+}
+
+/// The channel was renamed, changed members or settings
+#[derive(Clone, Debug, actix::Message)]
+#[rtype(result = "()")]
+pub struct ChannelUpdated {
+    pub channel: Channel,
+}
+
+/// A message was stored in the channel, system messages included
+#[derive(Clone, Debug, actix::Message)]
+#[rtype(result = "()")]
+pub struct MessageSent {
+    pub channel: Channel,
+    pub message: Message,
+    /// The members the message is not shown to, because its sender blocked them
+    pub hidden_from: Vec<IdType>,
+}
+
+/// A stored message changed after it was sent, like when its link previews
+/// were attached
+#[derive(Clone, Debug, actix::Message)]
+#[rtype(result = "()")]
+pub struct MessageUpdated {
+    pub channel: Channel,
+    pub message: Message,
+    /// The members the message is not shown to, because its sender blocked them
+    pub hidden_from: Vec<IdType>,
+}
+
+/// A message started with a `/name` command that no in-process handler
+/// answers, left to the bots registered for it
+#[derive(Clone, Debug, actix::Message)]
+#[rtype(result = "()")]
+pub struct SlashCommandInvoked {
+    pub channel: Channel,
+    pub message: Message,
+    /// The command name, without the leading slash
+    pub name: String,
+    /// The rest of the message after the command name
+    pub args: String,
+}
+
+/// A stored message matched flag rules of the content filter
+#[derive(Clone, Debug, actix::Message)]
+#[rtype(result = "()")]
+pub struct MessageFlagged {
+    pub channel: Channel,
+    pub message: Message,
+    /// The patterns of the matched rules
+    pub rules: Vec<String>,
+}
+
+impl DomainEvent for ContactCreated {}
+impl DomainEvent for ContactUpdated {}
+impl DomainEvent for ContactDeleted {}
+impl DomainEvent for ChannelCreated {}
+impl DomainEvent for ChannelUpdated {}
+impl DomainEvent for MessageSent {}
+impl DomainEvent for MessageUpdated {}
+impl DomainEvent for SlashCommandInvoked {}
+impl DomainEvent for MessageFlagged {}
+
+/// The recipients of each event type, as `Recipient<E>` keyed by the type of `E`
+type Subscribers = HashMap<TypeId, Vec<Box<dyn Any + Send + Sync>>>;
+
+/// Delivers the published events to the actors subscribed to their type.
+///
+/// Clones share the subscriptions, so a bus created at startup can be handed
+/// to every service. A bus without subscribers drops the events.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<RwLock<Subscribers>>,
+}
+
+impl EventBus {
+    pub fn subscribe<E: DomainEvent>(&self, recipient: Recipient<E>) {
+        let mut subscribers = self.subscribers.write().unwrap();
+        subscribers
+            .entry(TypeId::of::<E>())
+            .or_default()
+            .push(Box::new(recipient));
+    }
+
+    pub fn publish<E: DomainEvent>(&self, event: E) {
+        let subscribers = self.subscribers.read().unwrap();
+        let recipients = match subscribers.get(&TypeId::of::<E>()) {
+            Some(r) => r,
+            None => return,
+        };
+        for recipient in recipients.iter() {
+            if let Some(r) = recipient.downcast_ref::<Recipient<E>>() {
+                r.do_send(event.clone());
+            }
+        }
+    }
+}
+
+/// Keeps the events of one type so tests can check what was published
+#[cfg(test)]
+pub struct Recorder<E> {
+    events: Vec<E>,
+}
+
+#[cfg(test)]
+impl<E: DomainEvent> actix::Actor for Recorder<E> {
+    type Context = actix::Context<Self>;
+}
+
+#[cfg(test)]
+impl<E: DomainEvent> actix::Handler<E> for Recorder<E> {
+    type Result = ();
+
+    fn handle(&mut self, msg: E, _ctx: &mut Self::Context) -> Self::Result {
+        self.events.push(msg);
+    }
+}
+
+#[cfg(test)]
+pub struct Recorded;
+
+#[cfg(test)]
+impl actix::Message for Recorded {
+    type Result = usize;
+}
+
+#[cfg(test)]
+impl<E: DomainEvent> actix::Handler<Recorded> for Recorder<E> {
+    type Result = usize;
+
+    fn handle(&mut self, _msg: Recorded, _ctx: &mut Self::Context) -> Self::Result {
+        self.events.len()
+    }
+}
+
+/// Asks a recorder for the events it kept, oldest first
+#[cfg(test)]
+pub struct Replay<E>(std::marker::PhantomData<E>);
+
+#[cfg(test)]
+impl<E> Default for Replay<E> {
+    fn default() -> Self {
+        Replay(std::marker::PhantomData)
+    }
+}
+
+#[cfg(test)]
+impl<E: DomainEvent> actix::Message for Replay<E> {
+    type Result = Vec<E>;
+}
+
+#[cfg(test)]
+impl<E: DomainEvent> actix::Handler<Replay<E>> for Recorder<E> {
+    type Result = Vec<E>;
+
+    fn handle(&mut self, _msg: Replay<E>, _ctx: &mut Self::Context) -> Self::Result {
+        self.events.clone()
+    }
+}
+
+/// Subscribes a recorder of the event type to the bus
+#[cfg(test)]
+pub fn record<E: DomainEvent>(bus: &EventBus) -> actix::Addr<Recorder<E>> {
+    use actix::Actor;
+    let recorder = Recorder { events: Vec::new() }.start();
+    bus.subscribe::<E>(recorder.clone().recipient());
+    recorder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{record, ContactCreated, EventBus, MessageSent, Recorded};
+    use crate::models::Contact;
+
+    #[actix_web::test]
+    async fn delivers_events_to_the_subscribers_of_their_type() {
+        let bus = EventBus::default();
+        let created = record::<ContactCreated>(&bus);
+        let sent = record::<MessageSent>(&bus);
+        let contact = Contact::new("Jon Snow", "jon@winterfell.com");
+
+        bus.publish(ContactCreated { contact });
+
+        assert_eq!(created.send(Recorded).await.unwrap(), 1);
+        assert_eq!(sent.send(Recorded).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,459 @@
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use mongodb::bson::oid::ObjectId;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::adapters::channel_repository::ChannelRepository;
+use crate::adapters::contact_repository::ContactRepository;
+use crate::adapters::message_repository::MessageRepository;
+use crate::adapters::{IdType, Model};
+use crate::models::{Channel, ChannelType, Contact, Message, MessageContent, SystemEvent};
+
+const FIRST_NAMES: &[&str] = &[
+    "Jon",
+    "Arya",
+    "Sansa",
+    "Bran",
+    "Robb",
+    "Catelyn",
+    "Eddard",
+    "Tyrion",
+    "Cersei",
+    "Jaime",
+    "Daenerys",
+    "Jorah",
+    "Samwell",
+    "Brienne",
+    "Davos",
+    "Theon",
+    "Yara",
+    "Gendry",
+    "Podrick",
+    "Margaery",
+    "Olenna",
+    "Oberyn",
+    "Ellaria",
+    "Missandei",
+];
+
+const LAST_NAMES: &[&str] = &[
+    "Stark",
+    "Lannister",
+    "Targaryen",
+    "Baratheon",
+    "Greyjoy",
+    "Tyrell",
+    "Martell",
+    "Tully",
+    "Arryn",
+    "Mormont",
+    "Tarly",
+    "Seaworth",
+    "Clegane",
+    "Bolton",
+    "Frey",
+    "Payne",
+];
+
+const GROUP_NAMES: &[&str] = &[
+    "Small Council",
+    "Night's Watch",
+    "Kingsguard",
+    "Brotherhood Without Banners",
+    "Iron Bank",
+    "Citadel",
+    "Golden Company",
+    "Faceless Men",
+];
+
+const PHRASES: &[&str] = &[
+    "Winter is coming.",
+    "Has anyone seen the ravens from the Wall?",
+    "The council meets at noon tomorrow.",
+    "I'll bring the maps of the Riverlands.",
+    "Any news from Dorne?",
+    "The harvest was poor again this year.",
+    "We should send word to Winterfell before the snows close the roads.",
+    "Agreed.",
+    "Not yet, give me another day.",
+    "The Iron Bank will have its due.",
+    "Can someone check the stores of dragonglass?",
+    "The ships are ready to sail at dawn.",
+    "Thanks!",
+    "That went better than expected.",
+    "I'll look into it.",
+    "A Lannister always pays his debts.",
+    "Who's on watch tonight?",
+    "The road through the Neck is flooded.",
+    "Here are the notes from the last meeting: https://citadel.example/notes",
+    "Let's talk tomorrow.",
+];
+
+/// What to generate. The same seed and settings always generate the same
+/// contacts, channels and histories, ids included.
+#[derive(Debug, Clone)]
+pub struct GeneratorConfig {
+    pub seed: u64,
+    pub contacts: usize,
+    pub private_channels: usize,
+    pub group_channels: usize,
+    /// The average number of messages of a channel
+    pub messages_per_channel: usize,
+    /// The largest number of members of a group channel
+    pub max_group_size: usize,
+    /// How many days of history come before `until`
+    pub days: i64,
+    /// When the histories end. It defaults to a fixed date rather than the
+    /// current time, so the same seed generates the same data on every run.
+    pub until: DateTime<Utc>,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        GeneratorConfig {
+            seed: 0,
+            contacts: 100,
+            private_channels: 200,
+            group_channels: 20,
+            messages_per_channel: 50,
+            max_group_size: 25,
+            days: 365,
+            until: Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(),
+        }
+    }
+}
+
+/// How much was generated
+#[derive(Debug, Default, PartialEq)]
+pub struct Generated {
+    pub contacts: usize,
+    pub channels: usize,
+    pub messages: usize,
+}
+
+/// Fills the repositories with synthetic contacts, channels and message
+/// histories, to evaluate pagination and indexing on large datasets and to
+/// populate demo environments
+pub struct DataGenerator<'a> {
+    contact_repository: &'a mut dyn ContactRepository,
+    channel_repository: &'a mut dyn ChannelRepository,
+    message_repository: &'a mut dyn MessageRepository,
+    config: GeneratorConfig,
+    rng: ChaCha8Rng,
+}
+
+impl<'a> DataGenerator<'a> {
+    pub fn new(
+        contact_repository: &'a mut dyn ContactRepository,
+        channel_repository: &'a mut dyn ChannelRepository,
+        message_repository: &'a mut dyn MessageRepository,
+        config: GeneratorConfig,
+    ) -> Self {
+        let rng = ChaCha8Rng::seed_from_u64(config.seed);
+        DataGenerator {
+            contact_repository,
+            channel_repository,
+            message_repository,
+            config,
+            rng,
+        }
+    }
+
+    pub async fn generate(&mut self) -> Result<Generated, GeneratorError> {
+        self.validate()?;
+        let mut generated = Generated::default();
+        let start = self.config.until - Duration::days(self.config.days);
+        let mut contacts = Vec::with_capacity(self.config.contacts);
+        for i in 0..self.config.contacts {
+            let contact = self.generate_contact(i, start);
+            if let Err(e) = self.contact_repository.create(&contact).await {
+                return Err(GeneratorError {
+                    message: e.to_string(),
+                });
+            }
+            contacts.push(contact);
+            generated.contacts += 1;
+        }
+        let mut pairs: BTreeSet<(usize, usize)> = BTreeSet::new();
+        while pairs.len() < self.config.private_channels {
+            let a = self.rng.gen_range(0..contacts.len());
+            let b = self.rng.gen_range(0..contacts.len());
+            if a == b || !pairs.insert((a.min(b), a.max(b))) {
+                continue;
+            }
+            let members = vec![contacts[a].clone(), contacts[b].clone()];
+            generated.messages += self
+                .generate_channel(ChannelType::Private, None, &members, start)
+                .await?;
+            generated.channels += 1;
+        }
+        let max_group_size = self.config.max_group_size.min(contacts.len());
+        for i in 0..self.config.group_channels {
+            let size = self.rng.gen_range(2..=max_group_size);
+            let members: Vec<Contact> = contacts
+                .choose_multiple(&mut self.rng, size)
+                .cloned()
+                .collect();
+            let name = format!("{} {}", GROUP_NAMES[i % GROUP_NAMES.len()], i + 1);
+            generated.messages += self
+                .generate_channel(ChannelType::Group, Some(name), &members, start)
+                .await?;
+            generated.channels += 1;
+        }
+        Ok(generated)
+    }
+
+    fn validate(&self) -> Result<(), GeneratorError> {
+        let contacts = self.config.contacts;
+        let channels = self.config.private_channels + self.config.group_channels;
+        if channels > 0 && contacts < 2 {
+            return Err(GeneratorError {
+                message: "Channels need at least 2 contacts".to_string(),
+            });
+        }
+        if self.config.private_channels > contacts * contacts.saturating_sub(1) / 2 {
+            return Err(GeneratorError {
+                message: format!("{contacts} contacts cannot have that many private channels"),
+            });
+        }
+        if self.config.group_channels > 0 && self.config.max_group_size < 2 {
+            return Err(GeneratorError {
+                message: "Group channels need room for at least 2 members".to_string(),
+            });
+        }
+        if self.config.days <= 0 {
+            return Err(GeneratorError {
+                message: "The history must span at least a day".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Creates a contact that joined in the week before the histories start
+    fn generate_contact(&mut self, index: usize, start: DateTime<Utc>) -> Contact {
+        let first = *FIRST_NAMES.choose(&mut self.rng).unwrap();
+        let last = *LAST_NAMES.choose(&mut self.rng).unwrap();
+        let email = format!(
+            "{}.{}.{index}@example.com",
+            first.to_lowercase(),
+            last.to_lowercase()
+        );
+        let mut contact = Contact::new(&format!("{first} {last}"), &email);
+        let created_at = start - Duration::seconds(self.rng.gen_range(0..7 * 24 * 3600));
+        contact.id = Some(self.object_id(created_at));
+        contact.created_at = created_at;
+        contact.updated_at = created_at;
+        contact
+    }
+
+    /// Creates the channel and its history and returns the number of
+    /// messages. The channel starts in the first part of the period, and its
+    /// messages are spread between its creation and the end of the period.
+    async fn generate_channel(
+        &mut self,
+        channel_type: ChannelType,
+        name: Option<String>,
+        members: &[Contact],
+        start: DateTime<Utc>,
+    ) -> Result<usize, GeneratorError> {
+        let span = (self.config.until - start).num_seconds();
+        let created_at = start + Duration::seconds(self.rng.gen_range(0..span * 4 / 5));
+        let member_ids: Vec<IdType> = members.iter().map(|c| c.id()).collect();
+        let mut channel = Channel::new(name.as_deref().unwrap_or(""), channel_type, &member_ids);
+        channel.id = Some(self.object_id(created_at));
+        channel.created_at = created_at;
+
+        let count = self.rng.gen_range(0..=2 * self.config.messages_per_channel);
+        let history = (self.config.until - created_at).num_seconds();
+        let mut times: Vec<DateTime<Utc>> = (0..count)
+            .map(|_| created_at + Duration::seconds(self.rng.gen_range(1..=history)))
+            .collect();
+        times.sort();
+        channel.updated_at = times.last().copied().unwrap_or(created_at);
+
+        let event = SystemEvent::ChannelCreated { name };
+        let mut created = Message::system(&channel.id(), &member_ids[0], event);
+        self.store_message(&mut created, created_at).await?;
+        for at in times {
+            let mut message = self.generate_message(&channel, members);
+            self.store_message(&mut message, at).await?;
+        }
+        // Stored last, like the import, so the channel never lacks its history
+        if let Err(e) = self.channel_repository.create(&channel).await {
+            return Err(GeneratorError {
+                message: e.to_string(),
+            });
+        }
+        Ok(count + 1)
+    }
+
+    /// Writes a message from a random member, now and then mentioning another
+    fn generate_message(&mut self, channel: &Channel, members: &[Contact]) -> Message {
+        let from = members.choose(&mut self.rng).unwrap();
+        let to = match members.iter().find(|c| c.id() != from.id()) {
+            Some(c) => c,
+            None => from,
+        };
+        let phrase = *PHRASES.choose(&mut self.rng).unwrap();
+        let mentioned = members
+            .choose(&mut self.rng)
+            .filter(|c| c.id() != from.id() && self.rng.gen_bool(0.1));
+        let text = match mentioned {
+            Some(c) => format!("@{} {phrase}", c.name),
+            None => phrase.to_string(),
+        };
+        let mut message = Message::new(
+            &channel.id(),
+            &from.id(),
+            &to.id(),
+            &MessageContent::text(&text),
+        );
+        if let Some(c) = mentioned {
+            message.mentions = vec![c.id()];
+        }
+        message
+    }
+
+    async fn store_message(
+        &mut self,
+        message: &mut Message,
+        at: DateTime<Utc>,
+    ) -> Result<(), GeneratorError> {
+        message.id = Some(self.object_id(at));
+        message.created_at = at;
+        message.updated_at = at;
+        let res = match self.message_repository.next_seq(&message.channel_id).await {
+            Ok(seq) => {
+                message.seq = seq;
+                self.message_repository.create(message).await
+            }
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok(_) => Ok(()),
+            Err(e) => Err(GeneratorError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    /// Returns an id carrying the given time, like the ids Mongo generates,
+    /// with the rest drawn from the seed
+    fn object_id(&mut self, at: DateTime<Utc>) -> ObjectId {
+        let mut bytes = [0u8; 12];
+        bytes[..4].copy_from_slice(&(at.timestamp() as u32).to_be_bytes());
+        self.rng.fill(&mut bytes[4..]);
+        ObjectId::from_bytes(bytes)
+    }
+}
+
+#[derive(Debug)]
+pub struct GeneratorError {
+    pub message: String,
+}
+
+impl Display for GeneratorError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DataGenerator, Generated, GeneratorConfig};
+    use crate::adapters::{
+        mock_channel_repo, mock_contact_repo, mock_message_repo, Model, Repository,
+    };
+    use crate::models::{Channel, ChannelType, Contact, Message};
+    use chrono::{TimeZone, Utc};
+
+    async fn generate(config: &GeneratorConfig) -> (Vec<Contact>, Vec<Channel>, Vec<Message>) {
+        let mut contacts = mock_contact_repo();
+        let mut channels = mock_channel_repo();
+        let mut messages = mock_message_repo();
+        let mut generator =
+            DataGenerator::new(&mut contacts, &mut channels, &mut messages, config.clone());
+        let generated = generator.generate().await.unwrap();
+        let contacts = contacts.list(None, None).await.unwrap().1;
+        let channels = channels.list(None, None).await.unwrap().1;
+        let messages = messages.list(None, None).await.unwrap().1;
+        assert_eq!(
+            generated,
+            Generated {
+                contacts: contacts.len(),
+                channels: channels.len(),
+                messages: messages.len(),
+            }
+        );
+        (contacts, channels, messages)
+    }
+
+    #[actix_web::test]
+    async fn generates_the_same_data_from_the_same_seed() {
+        let config = GeneratorConfig {
+            seed: 7,
+            contacts: 12,
+            private_channels: 10,
+            group_channels: 3,
+            messages_per_channel: 8,
+            max_group_size: 6,
+            days: 30,
+            until: Utc.with_ymd_and_hms(2023, 3, 1, 0, 0, 0).unwrap(),
+        };
+        let (contacts, channels, messages) = generate(&config).await;
+        assert_eq!(contacts.len(), 12);
+        assert_eq!(channels.len(), 13);
+        let private = channels
+            .iter()
+            .filter(|c| c.channel_type == ChannelType::Private)
+            .count();
+        assert_eq!(private, 10);
+        assert!(messages.iter().all(|m| m.created_at <= config.until));
+        for channel in channels.iter() {
+            let mut history: Vec<&Message> = messages
+                .iter()
+                .filter(|m| m.channel_id == channel.id())
+                .collect();
+            history.sort_by_key(|m| m.seq);
+            assert!(history
+                .windows(2)
+                .all(|w| w[0].created_at <= w[1].created_at));
+            assert_eq!(history[0].seq, 1);
+        }
+
+        let (same_contacts, _, same_messages) = generate(&config).await;
+        assert_eq!(
+            serde_json::to_value(&contacts).unwrap(),
+            serde_json::to_value(&same_contacts).unwrap()
+        );
+        assert_eq!(
+            serde_json::to_value(&messages).unwrap(),
+            serde_json::to_value(&same_messages).unwrap()
+        );
+
+        let other = GeneratorConfig { seed: 8, ..config };
+        let (_, _, other_messages) = generate(&other).await;
+        assert_ne!(
+            serde_json::to_value(&messages).unwrap(),
+            serde_json::to_value(&other_messages).unwrap()
+        );
+    }
+
+    #[actix_web::test]
+    async fn refuses_impossible_mixes() {
+        let mut contacts = mock_contact_repo();
+        let mut channels = mock_channel_repo();
+        let mut messages = mock_message_repo();
+        let config = GeneratorConfig {
+            contacts: 3,
+            private_channels: 4,
+            ..GeneratorConfig::default()
+        };
+        let mut generator = DataGenerator::new(&mut contacts, &mut channels, &mut messages, config);
+        assert!(generator.generate().await.is_err());
+    }
+}
@@ -0,0 +1,33 @@
+pub mod adapters;
+pub mod api;
+pub mod bots;
+pub mod cli;
+pub mod commands;
+pub mod events;
+pub mod generator;
+pub mod link_previews;
+pub mod models;
+pub mod moderation;
+pub mod notifications;
+pub mod purger;
+pub mod rate_limit;
+pub mod scheduler;
+pub mod services;
+pub mod sweeper;
+pub mod webhooks;
+pub mod websocket;
+
+use actix::Addr;
+
+/// The database the server and the operator command line work on
+pub const DATABASE: &str = "chatapp";
+
+/// The state the server shares with every request handler
+pub struct AppState {
+    pub db: mongodb::Database,
+    pub chat_server: Addr<websocket::ChatServer>,
+    pub events: events::EventBus,
+    pub commands: services::CommandRegistry,
+    pub pipeline: services::Pipeline,
+    pub limiter: rate_limit::RateLimiter,
+}
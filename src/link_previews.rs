@@ -0,0 +1,537 @@
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::rc::Rc;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use actix::{
+    Actor, ActorFutureExt, AsyncContext, Context, ContextFutureSpawner, Handler, WrapFuture,
+};
+use actix_tls::connect::{Connector as TcpConnector, Resolve, Resolver};
+use actix_web::http::Uri;
+use futures::future::LocalBoxFuture;
+use futures::StreamExt;
+use regex::Regex;
+
+use crate::adapters::IdType;
+use crate::api::Repositories;
+use crate::events::{EventBus, MessageSent};
+use crate::models::LinkPreview;
+
+/// How long fetching a page may take, from the connection to its last byte
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The part of a page read for its metadata, which lives in the head
+const MAX_BODY_BYTES: usize = 256 * 1024;
+
+/// The redirects followed before giving up on a link
+const MAX_REDIRECTS: usize = 3;
+
+/// The links of a message that get a preview
+const MAX_PREVIEWS: usize = 3;
+
+/// The number of characters kept of a title or description
+const MAX_TEXT_LENGTH: usize = 300;
+
+/// How long a fetched preview, or a failure, is reused for the same URL
+const CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// The number of URLs cached before the expired entries are dropped
+const MAX_CACHED: usize = 1000;
+
+/// Fetches the previews of the links in the sent messages, attaches them to
+/// the messages and pushes the updated messages to their channels
+pub struct LinkPreviewer {
+    db: mongodb::Database,
+    events: EventBus,
+    fetcher: Rc<Fetcher>,
+    cache: PreviewCache,
+}
+
+impl LinkPreviewer {
+    pub fn new(db: mongodb::Database, events: EventBus) -> Self {
+        LinkPreviewer {
+            db,
+            events,
+            fetcher: Rc::new(Fetcher::default()),
+            cache: PreviewCache::default(),
+        }
+    }
+}
+
+impl Actor for LinkPreviewer {
+    type Context = Context<Self>;
+}
+
+impl Handler<MessageSent> for LinkPreviewer {
+    type Result = ();
+
+    fn handle(&mut self, msg: MessageSent, ctx: &mut Self::Context) -> Self::Result {
+        let links: Vec<String> = msg
+            .message
+            .links
+            .iter()
+            .take(MAX_PREVIEWS)
+            .cloned()
+            .collect();
+        if links.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        let missing: Vec<String> = links
+            .iter()
+            .filter(|l| self.cache.get(l, now).is_none())
+            .cloned()
+            .collect();
+        let fetcher = self.fetcher.clone();
+        let message_id = msg.message.id.map(IdType::ObjectId);
+        async move {
+            let mut fetched = Vec::new();
+            for url in missing {
+                let preview = fetcher.fetch(&url).await.ok();
+                fetched.push((url, preview));
+            }
+            fetched
+        }
+        .into_actor(self)
+        .map(move |fetched, act, ctx| {
+            let now = Instant::now();
+            for (url, preview) in fetched {
+                act.cache.insert(url, preview, now);
+            }
+            let previews: Vec<LinkPreview> = links
+                .iter()
+                .filter_map(|l| act.cache.get(l, now).flatten())
+                .collect();
+            if let (Some(message_id), false) = (message_id, previews.is_empty()) {
+                let attach = attach(act.db.clone(), act.events.clone(), message_id, previews);
+                ctx.spawn(attach.into_actor(act));
+            }
+        })
+        .spawn(ctx);
+    }
+}
+
+async fn attach(
+    db: mongodb::Database,
+    events: EventBus,
+    message_id: IdType,
+    previews: Vec<LinkPreview>,
+) {
+    let mut repos = Repositories::new(&db, &events);
+    let res = repos
+        .message_service()
+        .attach_previews(&message_id, previews)
+        .await;
+    if let Err(e) = res {
+        eprintln!("Failed to attach link previews: {e}");
+    }
+}
+
+/// The previews fetched recently, with `None` for the URLs that failed
+#[derive(Default)]
+struct PreviewCache {
+    entries: HashMap<String, (Instant, Option<LinkPreview>)>,
+}
+
+impl PreviewCache {
+    fn get(&self, url: &str, now: Instant) -> Option<Option<LinkPreview>> {
+        match self.entries.get(url) {
+            Some((fetched_at, preview)) if now.duration_since(*fetched_at) < CACHE_TTL => {
+                Some(preview.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn insert(&mut self, url: String, preview: Option<LinkPreview>, now: Instant) {
+        if self.entries.len() >= MAX_CACHED {
+            self.entries
+                .retain(|_, (fetched_at, _)| now.duration_since(*fetched_at) < CACHE_TTL);
+        }
+        if self.entries.len() >= MAX_CACHED {
+            self.entries.clear();
+        }
+        self.entries.insert(url, (now, preview));
+    }
+}
+
+/// Fetches the pages of the links, refusing the hosts on private networks
+/// unless told otherwise
+#[derive(Default)]
+pub struct Fetcher {
+    allow_private: bool,
+}
+
+impl Fetcher {
+    /// A fetcher reaching private networks too, for tests and local setups
+    #[cfg(test)]
+    pub fn allowing_private() -> Self {
+        Fetcher {
+            allow_private: true,
+        }
+    }
+
+    /// Follows the redirects of the URL and returns the preview of the page
+    pub async fn fetch(&self, url: &str) -> Result<LinkPreview, String> {
+        match actix_web::rt::time::timeout(FETCH_TIMEOUT, self.fetch_page(url)).await {
+            Ok(res) => res,
+            Err(_) => Err(format!("{url} took too long to answer")),
+        }
+    }
+
+    async fn fetch_page(&self, url: &str) -> Result<LinkPreview, String> {
+        let client = self.client();
+        let mut uri: Uri = url.parse().map_err(|_| format!("Invalid URL {url}"))?;
+        for _ in 0..=MAX_REDIRECTS {
+            self.check_uri(&uri)?;
+            let mut res = client
+                .get(uri.clone())
+                .insert_header(("Accept", "text/html"))
+                .insert_header(("User-Agent", "messaging-link-preview"))
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+            if res.status().is_redirection() {
+                let location = res
+                    .headers()
+                    .get("Location")
+                    .and_then(|l| l.to_str().ok())
+                    .ok_or(format!("{uri} redirects nowhere"))?;
+                uri = redirect_target(&uri, location)?;
+                continue;
+            }
+            if !res.status().is_success() {
+                return Err(format!("{uri} answered {}", res.status()));
+            }
+            let is_html = res
+                .headers()
+                .get("Content-Type")
+                .and_then(|t| t.to_str().ok())
+                .is_some_and(|t| t.starts_with("text/html"));
+            if !is_html {
+                return Err(format!("{uri} is not a web page"));
+            }
+            let mut body = Vec::new();
+            while let Some(chunk) = res.next().await {
+                body.extend_from_slice(&chunk.map_err(|e| e.to_string())?);
+                if body.len() >= MAX_BODY_BYTES {
+                    body.truncate(MAX_BODY_BYTES);
+                    break;
+                }
+            }
+            let html = String::from_utf8_lossy(&body);
+            return parse_preview(url, &uri, &html).ok_or(format!("{uri} has no metadata"));
+        }
+        Err(format!("{url} redirects too many times"))
+    }
+
+    fn client(&self) -> awc::Client {
+        guarded_client(FETCH_TIMEOUT, self.allow_private)
+    }
+
+    fn check_uri(&self, uri: &Uri) -> Result<(), String> {
+        if self.allow_private {
+            return check_scheme(uri);
+        }
+        check_public_uri(uri)
+    }
+}
+
+/// A client resolving the host names through the guard, and following no
+/// redirect on its own so every hop is checked
+pub fn guarded_client(timeout: Duration, allow_private: bool) -> awc::Client {
+    let resolver = Resolver::custom(GuardedResolver { allow_private });
+    let connector = awc::Connector::new()
+        .connector(TcpConnector::new(resolver).service())
+        .timeout(timeout);
+    awc::Client::builder()
+        .connector(connector)
+        .disable_redirects()
+        .timeout(timeout)
+        .finish()
+}
+
+/// Refuses the other schemes than http and https, and the private addresses
+/// written as the host, which skip the resolver
+pub fn check_public_uri(uri: &Uri) -> Result<(), String> {
+    check_scheme(uri)?;
+    let host = uri.host().ok_or(format!("{uri} has no host"))?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    match host.parse::<IpAddr>() {
+        Ok(ip) if !is_public(ip) => Err(format!("{uri} points to a private address")),
+        _ => Ok(()),
+    }
+}
+
+fn check_scheme(uri: &Uri) -> Result<(), String> {
+    match uri.scheme_str() {
+        Some("http") | Some("https") => Ok(()),
+        _ => Err(format!("{uri} is not a web address")),
+    }
+}
+
+/// Resolves host names, dropping the private addresses so a name pointing to
+/// the internal network cannot be reached
+struct GuardedResolver {
+    allow_private: bool,
+}
+
+impl Resolve for GuardedResolver {
+    fn lookup<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+    ) -> LocalBoxFuture<'a, Result<Vec<SocketAddr>, Box<dyn StdError>>> {
+        let allow_private = self.allow_private;
+        let target = (host.to_string(), port);
+        Box::pin(async move {
+            let addrs = actix_web::rt::task::spawn_blocking(move || {
+                target.to_socket_addrs().map(|a| a.collect::<Vec<_>>())
+            })
+            .await??;
+            let addrs: Vec<SocketAddr> = addrs
+                .into_iter()
+                .filter(|a| allow_private || is_public(a.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{host} has no public address").into());
+            }
+            Ok(addrs)
+        })
+    }
+}
+
+/// Whether the address is reachable on the internet, as opposed to loopback,
+/// private, link-local, shared, documentation and other reserved ranges
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(v4) => is_public_v4(v4),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        || a == 0
+        // Shared address space of carrier-grade NATs
+        || (a == 100 && (64..128).contains(&b))
+        // IETF protocol assignments
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking
+        || (a == 198 && (18..20).contains(&b))
+        // Reserved for future use
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local addresses
+        || (first & 0xfe00) == 0xfc00
+        // Link-local unicast
+        || (first & 0xffc0) == 0xfe80
+        // Documentation
+        || (first == 0x2001 && ip.segments()[1] == 0x0db8)
+        // IPv4 translation, which may reach private IPv4 addresses
+        || (first == 0x64 && ip.segments()[1] == 0xff9b))
+}
+
+/// Resolves the `Location` of a redirect against the URL it came from
+fn redirect_target(from: &Uri, location: &str) -> Result<Uri, String> {
+    if location.starts_with('/') && !location.starts_with("//") {
+        let scheme = from.scheme_str().unwrap_or("http");
+        let authority = from.authority().map(|a| a.as_str()).unwrap_or_default();
+        return format!("{scheme}://{authority}{location}")
+            .parse()
+            .map_err(|_| format!("Invalid redirect to {location}"));
+    }
+    location
+        .parse()
+        .map_err(|_| format!("Invalid redirect to {location}"))
+}
+
+/// Reads the Open Graph metadata of the page, falling back to its title and
+/// description, or returns `None` when it has neither
+pub fn parse_preview(url: &str, page: &Uri, html: &str) -> Option<LinkPreview> {
+    static META: OnceLock<Regex> = OnceLock::new();
+    static ATTRIBUTE: OnceLock<Regex> = OnceLock::new();
+    static TITLE: OnceLock<Regex> = OnceLock::new();
+    let meta = META.get_or_init(|| Regex::new(r"(?is)<meta\s[^>]*>").unwrap());
+    let attribute = ATTRIBUTE
+        .get_or_init(|| Regex::new(r#"(?is)([a-z:_-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap());
+    let title = TITLE.get_or_init(|| Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap());
+
+    let mut properties: HashMap<String, String> = HashMap::new();
+    for tag in meta.find_iter(html) {
+        let mut name = None;
+        let mut content = None;
+        for a in attribute.captures_iter(tag.as_str()) {
+            let value = a.get(2).or(a.get(3)).map(|v| v.as_str().to_string());
+            match a[1].to_ascii_lowercase().as_str() {
+                "property" | "name" => name = value.map(|v| v.to_ascii_lowercase()),
+                "content" => content = value,
+                _ => {}
+            }
+        }
+        if let (Some(name), Some(content)) = (name, content) {
+            properties.entry(name).or_insert(content);
+        }
+    }
+    let property = |names: &[&str]| {
+        names
+            .iter()
+            .find_map(|n| properties.get(*n))
+            .map(|v| clean_text(v))
+            .filter(|v| !v.is_empty())
+    };
+    let preview = LinkPreview {
+        url: url.to_string(),
+        title: property(&["og:title", "twitter:title"]).or_else(|| {
+            title
+                .captures(html)
+                .map(|t| clean_text(&t[1]))
+                .filter(|t| !t.is_empty())
+        }),
+        description: property(&["og:description", "twitter:description", "description"]),
+        image: property(&["og:image", "twitter:image"])
+            .and_then(|i| redirect_target(page, &i).ok())
+            .filter(|i| matches!(i.scheme_str(), Some("http") | Some("https")))
+            .map(|i| i.to_string()),
+    };
+    if preview.title.is_none() && preview.description.is_none() {
+        return None;
+    }
+    Some(preview)
+}
+
+/// Decodes the common entities, collapses the whitespace and shortens the text
+fn clean_text(text: &str) -> String {
+    let decoded = text
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&");
+    let collapsed = decoded.split_whitespace().collect::<Vec<_>>().join(" ");
+    collapsed.chars().take(MAX_TEXT_LENGTH).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    const PAGE: &str = r#"<html><head>
+        <title>Ignored title</title>
+        <meta property="og:title" content="The House of Black &amp; White">
+        <meta content='A temple in Braavos' name="description">
+        <meta property="og:image" content="/faces.png">
+        </head><body>Valar morghulis</body></html>"#;
+
+    /// Answers the given responses, one per connection, on a local port
+    fn local_server(responses: Vec<String>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for response in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 2 {
+                    line.clear();
+                }
+                let mut writer = stream;
+                writer.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn parses_page_metadata() {
+        let page: Uri = "https://braavos.example/temple".parse().unwrap();
+        let preview = parse_preview("https://braavos.example/temple", &page, PAGE).unwrap();
+        assert_eq!(
+            preview.title,
+            Some("The House of Black & White".to_string())
+        );
+        assert_eq!(preview.description, Some("A temple in Braavos".to_string()));
+        assert_eq!(
+            preview.image,
+            Some("https://braavos.example/faces.png".to_string())
+        );
+        let blank = "<html><body>No metadata</body></html>";
+        assert!(parse_preview("https://braavos.example", &page, blank).is_none());
+    }
+
+    #[test]
+    fn tells_public_addresses_apart() {
+        for ip in [
+            "10.0.0.1",
+            "172.16.4.2",
+            "192.168.1.1",
+            "127.0.0.1",
+            "169.254.169.254",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip} is private");
+        }
+        for ip in ["::1", "fd00::1", "fe80::1", "::ffff:10.0.0.1"] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip} is private");
+        }
+        assert!(is_public("93.184.216.34".parse().unwrap()));
+        assert!(is_public("2606:2800:220:1::".parse().unwrap()));
+    }
+
+    #[actix_web::test]
+    async fn refuses_private_hosts() {
+        let port = local_server(vec![]);
+        let fetcher = Fetcher::default();
+        for url in [
+            format!("http://127.0.0.1:{port}/"),
+            format!("http://localhost:{port}/"),
+            "file:///etc/passwd".to_string(),
+        ] {
+            assert!(fetcher.fetch(&url).await.is_err(), "{url} is refused");
+        }
+    }
+
+    #[actix_web::test]
+    async fn fetches_previews_through_redirects() {
+        let html = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{PAGE}",
+            PAGE.len()
+        );
+        let redirect = "HTTP/1.1 302 Found\r\nLocation: /temple\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+        let port = local_server(vec![redirect.to_string(), html]);
+        let url = format!("http://127.0.0.1:{port}/");
+
+        let preview = Fetcher::allowing_private().fetch(&url).await.unwrap();
+
+        assert_eq!(preview.url, url);
+        assert_eq!(
+            preview.title,
+            Some("The House of Black & White".to_string())
+        );
+        assert_eq!(
+            preview.image,
+            Some(format!("http://127.0.0.1:{port}/faces.png"))
+        );
+    }
+}
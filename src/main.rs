@@ -1,23 +1,67 @@
-mod adapters;
-mod api;
-pub mod commands;
-mod models;
-mod services;
-mod websocket;
-
+use actix::Actor;
 use actix_web::{web, App, HttpServer};
-
-pub struct AppState {
-    db: mongodb::Database,
-}
+use messaging::{
+    adapters, api, bots, events, link_previews, moderation, notifications, purger, rate_limit,
+    scheduler, services, sweeper, webhooks, websocket, AppState,
+};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let db = adapters::mongo::database::init("chatapp").await;
+    let db = adapters::mongo::database::init(messaging::DATABASE).await;
+    adapters::mongo::database::create_indexes(&db)
+        .await
+        .map_err(std::io::Error::other)?;
+    moderation::promote_moderators(&db).await;
+    let limits = rate_limit::RateLimits::from_env().map_err(std::io::Error::other)?;
+    let limiter = rate_limit::RateLimiter::new(limits);
+    // The content filter masks profanity and flags or rejects what its rules match
+    let pipeline = services::Pipeline::standard();
+    pipeline.register(moderation::filter_from_env().map_err(std::io::Error::other)?);
+    let events = events::EventBus::default();
+    let chat_server = websocket::ChatServer::default().start();
+    events.subscribe::<events::MessageSent>(chat_server.clone().recipient());
+    events.subscribe::<events::MessageUpdated>(chat_server.clone().recipient());
+    let link_previewer = link_previews::LinkPreviewer::new(db.clone(), events.clone()).start();
+    events.subscribe::<events::MessageSent>(link_previewer.recipient());
+    scheduler::MessageScheduler::new(db.clone(), events.clone(), pipeline.clone()).start();
+    sweeper::MessageSweeper::new(db.clone()).start();
+    purger::DeletionPurger::new(db.clone(), events.clone()).start();
+    let webhook_dispatcher = webhooks::WebhookDispatcher::new(db.clone()).start();
+    events.subscribe::<events::MessageSent>(webhook_dispatcher.clone().recipient());
+    events.subscribe::<events::ChannelCreated>(webhook_dispatcher.clone().recipient());
+    events.subscribe::<events::ChannelUpdated>(webhook_dispatcher.recipient());
+    let notifier = notifications::notifier_from_env().map_err(std::io::Error::other)?;
+    let notification_dispatcher =
+        notifications::NotificationDispatcher::new(db.clone(), chat_server.clone(), notifier)
+            .start();
+    events.subscribe::<events::MessageSent>(notification_dispatcher.recipient());
+    // Commands without an in-process handler go to the bots registered for them
+    let commands = services::CommandRegistry::default();
+    let command_dispatcher =
+        bots::CommandDispatcher::new(db.clone(), events.clone(), pipeline.clone()).start();
+    events.subscribe::<events::SlashCommandInvoked>(command_dispatcher.recipient());
+    let moderation_queue = moderation::ModerationQueue::new(db.clone(), events.clone()).start();
+    events.subscribe::<events::MessageFlagged>(moderation_queue.recipient());
     HttpServer::new(move || {
         App::new()
-            .app_data(web::Data::new(AppState { db: db.to_owned() }))
+            .app_data(web::Data::new(AppState {
+                db: db.to_owned(),
+                chat_server: chat_server.clone(),
+                events: events.clone(),
+                commands: commands.clone(),
+                pipeline: pipeline.clone(),
+                limiter: limiter.clone(),
+            }))
+            .wrap(rate_limit::LimitRequests::new(limiter.clone()))
             .service(api::contacts::get_scope())
+            .service(api::channels::get_scope())
+            .service(api::scheduled_messages::get_scope())
+            .service(api::webhooks::get_scope())
+            .service(api::incoming_webhooks::get_scope())
+            .service(api::incoming_webhooks::get_hooks_scope())
+            .service(api::bots::get_scope())
+            .service(api::moderation::get_scope())
+            .service(api::admin::get_scope())
             .route("/ws/", web::get().to(websocket::index))
     })
     .bind(("127.0.0.1", 8080))?
@@ -1,10 +1,17 @@
 use crate::adapters::{IdType, Model};
-use chrono::serde::ts_seconds;
+use crate::models::ChannelPreferences;
+use chrono::serde::{ts_seconds, ts_seconds_option};
 use chrono::{DateTime, Utc};
 use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 
+/// The maximum number of messages that can be pinned in a channel
+pub const MAX_PINNED_MESSAGES: usize = 50;
+
+/// The longest lifetime of the messages of a channel, in seconds
+pub const MAX_MESSAGE_TTL: i64 = 365 * 24 * 60 * 60;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Channel {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
@@ -12,6 +19,32 @@ pub struct Channel {
     pub name: Option<String>,
     pub channel_type: ChannelType,
     pub contact_ids: Vec<IdType>,
+    /// The members that administer the channel, starting with its creator
+    #[serde(default)]
+    pub admin_ids: Vec<IdType>,
+    /// The ids of the pinned messages, oldest pin first
+    #[serde(default)]
+    pub pinned_message_ids: Vec<IdType>,
+    /// How long the messages last before they disappear, in seconds
+    #[serde(default)]
+    pub message_ttl: Option<i64>,
+    /// The notification settings of the members that changed the defaults
+    #[serde(default)]
+    pub preferences: Vec<ChannelPreferences>,
+    /// When the channel was archived, which makes it read-only
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "ts_seconds_option"
+    )]
+    pub archived_at: Option<DateTime<Utc>>,
+    /// When the channel was deleted, until it is purged
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "ts_seconds_option"
+    )]
+    pub deleted_at: Option<DateTime<Utc>>,
     #[serde(with = "ts_seconds")]
     pub created_at: DateTime<Utc>,
     #[serde(with = "ts_seconds")]
@@ -22,19 +55,66 @@ impl Model for Channel {
     fn id(&self) -> IdType {
         IdType::ObjectId(self.id.unwrap())
     }
+
+    fn deleted_at(&self) -> Option<DateTime<Utc>> {
+        self.deleted_at
+    }
 }
 
 impl Channel {
-    pub fn new(name: &str, channel_type: ChannelType, contact_ids: &Vec<IdType>) -> Self {
+    pub fn new(name: &str, channel_type: ChannelType, contact_ids: &[IdType]) -> Self {
         Channel {
             id: Some(ObjectId::new()),
             name: Some(name.to_string()),
             channel_type,
             contact_ids: contact_ids.to_owned(),
+            admin_ids: vec![],
+            pinned_message_ids: vec![],
+            message_ttl: None,
+            preferences: vec![],
+            archived_at: None,
+            deleted_at: None,
             created_at: Utc::now(),
             updated_at: Utc::now(),
         }
     }
+
+    /// Whether the contact is a member that administers the channel
+    pub fn is_admin(&self, contact_id: &IdType) -> bool {
+        self.admin_ids.contains(contact_id) && self.contact_ids.contains(contact_id)
+    }
+
+    pub fn is_archived(&self) -> bool {
+        self.archived_at.is_some()
+    }
+
+    /// Returns the channel as shown to the member, with only their own
+    /// notification settings
+    pub fn for_member(&self, contact_id: &IdType) -> Channel {
+        let mut channel = self.clone();
+        channel.preferences.retain(|p| &p.contact_id == contact_id);
+        channel
+    }
+
+    /// Returns the channel as shown outside of it, without the notification
+    /// settings of its members
+    pub fn without_preferences(&self) -> Channel {
+        let mut channel = self.clone();
+        channel.preferences.clear();
+        channel
+    }
+
+    /// Returns the notification settings of the member, the defaults if unset
+    pub fn preferences_of(&self, contact_id: &IdType) -> ChannelPreferences {
+        match self
+            .preferences
+            .iter()
+            .find(|p| &p.contact_id == contact_id)
+        {
+            Some(p) => p.clone(),
+            None => ChannelPreferences::new(contact_id),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
@@ -1,5 +1,7 @@
 use crate::adapters::{IdType, Model};
-use chrono::serde::ts_seconds;
+use crate::models::token::{generate_token, hash_token};
+use crate::models::{DeletionPolicy, DoNotDisturb};
+use chrono::serde::{ts_seconds, ts_seconds_option};
 use chrono::{DateTime, Utc};
 
 use mongodb::bson::oid::ObjectId;
@@ -11,6 +13,32 @@ pub struct Contact {
     pub(crate) id: Option<ObjectId>,
     pub name: String,
     pub email: String,
+    #[serde(default)]
+    pub kind: ContactKind,
+    /// The SHA-256 of the API key bots authenticate with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub do_not_disturb: Option<DoNotDisturb>,
+    /// The contacts this contact blocked, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blocked: Vec<BlockedContact>,
+    /// Whether the contact works the moderation queue
+    #[serde(default)]
+    pub moderator: bool,
+    /// Whether a moderator banned the contact from sending messages
+    #[serde(default)]
+    pub banned: bool,
+    /// When the contact was deleted, until it is purged
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "ts_seconds_option"
+    )]
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// What the purge does with the data referencing the deleted contact
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deletion_policy: Option<DeletionPolicy>,
     #[serde(with = "ts_seconds")]
     pub created_at: DateTime<Utc>,
     #[serde(with = "ts_seconds")]
@@ -21,6 +49,10 @@ impl Model for Contact {
     fn id(&self) -> IdType {
         IdType::ObjectId(self.id.unwrap())
     }
+
+    fn deleted_at(&self) -> Option<DateTime<Utc>> {
+        self.deleted_at
+    }
 }
 
 impl Contact {
@@ -29,8 +61,62 @@ impl Contact {
             id: Some(ObjectId::new()),
             name: name.to_string(),
             email: email.to_string(),
+            kind: ContactKind::Person,
+            api_key_hash: None,
+            do_not_disturb: None,
+            blocked: vec![],
+            moderator: false,
+            banned: false,
+            deleted_at: None,
+            deletion_policy: None,
             created_at: Utc::now(),
             updated_at: Utc::now(),
         }
     }
+
+    /// Creates a bot contact and returns it with its API key
+    pub fn bot(name: &str, email: &str) -> (Self, String) {
+        let mut bot = Contact::new(name, email);
+        bot.kind = ContactKind::Bot;
+        let api_key = bot.issue_api_key();
+        (bot, api_key)
+    }
+
+    /// Replaces the API key and returns the new one, invalidating the previous key
+    pub fn issue_api_key(&mut self) -> String {
+        let api_key = generate_token();
+        self.api_key_hash = Some(hash_token(&api_key));
+        api_key
+    }
+
+    pub fn has_blocked(&self, contact_id: &IdType) -> bool {
+        self.blocked.iter().any(|b| &b.contact_id == contact_id)
+    }
+
+    /// Whether the messages of this contact are hidden from the given one
+    pub fn hides_messages_from(&self, contact_id: &IdType) -> bool {
+        self.blocked
+            .iter()
+            .any(|b| &b.contact_id == contact_id && b.hide_messages)
+    }
+}
+
+/// A contact blocked from sending direct messages
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BlockedContact {
+    pub contact_id: IdType,
+    /// Whether the blocked contact also stops seeing the messages of the
+    /// blocker in the groups they share
+    pub hide_messages: bool,
+    #[serde(with = "ts_seconds")]
+    pub blocked_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContactKind {
+    #[default]
+    Person,
+    /// An integration acting through the API with a key
+    Bot,
 }
@@ -0,0 +1,61 @@
+use crate::adapters::IdType;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// How many days deleted contacts and channels can be restored before they are purged
+pub const DELETION_GRACE_DAYS: i64 = 30;
+
+/// Returns when the deletions that can still be restored started
+pub fn grace_period_start() -> DateTime<Utc> {
+    Utc::now() - Duration::days(DELETION_GRACE_DAYS)
+}
+
+/// The id the references to deleted contacts are rewritten to
+const DELETED_CONTACT_ID: &str = "deleted-contact";
+
+/// Returns the placeholder that stands for any deleted contact
+pub fn deleted_contact_id() -> IdType {
+    IdType::String(DELETED_CONTACT_ID.to_string())
+}
+
+/// What happens to the data referencing a contact when it is deleted
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeletionPolicy {
+    #[serde(default)]
+    pub messages: MessagePolicy,
+    #[serde(default)]
+    pub groups: GroupPolicy,
+    #[serde(default)]
+    pub private_channels: PrivateChannelPolicy,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessagePolicy {
+    /// Rewrites the sender, recipient and mentions of the contact's messages
+    /// to the placeholder
+    #[default]
+    Anonymize,
+    /// Leaves the messages as sent, rendered as from the placeholder
+    Keep,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupPolicy {
+    /// Removes the contact from the members of its groups
+    #[default]
+    Remove,
+    Keep,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrivateChannelPolicy {
+    /// Keeps the channel and its history for the other member, with the
+    /// placeholder in place of the contact
+    #[default]
+    Orphan,
+    /// Deletes the channel and its messages
+    Delete,
+}
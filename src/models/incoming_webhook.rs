@@ -0,0 +1,51 @@
+use crate::adapters::{IdType, Model};
+use crate::models::token::{generate_token, hash_token};
+use chrono::serde::ts_seconds;
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+/// A secret URL that posts the payloads it receives into a channel
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IncomingWebhook {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub channel_id: IdType,
+    /// The name the posted messages are shown with
+    pub name: String,
+    /// The SHA-256 of the token, which is only shown when it is issued
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_hash: Option<String>,
+    pub created_by: IdType,
+    #[serde(with = "ts_seconds")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "ts_seconds")]
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Model for IncomingWebhook {
+    fn id(&self) -> IdType {
+        IdType::ObjectId(self.id.unwrap())
+    }
+}
+
+impl IncomingWebhook {
+    pub fn new(channel_id: &IdType, name: &str, created_by: &IdType) -> Self {
+        IncomingWebhook {
+            id: Some(ObjectId::new()),
+            channel_id: channel_id.clone(),
+            name: name.to_string(),
+            token_hash: None,
+            created_by: created_by.clone(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    /// Replaces the token and returns the new one, invalidating the previous URL
+    pub fn issue_token(&mut self) -> String {
+        let token = generate_token();
+        self.token_hash = Some(hash_token(&token));
+        token
+    }
+}
@@ -0,0 +1,14 @@
+use serde::{Deserialize, Serialize};
+
+/// The metadata of a page linked in a message
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LinkPreview {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// The URL of the image the page shares itself with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+}
@@ -1,4 +1,6 @@
 use crate::adapters::{IdType, Model};
+use crate::models::message_content::deserialize_content;
+use crate::models::{LinkPreview, MessageContent, SystemEvent};
 use chrono::serde::ts_seconds;
 use chrono::{DateTime, Utc};
 use mongodb::bson::oid::ObjectId;
@@ -10,11 +12,37 @@ pub struct Message {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
     pub channel_id: IdType,
-    /// The id of the contact that sent the message
+    /// The position of the message in the channel history
+    #[serde(default)]
+    pub seq: i64,
+    /// The id of the contact that sent the message, or of the integration for bot messages
     pub from: IdType,
     /// The id of the contact that received the message
     pub to: IdType,
-    pub content: String,
+    #[serde(deserialize_with = "deserialize_content")]
+    pub content: MessageContent,
+    /// The ids of the channel members mentioned in the content
+    #[serde(default)]
+    pub mentions: Vec<IdType>,
+    /// The URLs found in the content
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<String>,
+    /// The metadata of the linked pages, attached once fetched
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub previews: Vec<LinkPreview>,
+    /// The idempotency key the sender attached to the message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_message_id: Option<String>,
+    /// When the message disappears, for channels with a message lifetime
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "bson_datetime_option"
+    )]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// The display name of the integration that posted the message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bot_name: Option<String>,
     #[serde(with = "ts_seconds")]
     pub created_at: DateTime<Utc>,
     #[serde(with = "ts_seconds")]
@@ -25,20 +53,82 @@ impl Model for Message {
     fn id(&self) -> IdType {
         IdType::ObjectId(self.id.unwrap())
     }
+
+    fn expires_at(&self) -> Option<DateTime<Utc>> {
+        self.expires_at
+    }
 }
 
 impl Message {
-    pub fn new(channel_id: &IdType, from: &IdType, to: &IdType, content: &str) -> Self {
+    pub fn new(channel_id: &IdType, from: &IdType, to: &IdType, content: &MessageContent) -> Self {
         Message {
             id: Some(ObjectId::new()),
             channel_id: channel_id.clone(),
+            seq: 0,
             from: from.clone(),
             to: to.clone(),
-            content: content.to_string(),
+            content: content.clone(),
+            mentions: vec![],
+            links: vec![],
+            previews: vec![],
+            client_message_id: None,
+            expires_at: None,
+            bot_name: None,
             created_at: Utc::now(),
             updated_at: Utc::now(),
         }
     }
+
+    /// Creates a system message triggered by the given contact.
+    /// System messages are addressed to the channel itself.
+    pub fn system(channel_id: &IdType, triggered_by: &IdType, event: SystemEvent) -> Self {
+        Message::new(
+            channel_id,
+            triggered_by,
+            channel_id,
+            &MessageContent::System { event },
+        )
+    }
+
+    /// Creates a message posted by an integration rather than a contact.
+    /// Bot messages are addressed to the channel itself.
+    pub fn bot(
+        channel_id: &IdType,
+        bot_id: &IdType,
+        bot_name: &str,
+        content: &MessageContent,
+    ) -> Self {
+        let mut message = Message::new(channel_id, bot_id, channel_id, content);
+        message.bot_name = Some(bot_name.to_string());
+        message
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// Stores the date as a BSON date, which TTL indexes require, instead of a timestamp
+mod bson_datetime_option {
+    use chrono::{DateTime, TimeZone, Utc};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S>(value: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        value
+            .map(|v| mongodb::bson::DateTime::from_millis(v.timestamp_millis()))
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = Option::<mongodb::bson::DateTime>::deserialize(deserializer)?;
+        Ok(value.and_then(|v| Utc.timestamp_millis_opt(v.timestamp_millis()).single()))
+    }
 }
 
 pub trait DateUtils {
@@ -56,6 +146,7 @@ impl DateUtils for DateTime<Utc> {
         self.timestamp()
     }
 
+    #[allow(deprecated)]
     fn from_timestamp(timestamp: i64) -> DateTime<Utc> {
         DateTime::<Utc>::from_utc(chrono::NaiveDateTime::from_timestamp(timestamp, 0), Utc)
     }
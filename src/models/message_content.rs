@@ -0,0 +1,120 @@
+use crate::adapters::IdType;
+use serde::{Deserialize, Deserializer, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MessageContent {
+    Text {
+        text: String,
+    },
+    Markdown {
+        text: String,
+    },
+    /// A reference to a file stored outside of the message
+    Attachment {
+        url: String,
+        name: Option<String>,
+        mime_type: Option<String>,
+    },
+    /// An event emitted by the server into the channel history
+    System {
+        event: SystemEvent,
+    },
+}
+
+impl MessageContent {
+    pub fn text(text: &str) -> Self {
+        MessageContent::Text {
+            text: text.to_string(),
+        }
+    }
+
+    /// Returns the text written by the sender, if the content has any
+    pub fn body(&self) -> Option<&str> {
+        match self {
+            MessageContent::Text { text } | MessageContent::Markdown { text } => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Returns the same content with the text of the sender replaced
+    pub fn with_body(&self, body: &str) -> Self {
+        match self {
+            MessageContent::Text { .. } => MessageContent::text(body),
+            MessageContent::Markdown { .. } => MessageContent::Markdown {
+                text: body.to_string(),
+            },
+            other => other.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SystemEvent {
+    ChannelCreated { name: Option<String> },
+    MemberJoined { contact_id: IdType },
+    MemberLeft { contact_id: IdType },
+    ChannelRenamed { name: String },
+    MessagePinned { message_id: IdType },
+    MessageUnpinned { message_id: IdType },
+    MessageTtlChanged { message_ttl: Option<i64> },
+    ChannelArchived,
+    ChannelUnarchived,
+}
+
+/// Deserializes typed content, falling back to plain text for documents
+/// stored before the content was typed
+pub fn deserialize_content<'de, D>(deserializer: D) -> Result<MessageContent, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StoredContent {
+        Plain(String),
+        Typed(MessageContent),
+    }
+
+    Ok(match StoredContent::deserialize(deserializer)? {
+        StoredContent::Plain(text) => MessageContent::Text { text },
+        StoredContent::Typed(content) => content,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::{Message, MessageContent};
+    use mongodb::bson::{doc, from_document, oid::ObjectId};
+
+    #[test]
+    fn deserializes_plain_string_content() {
+        let channel_id = ObjectId::new();
+        let contact_id = ObjectId::new();
+        let document = doc! {
+            "_id": ObjectId::new(),
+            "channel_id": { "ObjectId": channel_id },
+            "from": { "ObjectId": contact_id },
+            "to": { "ObjectId": contact_id },
+            "content": "Winter is coming",
+            "created_at": 1678000000_i64,
+            "updated_at": 1678000000_i64,
+        };
+        let message: Message = from_document(document).unwrap();
+        assert_eq!(message.content, MessageContent::text("Winter is coming"));
+        assert!(message.mentions.is_empty());
+    }
+
+    #[test]
+    fn round_trips_typed_content() {
+        let content = MessageContent::Attachment {
+            url: "https://winterfell.com/map.png".to_string(),
+            name: Some("map.png".to_string()),
+            mime_type: None,
+        };
+        let json = serde_json::to_value(&content).unwrap();
+        assert_eq!(json["type"], "attachment");
+        let parsed: MessageContent = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, content);
+    }
+}
@@ -1,7 +1,32 @@
 mod channel;
 mod contact;
+mod deletion_policy;
+mod incoming_webhook;
+mod link_preview;
 mod message;
+mod message_content;
+mod notification;
+mod notification_preferences;
+mod report;
+mod scheduled_message;
+mod slash_command;
+mod token;
+mod webhook;
 
-pub use channel::{Channel, ChannelType};
-pub use contact::Contact;
-pub use message::Message;
+pub use channel::{Channel, ChannelType, MAX_MESSAGE_TTL, MAX_PINNED_MESSAGES};
+pub use contact::{BlockedContact, Contact, ContactKind};
+pub use deletion_policy::{
+    deleted_contact_id, grace_period_start, DeletionPolicy, GroupPolicy, MessagePolicy,
+    PrivateChannelPolicy, DELETION_GRACE_DAYS,
+};
+pub use incoming_webhook::IncomingWebhook;
+pub use link_preview::LinkPreview;
+pub use message::{DateUtils, Message};
+pub use message_content::{MessageContent, SystemEvent};
+pub use notification::{Notification, NotificationStatus};
+pub use notification_preferences::{ChannelPreferences, DoNotDisturb, NotificationLevel};
+pub use report::{ModerationAction, ModerationActionType, Report, ReportStatus};
+pub use scheduled_message::{ScheduledMessage, ScheduledMessageStatus};
+pub use slash_command::SlashCommand;
+pub use token::hash_token;
+pub use webhook::{Webhook, WebhookDelivery, WebhookDeliveryStatus, WebhookEventType};
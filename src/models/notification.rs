@@ -0,0 +1,74 @@
+use crate::adapters::{IdType, Model};
+use chrono::serde::{ts_seconds, ts_seconds_option};
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+/// A message waiting to be told to a contact that was offline when it arrived
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Notification {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub contact_id: IdType,
+    pub channel_id: IdType,
+    /// The name of the channel, `None` for direct messages
+    pub channel_name: Option<String>,
+    pub message_id: IdType,
+    pub sender_name: String,
+    /// The start of the message text, or a description of its content
+    pub preview: String,
+    pub status: NotificationStatus,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    /// When a failed notification can be retried, `None` before any failure
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "ts_seconds_option"
+    )]
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    #[serde(with = "ts_seconds")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "ts_seconds")]
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Model for Notification {
+    fn id(&self) -> IdType {
+        IdType::ObjectId(self.id.unwrap())
+    }
+}
+
+impl Notification {
+    pub fn new(
+        contact_id: &IdType,
+        channel_id: &IdType,
+        channel_name: Option<String>,
+        message_id: &IdType,
+        sender_name: &str,
+        preview: &str,
+    ) -> Self {
+        Notification {
+            id: Some(ObjectId::new()),
+            contact_id: contact_id.clone(),
+            channel_id: channel_id.clone(),
+            channel_name,
+            message_id: message_id.clone(),
+            sender_name: sender_name.to_string(),
+            preview: preview.to_string(),
+            status: NotificationStatus::Pending,
+            attempts: 0,
+            last_error: None,
+            next_attempt_at: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum NotificationStatus {
+    Pending,
+    Sent,
+    Failed,
+}
@@ -0,0 +1,111 @@
+use crate::adapters::IdType;
+use crate::models::Message;
+use chrono::serde::ts_seconds_option;
+use chrono::{DateTime, Duration, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Which messages of a channel a member is notified of
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationLevel {
+    #[default]
+    All,
+    /// Only the messages mentioning the member
+    Mentions,
+    #[serde(rename = "none")]
+    Nothing,
+}
+
+/// The notification settings of one member of a channel
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChannelPreferences {
+    pub contact_id: IdType,
+    pub level: NotificationLevel,
+    pub muted: bool,
+    /// When a mute ends, `None` for channels muted until unmuted
+    #[serde(default, with = "ts_seconds_option")]
+    pub muted_until: Option<DateTime<Utc>>,
+}
+
+impl ChannelPreferences {
+    pub fn new(contact_id: &IdType) -> Self {
+        ChannelPreferences {
+            contact_id: contact_id.clone(),
+            level: NotificationLevel::All,
+            muted: false,
+            muted_until: None,
+        }
+    }
+
+    pub fn is_muted(&self, now: DateTime<Utc>) -> bool {
+        self.muted && self.muted_until.is_none_or(|until| now < until)
+    }
+
+    /// Whether the member is notified of the message at the given time
+    pub fn notifies(&self, message: &Message, now: DateTime<Utc>) -> bool {
+        if self.is_muted(now) {
+            return false;
+        }
+        match self.level {
+            NotificationLevel::All => true,
+            NotificationLevel::Mentions => message.mentions.contains(&self.contact_id),
+            NotificationLevel::Nothing => false,
+        }
+    }
+}
+
+/// A daily period without notifications, in the local time of the contact.
+/// Periods ending before they start span midnight, like 22:00 to 07:00.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DoNotDisturb {
+    /// The start of the period, in minutes after midnight
+    pub start_minute: u32,
+    /// The end of the period, in minutes after midnight
+    pub end_minute: u32,
+    /// The offset of the local time of the contact from UTC
+    pub utc_offset_minutes: i32,
+}
+
+impl DoNotDisturb {
+    /// Whether the contact is not to be disturbed at the given time
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        let local = now + Duration::minutes(self.utc_offset_minutes as i64);
+        let minute = local.hour() * 60 + local.minute();
+        if self.start_minute <= self.end_minute {
+            self.start_minute <= minute && minute < self.end_minute
+        } else {
+            minute >= self.start_minute || minute < self.end_minute
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn do_not_disturb_spans_midnight() {
+        let night = DoNotDisturb {
+            start_minute: 22 * 60,
+            end_minute: 7 * 60,
+            utc_offset_minutes: 60,
+        };
+        let at = |hour, minute| Utc.with_ymd_and_hms(2024, 1, 1, hour, minute, 0).unwrap();
+        assert!(night.is_active(at(21, 0)), "22:00 local time");
+        assert!(night.is_active(at(5, 59)));
+        assert!(!night.is_active(at(6, 0)), "07:00 local time");
+        assert!(!night.is_active(at(12, 0)));
+    }
+
+    #[test]
+    fn mutes_until_the_given_time() {
+        let now = Utc::now();
+        let mut preferences = ChannelPreferences::new(&IdType::String("jon".to_string()));
+        preferences.muted = true;
+        assert!(preferences.is_muted(now));
+        preferences.muted_until = Some(now + Duration::hours(1));
+        assert!(preferences.is_muted(now));
+        assert!(!preferences.is_muted(now + Duration::hours(1)));
+    }
+}
@@ -0,0 +1,111 @@
+use crate::adapters::{IdType, Model};
+use chrono::serde::ts_seconds;
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+/// A message waiting in the moderation queue, reported by a member or
+/// flagged by the content filter
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Report {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub message_id: IdType,
+    pub channel_id: IdType,
+    /// The sender of the reported message
+    pub author_id: IdType,
+    /// The member that reported the message, `None` for flagged messages
+    pub reported_by: Option<IdType>,
+    pub reason: String,
+    pub status: ReportStatus,
+    #[serde(with = "ts_seconds")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "ts_seconds")]
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Model for Report {
+    fn id(&self) -> IdType {
+        IdType::ObjectId(self.id.unwrap())
+    }
+}
+
+impl Report {
+    pub fn new(
+        message_id: &IdType,
+        channel_id: &IdType,
+        author_id: &IdType,
+        reported_by: Option<IdType>,
+        reason: &str,
+    ) -> Self {
+        Report {
+            id: Some(ObjectId::new()),
+            message_id: message_id.clone(),
+            channel_id: channel_id.clone(),
+            author_id: author_id.clone(),
+            reported_by,
+            reason: reason.to_string(),
+            status: ReportStatus::Open,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportStatus {
+    Open,
+    Resolved,
+    Dismissed,
+}
+
+/// A decision a moderator took on a report, kept as the audit log
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ModerationAction {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub report_id: IdType,
+    pub moderator_id: IdType,
+    pub action: ModerationActionType,
+    pub note: Option<String>,
+    #[serde(with = "ts_seconds")]
+    pub created_at: DateTime<Utc>,
+}
+
+impl Model for ModerationAction {
+    fn id(&self) -> IdType {
+        IdType::ObjectId(self.id.unwrap())
+    }
+}
+
+impl ModerationAction {
+    pub fn new(
+        report_id: &IdType,
+        moderator_id: &IdType,
+        action: &ModerationActionType,
+        note: Option<String>,
+    ) -> Self {
+        ModerationAction {
+            id: Some(ObjectId::new()),
+            report_id: report_id.clone(),
+            moderator_id: moderator_id.clone(),
+            action: action.clone(),
+            note,
+            created_at: Utc::now(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationActionType {
+    /// Closes the report without further action
+    Resolve,
+    /// Closes the report as unfounded
+    Dismiss,
+    /// Deletes the reported message and closes the report
+    DeleteMessage,
+    /// Bans the author of the reported message from sending and closes the report
+    BanAuthor,
+}
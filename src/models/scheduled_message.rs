@@ -0,0 +1,74 @@
+use crate::adapters::{IdType, Model};
+use crate::commands::SendMessage;
+use crate::models::MessageContent;
+use chrono::serde::ts_seconds;
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+/// A message waiting to be sent at a later time
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScheduledMessage {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub channel_id: Option<IdType>,
+    pub from: IdType,
+    pub to: IdType,
+    pub content: MessageContent,
+    #[serde(with = "ts_seconds")]
+    pub deliver_at: DateTime<Utc>,
+    pub status: ScheduledMessageStatus,
+    /// The reason of the last failed delivery
+    pub error: Option<String>,
+    #[serde(with = "ts_seconds")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "ts_seconds")]
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Model for ScheduledMessage {
+    fn id(&self) -> IdType {
+        IdType::ObjectId(self.id.unwrap())
+    }
+}
+
+impl ScheduledMessage {
+    /// The client idempotency key is not kept, see [`ScheduledMessage::to_command`]
+    pub fn new(cmd: &SendMessage, deliver_at: DateTime<Utc>) -> Self {
+        ScheduledMessage {
+            id: Some(ObjectId::new()),
+            channel_id: cmd.channel_id.clone(),
+            from: cmd.from.clone(),
+            to: cmd.to.clone(),
+            content: cmd.content.clone(),
+            deliver_at,
+            status: ScheduledMessageStatus::Pending,
+            error: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    /// Returns the command that sends the message.
+    /// The scheduled message id is the idempotency key, so a delivery retried
+    /// after a crash does not send the message twice.
+    pub fn to_command(&self) -> SendMessage {
+        SendMessage {
+            channel_id: self.channel_id.clone(),
+            from: self.from.clone(),
+            to: self.to.clone(),
+            content: self.content.clone(),
+            client_message_id: Some(format!("scheduled:{}", self.id())),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ScheduledMessageStatus {
+    Pending,
+    /// Claimed by a delivery run, which is sending it
+    Delivering,
+    Delivered,
+    Cancelled,
+    Failed,
+}
@@ -0,0 +1,50 @@
+use crate::adapters::{IdType, Model};
+use chrono::serde::ts_seconds;
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// A `/name` command a bot answers through an HTTP callback
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SlashCommand {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    /// The command name, without the leading slash
+    pub name: String,
+    pub bot_id: IdType,
+    pub callback_url: String,
+    /// The key of the HMAC signature sent with every callback
+    pub secret: String,
+    pub description: Option<String>,
+    #[serde(with = "ts_seconds")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "ts_seconds")]
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Model for SlashCommand {
+    fn id(&self) -> IdType {
+        IdType::ObjectId(self.id.unwrap())
+    }
+}
+
+impl SlashCommand {
+    pub fn new(
+        name: &str,
+        bot_id: &IdType,
+        callback_url: &str,
+        description: Option<String>,
+    ) -> Self {
+        SlashCommand {
+            id: Some(ObjectId::new()),
+            name: name.to_string(),
+            bot_id: bot_id.clone(),
+            callback_url: callback_url.to_string(),
+            secret: Uuid::new_v4().simple().to_string(),
+            description,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+}
@@ -0,0 +1,12 @@
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Generates a secret token for the integrations to authenticate with
+pub fn generate_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/// Returns the SHA-256 of the token, which is what gets stored
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
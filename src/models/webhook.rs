@@ -0,0 +1,108 @@
+use crate::adapters::{IdType, Model};
+use chrono::serde::ts_seconds;
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// An HTTP endpoint notified of the message and channel events
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Webhook {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub url: String,
+    /// The key of the HMAC signature sent with every delivery
+    pub secret: String,
+    /// Limits the webhook to the events of one channel
+    pub channel_id: Option<IdType>,
+    /// The event types sent to the webhook, every type when empty
+    pub events: Vec<WebhookEventType>,
+    #[serde(with = "ts_seconds")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "ts_seconds")]
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Model for Webhook {
+    fn id(&self) -> IdType {
+        IdType::ObjectId(self.id.unwrap())
+    }
+}
+
+impl Webhook {
+    pub fn new(url: &str, channel_id: Option<IdType>, events: &[WebhookEventType]) -> Self {
+        Webhook {
+            id: Some(ObjectId::new()),
+            url: url.to_string(),
+            secret: Uuid::new_v4().simple().to_string(),
+            channel_id,
+            events: events.to_vec(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    /// Whether the webhook is notified of the event in the given channel
+    pub fn accepts(&self, event: &WebhookEventType, channel_id: &IdType) -> bool {
+        let in_scope = self.channel_id.as_ref().is_none_or(|id| id == channel_id);
+        in_scope && (self.events.is_empty() || self.events.contains(event))
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEventType {
+    MessageSent,
+    ChannelCreated,
+    ChannelUpdated,
+}
+
+/// One event sent to one webhook, with the outcome of the attempts so far
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WebhookDelivery {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub webhook_id: IdType,
+    pub event: WebhookEventType,
+    /// The signed request body, kept as sent so retries carry the same signature
+    pub payload: String,
+    pub status: WebhookDeliveryStatus,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    #[serde(with = "ts_seconds")]
+    pub next_attempt_at: DateTime<Utc>,
+    #[serde(with = "ts_seconds")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "ts_seconds")]
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Model for WebhookDelivery {
+    fn id(&self) -> IdType {
+        IdType::ObjectId(self.id.unwrap())
+    }
+}
+
+impl WebhookDelivery {
+    pub fn new(webhook: &Webhook, event: &WebhookEventType, payload: &str) -> Self {
+        WebhookDelivery {
+            id: Some(ObjectId::new()),
+            webhook_id: webhook.id(),
+            event: event.clone(),
+            payload: payload.to_string(),
+            status: WebhookDeliveryStatus::Pending,
+            attempts: 0,
+            last_error: None,
+            next_attempt_at: Utc::now(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum WebhookDeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
@@ -0,0 +1,81 @@
+use actix::{Actor, Context, ContextFutureSpawner, Handler, WrapFuture};
+use chrono::Utc;
+
+use crate::adapters::contact_repository::ContactRepository;
+use crate::adapters::mongo::repository::MongoRepository;
+use crate::adapters::Repository;
+use crate::api::Repositories;
+use crate::events::{EventBus, MessageFlagged};
+use crate::models::Contact;
+use crate::services::{ContentFilter, FilterRule};
+
+/// Queues a report for each message the content filter flagged
+pub struct ModerationQueue {
+    db: mongodb::Database,
+    events: EventBus,
+}
+
+impl ModerationQueue {
+    pub fn new(db: mongodb::Database, events: EventBus) -> Self {
+        ModerationQueue { db, events }
+    }
+}
+
+impl Actor for ModerationQueue {
+    type Context = Context<Self>;
+}
+
+impl Handler<MessageFlagged> for ModerationQueue {
+    type Result = ();
+
+    fn handle(&mut self, msg: MessageFlagged, ctx: &mut Self::Context) -> Self::Result {
+        flag_message(self.db.clone(), self.events.clone(), msg)
+            .into_actor(self)
+            .spawn(ctx);
+    }
+}
+
+async fn flag_message(db: mongodb::Database, events: EventBus, msg: MessageFlagged) {
+    let mut repos = Repositories::new(&db, &events);
+    let mut service = repos.moderation_service();
+    if let Err(e) = service.flag_message(&msg.message, &msg.rules).await {
+        eprintln!("Failed to report a flagged message: {e}");
+    }
+}
+
+/// Loads the content filter rules from the JSON array in the file named by
+/// `MODERATION_RULES`, or lets every message through when it is not set
+pub fn filter_from_env() -> Result<ContentFilter, String> {
+    let path = match std::env::var("MODERATION_RULES") {
+        Ok(p) => p,
+        Err(_) => return Ok(ContentFilter::default()),
+    };
+    let json = std::fs::read_to_string(&path).map_err(|e| format!("{path}: {e}"))?;
+    let rules: Vec<FilterRule> = serde_json::from_str(&json).map_err(|e| format!("{path}: {e}"))?;
+    ContentFilter::new(rules)
+}
+
+/// Makes moderators of the contacts whose emails are listed, comma separated,
+/// in `MODERATORS`, so the first moderators can appoint the others
+pub async fn promote_moderators(db: &mongodb::Database) {
+    let emails = match std::env::var("MODERATORS") {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+    let mut repo: MongoRepository<Contact> = MongoRepository::new(db, "contacts");
+    for email in emails.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let mut contact = match repo.find_by_email(email).await {
+            Some(c) if !c.moderator => c,
+            Some(_) => continue,
+            None => {
+                eprintln!("No contact with the moderator email {email}");
+                continue;
+            }
+        };
+        contact.moderator = true;
+        contact.updated_at = Utc::now();
+        if let Err(e) = repo.update(&contact).await {
+            eprintln!("Failed to promote {email} to moderator: {e}");
+        }
+    }
+}
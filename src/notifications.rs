@@ -0,0 +1,313 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Duration;
+
+use actix::{
+    Actor, ActorFutureExt, Addr, AsyncContext, Context, ContextFutureSpawner, Handler, WrapFuture,
+};
+use async_trait::async_trait;
+use chrono::Utc;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+
+use crate::adapters::mongo::repository::MongoRepository;
+use crate::adapters::IdType;
+use crate::events::MessageSent;
+use crate::models::{Contact, Notification};
+use crate::services::{Digest, NotificationService, Notifier};
+use crate::websocket::{ChatServer, FindOffline};
+
+/// How often the pending notifications are checked for digests to send
+const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Queues notifications of the sent messages for the members without an open
+/// session, and sends them in digests through the notifier.
+///
+/// The notifications live in the database, so the digests pending when the
+/// server stops are sent after a restart.
+pub struct NotificationDispatcher {
+    db: mongodb::Database,
+    chat_server: Addr<ChatServer>,
+    notifier: Rc<dyn Notifier>,
+    flushing: bool,
+}
+
+impl NotificationDispatcher {
+    pub fn new(
+        db: mongodb::Database,
+        chat_server: Addr<ChatServer>,
+        notifier: Rc<dyn Notifier>,
+    ) -> Self {
+        NotificationDispatcher {
+            db,
+            chat_server,
+            notifier,
+            flushing: false,
+        }
+    }
+
+    fn flush(&mut self, ctx: &mut Context<Self>) {
+        // Skip the tick while the previous run is still sending
+        if self.flushing {
+            return;
+        }
+        self.flushing = true;
+        flush_due(self.db.clone(), self.notifier.clone())
+            .into_actor(self)
+            .map(|_, act, _| act.flushing = false)
+            .spawn(ctx);
+    }
+}
+
+impl Actor for NotificationDispatcher {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(FLUSH_INTERVAL, |act, ctx| act.flush(ctx));
+    }
+}
+
+impl Handler<MessageSent> for NotificationDispatcher {
+    type Result = ();
+
+    fn handle(&mut self, msg: MessageSent, ctx: &mut Self::Context) -> Self::Result {
+        let db = self.db.clone();
+        let chat_server = self.chat_server.clone();
+        async move {
+            let contact_ids = msg
+                .channel
+                .contact_ids
+                .iter()
+                .filter(|id| !msg.hidden_from.contains(id))
+                .cloned()
+                .collect();
+            let find = FindOffline { contact_ids };
+            let offline = chat_server.send(find).await.unwrap_or_default();
+            if !offline.is_empty() {
+                enqueue(db, msg, offline).await;
+            }
+        }
+        .into_actor(self)
+        .spawn(ctx);
+    }
+}
+
+/// Picks the notifier from the environment: SMTP when `SMTP_HOST` is set,
+/// otherwise the log, written to `NOTIFICATION_LOG` or to the standard error
+pub fn notifier_from_env() -> Result<Rc<dyn Notifier>, String> {
+    let host = match std::env::var("SMTP_HOST") {
+        Ok(h) => h,
+        Err(_) => {
+            let path = std::env::var("NOTIFICATION_LOG").ok().map(PathBuf::from);
+            return Ok(Rc::new(LogNotifier { path }));
+        }
+    };
+    let from = std::env::var("SMTP_FROM").unwrap_or(format!("notifications@{host}"));
+    let notifier = match (
+        std::env::var("SMTP_USERNAME"),
+        std::env::var("SMTP_PASSWORD"),
+    ) {
+        (Ok(username), Ok(password)) => {
+            SmtpNotifier::relay(&host, Credentials::new(username, password), &from)?
+        }
+        _ => {
+            let port = match std::env::var("SMTP_PORT") {
+                Ok(p) => p.parse().map_err(|_| format!("Invalid SMTP_PORT {p}"))?,
+                Err(_) => 25,
+            };
+            SmtpNotifier::plain(&host, port, &from)?
+        }
+    };
+    Ok(Rc::new(notifier))
+}
+
+/// Emails the digests
+pub struct SmtpNotifier {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpNotifier {
+    /// Sends through an authenticated relay, over TLS
+    pub fn relay(host: &str, credentials: Credentials, from: &str) -> Result<Self, String> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+            .map_err(|e| e.to_string())?
+            .credentials(credentials)
+            .build();
+        SmtpNotifier::new(transport, from)
+    }
+
+    /// Sends unencrypted and without authentication, for local mail servers
+    pub fn plain(host: &str, port: u16, from: &str) -> Result<Self, String> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+            .port(port)
+            .build();
+        SmtpNotifier::new(transport, from)
+    }
+
+    fn new(transport: AsyncSmtpTransport<Tokio1Executor>, from: &str) -> Result<Self, String> {
+        let from = from
+            .parse()
+            .map_err(|e| format!("Invalid sender {from}: {e}"))?;
+        Ok(SmtpNotifier { transport, from })
+    }
+}
+
+#[async_trait(?Send)]
+impl Notifier for SmtpNotifier {
+    async fn notify(&self, digest: &Digest) -> Result<(), String> {
+        let to = mailbox(&digest.contact)?;
+        let email = lettre::Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(digest.subject())
+            .body(digest.body())
+            .map_err(|e| e.to_string())?;
+        match self.transport.send(email).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+fn mailbox(contact: &Contact) -> Result<Mailbox, String> {
+    let email = contact
+        .email
+        .parse()
+        .map_err(|e| format!("Invalid email {}: {e}", contact.email))?;
+    Ok(Mailbox::new(Some(contact.name.clone()), email))
+}
+
+/// Writes the digests to a file, or to the standard error, for development
+pub struct LogNotifier {
+    path: Option<PathBuf>,
+}
+
+#[async_trait(?Send)]
+impl Notifier for LogNotifier {
+    async fn notify(&self, digest: &Digest) -> Result<(), String> {
+        let entry = format!(
+            "{} To: {} <{}>\nSubject: {}\n{}\n",
+            Utc::now().to_rfc3339(),
+            digest.contact.name,
+            digest.contact.email,
+            digest.subject(),
+            digest.body()
+        );
+        match &self.path {
+            Some(path) => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut f| f.write_all(entry.as_bytes()))
+                .map_err(|e| e.to_string()),
+            None => {
+                eprint!("{entry}");
+                Ok(())
+            }
+        }
+    }
+}
+
+async fn enqueue(db: mongodb::Database, msg: MessageSent, offline: Vec<IdType>) {
+    let mut repo: MongoRepository<Notification> = MongoRepository::new(&db, "notifications");
+    let mut contact_repo: MongoRepository<Contact> = MongoRepository::new(&db, "contacts");
+    let mut service = NotificationService::new(&mut repo, &mut contact_repo);
+    if let Err(e) = service.enqueue(&msg.channel, &msg.message, &offline).await {
+        eprintln!("Failed to queue notifications: {e}");
+    }
+}
+
+async fn flush_due(db: mongodb::Database, notifier: Rc<dyn Notifier>) {
+    let mut repo: MongoRepository<Notification> = MongoRepository::new(&db, "notifications");
+    let mut contact_repo: MongoRepository<Contact> = MongoRepository::new(&db, "contacts");
+    let mut service = NotificationService::new(&mut repo, &mut contact_repo);
+    if let Err(e) = service.flush_due(Utc::now(), notifier.as_ref()).await {
+        eprintln!("Failed to send notifications: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapters::IdType;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    /// Accepts one SMTP session on a local port and returns the port and the
+    /// DATA it receives
+    fn fake_smtp_server() -> (u16, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut reader = BufReader::new(stream);
+            writer.write_all(b"220 localhost ESMTP\r\n").unwrap();
+            let mut data: Option<String> = None;
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                let reply: &[u8] = match data.as_mut() {
+                    Some(d) if line == ".\r\n" => {
+                        tx.send(std::mem::take(d)).unwrap();
+                        data = None;
+                        b"250 OK\r\n"
+                    }
+                    Some(d) => {
+                        d.push_str(&line);
+                        b""
+                    }
+                    None if line.starts_with("DATA") => {
+                        data = Some(String::new());
+                        b"354 Go ahead\r\n"
+                    }
+                    None if line.starts_with("QUIT") => {
+                        writer.write_all(b"221 Bye\r\n").unwrap();
+                        break;
+                    }
+                    None => b"250 OK\r\n",
+                };
+                writer.write_all(reply).unwrap();
+                line.clear();
+            }
+        });
+        (port, rx)
+    }
+
+    #[actix_web::test]
+    async fn emails_the_digest() {
+        let (port, received) = fake_smtp_server();
+        let notifier = SmtpNotifier::plain("127.0.0.1", port, "ravens@winterfell.com").unwrap();
+        let arya = Contact::new("Arya Stark", "arya@winterfell.com");
+        let channel_id = IdType::String("winterfell".to_string());
+        let message_id = IdType::String("message".to_string());
+        let digest = Digest {
+            contact: arya,
+            notifications: ["Winter is coming", "Hold the door"]
+                .into_iter()
+                .map(|text| {
+                    Notification::new(
+                        &channel_id,
+                        &channel_id,
+                        Some("Winterfell".to_string()),
+                        &message_id,
+                        "Jon Snow",
+                        text,
+                    )
+                })
+                .collect(),
+        };
+
+        notifier.notify(&digest).await.unwrap();
+
+        let email = received.recv().unwrap();
+        assert!(email.contains("To: \"Arya Stark\" <arya@winterfell.com>"));
+        assert!(email.contains("Subject: 2 new messages"));
+        assert!(email.contains("[Winterfell] Jon Snow: Hold the door"));
+    }
+}
@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+use actix::{Actor, ActorFutureExt, AsyncContext, Context, ContextFutureSpawner, WrapFuture};
+
+use crate::adapters::mongo::repository::MongoRepository;
+use crate::events::EventBus;
+use crate::models::{Channel, Contact, Message};
+use crate::services::{ChannelService, ContactService};
+
+/// How often the deletions past their grace period are purged
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Periodically purges the contacts and channels deleted before the grace
+/// period, applying the deletion policy of each contact.
+pub struct DeletionPurger {
+    db: mongodb::Database,
+    events: EventBus,
+    purging: bool,
+}
+
+impl DeletionPurger {
+    pub fn new(db: mongodb::Database, events: EventBus) -> Self {
+        DeletionPurger {
+            db,
+            events,
+            purging: false,
+        }
+    }
+
+    fn purge(&mut self, ctx: &mut Context<Self>) {
+        if self.purging {
+            return;
+        }
+        self.purging = true;
+        purge_deleted(self.db.clone(), self.events.clone())
+            .into_actor(self)
+            .map(|_, act, _| act.purging = false)
+            .spawn(ctx);
+    }
+}
+
+impl Actor for DeletionPurger {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(PURGE_INTERVAL, |act, ctx| act.purge(ctx));
+    }
+}
+
+async fn purge_deleted(db: mongodb::Database, events: EventBus) {
+    let mut message_repo: MongoRepository<Message> = MongoRepository::new(&db, "messages");
+    let mut channel_repo: MongoRepository<Channel> = MongoRepository::new(&db, "channels");
+    let mut contact_repo: MongoRepository<Contact> = MongoRepository::new(&db, "contacts");
+    let mut service = ContactService::new(&mut contact_repo)
+        .with_channels(&mut channel_repo, &mut message_repo)
+        .with_events(events.clone());
+    if let Err(e) = service.purge_deleted().await {
+        eprintln!("Failed to purge deleted contacts: {e}");
+    }
+    let mut service = ChannelService::new(&mut channel_repo, &mut contact_repo, &mut message_repo)
+        .with_events(events);
+    if let Err(e) = service.purge_deleted().await {
+        eprintln!("Failed to purge deleted channels: {e}");
+    }
+}
@@ -0,0 +1,423 @@
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::net::IpAddr;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::Method;
+use actix_web::{Error, HttpResponse};
+use futures::future::LocalBoxFuture;
+use serde_json::json;
+
+use crate::models::hash_token;
+
+/// The number of buckets kept before the idle ones are dropped
+const MAX_BUCKETS: usize = 10_000;
+
+/// The room made when the buckets reach the cap, so the sweep runs once for
+/// that many new clients rather than on every request
+const EVICTED_BUCKETS: usize = MAX_BUCKETS / 10;
+
+/// The kinds of traffic limited separately
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum RouteClass {
+    /// Requests that only read
+    Read,
+    /// Requests that change data, except sending messages
+    Write,
+    /// Messages sent through the API, the incoming webhooks or a socket
+    Message,
+    /// Frames received on a socket
+    Frame,
+}
+
+impl RouteClass {
+    pub fn of(method: &Method, path: &str) -> Self {
+        let sends = path == "/bots/messages" || path.starts_with("/hooks/");
+        match method {
+            &Method::GET | &Method::HEAD | &Method::OPTIONS => RouteClass::Read,
+            _ if sends => RouteClass::Message,
+            _ => RouteClass::Write,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            RouteClass::Read => "read",
+            RouteClass::Write => "write",
+            RouteClass::Message => "message",
+            RouteClass::Frame => "frame",
+        }
+    }
+}
+
+/// A bucket of `capacity` tokens refilled over `period`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limit {
+    pub capacity: u32,
+    pub period: Duration,
+}
+
+impl Limit {
+    pub fn new(capacity: u32, period_seconds: u64) -> Self {
+        Limit {
+            capacity,
+            period: Duration::from_secs(period_seconds),
+        }
+    }
+
+    /// Parses `<capacity>/<seconds>`, like `60/60` for 60 calls a minute
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid rate limit {value}, expected <capacity>/<seconds>");
+        let (capacity, seconds) = value.split_once('/').ok_or_else(invalid)?;
+        let capacity: u32 = capacity.trim().parse().map_err(|_| invalid())?;
+        let seconds: u64 = seconds.trim().parse().map_err(|_| invalid())?;
+        if capacity == 0 || seconds == 0 {
+            return Err(invalid());
+        }
+        Ok(Limit::new(capacity, seconds))
+    }
+
+    fn refill_rate(&self) -> f64 {
+        self.capacity as f64 / self.period.as_secs_f64()
+    }
+}
+
+/// The limits of each route class, and the number of sockets a contact can
+/// keep open at once
+#[derive(Clone, Debug)]
+pub struct RateLimits {
+    pub read: Limit,
+    pub write: Limit,
+    pub message: Limit,
+    pub frame: Limit,
+    pub max_sockets: usize,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits {
+            read: Limit::new(120, 60),
+            write: Limit::new(30, 60),
+            message: Limit::new(20, 10),
+            frame: Limit::new(60, 10),
+            max_sockets: 5,
+        }
+    }
+}
+
+impl RateLimits {
+    /// Overrides the defaults with `RATE_LIMIT_READ`, `RATE_LIMIT_WRITE`,
+    /// `RATE_LIMIT_MESSAGE` and `RATE_LIMIT_FRAME`, each `<capacity>/<seconds>`,
+    /// and `MAX_SOCKETS_PER_CONTACT`
+    pub fn from_env() -> Result<Self, String> {
+        let mut limits = RateLimits::default();
+        for (name, limit) in [
+            ("RATE_LIMIT_READ", &mut limits.read),
+            ("RATE_LIMIT_WRITE", &mut limits.write),
+            ("RATE_LIMIT_MESSAGE", &mut limits.message),
+            ("RATE_LIMIT_FRAME", &mut limits.frame),
+        ] {
+            if let Ok(value) = std::env::var(name) {
+                *limit = Limit::parse(&value).map_err(|e| format!("{name}: {e}"))?;
+            }
+        }
+        if let Ok(value) = std::env::var("MAX_SOCKETS_PER_CONTACT") {
+            limits.max_sockets = value
+                .parse()
+                .map_err(|_| format!("Invalid MAX_SOCKETS_PER_CONTACT {value}"))?;
+        }
+        Ok(limits)
+    }
+
+    fn of(&self, class: RouteClass) -> Limit {
+        match class {
+            RouteClass::Read => self.read,
+            RouteClass::Write => self.write,
+            RouteClass::Message => self.message,
+            RouteClass::Frame => self.frame,
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Token buckets per route class and client. Clones share the buckets, so
+/// the limiter created at startup covers every worker.
+#[derive(Clone, Default)]
+pub struct RateLimiter {
+    limits: Arc<RateLimits>,
+    buckets: Arc<Mutex<HashMap<(RouteClass, String), Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        RateLimiter {
+            limits: Arc::new(limits),
+            buckets: Arc::default(),
+        }
+    }
+
+    pub fn max_sockets(&self) -> usize {
+        self.limits.max_sockets
+    }
+
+    /// Takes a token from the bucket of each client key, or none of them when
+    /// one is empty, returning how long to wait for the next token
+    pub fn check(&self, class: RouteClass, keys: &[String]) -> Result<(), Duration> {
+        self.check_at(class, keys, Instant::now())
+    }
+
+    fn check_at(&self, class: RouteClass, keys: &[String], now: Instant) -> Result<(), Duration> {
+        let limit = self.limits.of(class);
+        let rate = limit.refill_rate();
+        let mut buckets = self.buckets.lock().unwrap();
+        let is_new = |key: &String| !buckets.contains_key(&(class, key.clone()));
+        if buckets.len() >= MAX_BUCKETS && keys.iter().any(is_new) {
+            self.evict(&mut buckets, now);
+        }
+        let mut wait = Duration::ZERO;
+        for key in keys {
+            let bucket = buckets
+                .entry((class, key.clone()))
+                .or_insert_with(|| Bucket {
+                    tokens: limit.capacity as f64,
+                    updated_at: now,
+                });
+            let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * rate).min(limit.capacity as f64);
+            bucket.updated_at = now;
+            if bucket.tokens < 1.0 {
+                wait = wait.max(Duration::from_secs_f64((1.0 - bucket.tokens) / rate));
+            }
+        }
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        for key in keys {
+            if let Some(bucket) = buckets.get_mut(&(class, key.clone())) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops the idle buckets, then the least recently used ones until there is
+    /// room for [`EVICTED_BUCKETS`] new clients
+    fn evict(&self, buckets: &mut HashMap<(RouteClass, String), Bucket>, now: Instant) {
+        // Buckets refilled to capacity hold no state worth keeping
+        buckets.retain(|(class, _), b| {
+            let limit = self.limits.of(*class);
+            now.duration_since(b.updated_at) < limit.period
+        });
+        let room = MAX_BUCKETS - EVICTED_BUCKETS;
+        if buckets.len() > room {
+            let mut updates: Vec<Instant> = buckets.values().map(|b| b.updated_at).collect();
+            let (_, cutoff, _) = updates.select_nth_unstable(buckets.len() - room - 1);
+            let cutoff = *cutoff;
+            buckets.retain(|_, b| b.updated_at > cutoff);
+        }
+    }
+}
+
+/// The bucket key of a bot, shared by its requests and its sockets
+pub fn bot_key(api_key: &str) -> String {
+    format!("bot:{}", hash_token(api_key))
+}
+
+/// The bucket keys of a request: its IP address, and the client it
+/// authenticates as through an API key or an incoming webhook token. The
+/// contact ids of the queries are not verified, so they are not keyed on.
+fn request_keys(req: &ServiceRequest) -> Vec<String> {
+    let mut keys = Vec::new();
+    if let Some(addr) = req.peer_addr() {
+        keys.push(ip_key(addr.ip()));
+    }
+    let api_key = req
+        .headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+    if let Some(key) = api_key {
+        keys.push(bot_key(key));
+    } else if let Some(token) = req.path().strip_prefix("/hooks/") {
+        keys.push(format!("hook:{}", hash_token(token)));
+    }
+    keys
+}
+
+pub fn ip_key(ip: IpAddr) -> String {
+    format!("ip:{ip}")
+}
+
+/// Seconds to wait before retrying, rounded up so clients never retry early
+pub fn retry_after_seconds(wait: Duration) -> u64 {
+    wait.as_secs() + u64::from(wait.subsec_nanos() > 0)
+}
+
+/// Answers the requests over their limit with `429 Too Many Requests`
+pub struct LimitRequests {
+    limiter: RateLimiter,
+}
+
+impl LimitRequests {
+    pub fn new(limiter: RateLimiter) -> Self {
+        LimitRequests { limiter }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for LimitRequests
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = LimitRequestsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(LimitRequestsMiddleware {
+            service: Rc::new(service),
+            limiter: self.limiter.clone(),
+        }))
+    }
+}
+
+pub struct LimitRequestsMiddleware<S> {
+    service: Rc<S>,
+    limiter: RateLimiter,
+}
+
+impl<S, B> Service<ServiceRequest> for LimitRequestsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let class = RouteClass::of(req.method(), req.path());
+        if let Err(wait) = self.limiter.check(class, &request_keys(&req)) {
+            let seconds = retry_after_seconds(wait);
+            let res = HttpResponse::TooManyRequests()
+                .insert_header(("Retry-After", seconds.to_string()))
+                .json(json!({
+                    "message": format!("Rate limit of {} requests exceeded", class.name())
+                }));
+            return Box::pin(ready(Ok(req.into_response(res).map_into_right_body())));
+        }
+        let service = self.service.clone();
+        Box::pin(async move {
+            let res = service.call(req).await?;
+            Ok(res.map_into_left_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{web, App};
+
+    #[test]
+    fn refills_the_buckets_over_time() {
+        let limits = RateLimits {
+            write: Limit::new(2, 10),
+            ..RateLimits::default()
+        };
+        let limiter = RateLimiter::new(limits);
+        let start = Instant::now();
+        let keys = ["ip:127.0.0.1".to_string(), "contact:arya".to_string()];
+        let write = |keys: &[String], after: u64| {
+            limiter.check_at(RouteClass::Write, keys, start + Duration::from_secs(after))
+        };
+
+        assert!(write(&keys, 0).is_ok());
+        assert!(write(&keys, 0).is_ok());
+        assert_eq!(write(&keys, 0), Err(Duration::from_secs(5)));
+        assert!(
+            write(&keys[..1], 0).is_err(),
+            "The rejected call took no token"
+        );
+        assert!(write(&["contact:sansa".to_string()], 0).is_ok());
+        assert!(write(&keys, 5).is_ok());
+        assert!(write(&keys, 5).is_err());
+        assert!(limiter.check_at(RouteClass::Read, &keys, start).is_ok());
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_buckets() {
+        let limiter = RateLimiter::default();
+        let start = Instant::now();
+        for i in 0..MAX_BUCKETS {
+            let at = start + Duration::from_millis(i as u64);
+            assert!(limiter
+                .check_at(RouteClass::Read, &[format!("ip:{i}")], at)
+                .is_ok());
+        }
+        let at = start + Duration::from_millis(MAX_BUCKETS as u64);
+        assert!(limiter
+            .check_at(RouteClass::Read, &["ip:new".to_string()], at)
+            .is_ok());
+
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.len(), MAX_BUCKETS - EVICTED_BUCKETS + 1);
+        assert!(!buckets.contains_key(&(RouteClass::Read, "ip:0".to_string())));
+        let last = format!("ip:{}", MAX_BUCKETS - 1);
+        assert!(buckets.contains_key(&(RouteClass::Read, last)));
+    }
+
+    #[test]
+    fn parses_limits() {
+        assert_eq!(Limit::parse("60/60"), Ok(Limit::new(60, 60)));
+        assert!(Limit::parse("60").is_err());
+        assert!(Limit::parse("0/60").is_err());
+        assert_eq!(retry_after_seconds(Duration::from_millis(1500)), 2);
+    }
+
+    #[actix_web::test]
+    async fn answers_too_many_requests() {
+        let limits = RateLimits {
+            read: Limit::new(1, 60),
+            ..RateLimits::default()
+        };
+        let app = init_service(
+            App::new()
+                .wrap(LimitRequests::new(RateLimiter::new(limits)))
+                .route("/contacts", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let get = || {
+            TestRequest::get()
+                .uri("/contacts?contact_id=arya")
+                .peer_addr("127.0.0.1:4000".parse().unwrap())
+                .to_request()
+        };
+
+        let res = call_service(&app, get()).await;
+        assert!(res.status().is_success());
+        let res = call_service(&app, get()).await;
+        assert_eq!(res.status(), 429);
+        assert_eq!(res.headers().get("Retry-After").unwrap(), "60");
+
+        // Claiming another contact in the query does not refill the bucket
+        let req = TestRequest::get()
+            .uri("/contacts?contact_id=sansa")
+            .peer_addr("127.0.0.1:4000".parse().unwrap())
+            .to_request();
+        assert_eq!(call_service(&app, req).await.status(), 429);
+    }
+}
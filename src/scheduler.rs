@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use actix::{Actor, ActorFutureExt, AsyncContext, Context, ContextFutureSpawner, WrapFuture};
+use chrono::Utc;
+
+use crate::adapters::mongo::repository::MongoRepository;
+use crate::events::EventBus;
+use crate::models::{Channel, Contact, Message, ScheduledMessage};
+use crate::services::{MessageService, Pipeline, ScheduledMessageService};
+
+/// How often the pending messages are checked
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Delivers the scheduled messages once they are due.
+///
+/// Pending messages live in the database, so the messages that came due while
+/// the server was down are delivered on the first run after a restart.
+pub struct MessageScheduler {
+    db: mongodb::Database,
+    events: EventBus,
+    pipeline: Pipeline,
+    delivering: bool,
+}
+
+impl MessageScheduler {
+    pub fn new(db: mongodb::Database, events: EventBus, pipeline: Pipeline) -> Self {
+        MessageScheduler {
+            db,
+            events,
+            pipeline,
+            delivering: false,
+        }
+    }
+
+    fn deliver(&mut self, ctx: &mut Context<Self>) {
+        // Skip the tick while the previous run is still sending
+        if self.delivering {
+            return;
+        }
+        self.delivering = true;
+        deliver_due(self.db.clone(), self.events.clone(), self.pipeline.clone())
+            .into_actor(self)
+            .map(|_, act, _| act.delivering = false)
+            .spawn(ctx);
+    }
+}
+
+impl Actor for MessageScheduler {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.deliver(ctx);
+        ctx.run_interval(POLL_INTERVAL, |act, ctx| act.deliver(ctx));
+    }
+}
+
+async fn deliver_due(db: mongodb::Database, events: EventBus, pipeline: Pipeline) {
+    let mut repo: MongoRepository<ScheduledMessage> =
+        MongoRepository::new(&db, "scheduled_messages");
+    let mut message_repo: MongoRepository<Message> = MongoRepository::new(&db, "messages");
+    let mut channel_repo: MongoRepository<Channel> = MongoRepository::new(&db, "channels");
+    let mut contact_repo: MongoRepository<Contact> = MongoRepository::new(&db, "contacts");
+    let mut message_service =
+        MessageService::new(&mut message_repo, &mut channel_repo, &mut contact_repo)
+            .with_events(events)
+            .with_pipeline(pipeline);
+    let mut service = ScheduledMessageService::new(&mut repo);
+    match service.deliver_due(Utc::now(), &mut message_service).await {
+        Ok(processed) => {
+            for m in processed.iter().filter(|m| m.error.is_some()) {
+                eprintln!(
+                    "Scheduled message {:?} failed: {}",
+                    m.id,
+                    m.error.clone().unwrap_or_default()
+                );
+            }
+        }
+        Err(e) => eprintln!("Failed to deliver scheduled messages: {e}"),
+    }
+}
@@ -1,45 +1,449 @@
 use crate::adapters::channel_repository::ChannelRepository;
 use crate::adapters::contact_repository::ContactRepository;
-use crate::adapters::{IdType, Repository};
+use crate::adapters::message_repository::MessageRepository;
+use crate::adapters::{IdType, Model};
 use crate::commands;
-use crate::models::{Channel, ChannelType};
+use crate::events::{ChannelCreated, ChannelUpdated, EventBus, MessageSent};
+use crate::models::{
+    grace_period_start, Channel, ChannelPreferences, ChannelType, Message, SystemEvent,
+    DELETION_GRACE_DAYS, MAX_MESSAGE_TTL,
+};
+use crate::services::message_handlers::set_expiry;
+use chrono::Utc;
 
 pub struct ChannelService<'a> {
     repository: &'a mut dyn ChannelRepository,
     contact_repository: &'a mut dyn ContactRepository,
+    message_repository: &'a mut dyn MessageRepository,
+    events: EventBus,
 }
 
 impl<'a> ChannelService<'a> {
-    fn new(
+    pub fn new(
         repo: &'a mut dyn ChannelRepository,
         contact_repository: &'a mut dyn ContactRepository,
+        message_repository: &'a mut dyn MessageRepository,
     ) -> Self {
         ChannelService {
             repository: repo,
             contact_repository,
+            message_repository,
+            events: EventBus::default(),
         }
     }
 
+    /// Publishes the changes to the given bus
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = events;
+        self
+    }
+
     pub async fn create_channel(
         &mut self,
         cmd: &commands::CreateChannel,
     ) -> Result<Channel, ChannelError> {
         validate_channel(cmd)?;
-        let channel = Channel::new(&cmd.name, cmd.channel_type.clone(), &cmd.contact_ids);
-        match self.repository.create(&channel).await {
-            Ok(c) => Ok(c),
+        let mut channel = Channel::new(&cmd.name, cmd.channel_type.clone(), &cmd.contact_ids);
+        channel.admin_ids = vec![cmd.created_by.clone()];
+        let channel = match self.repository.create(&channel).await {
+            Ok(c) => c,
+            Err(e) => {
+                return Err(ChannelError {
+                    message: e.to_string(),
+                })
+            }
+        };
+        self.events.publish(ChannelCreated {
+            channel: channel.clone(),
+        });
+        let event = SystemEvent::ChannelCreated {
+            name: channel.name.clone(),
+        };
+        self.emit(&channel, &cmd.created_by, event).await?;
+        Ok(channel)
+    }
+
+    pub async fn rename_channel(
+        &mut self,
+        cmd: &commands::RenameChannel,
+    ) -> Result<Channel, ChannelError> {
+        let mut channel = self.get_writable_channel(&cmd.channel_id).await?;
+        if !channel.contact_ids.contains(&cmd.renamed_by) {
+            return Err(ChannelError {
+                message: "Only channel members can rename the channel".to_string(),
+            });
+        }
+        channel.name = Some(cmd.name.clone());
+        self.save(&mut channel).await?;
+        let event = SystemEvent::ChannelRenamed {
+            name: cmd.name.clone(),
+        };
+        self.emit(&channel, &cmd.renamed_by, event).await?;
+        Ok(channel)
+    }
+
+    /// Sets how long new messages of the channel last before they disappear
+    pub async fn set_message_ttl(
+        &mut self,
+        cmd: &commands::SetMessageTtl,
+    ) -> Result<Channel, ChannelError> {
+        if cmd.message_ttl.is_some_and(|ttl| ttl <= 0) {
+            return Err(ChannelError {
+                message: "Message lifetime must be positive".to_string(),
+            });
+        }
+        if cmd.message_ttl.is_some_and(|ttl| ttl > MAX_MESSAGE_TTL) {
+            return Err(ChannelError {
+                message: format!("Message lifetime cannot exceed {MAX_MESSAGE_TTL} seconds"),
+            });
+        }
+        let mut channel = self.get_writable_channel(&cmd.channel_id).await?;
+        if !channel.contact_ids.contains(&cmd.set_by) {
+            return Err(ChannelError {
+                message: "Only channel members can change the message lifetime".to_string(),
+            });
+        }
+        channel.message_ttl = cmd.message_ttl;
+        self.save(&mut channel).await?;
+        let event = SystemEvent::MessageTtlChanged {
+            message_ttl: cmd.message_ttl,
+        };
+        self.emit(&channel, &cmd.set_by, event).await?;
+        Ok(channel)
+    }
+
+    pub async fn join_channel(
+        &mut self,
+        cmd: &commands::JoinChannel,
+    ) -> Result<Channel, ChannelError> {
+        let mut channel = self.get_group_channel(&cmd.channel_id).await?;
+        if self.contact_repository.get(&cmd.contact_id).await.is_none() {
+            return Err(ChannelError {
+                message: format!("Contact with id {} not found", cmd.contact_id),
+            });
+        }
+        if channel.contact_ids.contains(&cmd.contact_id) {
+            return Ok(channel);
+        }
+        channel.contact_ids.push(cmd.contact_id.clone());
+        self.save(&mut channel).await?;
+        let event = SystemEvent::MemberJoined {
+            contact_id: cmd.contact_id.clone(),
+        };
+        self.emit(&channel, &cmd.contact_id, event).await?;
+        Ok(channel)
+    }
+
+    pub async fn leave_channel(
+        &mut self,
+        cmd: &commands::LeaveChannel,
+    ) -> Result<Channel, ChannelError> {
+        let mut channel = self.get_group_channel(&cmd.channel_id).await?;
+        if !channel.contact_ids.contains(&cmd.contact_id) {
+            return Err(ChannelError {
+                message: "Contact is not a member of the channel".to_string(),
+            });
+        }
+        channel.contact_ids.retain(|id| id != &cmd.contact_id);
+        channel
+            .preferences
+            .retain(|p| p.contact_id != cmd.contact_id);
+        self.save(&mut channel).await?;
+        if let Err(e) = self
+            .repository
+            .set_preferences(&channel.id(), &cmd.contact_id, None)
+            .await
+        {
+            return Err(ChannelError {
+                message: e.to_string(),
+            });
+        }
+        let event = SystemEvent::MemberLeft {
+            contact_id: cmd.contact_id.clone(),
+        };
+        self.emit(&channel, &cmd.contact_id, event).await?;
+        Ok(channel)
+    }
+
+    /// Makes the channel read-only. Its history stays readable, but it takes
+    /// no new messages, members or changes until it is unarchived.
+    pub async fn archive_channel(
+        &mut self,
+        cmd: &commands::ArchiveChannel,
+    ) -> Result<Channel, ChannelError> {
+        let mut channel = self.get_writable_channel(&cmd.channel_id).await?;
+        if !self.can_administer(&channel, &cmd.archived_by).await {
+            return Err(ChannelError {
+                message: "Only channel admins can archive the channel".to_string(),
+            });
+        }
+        channel.archived_at = Some(Utc::now());
+        self.save(&mut channel).await?;
+        self.emit(&channel, &cmd.archived_by, SystemEvent::ChannelArchived)
+            .await?;
+        Ok(channel)
+    }
+
+    pub async fn unarchive_channel(
+        &mut self,
+        cmd: &commands::UnarchiveChannel,
+    ) -> Result<Channel, ChannelError> {
+        let mut channel = self.get_channel(&cmd.channel_id).await?;
+        if !channel.is_archived() {
+            return Err(ChannelError {
+                message: "Channel is not archived".to_string(),
+            });
+        }
+        if !self.can_administer(&channel, &cmd.unarchived_by).await {
+            return Err(ChannelError {
+                message: "Only channel admins can unarchive the channel".to_string(),
+            });
+        }
+        channel.archived_at = None;
+        self.save(&mut channel).await?;
+        self.emit(&channel, &cmd.unarchived_by, SystemEvent::ChannelUnarchived)
+            .await?;
+        Ok(channel)
+    }
+
+    /// Marks the channel deleted. It can be restored until the grace period
+    /// ends, when it is purged with its messages.
+    pub async fn delete_channel(
+        &mut self,
+        cmd: &commands::DeleteChannel,
+    ) -> Result<Channel, ChannelError> {
+        let mut channel = self.get_channel(&cmd.channel_id).await?;
+        if !channel.contact_ids.contains(&cmd.deleted_by) {
+            return Err(ChannelError {
+                message: "Only channel members can delete the channel".to_string(),
+            });
+        }
+        channel.deleted_at = Some(Utc::now());
+        self.save(&mut channel).await?;
+        Ok(channel)
+    }
+
+    /// Restores a channel deleted during the grace period
+    pub async fn restore_channel(
+        &mut self,
+        cmd: &commands::RestoreChannel,
+    ) -> Result<Channel, ChannelError> {
+        let mut channel = match self.repository.get_with_deleted(&cmd.channel_id).await {
+            Some(c) if c.deleted_at.is_some() => c,
+            _ => {
+                return Err(ChannelError {
+                    message: format!("No deleted channel with id {} to restore", cmd.channel_id),
+                })
+            }
+        };
+        if channel
+            .deleted_at
+            .is_some_and(|d| d <= grace_period_start())
+        {
+            return Err(ChannelError {
+                message: format!(
+                    "Channels can only be restored within {DELETION_GRACE_DAYS} days of their deletion"
+                ),
+            });
+        }
+        if !channel.contact_ids.contains(&cmd.restored_by) {
+            return Err(ChannelError {
+                message: "Only channel members can restore the channel".to_string(),
+            });
+        }
+        // The members may have started another private channel since
+        if channel.channel_type == ChannelType::Private {
+            let other = self
+                .repository
+                .get_by_contact_ids(&channel.contact_ids)
+                .await;
+            if other.is_some_and(|c| c.channel_type == ChannelType::Private) {
+                return Err(ChannelError {
+                    message: "A private channel between the contacts already exists".to_string(),
+                });
+            }
+        }
+        channel.deleted_at = None;
+        self.save(&mut channel).await?;
+        Ok(channel)
+    }
+
+    /// Purges the channels deleted before the grace period with their messages
+    /// and returns how many were purged
+    pub async fn purge_deleted(&mut self) -> Result<usize, ChannelError> {
+        let deleted = match self
+            .repository
+            .find_deleted_before(grace_period_start())
+            .await
+        {
+            Ok(d) => d,
+            Err(e) => {
+                return Err(ChannelError {
+                    message: e.to_string(),
+                })
+            }
+        };
+        for channel in deleted.iter() {
+            let res = match self
+                .message_repository
+                .delete_by_channel_id(&channel.id())
+                .await
+            {
+                Ok(_) => self.repository.delete(&channel.id()).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = res {
+                return Err(ChannelError {
+                    message: e.to_string(),
+                });
+            }
+        }
+        Ok(deleted.len())
+    }
+
+    /// Returns the notification settings of a member for the channel
+    pub async fn get_preferences(
+        &self,
+        channel_id: &IdType,
+        contact_id: &IdType,
+    ) -> Result<ChannelPreferences, ChannelError> {
+        let channel = self.get_channel(channel_id).await?;
+        if !channel.contact_ids.contains(contact_id) {
+            return Err(ChannelError {
+                message: "Contact is not a member of the channel".to_string(),
+            });
+        }
+        Ok(channel.preferences_of(contact_id))
+    }
+
+    /// Changes the notification settings of a member for the channel. The
+    /// settings are personal, so other members are not told of the change.
+    pub async fn set_preferences(
+        &mut self,
+        cmd: &commands::SetChannelPreferences,
+    ) -> Result<ChannelPreferences, ChannelError> {
+        let channel = self.get_channel(&cmd.channel_id).await?;
+        if !channel.contact_ids.contains(&cmd.contact_id) {
+            return Err(ChannelError {
+                message: "Contact is not a member of the channel".to_string(),
+            });
+        }
+        let preferences = ChannelPreferences {
+            contact_id: cmd.contact_id.clone(),
+            level: cmd.level.clone(),
+            muted: cmd.muted,
+            muted_until: cmd.muted_until.filter(|_| cmd.muted),
+        };
+        // The defaults are not stored
+        let stored = Some(&preferences).filter(|p| **p != ChannelPreferences::new(&cmd.contact_id));
+        match self
+            .repository
+            .set_preferences(&channel.id(), &cmd.contact_id, stored)
+            .await
+        {
+            Ok(_) => Ok(preferences),
             Err(e) => Err(ChannelError {
                 message: e.to_string(),
             }),
         }
     }
 
+    /// Returns the channels of the contact, without the archived ones unless asked
     pub async fn find_contact_channels(
         &mut self,
         contact_id: &IdType,
+        include_archived: bool,
     ) -> Result<Vec<Channel>, ChannelError> {
         match self.repository.find_by_contact_id(contact_id).await {
-            Ok(c) => Ok(c),
+            Ok(c) => Ok(c
+                .into_iter()
+                .filter(|c| include_archived || !c.is_archived())
+                .collect()),
+            Err(e) => Err(ChannelError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    async fn get_channel(&self, id: &IdType) -> Result<Channel, ChannelError> {
+        match self.repository.get(id).await {
+            None => Err(ChannelError {
+                message: format!("Channel with id {id} not found"),
+            }),
+            Some(c) => Ok(c),
+        }
+    }
+
+    /// Whether the contact administers the channel, which moderators do for
+    /// every channel
+    async fn can_administer(&self, channel: &Channel, contact_id: &IdType) -> bool {
+        if channel.is_admin(contact_id) {
+            return true;
+        }
+        match self.contact_repository.get(contact_id).await {
+            Some(c) => c.moderator,
+            None => false,
+        }
+    }
+
+    /// Returns the channel unless it is archived, which makes it read-only
+    async fn get_writable_channel(&self, id: &IdType) -> Result<Channel, ChannelError> {
+        let channel = self.get_channel(id).await?;
+        if channel.is_archived() {
+            return Err(ChannelError {
+                message: "Archived channels cannot change".to_string(),
+            });
+        }
+        Ok(channel)
+    }
+
+    async fn get_group_channel(&self, id: &IdType) -> Result<Channel, ChannelError> {
+        let channel = self.get_writable_channel(id).await?;
+        if channel.channel_type == ChannelType::Private {
+            return Err(ChannelError {
+                message: "Private channel members cannot change".to_string(),
+            });
+        }
+        Ok(channel)
+    }
+
+    /// Saves the changes of the channel, leaving the notification settings of
+    /// the members to `set_preferences`
+    async fn save(&mut self, channel: &mut Channel) -> Result<(), ChannelError> {
+        channel.updated_at = Utc::now();
+        match self.repository.update_without_preferences(channel).await {
+            Ok(_) => {
+                self.events.publish(ChannelUpdated {
+                    channel: channel.clone(),
+                });
+                Ok(())
+            }
+            Err(e) => Err(ChannelError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    /// Records the event as a system message in the channel history
+    async fn emit(
+        &mut self,
+        channel: &Channel,
+        triggered_by: &IdType,
+        event: SystemEvent,
+    ) -> Result<Message, ChannelError> {
+        let mut message = Message::system(&channel.id(), triggered_by, event);
+        if let Err(e) = set_expiry(channel, &mut message) {
+            return Err(ChannelError { message: e.message });
+        }
+        match self.message_repository.create_in_sequence(&message).await {
+            Ok(m) => {
+                self.events.publish(MessageSent {
+                    channel: channel.clone(),
+                    message: m.clone(),
+                    hidden_from: vec![],
+                });
+                Ok(m)
+            }
             Err(e) => Err(ChannelError {
                 message: e.to_string(),
             }),
@@ -77,10 +481,17 @@ fn validate_private_channel(cmd: &commands::CreateChannel) -> Result<(), Channel
 
 #[cfg(test)]
 mod tests {
-    use crate::adapters::{mock_channel_repo, mock_contact_repo, Model, Repository};
+    use crate::adapters::message_repository::MessageRepository;
+    use crate::adapters::{
+        mock_channel_repo, mock_contact_repo, mock_message_repo, IdType, Model, Repository,
+    };
     use crate::commands;
-    use crate::models::{ChannelType, Contact};
+    use crate::models::{
+        ChannelType, Contact, MessageContent, NotificationLevel, SystemEvent, DELETION_GRACE_DAYS,
+        MAX_MESSAGE_TTL,
+    };
     use crate::services::channel_handlers::ChannelService;
+    use chrono::{Duration, Utc};
 
     pub async fn add_mock_contacts(repo: &mut impl Repository<Contact>) -> Vec<Contact> {
         let jon = repo
@@ -98,12 +509,14 @@ mod tests {
     async fn create_private_channel() {
         let mut repo = mock_channel_repo();
         let mut c_repo = mock_contact_repo();
+        let mut m_repo = mock_message_repo();
         let contacts = add_mock_contacts(&mut c_repo).await;
-        let mut service = ChannelService::new(&mut repo, &mut c_repo);
+        let mut service = ChannelService::new(&mut repo, &mut c_repo, &mut m_repo);
         let cmd = commands::CreateChannel {
             name: "Private channel".to_string(),
             channel_type: ChannelType::Private,
             contact_ids: contacts.iter().map(|c| c.id()).collect(),
+            created_by: contacts[0].id(),
         };
         let res = service.create_channel(&cmd).await;
         assert!(res.is_ok());
@@ -115,11 +528,13 @@ mod tests {
     async fn cannot_create_private_channel_with_less_than_two_contacts() {
         let mut repo = mock_channel_repo();
         let mut c_repo = mock_contact_repo();
-        let mut service = ChannelService::new(&mut repo, &mut c_repo);
+        let mut m_repo = mock_message_repo();
+        let mut service = ChannelService::new(&mut repo, &mut c_repo, &mut m_repo);
         let cmd = commands::CreateChannel {
             name: "Private channel without contacts".to_string(),
             channel_type: ChannelType::Private,
             contact_ids: vec![],
+            created_by: IdType::String("jon".to_string()),
         };
         let res = service.create_channel(&cmd).await;
         assert!(res.is_err());
@@ -131,12 +546,14 @@ mod tests {
     async fn create_group_channel() {
         let mut repo = mock_channel_repo();
         let mut c_repo = mock_contact_repo();
+        let mut m_repo = mock_message_repo();
         let contacts = add_mock_contacts(&mut c_repo).await;
-        let mut service = ChannelService::new(&mut repo, &mut c_repo);
+        let mut service = ChannelService::new(&mut repo, &mut c_repo, &mut m_repo);
         let cmd = commands::CreateChannel {
             name: "Group channel".to_string(),
             channel_type: ChannelType::Group,
             contact_ids: contacts.iter().map(|c| c.id()).collect(),
+            created_by: contacts[0].id(),
         };
         let res = service.create_channel(&cmd).await;
         assert!(res.is_ok());
@@ -149,12 +566,14 @@ mod tests {
     async fn find_contact_channels() {
         let mut repo = mock_channel_repo();
         let mut c_repo = mock_contact_repo();
+        let mut m_repo = mock_message_repo();
         let contacts = add_mock_contacts(&mut c_repo).await;
-        let mut service = ChannelService::new(&mut repo, &mut c_repo);
+        let mut service = ChannelService::new(&mut repo, &mut c_repo, &mut m_repo);
         let cmd = commands::CreateChannel {
             name: "Private channel".to_string(),
             channel_type: ChannelType::Private,
             contact_ids: contacts.iter().map(|c| c.id()).collect(),
+            created_by: contacts[0].id(),
         };
 
         // Create a private channel
@@ -168,6 +587,7 @@ mod tests {
             name: "Group channel".to_string(),
             channel_type: ChannelType::Group,
             contact_ids: contacts.iter().map(|c| c.id()).collect(),
+            created_by: contacts[0].id(),
         };
         let res = service.create_channel(&cmd).await;
         assert!(res.is_ok());
@@ -175,11 +595,392 @@ mod tests {
         assert_eq!(channel.contact_ids.len(), 2);
 
         // Find channels for a contact
-        let res = service.find_contact_channels(&contacts[0].id()).await;
+        let res = service
+            .find_contact_channels(&contacts[0].id(), false)
+            .await;
         assert!(res.is_ok());
         let channels = res.unwrap();
         assert_eq!(channels.len(), 2);
     }
+
+    #[actix_web::test]
+    async fn membership_changes_are_recorded_as_system_messages() {
+        let mut repo = mock_channel_repo();
+        let mut c_repo = mock_contact_repo();
+        let mut m_repo = mock_message_repo();
+        let contacts = add_mock_contacts(&mut c_repo).await;
+        let bran = c_repo
+            .create(&Contact::new("Bran Stark", "bran@winterfell.com"))
+            .await
+            .unwrap();
+        let mut service = ChannelService::new(&mut repo, &mut c_repo, &mut m_repo);
+        let cmd = commands::CreateChannel {
+            name: "Stark family".to_string(),
+            channel_type: ChannelType::Group,
+            contact_ids: contacts.iter().map(|c| c.id()).collect(),
+            created_by: contacts[0].id(),
+        };
+        let channel = service.create_channel(&cmd).await.unwrap();
+
+        let join = commands::JoinChannel {
+            channel_id: channel.id(),
+            contact_id: bran.id(),
+        };
+        let channel = service.join_channel(&join).await.unwrap();
+        assert!(channel.contact_ids.contains(&bran.id()));
+
+        let rename = commands::RenameChannel {
+            channel_id: channel.id(),
+            name: "House Stark".to_string(),
+            renamed_by: bran.id(),
+        };
+        service.rename_channel(&rename).await.unwrap();
+
+        let leave = commands::LeaveChannel {
+            channel_id: channel.id(),
+            contact_id: bran.id(),
+        };
+        let channel = service.leave_channel(&leave).await.unwrap();
+        assert_eq!(channel.name.clone().unwrap(), "House Stark");
+
+        let messages = service
+            .message_repository
+            .get_by_channel_id(&channel.id(), 100, 0)
+            .await
+            .unwrap();
+        let events: Vec<SystemEvent> = messages
+            .into_iter()
+            .filter_map(|m| match m.content {
+                MessageContent::System { event } => Some(event),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                SystemEvent::ChannelCreated {
+                    name: Some("Stark family".to_string())
+                },
+                SystemEvent::MemberJoined {
+                    contact_id: bran.id()
+                },
+                SystemEvent::ChannelRenamed {
+                    name: "House Stark".to_string()
+                },
+                SystemEvent::MemberLeft {
+                    contact_id: bran.id()
+                },
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn cannot_join_private_channel() {
+        let mut repo = mock_channel_repo();
+        let mut c_repo = mock_contact_repo();
+        let mut m_repo = mock_message_repo();
+        let contacts = add_mock_contacts(&mut c_repo).await;
+        let mut service = ChannelService::new(&mut repo, &mut c_repo, &mut m_repo);
+        let cmd = commands::CreateChannel {
+            name: "Private channel".to_string(),
+            channel_type: ChannelType::Private,
+            contact_ids: contacts.iter().map(|c| c.id()).collect(),
+            created_by: contacts[0].id(),
+        };
+        let channel = service.create_channel(&cmd).await.unwrap();
+        let join = commands::JoinChannel {
+            channel_id: channel.id(),
+            contact_id: contacts[0].id(),
+        };
+        assert!(service.join_channel(&join).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn members_keep_their_own_preferences() {
+        let mut repo = mock_channel_repo();
+        let mut c_repo = mock_contact_repo();
+        let mut m_repo = mock_message_repo();
+        let contacts = add_mock_contacts(&mut c_repo).await;
+        let mut service = ChannelService::new(&mut repo, &mut c_repo, &mut m_repo);
+        let cmd = commands::CreateChannel {
+            name: "Night's Watch".to_string(),
+            channel_type: ChannelType::Group,
+            contact_ids: contacts.iter().map(|c| c.id()).collect(),
+            created_by: contacts[0].id(),
+        };
+        let channel = service.create_channel(&cmd).await.unwrap();
+        let set = commands::SetChannelPreferences {
+            channel_id: channel.id(),
+            contact_id: contacts[0].id(),
+            level: NotificationLevel::Mentions,
+            muted: false,
+            muted_until: None,
+        };
+        service.set_preferences(&set).await.unwrap();
+
+        let jon = service
+            .get_preferences(&channel.id(), &contacts[0].id())
+            .await
+            .unwrap();
+        assert_eq!(jon.level, NotificationLevel::Mentions);
+        let arya = service
+            .get_preferences(&channel.id(), &contacts[1].id())
+            .await
+            .unwrap();
+        assert_eq!(arya.level, NotificationLevel::All);
+
+        // Jon's settings stay out of what Arya and the integrations see
+        let stored = service.get_channel(&channel.id()).await.unwrap();
+        assert_eq!(stored.preferences.len(), 1);
+        assert!(stored.for_member(&contacts[1].id()).preferences.is_empty());
+        assert_eq!(stored.for_member(&contacts[0].id()).preferences.len(), 1);
+        assert!(stored.without_preferences().preferences.is_empty());
+
+        let defaults = commands::SetChannelPreferences {
+            channel_id: channel.id(),
+            contact_id: contacts[0].id(),
+            level: NotificationLevel::All,
+            muted: false,
+            muted_until: None,
+        };
+        service.set_preferences(&defaults).await.unwrap();
+        let stored = service.get_channel(&channel.id()).await.unwrap();
+        assert!(stored.preferences.is_empty(), "The defaults are not stored");
+        service.set_preferences(&set).await.unwrap();
+
+        let leave = commands::LeaveChannel {
+            channel_id: channel.id(),
+            contact_id: contacts[0].id(),
+        };
+        let channel = service.leave_channel(&leave).await.unwrap();
+        assert!(channel.preferences.is_empty());
+        let stored = service.get_channel(&channel.id()).await.unwrap();
+        assert!(stored.preferences.is_empty(), "Dropped with the member");
+        assert!(service.set_preferences(&set).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn saving_a_channel_keeps_the_preferences_set_meanwhile() {
+        let mut repo = mock_channel_repo();
+        let mut c_repo = mock_contact_repo();
+        let mut m_repo = mock_message_repo();
+        let contacts = add_mock_contacts(&mut c_repo).await;
+        let mut service = ChannelService::new(&mut repo, &mut c_repo, &mut m_repo);
+        let cmd = commands::CreateChannel {
+            name: "Night's Watch".to_string(),
+            channel_type: ChannelType::Group,
+            contact_ids: contacts.iter().map(|c| c.id()).collect(),
+            created_by: contacts[0].id(),
+        };
+        let channel = service.create_channel(&cmd).await.unwrap();
+        let mut stale = service.get_channel(&channel.id()).await.unwrap();
+        let set = commands::SetChannelPreferences {
+            channel_id: channel.id(),
+            contact_id: contacts[1].id(),
+            level: NotificationLevel::Nothing,
+            muted: true,
+            muted_until: None,
+        };
+        service.set_preferences(&set).await.unwrap();
+
+        stale.name = Some("The Wall".to_string());
+        service.save(&mut stale).await.unwrap();
+        let stored = service.get_channel(&channel.id()).await.unwrap();
+        assert_eq!(stored.name.as_deref(), Some("The Wall"));
+        assert_eq!(stored.preferences.len(), 1);
+        assert!(stored.preferences_of(&contacts[1].id()).muted);
+    }
+
+    #[actix_web::test]
+    async fn message_lifetime_is_bounded() {
+        let mut repo = mock_channel_repo();
+        let mut c_repo = mock_contact_repo();
+        let mut m_repo = mock_message_repo();
+        let contacts = add_mock_contacts(&mut c_repo).await;
+        let mut service = ChannelService::new(&mut repo, &mut c_repo, &mut m_repo);
+        let cmd = commands::CreateChannel {
+            name: "Faceless Men".to_string(),
+            channel_type: ChannelType::Group,
+            contact_ids: vec![contacts[0].id()],
+            created_by: contacts[0].id(),
+        };
+        let channel = service.create_channel(&cmd).await.unwrap();
+        for ttl in [0, MAX_MESSAGE_TTL + 1, i64::MAX] {
+            let cmd = commands::SetMessageTtl {
+                channel_id: channel.id(),
+                message_ttl: Some(ttl),
+                set_by: contacts[0].id(),
+            };
+            assert!(service.set_message_ttl(&cmd).await.is_err(), "{ttl}");
+        }
+        let cmd = commands::SetMessageTtl {
+            channel_id: channel.id(),
+            message_ttl: Some(MAX_MESSAGE_TTL),
+            set_by: contacts[0].id(),
+        };
+        let channel = service.set_message_ttl(&cmd).await.unwrap();
+        assert_eq!(channel.message_ttl, Some(MAX_MESSAGE_TTL));
+
+        // The change is recorded by a message that disappears like the others
+        let messages = m_repo
+            .get_by_channel_id(&channel.id(), 10, 0)
+            .await
+            .unwrap();
+        let recorded = messages.iter().find(|m| {
+            m.content
+                == MessageContent::System {
+                    event: SystemEvent::MessageTtlChanged {
+                        message_ttl: Some(MAX_MESSAGE_TTL),
+                    },
+                }
+        });
+        let recorded = recorded.unwrap();
+        let lifetime = Duration::seconds(MAX_MESSAGE_TTL);
+        assert_eq!(recorded.expires_at, Some(recorded.created_at + lifetime));
+    }
+
+    #[actix_web::test]
+    async fn archived_channels_cannot_change() {
+        let mut repo = mock_channel_repo();
+        let mut c_repo = mock_contact_repo();
+        let mut m_repo = mock_message_repo();
+        let contacts = add_mock_contacts(&mut c_repo).await;
+        let mut service = ChannelService::new(&mut repo, &mut c_repo, &mut m_repo);
+        let cmd = commands::CreateChannel {
+            name: "Small Council".to_string(),
+            channel_type: ChannelType::Group,
+            contact_ids: vec![contacts[0].id()],
+            created_by: contacts[0].id(),
+        };
+        let channel = service.create_channel(&cmd).await.unwrap();
+        let archive = commands::ArchiveChannel {
+            channel_id: channel.id(),
+            archived_by: contacts[1].id(),
+        };
+        assert!(
+            service.archive_channel(&archive).await.is_err(),
+            "Not a member"
+        );
+        let join = commands::JoinChannel {
+            channel_id: channel.id(),
+            contact_id: contacts[1].id(),
+        };
+        service.join_channel(&join).await.unwrap();
+        assert!(
+            service.archive_channel(&archive).await.is_err(),
+            "Not an admin"
+        );
+        let archive = commands::ArchiveChannel {
+            channel_id: channel.id(),
+            archived_by: contacts[0].id(),
+        };
+        service.archive_channel(&archive).await.unwrap();
+
+        let leave = commands::LeaveChannel {
+            channel_id: channel.id(),
+            contact_id: contacts[1].id(),
+        };
+        assert!(service.leave_channel(&leave).await.is_err());
+        let rename = commands::RenameChannel {
+            channel_id: channel.id(),
+            name: "Iron Bank".to_string(),
+            renamed_by: contacts[0].id(),
+        };
+        assert!(service.rename_channel(&rename).await.is_err());
+        let channels = service
+            .find_contact_channels(&contacts[0].id(), false)
+            .await;
+        assert!(channels.unwrap().is_empty());
+        let channels = service.find_contact_channels(&contacts[0].id(), true).await;
+        assert_eq!(channels.unwrap().len(), 1);
+
+        let unarchive = commands::UnarchiveChannel {
+            channel_id: channel.id(),
+            unarchived_by: contacts[1].id(),
+        };
+        assert!(
+            service.unarchive_channel(&unarchive).await.is_err(),
+            "Not an admin"
+        );
+        let unarchive = commands::UnarchiveChannel {
+            channel_id: channel.id(),
+            unarchived_by: contacts[0].id(),
+        };
+        service.unarchive_channel(&unarchive).await.unwrap();
+        assert!(service.unarchive_channel(&unarchive).await.is_err());
+        service.leave_channel(&leave).await.unwrap();
+
+        let history = m_repo.get_after_seq(&channel.id(), 0, 10).await.unwrap();
+        let events: Vec<SystemEvent> = history
+            .into_iter()
+            .filter_map(|m| match m.content {
+                MessageContent::System { event } => Some(event),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(events.len(), 5);
+        assert_eq!(events[2], SystemEvent::ChannelArchived);
+        assert_eq!(events[3], SystemEvent::ChannelUnarchived);
+    }
+
+    #[actix_web::test]
+    async fn deleted_channels_are_restored_or_purged() {
+        let mut repo = mock_channel_repo();
+        let mut c_repo = mock_contact_repo();
+        let mut m_repo = mock_message_repo();
+        let contacts = add_mock_contacts(&mut c_repo).await;
+        let mut service = ChannelService::new(&mut repo, &mut c_repo, &mut m_repo);
+        let cmd = commands::CreateChannel {
+            name: "Private channel".to_string(),
+            channel_type: ChannelType::Private,
+            contact_ids: contacts.iter().map(|c| c.id()).collect(),
+            created_by: contacts[0].id(),
+        };
+        let channel = service.create_channel(&cmd).await.unwrap();
+        let delete = commands::DeleteChannel {
+            channel_id: channel.id(),
+            deleted_by: contacts[1].id(),
+        };
+        service.delete_channel(&delete).await.unwrap();
+        let channels = service
+            .find_contact_channels(&contacts[0].id(), false)
+            .await;
+        assert!(channels.unwrap().is_empty());
+
+        // Another private channel between the same contacts blocks the restore
+        let other = service.create_channel(&cmd).await.unwrap();
+        let restore = commands::RestoreChannel {
+            channel_id: channel.id(),
+            restored_by: contacts[0].id(),
+        };
+        assert!(service.restore_channel(&restore).await.is_err());
+        let delete_other = commands::DeleteChannel {
+            channel_id: other.id(),
+            deleted_by: contacts[0].id(),
+        };
+        service.delete_channel(&delete_other).await.unwrap();
+        let restored = service.restore_channel(&restore).await.unwrap();
+        assert!(restored.deleted_at.is_none());
+        assert_eq!(service.purge_deleted().await.unwrap(), 0);
+
+        let mut other = service
+            .repository
+            .get_with_deleted(&other.id())
+            .await
+            .unwrap();
+        other.deleted_at = Some(Utc::now() - Duration::days(DELETION_GRACE_DAYS + 1));
+        service.repository.update(&other).await.unwrap();
+        assert_eq!(service.purge_deleted().await.unwrap(), 1);
+        assert!(service
+            .repository
+            .get_with_deleted(&other.id())
+            .await
+            .is_none());
+        let history = m_repo.get_after_seq(&other.id(), 0, 10).await.unwrap();
+        assert!(history.is_empty());
+        let history = m_repo.get_after_seq(&channel.id(), 0, 10).await.unwrap();
+        assert_eq!(history.len(), 1);
+    }
 }
 
 #[cfg(test)]
@@ -187,7 +988,7 @@ mod tests_mongo {
     use crate::adapters::mongo::repository::MongoRepository;
     use crate::adapters::{Model, Repository};
     use crate::commands;
-    use crate::models::ChannelType;
+    use crate::models::{ChannelType, NotificationLevel};
     use crate::services::channel_handlers::tests::add_mock_contacts;
     use crate::services::ChannelService;
 
@@ -197,13 +998,15 @@ mod tests_mongo {
         let db = crate::adapters::mongo::database::init("test").await;
         let mut repo = MongoRepository::new(&db, "channels");
         let mut c_repo = MongoRepository::new(&db, "contacts");
+        let mut m_repo = MongoRepository::new(&db, "messages");
         let contacts = add_mock_contacts(&mut c_repo).await;
 
-        let mut service = ChannelService::new(&mut repo, &mut c_repo);
+        let mut service = ChannelService::new(&mut repo, &mut c_repo, &mut m_repo);
         let cmd = crate::commands::CreateChannel {
             name: "Private channel".to_string(),
             channel_type: crate::models::ChannelType::Private,
             contact_ids: contacts.iter().map(|c| c.id()).collect(),
+            created_by: contacts[0].id(),
         };
         let res = service.create_channel(&cmd).await;
         assert!(res.is_ok());
@@ -232,13 +1035,15 @@ mod tests_mongo {
         let db = crate::adapters::mongo::database::init("test").await;
         let mut repo = MongoRepository::new(&db, "channels");
         let mut c_repo = MongoRepository::new(&db, "contacts");
+        let mut m_repo = MongoRepository::new(&db, "messages");
         let contacts = add_mock_contacts(&mut c_repo).await;
 
-        let mut service = ChannelService::new(&mut repo, &mut c_repo);
+        let mut service = ChannelService::new(&mut repo, &mut c_repo, &mut m_repo);
         let cmd = commands::CreateChannel {
             name: "Private channel".to_string(),
             channel_type: crate::models::ChannelType::Private,
             contact_ids: contacts.iter().map(|c| c.id()).collect(),
+            created_by: contacts[0].id(),
         };
         let res = service.create_channel(&cmd).await;
         assert!(res.is_ok());
@@ -251,6 +1056,7 @@ mod tests_mongo {
             name: "Group channel".to_string(),
             channel_type: ChannelType::Group,
             contact_ids: contacts.iter().map(|c| c.id()).collect(),
+            created_by: contacts[0].id(),
         };
         let res = service.create_channel(&cmd).await;
         assert!(res.is_ok());
@@ -258,9 +1064,49 @@ mod tests_mongo {
         assert_eq!(channel.contact_ids.len(), 2);
 
         // Find channels for a contact
-        let res = service.find_contact_channels(&contacts[0].id()).await;
+        let res = service
+            .find_contact_channels(&contacts[0].id(), false)
+            .await;
         assert!(res.is_ok());
         let channels = res.unwrap();
         assert_eq!(channels.len(), 2);
     }
+
+    #[actix_web::test]
+    #[ignore]
+    async fn set_preferences_in_place() {
+        let db = crate::adapters::mongo::database::init("test").await;
+        let mut repo = MongoRepository::new(&db, "channels");
+        let mut c_repo = MongoRepository::new(&db, "contacts");
+        let mut m_repo = MongoRepository::new(&db, "messages");
+        let contacts = add_mock_contacts(&mut c_repo).await;
+
+        let mut service = ChannelService::new(&mut repo, &mut c_repo, &mut m_repo);
+        let cmd = commands::CreateChannel {
+            name: "Group channel".to_string(),
+            channel_type: ChannelType::Group,
+            contact_ids: contacts.iter().map(|c| c.id()).collect(),
+            created_by: contacts[0].id(),
+        };
+        let channel = service.create_channel(&cmd).await.unwrap();
+        for (contact, level) in [
+            (&contacts[0], NotificationLevel::Mentions),
+            (&contacts[1], NotificationLevel::Mentions),
+            (&contacts[0], NotificationLevel::All),
+        ] {
+            let set = commands::SetChannelPreferences {
+                channel_id: channel.id(),
+                contact_id: contact.id(),
+                level,
+                muted: false,
+                muted_until: None,
+            };
+            service.set_preferences(&set).await.unwrap();
+        }
+
+        let channel = service.repository.get(&channel.id()).await.unwrap();
+        assert_eq!(channel.preferences.len(), 1);
+        assert_eq!(channel.preferences[0].contact_id, contacts[1].id());
+        assert_eq!(channel.name.as_deref(), Some("Group channel"));
+    }
 }
@@ -1,15 +1,51 @@
-use crate::adapters::contact_repository::ContactRepository;
-use crate::adapters::{IdType, Repository, RepositoryError};
+use crate::adapters::channel_repository::ChannelRepository;
+use crate::adapters::contact_repository::{ContactDeletion, ContactRepository};
+use crate::adapters::message_repository::MessageRepository;
+use crate::adapters::{IdType, Model, RepositoryError};
 use crate::commands;
-use crate::models::Contact;
+use crate::events::{ChannelUpdated, ContactCreated, ContactDeleted, ContactUpdated, EventBus};
+use crate::models::{
+    grace_period_start, hash_token, BlockedContact, ChannelType, Contact, ContactKind,
+    DeletionPolicy, DoNotDisturb, GroupPolicy, MessagePolicy, PrivateChannelPolicy,
+    DELETION_GRACE_DAYS,
+};
+use chrono::Utc;
 
 pub struct ContactService<'a> {
     repository: &'a mut dyn ContactRepository,
+    /// The channels and messages of the contacts, which deleting a contact
+    /// changes
+    channel_repository: Option<&'a mut dyn ChannelRepository>,
+    message_repository: Option<&'a mut dyn MessageRepository>,
+    events: EventBus,
 }
 
 impl<'a> ContactService<'a> {
     pub fn new(repo: &'a mut dyn ContactRepository) -> Self {
-        ContactService { repository: repo }
+        ContactService {
+            repository: repo,
+            channel_repository: None,
+            message_repository: None,
+            events: EventBus::default(),
+        }
+    }
+
+    /// Publishes the changes to the given bus
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Lets the service delete contacts, along with their place in the channels
+    /// and their messages
+    pub fn with_channels(
+        mut self,
+        channel_repository: &'a mut dyn ChannelRepository,
+        message_repository: &'a mut dyn MessageRepository,
+    ) -> Self {
+        self.channel_repository = Some(channel_repository);
+        self.message_repository = Some(message_repository);
+        self
     }
 
     pub async fn list(
@@ -30,6 +66,7 @@ impl<'a> ContactService<'a> {
         cmd: &commands::CreateContact,
     ) -> Result<Contact, RepositoryError> {
         let contact = Contact::new(&cmd.name, &cmd.email);
+        #[allow(clippy::single_match)]
         match self.repository.find_by_email(&contact.email).await {
             Some(_) => {
                 return Err(RepositoryError {
@@ -39,9 +76,51 @@ impl<'a> ContactService<'a> {
             None => (),
         }
         self.repository.create(&contact).await?;
+        self.events.publish(ContactCreated {
+            contact: contact.clone(),
+        });
         Ok(contact)
     }
 
+    /// Creates a bot contact and returns it with its API key, which is not
+    /// shown again
+    pub async fn create_bot(
+        &mut self,
+        cmd: &commands::CreateContact,
+    ) -> Result<(Contact, String), RepositoryError> {
+        let (bot, api_key) = Contact::bot(&cmd.name, &cmd.email);
+        if self.repository.find_by_email(&bot.email).await.is_some() {
+            return Err(RepositoryError {
+                message: format!("Contact with email {} already exists", bot.email),
+            });
+        }
+        self.repository.create(&bot).await?;
+        self.events.publish(ContactCreated {
+            contact: bot.clone(),
+        });
+        Ok((bot, api_key))
+    }
+
+    /// Issues a new API key to the bot, which stops the previous one from working
+    pub async fn rotate_api_key(
+        &mut self,
+        bot: &Contact,
+    ) -> Result<(Contact, String), RepositoryError> {
+        let mut bot = bot.clone();
+        let api_key = bot.issue_api_key();
+        bot.updated_at = chrono::Utc::now();
+        self.repository.update(&bot).await?;
+        Ok((bot, api_key))
+    }
+
+    /// Returns the bot holding the API key
+    pub async fn authenticate_bot(&self, api_key: &str) -> Option<Contact> {
+        self.repository
+            .find_by_api_key_hash(&hash_token(api_key))
+            .await
+            .filter(|c| c.kind == ContactKind::Bot)
+    }
+
     pub async fn update_contact(
         &mut self,
         cmd: &commands::UpdateContact,
@@ -70,21 +149,257 @@ impl<'a> ContactService<'a> {
         }
 
         self.repository.update(&contact).await?;
+        self.events.publish(ContactUpdated {
+            contact: contact.clone(),
+        });
         Ok(contact)
     }
 
-    pub async fn delete_contact(&mut self, id: &str) -> Result<(), RepositoryError> {
-        let id_type = IdType::String(id.to_string());
-        self.repository.delete(&id_type).await
+    /// Sets the daily period without notifications, or clears it with `None`
+    pub async fn set_do_not_disturb(
+        &mut self,
+        id: &IdType,
+        do_not_disturb: Option<DoNotDisturb>,
+    ) -> Result<Contact, RepositoryError> {
+        if let Some(dnd) = &do_not_disturb {
+            if dnd.start_minute >= 24 * 60 || dnd.end_minute >= 24 * 60 {
+                return Err(RepositoryError {
+                    message: "Do not disturb times must be within the day".to_string(),
+                });
+            }
+            if dnd.utc_offset_minutes.abs() > 14 * 60 {
+                return Err(RepositoryError {
+                    message: "UTC offsets must be within 14 hours".to_string(),
+                });
+            }
+        }
+        let mut contact = match self.repository.get(id).await {
+            Some(c) => c,
+            None => {
+                return Err(RepositoryError {
+                    message: "Contact not found".to_string(),
+                })
+            }
+        };
+        contact.do_not_disturb = do_not_disturb;
+        contact.updated_at = chrono::Utc::now();
+        self.repository.update(&contact).await?;
+        Ok(contact)
+    }
+
+    /// Blocks a contact, or changes whether the block hides messages when the
+    /// contact is already blocked
+    pub async fn block_contact(
+        &mut self,
+        cmd: &commands::BlockContact,
+    ) -> Result<Contact, RepositoryError> {
+        if cmd.contact_id == cmd.blocked_id {
+            return Err(RepositoryError {
+                message: "Contacts cannot block themselves".to_string(),
+            });
+        }
+        let mut contact = self.get_contact(&cmd.contact_id).await?;
+        self.get_contact(&cmd.blocked_id).await?;
+        match contact
+            .blocked
+            .iter_mut()
+            .find(|b| b.contact_id == cmd.blocked_id)
+        {
+            Some(b) => b.hide_messages = cmd.hide_messages,
+            None => contact.blocked.push(BlockedContact {
+                contact_id: cmd.blocked_id.clone(),
+                hide_messages: cmd.hide_messages,
+                blocked_at: chrono::Utc::now(),
+            }),
+        }
+        contact.updated_at = chrono::Utc::now();
+        self.repository.update(&contact).await?;
+        Ok(contact)
+    }
+
+    pub async fn unblock_contact(
+        &mut self,
+        contact_id: &IdType,
+        blocked_id: &IdType,
+    ) -> Result<Contact, RepositoryError> {
+        let mut contact = self.get_contact(contact_id).await?;
+        if !contact.has_blocked(blocked_id) {
+            return Err(RepositoryError {
+                message: "Contact is not blocked".to_string(),
+            });
+        }
+        contact.blocked.retain(|b| &b.contact_id != blocked_id);
+        contact.updated_at = chrono::Utc::now();
+        self.repository.update(&contact).await?;
+        Ok(contact)
+    }
+
+    pub async fn list_blocked(
+        &self,
+        contact_id: &IdType,
+    ) -> Result<Vec<BlockedContact>, RepositoryError> {
+        Ok(self.get_contact(contact_id).await?.blocked)
+    }
+
+    async fn get_contact(&self, id: &IdType) -> Result<Contact, RepositoryError> {
+        match self.repository.get(id).await {
+            Some(c) => Ok(c),
+            None => Err(RepositoryError {
+                message: format!("Contact with id {id} not found"),
+            }),
+        }
+    }
+
+    /// Marks the contact deleted. It can be restored until the grace period
+    /// ends, when the purge applies the policy to the data referencing it.
+    pub async fn delete_contact(
+        &mut self,
+        id: &IdType,
+        policy: &DeletionPolicy,
+    ) -> Result<Contact, RepositoryError> {
+        let mut contact = self.get_contact(id).await?;
+        contact.deleted_at = Some(Utc::now());
+        contact.deletion_policy = Some(policy.clone());
+        contact.updated_at = Utc::now();
+        self.repository.update(&contact).await?;
+        self.events.publish(ContactDeleted {
+            contact_id: contact.id(),
+        });
+        Ok(contact)
+    }
+
+    /// Brings back a contact deleted during the grace period
+    pub async fn restore_contact(&mut self, id: &IdType) -> Result<Contact, RepositoryError> {
+        let mut contact = match self.repository.get_with_deleted(id).await {
+            Some(c) if c.deleted_at.is_some() => c,
+            _ => {
+                return Err(RepositoryError {
+                    message: format!("No deleted contact with id {id} to restore"),
+                })
+            }
+        };
+        if contact
+            .deleted_at
+            .is_some_and(|d| d <= grace_period_start())
+        {
+            return Err(RepositoryError {
+                message: format!(
+                    "Contacts can only be restored within {DELETION_GRACE_DAYS} days of their deletion"
+                ),
+            });
+        }
+        if self
+            .repository
+            .find_by_email(&contact.email)
+            .await
+            .is_some()
+        {
+            return Err(RepositoryError {
+                message: format!("Contact with email {} already exists", contact.email),
+            });
+        }
+        contact.deleted_at = None;
+        contact.deletion_policy = None;
+        contact.updated_at = Utc::now();
+        self.repository.update(&contact).await?;
+        self.events.publish(ContactUpdated {
+            contact: contact.clone(),
+        });
+        Ok(contact)
+    }
+
+    /// Purges the contacts deleted before the grace period and returns the
+    /// changes made for each
+    pub async fn purge_deleted(&mut self) -> Result<Vec<ContactDeletion>, RepositoryError> {
+        let deleted = self
+            .repository
+            .find_deleted_before(grace_period_start())
+            .await?;
+        let mut deletions = Vec::new();
+        for contact in deleted.iter() {
+            deletions.push(self.purge_contact(contact).await?);
+        }
+        Ok(deletions)
+    }
+
+    /// Removes the deleted contact and, following its policy, removes it from
+    /// its groups, orphans or deletes its private channels and anonymizes its
+    /// messages. The changes are made together or not at all.
+    async fn purge_contact(
+        &mut self,
+        contact: &Contact,
+    ) -> Result<ContactDeletion, RepositoryError> {
+        let id = &contact.id();
+        let policy = contact.deletion_policy.clone().unwrap_or_default();
+        let (channel_repository, message_repository) =
+            match (&mut self.channel_repository, &mut self.message_repository) {
+                (Some(c), Some(m)) => (c, m),
+                _ => {
+                    return Err(RepositoryError {
+                        message: "Contacts cannot be deleted without their channels".to_string(),
+                    })
+                }
+            };
+        // Channels deleted within the grace period keep the contact until purged
+        let channels = channel_repository
+            .find_by_contact_id_with_deleted(id)
+            .await?;
+        let mut deletion = ContactDeletion {
+            contact_id: id.clone(),
+            left_channel_ids: vec![],
+            orphaned_channel_ids: vec![],
+            deleted_channel_ids: vec![],
+            anonymize_messages: policy.messages == MessagePolicy::Anonymize,
+        };
+        for channel in channels {
+            let ids = match (&channel.channel_type, &policy.private_channels) {
+                (ChannelType::Group, _) if policy.groups == GroupPolicy::Keep => continue,
+                (ChannelType::Group, _) => &mut deletion.left_channel_ids,
+                (ChannelType::Private, PrivateChannelPolicy::Delete) => {
+                    &mut deletion.deleted_channel_ids
+                }
+                (ChannelType::Private, PrivateChannelPolicy::Orphan) => {
+                    &mut deletion.orphaned_channel_ids
+                }
+            };
+            ids.push(channel.id());
+        }
+        self.repository
+            .delete_cascade(
+                &deletion,
+                &mut **channel_repository,
+                &mut **message_repository,
+            )
+            .await?;
+        let updated = deletion
+            .left_channel_ids
+            .iter()
+            .chain(deletion.orphaned_channel_ids.iter());
+        for channel_id in updated {
+            if let Some(channel) = channel_repository.get(channel_id).await {
+                self.events.publish(ChannelUpdated { channel });
+            }
+        }
+        Ok(deletion)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::adapters::{mock_contact_repo, Model, RepositoryError};
+    use crate::adapters::contact_repository::ContactRepository;
+    use crate::adapters::{
+        mock_channel_repo, mock_contact_repo, mock_message_repo, IdType, Model, Repository,
+        RepositoryError, SoftDeleteRepository,
+    };
     use crate::commands;
-    use crate::models::Contact;
-    use crate::services::contact_handlers::{ContactService, Repository};
+    use crate::events::{record, ContactCreated, ContactUpdated, EventBus, Recorded};
+    use crate::models::{
+        deleted_contact_id, BlockedContact, Channel, ChannelPreferences, ChannelType, Contact,
+        DeletionPolicy, Message, MessageContent, MessagePolicy, PrivateChannelPolicy, SystemEvent,
+        DELETION_GRACE_DAYS,
+    };
+    use crate::services::contact_handlers::ContactService;
+    use chrono::{Duration, Utc};
 
     async fn _create_contact(service: &mut ContactService<'_>) -> Result<Contact, RepositoryError> {
         let cmd = commands::CreateContact {
@@ -140,6 +455,292 @@ mod tests {
         let (_total, contacts) = service.repository.list(None, None).await.unwrap();
         assert_eq!(contacts.len(), 0);
     }
+
+    #[actix_web::test]
+    async fn deletes_contacts_following_the_policy() {
+        let mut repo = mock_contact_repo();
+        let mut channel_repo = mock_channel_repo();
+        let mut message_repo = mock_message_repo();
+        let ned = repo
+            .create(&Contact::new("Eddard Stark", "ned@winterfell.com"))
+            .await
+            .unwrap()
+            .id();
+        let mut catelyn = Contact::new("Catelyn Stark", "catelyn@riverrun.com");
+        catelyn.blocked.push(BlockedContact {
+            contact_id: ned.clone(),
+            hide_messages: false,
+            blocked_at: Utc::now(),
+        });
+        let catelyn = repo.create(&catelyn).await.unwrap().id();
+        let robert = repo
+            .create(&Contact::new("Robert Baratheon", "robert@kingslanding.com"))
+            .await
+            .unwrap()
+            .id();
+        let mut council = Channel::new(
+            "Small Council",
+            ChannelType::Group,
+            &[ned.clone(), robert.clone()],
+        );
+        council.preferences.push(ChannelPreferences::new(&ned));
+        channel_repo.create(&council).await.unwrap();
+        let letters = Channel::new("", ChannelType::Private, &[ned.clone(), catelyn.clone()]);
+        channel_repo.create(&letters).await.unwrap();
+        let mut counsel = Message::new(
+            &council.id(),
+            &ned,
+            &council.id(),
+            &MessageContent::text("@Robert Baratheon, beware the Lannisters"),
+        );
+        counsel.mentions = vec![robert.clone()];
+        message_repo.create(&counsel).await.unwrap();
+        let mut reply = Message::new(
+            &council.id(),
+            &robert,
+            &council.id(),
+            &MessageContent::text("@Eddard Stark, you worry too much"),
+        );
+        reply.mentions = vec![ned.clone()];
+        message_repo.create(&reply).await.unwrap();
+        let joined = Message::system(
+            &council.id(),
+            &ned,
+            SystemEvent::MemberJoined {
+                contact_id: ned.clone(),
+            },
+        );
+        message_repo.create(&joined).await.unwrap();
+        let letter = Message::new(
+            &letters.id(),
+            &ned,
+            &catelyn,
+            &MessageContent::text("Winter is coming"),
+        );
+        message_repo.create(&letter).await.unwrap();
+
+        let mut service = ContactService::new(&mut repo);
+        let policy = DeletionPolicy {
+            private_channels: PrivateChannelPolicy::Delete,
+            ..DeletionPolicy::default()
+        };
+        service.delete_contact(&ned, &policy).await.unwrap();
+        assert!(service.purge_deleted().await.unwrap().is_empty());
+        end_grace_period(service.repository, &ned).await;
+        assert!(service.purge_deleted().await.is_err());
+
+        let mut service =
+            ContactService::new(&mut repo).with_channels(&mut channel_repo, &mut message_repo);
+        let deletions = service.purge_deleted().await.unwrap();
+        let deletion = &deletions[0];
+        assert!(deletion.anonymize_messages);
+        assert_eq!(deletion.deleted_channel_ids, vec![letters.id()]);
+        assert_eq!(deletion.left_channel_ids, vec![council.id()]);
+
+        assert!(repo.get_with_deleted(&ned).await.is_none());
+        assert!(repo.get(&catelyn).await.unwrap().blocked.is_empty());
+        let council = channel_repo.get(&council.id()).await.unwrap();
+        assert_eq!(council.contact_ids, vec![robert.clone()]);
+        assert!(council.preferences.is_empty());
+        assert!(channel_repo.get_with_deleted(&letters.id()).await.is_none());
+
+        assert!(message_repo.get(&letter.id()).await.is_none());
+        let counsel = message_repo.get(&counsel.id()).await.unwrap();
+        assert_eq!(counsel.from, deleted_contact_id());
+        assert_eq!(counsel.mentions, vec![robert]);
+        assert!(message_repo
+            .get(&reply.id())
+            .await
+            .unwrap()
+            .mentions
+            .is_empty());
+        let joined = message_repo.get(&joined.id()).await.unwrap();
+        assert_eq!(
+            joined.content,
+            MessageContent::System {
+                event: SystemEvent::MemberJoined {
+                    contact_id: deleted_contact_id()
+                }
+            }
+        );
+    }
+
+    #[actix_web::test]
+    async fn orphans_private_channels_by_default() {
+        let mut repo = mock_contact_repo();
+        let mut channel_repo = mock_channel_repo();
+        let mut message_repo = mock_message_repo();
+        let jon = repo
+            .create(&Contact::new("Jon Snow", "jon@winterfell.com"))
+            .await
+            .unwrap()
+            .id();
+        let arya = repo
+            .create(&Contact::new("Arya Stark", "arya@winterfell.com"))
+            .await
+            .unwrap()
+            .id();
+        let letters = Channel::new("", ChannelType::Private, &[jon.clone(), arya.clone()]);
+        channel_repo.create(&letters).await.unwrap();
+        let letter = Message::new(
+            &letters.id(),
+            &jon,
+            &arya,
+            &MessageContent::text("Stick them with the pointy end"),
+        );
+        message_repo.create(&letter).await.unwrap();
+        // Deleted, but still restorable until its own grace period ends
+        let mut watch = Channel::new(
+            "Night's Watch",
+            ChannelType::Group,
+            &[jon.clone(), arya.clone()],
+        );
+        watch.deleted_at = Some(Utc::now());
+        channel_repo.create(&watch).await.unwrap();
+
+        let mut service =
+            ContactService::new(&mut repo).with_channels(&mut channel_repo, &mut message_repo);
+        let policy = DeletionPolicy {
+            messages: MessagePolicy::Keep,
+            ..DeletionPolicy::default()
+        };
+        service.delete_contact(&jon, &policy).await.unwrap();
+        end_grace_period(service.repository, &jon).await;
+        let deletion = service.purge_deleted().await.unwrap().remove(0);
+        assert!(deletion.deleted_channel_ids.is_empty());
+        assert_eq!(deletion.orphaned_channel_ids, vec![letters.id()]);
+        assert_eq!(deletion.left_channel_ids, vec![watch.id()]);
+
+        let letters = channel_repo.get(&letters.id()).await.unwrap();
+        assert_eq!(
+            letters.contact_ids,
+            vec![deleted_contact_id(), arya.clone()]
+        );
+        let watch = channel_repo.get_with_deleted(&watch.id()).await.unwrap();
+        assert_eq!(watch.contact_ids, vec![arya]);
+        let letter = message_repo.get(&letter.id()).await.unwrap();
+        assert_eq!(letter.from, jon, "Kept as sent");
+    }
+
+    #[actix_web::test]
+    async fn restores_contacts_during_the_grace_period() {
+        let mut repo = mock_contact_repo();
+        let mut service = ContactService::new(&mut repo);
+        let jon = _create_contact(&mut service).await.unwrap().id();
+        service
+            .delete_contact(&jon, &DeletionPolicy::default())
+            .await
+            .unwrap();
+        assert!(service.repository.get(&jon).await.is_none());
+        assert_eq!(service.list(None, None).await.unwrap().0, 0);
+        assert!(service
+            .repository
+            .find_by_email("jon@winterfell.com")
+            .await
+            .is_none());
+
+        let restored = service.restore_contact(&jon).await.unwrap();
+        assert!(restored.deleted_at.is_none());
+        assert!(service.repository.get(&jon).await.is_some());
+        assert!(service.restore_contact(&jon).await.is_err());
+
+        service
+            .delete_contact(&jon, &DeletionPolicy::default())
+            .await
+            .unwrap();
+        end_grace_period(service.repository, &jon).await;
+        assert!(service.restore_contact(&jon).await.is_err());
+    }
+
+    /// Moves the deletion of the contact to before the grace period
+    async fn end_grace_period(repo: &mut dyn ContactRepository, id: &IdType) {
+        let mut contact = repo.get_with_deleted(id).await.unwrap();
+        contact.deleted_at = Some(Utc::now() - Duration::days(DELETION_GRACE_DAYS + 1));
+        repo.update(&contact).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn publishes_contact_changes() {
+        let mut repo = mock_contact_repo();
+        let events = EventBus::default();
+        let created = record::<ContactCreated>(&events);
+        let updated = record::<ContactUpdated>(&events);
+        let mut service = ContactService::new(&mut repo).with_events(events);
+        let contact = _create_contact(&mut service).await.unwrap();
+        // A duplicate email is rejected without an event
+        assert!(_create_contact(&mut service).await.is_err());
+
+        let cmd = commands::UpdateContact {
+            id: contact.id(),
+            name: Some("Lord Commander".to_string()),
+            email: None,
+        };
+        service.update_contact(&cmd).await.unwrap();
+
+        assert_eq!(created.send(Recorded).await.unwrap(), 1);
+        assert_eq!(updated.send(Recorded).await.unwrap(), 1);
+    }
+
+    #[actix_web::test]
+    async fn can_block_and_unblock_contacts() {
+        let mut repo = mock_contact_repo();
+        let mut service = ContactService::new(&mut repo);
+        let jon = _create_contact(&mut service).await.unwrap();
+        let cmd = commands::CreateContact {
+            name: "Ramsay Bolton".to_string(),
+            email: "ramsay@dreadfort.com".to_string(),
+        };
+        let ramsay = service.create_contact(&cmd).await.unwrap();
+
+        let block = commands::BlockContact {
+            contact_id: jon.id(),
+            blocked_id: jon.id(),
+            hide_messages: false,
+        };
+        assert!(service.block_contact(&block).await.is_err());
+        let block = commands::BlockContact {
+            blocked_id: ramsay.id(),
+            ..block
+        };
+        service.block_contact(&block).await.unwrap();
+        // Blocking again only updates the block
+        let contact = service.block_contact(&block).await.unwrap();
+        assert_eq!(contact.blocked.len(), 1);
+        assert!(contact.has_blocked(&ramsay.id()));
+
+        service
+            .unblock_contact(&jon.id(), &ramsay.id())
+            .await
+            .unwrap();
+        assert!(service.list_blocked(&jon.id()).await.unwrap().is_empty());
+        assert!(service
+            .unblock_contact(&jon.id(), &ramsay.id())
+            .await
+            .is_err());
+    }
+
+    #[actix_web::test]
+    async fn authenticates_bots_by_api_key() {
+        let mut repo = mock_contact_repo();
+        let mut service = ContactService::new(&mut repo);
+        let cmd = commands::CreateContact {
+            name: "Maester Aemon".to_string(),
+            email: "aemon@thewall.com".to_string(),
+        };
+        let (bot, api_key) = service.create_bot(&cmd).await.unwrap();
+        assert_eq!(
+            service.authenticate_bot(&api_key).await.unwrap().id(),
+            bot.id()
+        );
+
+        let (_, rotated) = service.rotate_api_key(&bot).await.unwrap();
+        assert!(service.authenticate_bot(&api_key).await.is_none());
+        assert!(service.authenticate_bot(&rotated).await.is_some());
+
+        // People cannot authenticate as bots
+        let person = _create_contact(&mut service).await.unwrap();
+        assert!(person.api_key_hash.is_none());
+    }
 }
 
 #[cfg(test)]
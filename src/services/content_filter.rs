@@ -0,0 +1,144 @@
+use crate::services::pipeline::{Draft, MessageProcessor};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// A keyword or pattern checked against the text of every message sent
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FilterRule {
+    /// A whole word matched regardless of case, or a regular expression
+    /// when `regex` is set
+    pub pattern: String,
+    #[serde(default)]
+    pub regex: bool,
+    pub action: FilterAction,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterAction {
+    /// Refuses the message
+    Reject,
+    /// Stores the message and reports it to the moderators
+    Flag,
+    /// Stores the message with the matches replaced by asterisks
+    Mask,
+}
+
+/// The text of a message that passed the filter
+#[derive(Debug, PartialEq)]
+pub struct Filtered {
+    pub text: String,
+    /// The patterns of the flag rules the text matched
+    pub flagged_by: Vec<String>,
+}
+
+/// The compiled filter rules. Clones share them, and the default filter lets
+/// every message through.
+#[derive(Clone, Default)]
+pub struct ContentFilter {
+    rules: Arc<Vec<(FilterRule, Regex)>>,
+}
+
+impl ContentFilter {
+    pub fn new(rules: Vec<FilterRule>) -> Result<Self, String> {
+        let mut compiled = Vec::with_capacity(rules.len());
+        for rule in rules {
+            let pattern = if rule.regex {
+                rule.pattern.clone()
+            } else {
+                format!(r"(?i)\b{}\b", regex::escape(&rule.pattern))
+            };
+            match Regex::new(&pattern) {
+                Ok(r) => compiled.push((rule, r)),
+                Err(e) => return Err(format!("Invalid pattern {}: {e}", rule.pattern)),
+            }
+        }
+        Ok(ContentFilter {
+            rules: Arc::new(compiled),
+        })
+    }
+
+    /// Runs the rules over the text in order, returning the pattern of the
+    /// first reject rule that matches as the error
+    pub fn apply(&self, text: &str) -> Result<Filtered, String> {
+        let mut filtered = Filtered {
+            text: text.to_string(),
+            flagged_by: vec![],
+        };
+        for (rule, regex) in self.rules.iter() {
+            if !regex.is_match(&filtered.text) {
+                continue;
+            }
+            match rule.action {
+                FilterAction::Reject => return Err(rule.pattern.clone()),
+                FilterAction::Flag => filtered.flagged_by.push(rule.pattern.clone()),
+                FilterAction::Mask => {
+                    let masked = regex.replace_all(&filtered.text, |c: &regex::Captures| {
+                        "*".repeat(c[0].chars().count())
+                    });
+                    filtered.text = masked.into_owned();
+                }
+            }
+        }
+        Ok(filtered)
+    }
+}
+
+/// Masks the matches in the text and records the flag rules it matched
+impl MessageProcessor for ContentFilter {
+    fn process(&self, draft: &mut Draft) -> Result<(), String> {
+        let body = match draft.body() {
+            Some(b) => b,
+            None => return Ok(()),
+        };
+        match self.apply(body) {
+            Ok(f) => {
+                draft.set_body(&f.text);
+                draft.flagged_by.extend(f.flagged_by);
+                Ok(())
+            }
+            Err(_) => Err("The message was rejected by the content filter".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ContentFilter, FilterAction, FilterRule};
+
+    fn rule(pattern: &str, regex: bool, action: FilterAction) -> FilterRule {
+        FilterRule {
+            pattern: pattern.to_string(),
+            regex,
+            action,
+        }
+    }
+
+    #[test]
+    fn rejects_flags_and_masks_matches() {
+        let filter = ContentFilter::new(vec![
+            rule("red wedding", false, FilterAction::Reject),
+            rule("kingslayer", false, FilterAction::Mask),
+            rule(r"\bdracarys\b", true, FilterAction::Flag),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            filter.apply("Invitation to the Red Wedding"),
+            Err("red wedding".to_string())
+        );
+        let filtered = filter.apply("The Kingslayer says dracarys").unwrap();
+        assert_eq!(filtered.text, "The ********** says dracarys");
+        assert_eq!(filtered.flagged_by, vec![r"\bdracarys\b".to_string()]);
+        let filtered = filter.apply("Kingslayers are not whole words").unwrap();
+        assert_eq!(filtered.text, "Kingslayers are not whole words");
+        assert!(filtered.flagged_by.is_empty());
+    }
+
+    #[test]
+    fn refuses_invalid_patterns() {
+        assert!(ContentFilter::new(vec![rule("(", true, FilterAction::Flag)]).is_err());
+        assert!(ContentFilter::new(vec![rule("(", false, FilterAction::Flag)]).is_ok());
+    }
+}
@@ -0,0 +1,824 @@
+use crate::adapters::channel_repository::ChannelRepository;
+use crate::adapters::contact_repository::ContactRepository;
+use crate::adapters::message_repository::MessageRepository;
+use crate::adapters::{IdType, Model};
+use crate::events::{ChannelCreated, EventBus, MessageFlagged};
+use crate::models::{
+    deleted_contact_id, Channel, ChannelType, Contact, Message, MessageContent, SystemEvent,
+};
+use crate::services::pipeline::{Draft, Pipeline};
+use crate::services::MAX_SYNC_BATCH;
+use chrono::Utc;
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+/// A line of a channel export. The first line describes the channel and the
+/// others its messages, in sequence order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ExportRecord {
+    Channel {
+        channel: Channel,
+    },
+    Message {
+        message: Message,
+        /// The name of the sender when the channel was exported
+        sender_name: Option<String>,
+    },
+}
+
+impl ExportRecord {
+    /// Reads the records of an NDJSON export, skipping the blank lines
+    pub fn parse_ndjson(text: &str) -> Result<Vec<ExportRecord>, ExportError> {
+        let mut records = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<ExportRecord>(line) {
+                Ok(r) => records.push(r),
+                Err(e) => {
+                    return Err(ExportError {
+                        message: format!("Line {}: {e}", i + 1),
+                    })
+                }
+            }
+        }
+        Ok(records)
+    }
+}
+
+/// A line of the archive of a contact's personal data. The contact comes
+/// first, then the channels they belong to and the messages they sent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ArchiveRecord {
+    Contact { contact: Contact },
+    Channel { channel: Channel },
+    Message { message: Message },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// One JSON record per line, which the import reads back
+    #[default]
+    Ndjson,
+    /// A transcript to read in a browser, with its styles inlined
+    Html,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "application/x-ndjson",
+            ExportFormat::Html => "text/html; charset=utf-8",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "ndjson",
+            ExportFormat::Html => "html",
+        }
+    }
+
+    /// Renders the record as it appears in the export
+    pub fn render(&self, record: &ExportRecord) -> String {
+        match self {
+            ExportFormat::Ndjson => {
+                let mut line = serde_json::to_string(record).unwrap();
+                line.push('\n');
+                line
+            }
+            ExportFormat::Html => render_html(record),
+        }
+    }
+
+    /// Returns what follows the last record
+    pub fn footer(&self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "",
+            ExportFormat::Html => "</ol>\n</body>\n</html>\n",
+        }
+    }
+}
+
+/// Where an export is in the channel history
+pub struct ExportCursor {
+    channel: Channel,
+    contact_id: IdType,
+    after_seq: i64,
+    /// The senders looked up so far, `None` for those not found
+    senders: BTreeMap<IdType, Option<Contact>>,
+    done: bool,
+}
+
+impl ExportCursor {
+    /// Returns the record the export starts with
+    pub fn header(&self) -> ExportRecord {
+        ExportRecord::Channel {
+            channel: self.channel.for_member(&self.contact_id),
+        }
+    }
+}
+
+/// Where the archive of a contact is in their sent messages
+pub struct ArchiveCursor {
+    contact: Contact,
+    channels: Vec<Channel>,
+    offset: u64,
+    done: bool,
+}
+
+impl ArchiveCursor {
+    /// Returns the records the archive starts with
+    pub fn header(&self) -> Vec<ArchiveRecord> {
+        let mut records = vec![ArchiveRecord::Contact {
+            contact: self.contact.clone(),
+        }];
+        for channel in self.channels.iter() {
+            records.push(ArchiveRecord::Channel {
+                channel: channel.clone(),
+            });
+        }
+        records
+    }
+}
+
+pub struct ExportService<'a> {
+    repository: &'a mut dyn MessageRepository,
+    channel_repository: &'a mut dyn ChannelRepository,
+    contact_repository: &'a mut dyn ContactRepository,
+    events: EventBus,
+    pipeline: Pipeline,
+}
+
+impl<'a> ExportService<'a> {
+    pub fn new(
+        repo: &'a mut dyn MessageRepository,
+        channel_repository: &'a mut dyn ChannelRepository,
+        contact_repository: &'a mut dyn ContactRepository,
+    ) -> Self {
+        ExportService {
+            repository: repo,
+            channel_repository,
+            contact_repository,
+            events: EventBus::default(),
+            pipeline: Pipeline::default(),
+        }
+    }
+
+    /// Publishes the changes to the given bus
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Runs the imported texts through the given stages before storing them
+    pub fn with_pipeline(mut self, pipeline: Pipeline) -> Self {
+        self.pipeline = pipeline;
+        self
+    }
+
+    /// Starts exporting the channel for one of its members
+    pub async fn start_export(
+        &self,
+        channel_id: &IdType,
+        contact_id: &IdType,
+    ) -> Result<ExportCursor, ExportError> {
+        let channel = match self.channel_repository.get(channel_id).await {
+            Some(c) => c,
+            None => {
+                return Err(ExportError {
+                    message: format!("Channel with id {channel_id} not found"),
+                })
+            }
+        };
+        if !channel.contact_ids.contains(contact_id) {
+            return Err(ExportError {
+                message: format!("Contact with id {contact_id} is not a channel member"),
+            });
+        }
+        Ok(ExportCursor {
+            channel,
+            contact_id: contact_id.clone(),
+            after_seq: 0,
+            senders: BTreeMap::new(),
+            done: false,
+        })
+    }
+
+    /// Returns the next messages of the export, none once the history is
+    /// exhausted. Messages the member would not see in the channel are left out.
+    pub async fn next_page(
+        &self,
+        cursor: &mut ExportCursor,
+    ) -> Result<Vec<ExportRecord>, ExportError> {
+        if cursor.done {
+            return Ok(vec![]);
+        }
+        loop {
+            let messages = match self
+                .repository
+                .get_after_seq(&cursor.channel.id(), cursor.after_seq, MAX_SYNC_BATCH)
+                .await
+            {
+                Ok(m) => m,
+                Err(e) => {
+                    return Err(ExportError {
+                        message: e.to_string(),
+                    })
+                }
+            };
+            cursor.done = (messages.len() as i64) < MAX_SYNC_BATCH;
+            if let Some(last) = messages.last() {
+                cursor.after_seq = last.seq;
+            }
+            let mut records = Vec::new();
+            for mut message in messages {
+                if !cursor.senders.contains_key(&message.from) {
+                    let sender = self.contact_repository.get(&message.from).await;
+                    cursor.senders.insert(message.from.clone(), sender);
+                }
+                let sender = cursor.senders[&message.from].as_ref();
+                if sender.is_some_and(|s| s.hides_messages_from(&cursor.contact_id)) {
+                    continue;
+                }
+                let sender_name = match (&message.bot_name, sender) {
+                    (Some(name), _) => Some(name.clone()),
+                    (None, Some(s)) => Some(s.name.clone()),
+                    (None, None) => {
+                        message.from = deleted_contact_id();
+                        None
+                    }
+                };
+                records.push(ExportRecord::Message {
+                    message,
+                    sender_name,
+                });
+            }
+            // A page hidden entirely would look like the end of the history
+            if !records.is_empty() || cursor.done {
+                return Ok(records);
+            }
+        }
+    }
+
+    /// Starts the archive of everything tied to the contact, for a privacy
+    /// request. Moderators can archive any contact, the others only themselves.
+    pub async fn start_archive(
+        &self,
+        requested_by: &IdType,
+        contact_id: &IdType,
+    ) -> Result<ArchiveCursor, ExportError> {
+        if requested_by != contact_id {
+            match self.contact_repository.get(requested_by).await {
+                Some(c) if c.moderator => {}
+                _ => {
+                    return Err(ExportError {
+                        message: "Only moderators can archive other contacts".to_string(),
+                    })
+                }
+            }
+        }
+        let mut contact = match self.contact_repository.get(contact_id).await {
+            Some(c) => c,
+            None => {
+                return Err(ExportError {
+                    message: format!("Contact with id {contact_id} not found"),
+                })
+            }
+        };
+        // The key hash is a credential rather than personal data
+        contact.api_key_hash = None;
+        let mut channels = match self.channel_repository.find_by_contact_id(contact_id).await {
+            Ok(c) => c,
+            Err(e) => {
+                return Err(ExportError {
+                    message: e.to_string(),
+                })
+            }
+        };
+        // The settings of the other members are theirs
+        for channel in channels.iter_mut() {
+            channel.preferences.retain(|p| &p.contact_id == contact_id);
+        }
+        Ok(ArchiveCursor {
+            contact,
+            channels,
+            offset: 0,
+            done: false,
+        })
+    }
+
+    /// Returns the next messages the contact sent, none once all are archived
+    pub async fn next_archive_page(
+        &self,
+        cursor: &mut ArchiveCursor,
+    ) -> Result<Vec<ArchiveRecord>, ExportError> {
+        if cursor.done {
+            return Ok(vec![]);
+        }
+        let messages = match self
+            .repository
+            .find_by_sender(&cursor.contact.id(), MAX_SYNC_BATCH, cursor.offset)
+            .await
+        {
+            Ok(m) => m,
+            Err(e) => {
+                return Err(ExportError {
+                    message: e.to_string(),
+                })
+            }
+        };
+        cursor.done = (messages.len() as i64) < MAX_SYNC_BATCH;
+        cursor.offset += messages.len() as u64;
+        Ok(messages
+            .into_iter()
+            .map(|message| ArchiveRecord::Message { message })
+            .collect())
+    }
+
+    /// Recreates an exported channel and its messages with their original
+    /// timestamps, for moderators restoring a history. The channel and
+    /// messages get new ids, only the members that still exist are added
+    /// back, and the texts go through the pipeline like sent messages.
+    pub async fn import_channel(
+        &mut self,
+        imported_by: &IdType,
+        records: Vec<ExportRecord>,
+    ) -> Result<Channel, ExportError> {
+        match self.contact_repository.get(imported_by).await {
+            Some(c) if c.moderator => {}
+            _ => {
+                return Err(ExportError {
+                    message: "Only moderators can import channels".to_string(),
+                })
+            }
+        }
+        let mut records = records.into_iter();
+        let exported = match records.next() {
+            Some(ExportRecord::Channel { channel }) => channel,
+            _ => {
+                return Err(ExportError {
+                    message: "Exports must start with the channel".to_string(),
+                })
+            }
+        };
+        let mut channel = exported.clone();
+        channel.id = Some(ObjectId::new());
+        channel.contact_ids.clear();
+        for id in exported.contact_ids.iter() {
+            if self.contact_repository.get(id).await.is_some() {
+                channel.contact_ids.push(id.clone());
+            }
+        }
+        if channel.channel_type == ChannelType::Private && channel.contact_ids.len() != 2 {
+            return Err(ExportError {
+                message: "Private channels must have exactly 2 contacts".to_string(),
+            });
+        }
+        channel
+            .preferences
+            .retain(|p| channel.contact_ids.contains(&p.contact_id));
+
+        let mut flagged = Vec::new();
+        let mut res = self
+            .import_messages(&exported, &mut channel, records.collect(), &mut flagged)
+            .await;
+        if res.is_ok() {
+            res = match self.channel_repository.create(&channel).await {
+                Ok(_) => Ok(()),
+                Err(e) => Err(ExportError {
+                    message: e.to_string(),
+                }),
+            };
+        }
+        if let Err(e) = res {
+            // Nothing points to the messages stored so far
+            let _ = self.repository.delete_by_channel_id(&channel.id()).await;
+            return Err(e);
+        }
+        self.events.publish(ChannelCreated {
+            channel: channel.clone(),
+        });
+        for (message, rules) in flagged {
+            self.events.publish(MessageFlagged {
+                channel: channel.clone(),
+                message,
+                rules,
+            });
+        }
+        Ok(channel)
+    }
+
+    /// Stores the exported messages in the new channel and points its pins to
+    /// them, collecting the messages the content filter flagged
+    async fn import_messages(
+        &mut self,
+        exported: &Channel,
+        channel: &mut Channel,
+        records: Vec<ExportRecord>,
+        flagged: &mut Vec<(Message, Vec<String>)>,
+    ) -> Result<(), ExportError> {
+        let members = recorded_members(exported, &records);
+        let now = Utc::now();
+        let mut new_ids: BTreeMap<IdType, IdType> = BTreeMap::new();
+        for record in records {
+            let mut message = match record {
+                ExportRecord::Message { message, .. } => message,
+                ExportRecord::Channel { .. } => {
+                    return Err(ExportError {
+                        message: "Exports can only contain one channel".to_string(),
+                    })
+                }
+            };
+            if message.channel_id != exported.id() || message.is_expired(now) {
+                continue;
+            }
+            let old_id = message.id();
+            if message.bot_name.is_none() && !members.contains(&message.from) {
+                return Err(ExportError {
+                    message: format!("Message {old_id} is not from a channel member"),
+                });
+            }
+            message.id = Some(ObjectId::new());
+            message.channel_id = channel.id();
+            if message.to == exported.id() {
+                message.to = channel.id();
+            }
+            // Retried sends must not find the imported copy
+            message.client_message_id = None;
+            message
+                .mentions
+                .retain(|id| channel.contact_ids.contains(id));
+            match &mut message.content {
+                MessageContent::System { event } => remap_event(event, &new_ids),
+                content => {
+                    let mut draft = Draft::new(Some(channel.id()), &message.from, content);
+                    if let Err(reason) = self.pipeline.run(&mut draft) {
+                        return Err(ExportError {
+                            message: format!("Message {old_id}: {reason}"),
+                        });
+                    }
+                    message.content = draft.content;
+                    message.links = draft.links;
+                    if !draft.flagged_by.is_empty() {
+                        flagged.push((message.clone(), draft.flagged_by));
+                    }
+                }
+            }
+            message.seq = match self.repository.next_seq(&channel.id()).await {
+                Ok(seq) => seq,
+                Err(e) => {
+                    return Err(ExportError {
+                        message: e.to_string(),
+                    })
+                }
+            };
+            if let Err(e) = self.repository.create(&message).await {
+                return Err(ExportError {
+                    message: e.to_string(),
+                });
+            }
+            new_ids.insert(old_id, message.id());
+        }
+        channel.pinned_message_ids = exported
+            .pinned_message_ids
+            .iter()
+            .filter_map(|id| new_ids.get(id).cloned())
+            .collect();
+        Ok(())
+    }
+}
+
+/// Returns the contacts the export records as members at some point: the
+/// current members, those who joined or left, and the deleted placeholder
+fn recorded_members(exported: &Channel, records: &[ExportRecord]) -> Vec<IdType> {
+    let mut members = exported.contact_ids.clone();
+    members.push(deleted_contact_id());
+    for record in records {
+        if let ExportRecord::Message { message, .. } = record {
+            if let MessageContent::System {
+                event:
+                    SystemEvent::MemberJoined { contact_id } | SystemEvent::MemberLeft { contact_id },
+            } = &message.content
+            {
+                if !members.contains(contact_id) {
+                    members.push(contact_id.clone());
+                }
+            }
+        }
+    }
+    members
+}
+
+/// Points the events about messages to their imported copies
+fn remap_event(event: &mut SystemEvent, new_ids: &BTreeMap<IdType, IdType>) {
+    match event {
+        SystemEvent::MessagePinned { message_id } | SystemEvent::MessageUnpinned { message_id } => {
+            if let Some(id) = new_ids.get(message_id) {
+                *message_id = id.clone();
+            }
+        }
+        _ => {}
+    }
+}
+
+fn render_html(record: &ExportRecord) -> String {
+    match record {
+        ExportRecord::Channel { channel } => {
+            let title = escape_html(channel.name.as_deref().unwrap_or("Direct messages"));
+            format!(
+                "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+                 <title>{title}</title>\n<style>{HTML_STYLE}</style>\n</head>\n<body>\n\
+                 <h1>{title}</h1>\n<p class=\"meta\">Exported {}</p>\n<ol>\n",
+                Utc::now().format("%Y-%m-%d %H:%M UTC")
+            )
+        }
+        ExportRecord::Message {
+            message,
+            sender_name,
+        } => {
+            let sender = escape_html(sender_name.as_deref().unwrap_or("Deleted contact"));
+            let time = message.created_at.format("%Y-%m-%d %H:%M");
+            let body = match &message.content {
+                MessageContent::Text { text } | MessageContent::Markdown { text } => {
+                    format!("<p>{}</p>", escape_html(text).replace('\n', "<br>"))
+                }
+                MessageContent::Attachment { url, name, .. } => format!(
+                    "<p><a href=\"{}\">{}</a></p>",
+                    escape_html(url),
+                    escape_html(name.as_deref().unwrap_or(url))
+                ),
+                MessageContent::System { event } => {
+                    format!("<p class=\"system\">{}</p>", describe_event(event))
+                }
+            };
+            format!(
+                "<li><span class=\"time\">{time}</span> <span class=\"sender\">{sender}</span>{body}</li>\n"
+            )
+        }
+    }
+}
+
+const HTML_STYLE: &str = "body{font-family:sans-serif;max-width:48em;margin:2em auto;color:#222}\
+ol{list-style:none;padding:0}li{padding:.4em 0;border-bottom:1px solid #eee}\
+p{margin:.2em 0}.time,.meta{color:#888;font-size:.85em}.sender{font-weight:bold}\
+.system{color:#666;font-style:italic}";
+
+fn describe_event(event: &SystemEvent) -> String {
+    match event {
+        SystemEvent::ChannelCreated { .. } => "created the channel".to_string(),
+        SystemEvent::MemberJoined { .. } => "joined the channel".to_string(),
+        SystemEvent::MemberLeft { .. } => "left the channel".to_string(),
+        SystemEvent::ChannelRenamed { name } => {
+            format!("renamed the channel to {}", escape_html(name))
+        }
+        SystemEvent::MessagePinned { .. } => "pinned a message".to_string(),
+        SystemEvent::MessageUnpinned { .. } => "unpinned a message".to_string(),
+        SystemEvent::MessageTtlChanged {
+            message_ttl: Some(ttl),
+        } => format!("made new messages disappear after {ttl} seconds"),
+        SystemEvent::MessageTtlChanged { message_ttl: None } => {
+            "turned off disappearing messages".to_string()
+        }
+        SystemEvent::ChannelArchived => "archived the channel".to_string(),
+        SystemEvent::ChannelUnarchived => "unarchived the channel".to_string(),
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[derive(Debug)]
+pub struct ExportError {
+    pub message: String,
+}
+
+impl Display for ExportError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ArchiveRecord, ExportFormat, ExportRecord, ExportService};
+    use crate::adapters::message_repository::MessageRepository;
+    use crate::adapters::{
+        mock_channel_repo, mock_contact_repo, mock_message_repo, IdType, Model, Repository,
+    };
+    use crate::models::{Channel, ChannelType, Contact, Message, MessageContent, SystemEvent};
+    use crate::services::Pipeline;
+
+    #[actix_web::test]
+    async fn exports_and_imports_a_channel() {
+        let mut repo = mock_message_repo();
+        let mut channel_repo = mock_channel_repo();
+        let mut contact_repo = mock_contact_repo();
+        let sam = contact_repo
+            .create(&Contact::new("Samwell Tarly", "sam@citadel.com"))
+            .await
+            .unwrap();
+        let mut gilly = Contact::new("Gilly", "gilly@craster.com");
+        gilly.moderator = true;
+        let gilly = contact_repo.create(&gilly).await.unwrap();
+        let mut channel = Channel::new(
+            "Citadel <library>",
+            ChannelType::Group,
+            &[sam.id(), gilly.id()],
+        );
+        let mut history = Vec::new();
+        for (from, text) in [
+            (&sam, "The books say <nothing>"),
+            (&gilly, " Read another "),
+        ] {
+            let mut m = Message::new(
+                &channel.id(),
+                &from.id(),
+                &channel.id(),
+                &MessageContent::text(text),
+            );
+            m.seq = repo.next_seq(&channel.id()).await.unwrap();
+            m.created_at -= chrono::Duration::days(30);
+            history.push(repo.create(&m).await.unwrap());
+        }
+        let mut pin = Message::system(
+            &channel.id(),
+            &sam.id(),
+            SystemEvent::MessagePinned {
+                message_id: history[0].id(),
+            },
+        );
+        pin.seq = repo.next_seq(&channel.id()).await.unwrap();
+        repo.create(&pin).await.unwrap();
+        channel.pinned_message_ids = vec![history[0].id()];
+        let channel = channel_repo.create(&channel).await.unwrap();
+
+        let service = ExportService::new(&mut repo, &mut channel_repo, &mut contact_repo);
+        let stranger = IdType::String("stranger".to_string());
+        assert!(service
+            .start_export(&channel.id(), &stranger)
+            .await
+            .is_err());
+
+        let mut cursor = service
+            .start_export(&channel.id(), &gilly.id())
+            .await
+            .unwrap();
+        let mut records = vec![cursor.header()];
+        loop {
+            let page = service.next_page(&mut cursor).await.unwrap();
+            if page.is_empty() {
+                break;
+            }
+            records.extend(page);
+        }
+        assert_eq!(records.len(), 4);
+        match &records[1] {
+            ExportRecord::Message { sender_name, .. } => {
+                assert_eq!(sender_name.as_deref(), Some("Samwell Tarly"))
+            }
+            _ => panic!("Expected a message"),
+        }
+
+        let html: String = records
+            .iter()
+            .map(|r| ExportFormat::Html.render(r))
+            .collect();
+        assert!(html.contains("<title>Citadel &lt;library&gt;</title>"));
+        assert!(html.contains("The books say &lt;nothing&gt;"));
+        assert!(html.contains("pinned a message"));
+
+        let ndjson: String = records
+            .iter()
+            .map(|r| ExportFormat::Ndjson.render(r))
+            .collect();
+        let parsed: Vec<ExportRecord> = ndjson
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let mut service = ExportService::new(&mut repo, &mut channel_repo, &mut contact_repo)
+            .with_pipeline(Pipeline::standard());
+        assert!(
+            service
+                .import_channel(&sam.id(), parsed.clone())
+                .await
+                .is_err(),
+            "Not a moderator"
+        );
+
+        // A message forged from outside the channel fails the whole import
+        let mut forged = parsed.clone();
+        if let ExportRecord::Message { message, .. } = &mut forged[2] {
+            message.from = stranger.clone();
+        }
+        assert!(service.import_channel(&gilly.id(), forged).await.is_err());
+        let (_, stored) = service.repository.list(None, None).await.unwrap();
+        assert_eq!(stored.len(), 3, "The failed import left messages behind");
+
+        let imported = service.import_channel(&gilly.id(), parsed).await.unwrap();
+        assert_ne!(imported.id(), channel.id());
+        assert_eq!(imported.contact_ids, vec![sam.id(), gilly.id()]);
+
+        let messages = repo.get_after_seq(&imported.id(), 0, 10).await.unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(
+            messages[0].created_at.timestamp(),
+            history[0].created_at.timestamp()
+        );
+        assert_ne!(messages[0].id(), history[0].id());
+        assert_eq!(messages[1].content, MessageContent::text("Read another"));
+        assert_eq!(imported.pinned_message_ids, vec![messages[0].id()]);
+        assert_eq!(
+            messages[2].content,
+            MessageContent::System {
+                event: SystemEvent::MessagePinned {
+                    message_id: messages[0].id()
+                }
+            }
+        );
+    }
+
+    #[actix_web::test]
+    async fn archives_the_data_of_a_contact() {
+        let mut repo = mock_message_repo();
+        let mut channel_repo = mock_channel_repo();
+        let mut contact_repo = mock_contact_repo();
+        let mut tyrion = Contact::new("Tyrion Lannister", "tyrion@casterlyrock.com");
+        tyrion.api_key_hash = Some("wine".to_string());
+        let tyrion = contact_repo.create(&tyrion).await.unwrap();
+        let bronn = contact_repo
+            .create(&Contact::new("Bronn", "bronn@blackwater.com"))
+            .await
+            .unwrap();
+        let channel = channel_repo
+            .create(&Channel::new(
+                "Small Council",
+                ChannelType::Group,
+                &[tyrion.id(), bronn.id()],
+            ))
+            .await
+            .unwrap();
+        for (from, text) in [
+            (&tyrion, "I drink"),
+            (&bronn, "I sell"),
+            (&tyrion, "I know"),
+        ] {
+            let m = Message::new(
+                &channel.id(),
+                &from.id(),
+                &channel.id(),
+                &MessageContent::text(text),
+            );
+            repo.create(&m).await.unwrap();
+        }
+
+        let service = ExportService::new(&mut repo, &mut channel_repo, &mut contact_repo);
+        assert!(service
+            .start_archive(&bronn.id(), &tyrion.id())
+            .await
+            .is_err());
+        let mut cursor = service
+            .start_archive(&tyrion.id(), &tyrion.id())
+            .await
+            .unwrap();
+        let mut records = cursor.header();
+        records.extend(service.next_archive_page(&mut cursor).await.unwrap());
+        assert!(service
+            .next_archive_page(&mut cursor)
+            .await
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(records.len(), 4);
+        match &records[0] {
+            ArchiveRecord::Contact { contact } => assert_eq!(contact.api_key_hash, None),
+            _ => panic!("Expected the contact"),
+        }
+        let texts: Vec<_> = records
+            .iter()
+            .filter_map(|r| match r {
+                ArchiveRecord::Message { message } => message.content.body(),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(texts, vec!["I drink", "I know"]);
+    }
+}
@@ -0,0 +1,264 @@
+use crate::adapters::channel_repository::ChannelRepository;
+use crate::adapters::incoming_webhook_repository::IncomingWebhookRepository;
+use crate::adapters::{IdType, Model};
+use crate::commands;
+use crate::models::{hash_token, IncomingWebhook, Message, MessageContent};
+use crate::services::MessageService;
+use chrono::Utc;
+use std::fmt::{Display, Formatter};
+
+pub struct IncomingWebhookService<'a> {
+    repository: &'a mut dyn IncomingWebhookRepository,
+    channel_repository: &'a mut dyn ChannelRepository,
+}
+
+impl<'a> IncomingWebhookService<'a> {
+    pub fn new(
+        repo: &'a mut dyn IncomingWebhookRepository,
+        channel_repository: &'a mut dyn ChannelRepository,
+    ) -> Self {
+        IncomingWebhookService {
+            repository: repo,
+            channel_repository,
+        }
+    }
+
+    /// Creates the webhook and returns it with its token
+    pub async fn create_incoming_webhook(
+        &mut self,
+        cmd: &commands::CreateIncomingWebhook,
+    ) -> Result<(IncomingWebhook, String), IncomingWebhookError> {
+        if cmd.name.trim().is_empty() {
+            return Err(IncomingWebhookError {
+                message: "Incoming webhooks must have a name".to_string(),
+            });
+        }
+        self.check_member(&cmd.channel_id, &cmd.created_by).await?;
+        let mut webhook = IncomingWebhook::new(&cmd.channel_id, &cmd.name, &cmd.created_by);
+        let token = webhook.issue_token();
+        match self.repository.create(&webhook).await {
+            Ok(w) => Ok((w, token)),
+            Err(e) => Err(IncomingWebhookError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    pub async fn list_incoming_webhooks(
+        &self,
+        channel_id: &IdType,
+        contact_id: &IdType,
+    ) -> Result<Vec<IncomingWebhook>, IncomingWebhookError> {
+        self.check_member(channel_id, contact_id).await?;
+        match self.repository.find_by_channel_id(channel_id).await {
+            Ok(w) => Ok(w),
+            Err(e) => Err(IncomingWebhookError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    /// Issues a new token, which stops the previous one from working
+    pub async fn rotate_token(
+        &mut self,
+        cmd: &commands::ChangeIncomingWebhookToken,
+    ) -> Result<(IncomingWebhook, String), IncomingWebhookError> {
+        let mut webhook = self.get_managed(&cmd.id, &cmd.contact_id).await?;
+        let token = webhook.issue_token();
+        self.save(&mut webhook).await?;
+        Ok((webhook, token))
+    }
+
+    /// Revokes the token until a new one is issued
+    pub async fn revoke_token(
+        &mut self,
+        cmd: &commands::ChangeIncomingWebhookToken,
+    ) -> Result<IncomingWebhook, IncomingWebhookError> {
+        let mut webhook = self.get_managed(&cmd.id, &cmd.contact_id).await?;
+        webhook.token_hash = None;
+        self.save(&mut webhook).await?;
+        Ok(webhook)
+    }
+
+    /// Posts the content into the channel of the webhook holding the token
+    pub async fn post(
+        &self,
+        token: &str,
+        content: &MessageContent,
+        message_service: &mut MessageService<'_>,
+    ) -> Result<Message, IncomingWebhookError> {
+        let webhook = match self.repository.find_by_token_hash(&hash_token(token)).await {
+            Some(w) => w,
+            None => {
+                return Err(IncomingWebhookError {
+                    message: "Invalid webhook token".to_string(),
+                })
+            }
+        };
+        let cmd = commands::PostBotMessage {
+            channel_id: webhook.channel_id.clone(),
+            bot_id: webhook.id(),
+            bot_name: webhook.name.clone(),
+            content: content.clone(),
+        };
+        match message_service.post_bot_message(&cmd).await {
+            Ok(m) => Ok(m),
+            Err(e) => Err(IncomingWebhookError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    /// Returns the webhook if the contact is a member of its channel
+    async fn get_managed(
+        &self,
+        id: &IdType,
+        contact_id: &IdType,
+    ) -> Result<IncomingWebhook, IncomingWebhookError> {
+        let webhook = match self.repository.get(id).await {
+            Some(w) => w,
+            None => {
+                return Err(IncomingWebhookError {
+                    message: format!("Incoming webhook with id {id} not found"),
+                })
+            }
+        };
+        self.check_member(&webhook.channel_id, contact_id).await?;
+        Ok(webhook)
+    }
+
+    async fn check_member(
+        &self,
+        channel_id: &IdType,
+        contact_id: &IdType,
+    ) -> Result<(), IncomingWebhookError> {
+        match self.channel_repository.get(channel_id).await {
+            Some(c) if c.contact_ids.contains(contact_id) => Ok(()),
+            Some(_) => Err(IncomingWebhookError {
+                message: "Only channel members can manage incoming webhooks".to_string(),
+            }),
+            None => Err(IncomingWebhookError {
+                message: format!("Channel with id {channel_id} not found"),
+            }),
+        }
+    }
+
+    async fn save(&mut self, webhook: &mut IncomingWebhook) -> Result<(), IncomingWebhookError> {
+        webhook.updated_at = Utc::now();
+        match self.repository.update(webhook).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(IncomingWebhookError {
+                message: e.to_string(),
+            }),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct IncomingWebhookError {
+    pub message: String,
+}
+
+impl Display for IncomingWebhookError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapters::message_repository::MessageRepository;
+    use crate::adapters::{
+        mock_channel_repo, mock_contact_repo, mock_incoming_webhook_repo, mock_message_repo,
+        Repository,
+    };
+    use crate::models::{Channel, ChannelType, Contact};
+
+    async fn add_test_channel(
+        repo: &mut impl Repository<Channel>,
+        contacts: &mut impl Repository<Contact>,
+    ) -> (Channel, Contact) {
+        let bran = contacts
+            .create(&Contact::new("Bran Stark", "bran@winterfell.com"))
+            .await
+            .unwrap();
+        let channel = repo
+            .create(&Channel::new(
+                "Three-eyed ravens",
+                ChannelType::Group,
+                &[bran.id()],
+            ))
+            .await
+            .unwrap();
+        (channel, bran)
+    }
+
+    #[actix_web::test]
+    async fn posts_into_the_channel_until_revoked() {
+        let mut repo = mock_incoming_webhook_repo();
+        let mut channel_repo = mock_channel_repo();
+        let mut contact_repo = mock_contact_repo();
+        let mut message_repo = mock_message_repo();
+        let (channel, bran) = add_test_channel(&mut channel_repo, &mut contact_repo).await;
+
+        let mut service = IncomingWebhookService::new(&mut repo, &mut channel_repo);
+        let cmd = commands::CreateIncomingWebhook {
+            channel_id: channel.id(),
+            name: "Weirwood".to_string(),
+            created_by: bran.id(),
+        };
+        let (webhook, token) = service.create_incoming_webhook(&cmd).await.unwrap();
+        let change = commands::ChangeIncomingWebhookToken {
+            id: webhook.id(),
+            contact_id: bran.id(),
+        };
+        let (_, rotated) = service.rotate_token(&change).await.unwrap();
+
+        // Both services need the channels, so the message service gets a copy
+        let mut channels = mock_channel_repo();
+        channels.create(&channel).await.unwrap();
+        let mut message_service =
+            MessageService::new(&mut message_repo, &mut channels, &mut contact_repo);
+        let mut service = IncomingWebhookService::new(&mut repo, &mut channel_repo);
+        let content = MessageContent::text("Build #42 passed");
+
+        let res = service.post(&token, &content, &mut message_service).await;
+        assert!(res.is_err(), "The rotated token no longer works");
+
+        let message = service
+            .post(&rotated, &content, &mut message_service)
+            .await
+            .unwrap();
+        assert_eq!(message.channel_id, channel.id());
+        assert_eq!(message.from, webhook.id());
+        assert_eq!(message.bot_name, Some("Weirwood".to_string()));
+
+        service.revoke_token(&change).await.unwrap();
+        let res = service.post(&rotated, &content, &mut message_service).await;
+        assert!(res.is_err(), "The revoked token no longer works");
+
+        let messages = message_repo
+            .get_by_channel_id(&channel.id(), 10, 0)
+            .await
+            .unwrap();
+        assert_eq!(messages.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn only_members_manage_incoming_webhooks() {
+        let mut repo = mock_incoming_webhook_repo();
+        let mut channel_repo = mock_channel_repo();
+        let mut contact_repo = mock_contact_repo();
+        let (channel, _) = add_test_channel(&mut channel_repo, &mut contact_repo).await;
+        let outsider = Contact::new("Walder Frey", "walder@thetwins.com");
+
+        let mut service = IncomingWebhookService::new(&mut repo, &mut channel_repo);
+        let cmd = commands::CreateIncomingWebhook {
+            channel_id: channel.id(),
+            name: "Red wedding".to_string(),
+            created_by: outsider.id(),
+        };
+        assert!(service.create_incoming_webhook(&cmd).await.is_err());
+    }
+}
@@ -0,0 +1,91 @@
+use crate::adapters::{IdType, Model};
+use crate::models::Contact;
+
+/// Finds the `@name` and `@email` mentions in the content and returns the ids
+/// of the matching contacts, in order of first appearance.
+///
+/// Only the given candidates can be mentioned. Matching ignores ASCII case and
+/// prefers the longest candidate, so `@Jon Snow` wins over `@Jon`.
+pub fn parse_mentions(content: &str, candidates: &[Contact]) -> Vec<IdType> {
+    let mut mentions: Vec<IdType> = Vec::new();
+    for (index, _) in content.match_indices('@') {
+        let preceded_by_word = content[..index]
+            .chars()
+            .next_back()
+            .is_some_and(|c| !c.is_whitespace());
+        if preceded_by_word {
+            continue;
+        }
+        let rest = &content[index + 1..];
+        let best = candidates
+            .iter()
+            .filter_map(|c| {
+                [c.email.as_str(), c.name.as_str()]
+                    .into_iter()
+                    .filter(|handle| matches_handle(rest, handle))
+                    .map(|handle| handle.len())
+                    .max()
+                    .map(|len| (len, c))
+            })
+            .max_by_key(|(len, _)| *len);
+        if let Some((_, contact)) = best {
+            let id = contact.id();
+            if !mentions.contains(&id) {
+                mentions.push(id);
+            }
+        }
+    }
+    mentions
+}
+
+fn matches_handle(text: &str, handle: &str) -> bool {
+    if handle.is_empty() {
+        return false;
+    }
+    match text.get(..handle.len()) {
+        Some(prefix) if prefix.eq_ignore_ascii_case(handle) => !matches!(
+            text[handle.len()..].chars().next(),
+            Some(c) if c.is_alphanumeric() || c == '_'
+        ),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_mentions;
+    use crate::adapters::Model;
+    use crate::models::Contact;
+
+    fn contacts() -> Vec<Contact> {
+        vec![
+            Contact::new("Jon", "jon@nightswatch.com"),
+            Contact::new("Jon Snow", "jon@winterfell.com"),
+            Contact::new("Arya Stark", "arya@winterfell.com"),
+        ]
+    }
+
+    #[test]
+    fn parses_names_and_emails() {
+        let contacts = contacts();
+        let mentions = parse_mentions(
+            "@arya@winterfell.com, tell @jon snow that winter is coming",
+            &contacts,
+        );
+        assert_eq!(mentions, vec![contacts[2].id(), contacts[1].id()]);
+    }
+
+    #[test]
+    fn ignores_partial_words_and_unknown_handles() {
+        let contacts = contacts();
+        let mentions = parse_mentions("hodor@Jon @Jonny @Bran", &contacts);
+        assert!(mentions.is_empty());
+    }
+
+    #[test]
+    fn deduplicates_mentions() {
+        let contacts = contacts();
+        let mentions = parse_mentions("@Jon! @jon@nightswatch.com", &contacts);
+        assert_eq!(mentions, vec![contacts[0].id()]);
+    }
+}
@@ -1,17 +1,34 @@
-use crate::adapters::{IdType, Model, Repository};
+use crate::adapters::{IdType, Model};
 use crate::commands;
+use crate::events::{
+    ChannelCreated, ChannelUpdated, EventBus, MessageFlagged, MessageSent, MessageUpdated,
+    SlashCommandInvoked,
+};
 
-use crate::models::{Channel, ChannelType, Contact, Message};
+use crate::models::{
+    deleted_contact_id, Channel, ChannelType, Contact, LinkPreview, Message, MessageContent,
+    SystemEvent, MAX_PINNED_MESSAGES,
+};
 
 use crate::adapters::channel_repository::ChannelRepository;
 use crate::adapters::contact_repository::ContactRepository;
 use crate::adapters::message_repository::MessageRepository;
+use crate::services::mentions::parse_mentions;
+use crate::services::pipeline::{Draft, Pipeline};
+use crate::services::slash_commands::{parse_command, CommandRegistry};
+use chrono::{DateTime, Duration, Utc};
 use std::fmt::{Display, Formatter};
 
+/// The maximum number of messages returned by a single catch up query
+pub const MAX_SYNC_BATCH: i64 = 500;
+
 pub struct MessageService<'a> {
     repository: &'a mut dyn MessageRepository,
     channel_repository: &'a mut dyn ChannelRepository,
     contact_repository: &'a mut dyn ContactRepository,
+    events: EventBus,
+    commands: CommandRegistry,
+    pipeline: Pipeline,
 }
 
 impl<'a> MessageService<'a> {
@@ -24,28 +41,525 @@ impl<'a> MessageService<'a> {
             repository: repo,
             channel_repository,
             contact_repository,
+            events: EventBus::default(),
+            commands: CommandRegistry::default(),
+            pipeline: Pipeline::default(),
         }
     }
 
-    pub async fn send_message(&mut self, cmd: &commands::SendMessage) -> Result<(), MessageError> {
+    /// Publishes the changes to the given bus
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Answers the slash commands of the sent messages with the given handlers
+    pub fn with_commands(mut self, commands: CommandRegistry) -> Self {
+        self.commands = commands;
+        self
+    }
+
+    /// Runs the sent messages through the given stages before storing them
+    pub fn with_pipeline(mut self, pipeline: Pipeline) -> Self {
+        self.pipeline = pipeline;
+        self
+    }
+
+    /// Stores the message and returns it. A retried send carrying the same
+    /// client message id returns the originally stored message instead.
+    pub async fn send_message(
+        &mut self,
+        cmd: &commands::SendMessage,
+    ) -> Result<Message, MessageError> {
+        if let MessageContent::System { .. } = cmd.content {
+            return Err(MessageError {
+                message: "System messages cannot be sent by contacts".to_string(),
+            });
+        }
+        if let Some(m) = self.find_sent(cmd).await {
+            return Ok(m);
+        }
         let contact_from = self.get_contact(&cmd.from).await?;
+        if contact_from.banned {
+            return Err(MessageError {
+                message: "You are banned from sending messages".to_string(),
+            });
+        }
+        let draft = self.process(cmd.channel_id.clone(), &cmd.from, &cmd.content)?;
         let contact_to = self.get_contact(&cmd.to).await?;
         let channel = match &cmd.channel_id {
             None => {
-                self.create_private_channel(&vec![contact_from.id(), contact_to.id()])
+                self.check_not_blocked(&cmd.from, &[contact_to.id()])
+                    .await?;
+                self.create_private_channel(&[contact_from.id(), contact_to.id()], &cmd.from)
                     .await?
             }
             Some(c) => self.get_channel(c).await?,
         };
-        let message = Message::new(&channel.id(), &cmd.from, &cmd.to, &cmd.content);
-        match self.repository.create(&message).await {
-            Ok(_) => Ok(()),
+        if channel.channel_type == ChannelType::Private {
+            self.check_not_blocked(&cmd.from, &channel.contact_ids)
+                .await?;
+        }
+        let mut message = Message::new(&channel.id(), &cmd.from, &cmd.to, &draft.content);
+        message.client_message_id = cmd.client_message_id.clone();
+        message.links = draft.links;
+        match self.store(&channel, message).await {
+            Ok(m) => {
+                self.publish_flagged(&channel, &m, draft.flagged_by);
+                self.run_command(&channel, &m).await;
+                Ok(m)
+            }
+            // A concurrent send with the same key may have won the race
+            Err(e) => match self.find_sent(cmd).await {
+                Some(m) => Ok(m),
+                None => Err(e),
+            },
+        }
+    }
+
+    /// Stores a message posted by an integration into the channel
+    pub async fn post_bot_message(
+        &mut self,
+        cmd: &commands::PostBotMessage,
+    ) -> Result<Message, MessageError> {
+        if let MessageContent::System { .. } = cmd.content {
+            return Err(MessageError {
+                message: "System messages cannot be posted by bots".to_string(),
+            });
+        }
+        let channel = self.get_channel(&cmd.channel_id).await?;
+        let draft = self.process(Some(channel.id()), &cmd.bot_id, &cmd.content)?;
+        let mut message = Message::bot(&channel.id(), &cmd.bot_id, &cmd.bot_name, &draft.content);
+        message.links = draft.links;
+        let message = self.store(&channel, message).await?;
+        self.publish_flagged(&channel, &message, draft.flagged_by);
+        Ok(message)
+    }
+
+    /// Stores a message a bot contact sends to a channel it is a member of
+    pub async fn send_bot_message(
+        &mut self,
+        bot: &Contact,
+        channel_id: &IdType,
+        content: &MessageContent,
+    ) -> Result<Message, MessageError> {
+        if let MessageContent::System { .. } = content {
+            return Err(MessageError {
+                message: "System messages cannot be posted by bots".to_string(),
+            });
+        }
+        if bot.banned {
+            return Err(MessageError {
+                message: "You are banned from sending messages".to_string(),
+            });
+        }
+        let channel = self.get_member_channel(channel_id, &bot.id()).await?;
+        let draft = self.process(Some(channel.id()), &bot.id(), content)?;
+        let mut message = Message::bot(&channel.id(), &bot.id(), &bot.name, &draft.content);
+        message.links = draft.links;
+        let message = self.store(&channel, message).await?;
+        self.publish_flagged(&channel, &message, draft.flagged_by);
+        Ok(message)
+    }
+
+    /// Runs the message through the pipeline and returns what is left to store
+    fn process(
+        &self,
+        channel_id: Option<IdType>,
+        from: &IdType,
+        content: &MessageContent,
+    ) -> Result<Draft, MessageError> {
+        let mut draft = Draft::new(channel_id, from, content);
+        match self.pipeline.run(&mut draft) {
+            Ok(_) => Ok(draft),
+            Err(message) => Err(MessageError { message }),
+        }
+    }
+
+    /// Sends the stored message to moderation when filter rules flagged it
+    fn publish_flagged(&self, channel: &Channel, message: &Message, rules: Vec<String>) {
+        if !rules.is_empty() {
+            self.events.publish(MessageFlagged {
+                channel: channel.clone(),
+                message: message.clone(),
+                rules,
+            });
+        }
+    }
+
+    /// Answers a slash command with its in-process handler, or publishes it
+    /// for the bots registered over HTTP. Unknown commands stay plain messages.
+    async fn run_command(&mut self, channel: &Channel, message: &Message) {
+        let (name, args) = match message.content.body().and_then(parse_command) {
+            Some(c) => c,
+            None => return,
+        };
+        let invocation = SlashCommandInvoked {
+            channel: channel.clone(),
+            message: message.clone(),
+            name,
+            args,
+        };
+        let handler = match self.commands.get(&invocation.name) {
+            Some(h) => h,
+            None => {
+                self.events.publish(invocation);
+                return;
+            }
+        };
+        let content = match handler.handle(&invocation).await {
+            Ok(Some(c)) => c,
+            Ok(None) => return,
+            Err(e) => MessageContent::text(&format!("/{} failed: {e}", invocation.name)),
+        };
+        // In-process handlers are part of the server, so their replies skip
+        // the pipeline the messages of contacts and bots go through
+        let bot_name = format!("/{}", invocation.name);
+        let reply = Message::bot(&channel.id(), &channel.id(), &bot_name, &content);
+        // The command message is stored either way, so a failed reply is not
+        // reported to its sender
+        let _ = self.store(channel, reply).await;
+    }
+
+    /// Resolves the mentions and lifetime of the message, then stores it at
+    /// the end of the channel history
+    async fn store(
+        &mut self,
+        channel: &Channel,
+        mut message: Message,
+    ) -> Result<Message, MessageError> {
+        check_not_archived(channel)?;
+        if let Some(body) = message.content.body() {
+            message.mentions = self.resolve_mentions(channel, &message.from, body).await;
+        }
+        set_expiry(channel, &mut message)?;
+        let hidden_from = self.find_hidden_from(channel, &message.from).await;
+        match self.repository.create_in_sequence(&message).await {
+            Ok(m) => {
+                self.events.publish(MessageSent {
+                    channel: channel.clone(),
+                    message: m.clone(),
+                    hidden_from,
+                });
+                Ok(m)
+            }
+            Err(e) => Err(MessageError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    /// Refuses direct messages to the contacts that blocked the sender
+    async fn check_not_blocked(
+        &self,
+        from: &IdType,
+        recipients: &[IdType],
+    ) -> Result<(), MessageError> {
+        for id in recipients.iter().filter(|id| *id != from) {
+            if let Some(c) = self.contact_repository.get(id).await {
+                if c.has_blocked(from) {
+                    return Err(MessageError {
+                        message: "You cannot send direct messages to this contact".to_string(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the group members the sender hides its messages from
+    async fn find_hidden_from(&self, channel: &Channel, from: &IdType) -> Vec<IdType> {
+        if channel.channel_type != ChannelType::Group {
+            return vec![];
+        }
+        match self.contact_repository.get(from).await {
+            Some(sender) => channel
+                .contact_ids
+                .iter()
+                .filter(|id| sender.hides_messages_from(id))
+                .cloned()
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    /// Drops the group messages whose senders hide them from the given
+    /// contact, as `find_hidden_from` does for the live pushes
+    async fn hide_blocked(&self, contact_id: &IdType, messages: Vec<Message>) -> Vec<Message> {
+        let mut groups: Vec<IdType> = Vec::new();
+        let mut hiding: Vec<IdType> = Vec::new();
+        let mut checked_channels: Vec<IdType> = Vec::new();
+        let mut checked_senders: Vec<IdType> = Vec::new();
+        for message in messages.iter().filter(|m| !is_system(m)) {
+            if !checked_channels.contains(&message.channel_id) {
+                checked_channels.push(message.channel_id.clone());
+                if let Some(c) = self.channel_repository.get(&message.channel_id).await {
+                    if c.channel_type == ChannelType::Group {
+                        groups.push(message.channel_id.clone());
+                    }
+                }
+            }
+            if !groups.contains(&message.channel_id) || checked_senders.contains(&message.from) {
+                continue;
+            }
+            checked_senders.push(message.from.clone());
+            if let Some(sender) = self.contact_repository.get(&message.from).await {
+                if sender.hides_messages_from(contact_id) {
+                    hiding.push(message.from.clone());
+                }
+            }
+        }
+        messages
+            .into_iter()
+            .filter(|m| {
+                is_system(m) || !groups.contains(&m.channel_id) || !hiding.contains(&m.from)
+            })
+            .collect()
+    }
+
+    /// Shows the messages of the contacts since deleted as sent by the placeholder
+    async fn render_deleted_senders(&self, mut messages: Vec<Message>) -> Vec<Message> {
+        let mut deleted: Vec<IdType> = Vec::new();
+        let mut checked: Vec<IdType> = Vec::new();
+        for message in messages.iter().filter(|m| m.bot_name.is_none()) {
+            if checked.contains(&message.from) {
+                continue;
+            }
+            checked.push(message.from.clone());
+            if self.contact_repository.get(&message.from).await.is_none() {
+                deleted.push(message.from.clone());
+            }
+        }
+        for message in messages.iter_mut() {
+            if message.bot_name.is_none() && deleted.contains(&message.from) {
+                message.from = deleted_contact_id();
+            }
+        }
+        messages
+    }
+
+    async fn find_sent(&self, cmd: &commands::SendMessage) -> Option<Message> {
+        match &cmd.client_message_id {
+            Some(key) => {
+                self.repository
+                    .find_by_client_message_id(&cmd.from, key)
+                    .await
+            }
+            None => None,
+        }
+    }
+
+    /// Returns the messages of the channel after the given sequence number,
+    /// oldest first, so clients can catch up after reconnecting
+    pub async fn get_messages_after(
+        &mut self,
+        channel_id: &IdType,
+        contact_id: &IdType,
+        after_seq: i64,
+        limit: i64,
+    ) -> Result<Vec<Message>, MessageError> {
+        let channel = self.get_member_channel(channel_id, contact_id).await?;
+        let limit = limit.clamp(1, MAX_SYNC_BATCH);
+        match self
+            .repository
+            .get_after_seq(&channel.id(), after_seq, limit)
+            .await
+        {
+            Ok(m) => {
+                let m = self.hide_blocked(contact_id, m).await;
+                Ok(self.render_deleted_senders(m).await)
+            }
+            Err(e) => Err(MessageError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    /// Returns the messages mentioning the given contact, newest first
+    pub async fn get_mentions(
+        &self,
+        contact_id: &IdType,
+        limit: i64,
+        offset: u64,
+    ) -> Result<Vec<Message>, MessageError> {
+        match self
+            .repository
+            .find_by_mention(contact_id, limit, offset)
+            .await
+        {
+            Ok(m) => {
+                let m = self.hide_blocked(contact_id, m).await;
+                Ok(self.render_deleted_senders(m).await)
+            }
+            Err(e) => Err(MessageError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    /// Pins a message of the channel and returns the system message recording it
+    pub async fn pin_message(
+        &mut self,
+        cmd: &commands::PinMessage,
+    ) -> Result<Message, MessageError> {
+        let channel = self
+            .get_member_channel(&cmd.channel_id, &cmd.pinned_by)
+            .await?;
+        check_not_archived(&channel)?;
+        let message = self.get_channel_message(&channel, &cmd.message_id).await?;
+        if channel.pinned_message_ids.contains(&message.id()) {
+            return Err(MessageError {
+                message: "Message is already pinned".to_string(),
+            });
+        }
+        if channel.pinned_message_ids.len() >= MAX_PINNED_MESSAGES {
+            return Err(MessageError {
+                message: format!("Channels cannot have more than {MAX_PINNED_MESSAGES} pins"),
+            });
+        }
+        // Checked again by the update, against the pins of concurrent requests
+        match self
+            .channel_repository
+            .pin_message(&channel.id(), &message.id())
+            .await
+        {
+            Ok(true) => (),
+            Ok(false) => {
+                return Err(MessageError {
+                    message: format!(
+                        "Message is already pinned or the channel has {MAX_PINNED_MESSAGES} pins"
+                    ),
+                })
+            }
+            Err(e) => {
+                return Err(MessageError {
+                    message: e.to_string(),
+                })
+            }
+        }
+        let channel = self.publish_channel_update(&channel.id()).await?;
+        let event = SystemEvent::MessagePinned {
+            message_id: message.id(),
+        };
+        self.create_system_message(&channel, &cmd.pinned_by, event)
+            .await
+    }
+
+    /// Unpins a message of the channel and returns the system message recording it
+    pub async fn unpin_message(
+        &mut self,
+        cmd: &commands::UnpinMessage,
+    ) -> Result<Message, MessageError> {
+        let channel = self
+            .get_member_channel(&cmd.channel_id, &cmd.unpinned_by)
+            .await?;
+        check_not_archived(&channel)?;
+        let message = self.get_channel_message(&channel, &cmd.message_id).await?;
+        match self
+            .channel_repository
+            .unpin_message(&channel.id(), &message.id())
+            .await
+        {
+            Ok(true) => (),
+            Ok(false) => {
+                return Err(MessageError {
+                    message: "Message is not pinned".to_string(),
+                })
+            }
+            Err(e) => {
+                return Err(MessageError {
+                    message: e.to_string(),
+                })
+            }
+        }
+        let channel = self.publish_channel_update(&channel.id()).await?;
+        let event = SystemEvent::MessageUnpinned {
+            message_id: message.id(),
+        };
+        self.create_system_message(&channel, &cmd.unpinned_by, event)
+            .await
+    }
+
+    /// Returns the pinned messages of the channel to one of its members,
+    /// oldest pin first
+    pub async fn get_pins(
+        &mut self,
+        channel_id: &IdType,
+        contact_id: &IdType,
+    ) -> Result<Vec<Message>, MessageError> {
+        let channel = self.get_member_channel(channel_id, contact_id).await?;
+        let mut messages = Vec::new();
+        for id in channel.pinned_message_ids.iter() {
+            if let Some(m) = self.repository.get(id).await {
+                messages.push(m);
+            }
+        }
+        let messages = self.hide_blocked(contact_id, messages).await;
+        Ok(self.render_deleted_senders(messages).await)
+    }
+
+    /// Attaches the fetched link previews to the message and pushes the change
+    /// to the channel
+    pub async fn attach_previews(
+        &mut self,
+        message_id: &IdType,
+        previews: Vec<LinkPreview>,
+    ) -> Result<Message, MessageError> {
+        let mut message = match self.repository.get(message_id).await {
+            Some(m) => m,
+            None => {
+                return Err(MessageError {
+                    message: format!("Message with id {message_id} not found"),
+                })
+            }
+        };
+        let channel = self.get_channel(&message.channel_id).await?;
+        message.previews = previews;
+        message.updated_at = Utc::now();
+        if let Err(e) = self.repository.update(&message).await {
+            return Err(MessageError {
+                message: e.to_string(),
+            });
+        }
+        let hidden_from = self.find_hidden_from(&channel, &message.from).await;
+        self.events.publish(MessageUpdated {
+            channel,
+            message: message.clone(),
+            hidden_from,
+        });
+        Ok(message)
+    }
+
+    /// Deletes the messages expired by the given time
+    pub async fn purge_expired(&mut self, now: DateTime<Utc>) -> Result<u64, MessageError> {
+        match self.repository.purge_expired(now).await {
+            Ok(count) => Ok(count),
             Err(e) => Err(MessageError {
                 message: e.to_string(),
             }),
         }
     }
 
+    /// Resolves the mentions in the content against the channel members,
+    /// excluding the sender
+    async fn resolve_mentions(
+        &self,
+        channel: &Channel,
+        from: &IdType,
+        content: &str,
+    ) -> Vec<IdType> {
+        if !content.contains('@') {
+            return vec![];
+        }
+        let mut members = Vec::new();
+        for id in channel.contact_ids.iter().filter(|id| *id != from) {
+            if let Some(c) = self.contact_repository.get(id).await {
+                members.push(c);
+            }
+        }
+        parse_mentions(content, &members)
+    }
+
     async fn get_contact(&mut self, id: &IdType) -> Result<Contact, MessageError> {
         match self.contact_repository.get(id).await {
             None => Err(MessageError {
@@ -64,30 +578,103 @@ impl<'a> MessageService<'a> {
         }
     }
 
+    async fn get_member_channel(
+        &mut self,
+        channel_id: &IdType,
+        contact_id: &IdType,
+    ) -> Result<Channel, MessageError> {
+        let channel = self.get_channel(channel_id).await?;
+        if !channel.contact_ids.contains(contact_id) {
+            return Err(MessageError {
+                message: format!("Contact with id {contact_id} is not a channel member"),
+            });
+        }
+        Ok(channel)
+    }
+
+    async fn get_channel_message(
+        &self,
+        channel: &Channel,
+        message_id: &IdType,
+    ) -> Result<Message, MessageError> {
+        match self.repository.get(message_id).await {
+            Some(m) if m.channel_id == channel.id() => Ok(m),
+            _ => Err(MessageError {
+                message: format!("Message with id {message_id} not found"),
+            }),
+        }
+    }
+
+    /// Reads back the channel changed by a targeted update and pushes it
+    async fn publish_channel_update(
+        &mut self,
+        channel_id: &IdType,
+    ) -> Result<Channel, MessageError> {
+        let channel = self.get_channel(channel_id).await?;
+        self.events.publish(ChannelUpdated {
+            channel: channel.clone(),
+        });
+        Ok(channel)
+    }
+
+    async fn create_system_message(
+        &mut self,
+        channel: &Channel,
+        triggered_by: &IdType,
+        event: SystemEvent,
+    ) -> Result<Message, MessageError> {
+        let mut message = Message::system(&channel.id(), triggered_by, event);
+        set_expiry(channel, &mut message)?;
+        match self.repository.create_in_sequence(&message).await {
+            Ok(m) => {
+                self.events.publish(MessageSent {
+                    channel: channel.clone(),
+                    message: m.clone(),
+                    hidden_from: vec![],
+                });
+                Ok(m)
+            }
+            Err(e) => Err(MessageError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
     async fn create_private_channel(
         &mut self,
-        contact_ids: &Vec<IdType>,
+        contact_ids: &[IdType],
+        created_by: &IdType,
     ) -> Result<Channel, MessageError> {
         match self
             .channel_repository
             .get_by_contact_ids(contact_ids)
             .await
         {
-            /// Returns channel if already exists
+            // Returns channel if already exists
             Some(c) => Ok(c),
-            /// Creates a new channel if it doesn't exist
+            // Creates a new channel if it doesn't exist
             None => {
                 let channel = Channel::new("", ChannelType::Private, contact_ids);
-                match self.channel_repository.create(&channel).await {
-                    Ok(c) => Ok(c),
-                    Err(e) => Err(MessageError {
-                        message: e.to_string(),
-                    }),
-                }
+                let channel = match self.channel_repository.create(&channel).await {
+                    Ok(c) => c,
+                    Err(e) => {
+                        return Err(MessageError {
+                            message: e.to_string(),
+                        })
+                    }
+                };
+                self.events.publish(ChannelCreated {
+                    channel: channel.clone(),
+                });
+                let event = SystemEvent::ChannelCreated { name: None };
+                self.create_system_message(&channel, created_by, event)
+                    .await?;
+                Ok(channel)
             }
         }
     }
 
+    #[cfg(test)]
     async fn get_messages(&mut self, channel_id: &IdType) -> Result<Vec<Message>, MessageError> {
         match self.repository.get_by_channel_id(channel_id, 100, 0).await {
             Ok(m) => Ok(m),
@@ -109,6 +696,43 @@ impl Display for MessageError {
     }
 }
 
+fn is_system(message: &Message) -> bool {
+    matches!(message.content, MessageContent::System { .. })
+}
+
+/// Makes the message disappear after the lifetime of its channel, if any
+pub(crate) fn set_expiry(channel: &Channel, message: &mut Message) -> Result<(), MessageError> {
+    if let Some(ttl) = channel.message_ttl {
+        let expires_at = ttl.checked_mul(1000).and_then(|ms| {
+            message
+                .created_at
+                .checked_add_signed(Duration::milliseconds(ms))
+        });
+        match expires_at {
+            Some(t) => message.expires_at = Some(t),
+            None => {
+                return Err(MessageError {
+                    message: format!("Message lifetime of {ttl} seconds is out of range"),
+                })
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Refuses the changes to archived channels, which are read-only
+fn check_not_archived(channel: &Channel) -> Result<(), MessageError> {
+    if channel.is_archived() {
+        return Err(MessageError {
+            message: "Archived channels take no new messages".to_string(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+use crate::adapters::Repository;
+
 #[cfg(test)]
 async fn add_test_contacts(repo: &mut impl Repository<Contact>) -> Vec<Contact> {
     let c1 = repo
@@ -128,6 +752,7 @@ async fn add_test_channel(repo: &mut impl Repository<Channel>, contacts: &[Conta
         name: "The North Remembers".to_string(),
         channel_type: ChannelType::Private,
         contact_ids: vec![contacts[0].id(), contacts[1].id()],
+        created_by: contacts[0].id(),
     };
     repo.create(&Channel::new(
         &cmd.name,
@@ -142,6 +767,10 @@ async fn add_test_channel(repo: &mut impl Repository<Channel>, contacts: &[Conta
 mod tests {
     use super::*;
     use crate::adapters::{mock_channel_repo, mock_contact_repo, mock_message_repo, Model};
+    use crate::events::{record, Recorded, Replay};
+    use crate::models::BlockedContact;
+    use crate::services::content_filter::{ContentFilter, FilterAction, FilterRule};
+    use crate::services::slash_commands::SlashCommandHandler;
 
     #[actix_web::test]
     async fn can_send_message() {
@@ -158,7 +787,8 @@ mod tests {
             channel_id: None,
             from: contacts[0].id(),
             to: contacts[1].id(),
-            content: "The north remembers!".to_string(),
+            content: MessageContent::text("The north remembers!"),
+            client_message_id: None,
         };
         let res = service.send_message(&cmd).await;
 
@@ -173,6 +803,688 @@ mod tests {
         assert_eq!(message.to, cmd.to);
         assert_eq!(message.content, cmd.content);
     }
+
+    #[actix_web::test]
+    async fn retried_sends_return_the_stored_message() {
+        let mut repo = mock_message_repo();
+        let mut contact_repo = mock_contact_repo();
+        let mut channel_repo = mock_channel_repo();
+
+        let contacts = add_test_contacts(&mut contact_repo).await;
+        let channel = add_test_channel(&mut channel_repo, &contacts).await;
+
+        let mut service = MessageService::new(&mut repo, &mut channel_repo, &mut contact_repo);
+        let cmd = commands::SendMessage {
+            channel_id: Some(channel.id()),
+            from: contacts[0].id(),
+            to: contacts[1].id(),
+            content: MessageContent::text("Hold the door!"),
+            client_message_id: Some("hodor-1".to_string()),
+        };
+        let first = service.send_message(&cmd).await.unwrap();
+        let retry = service.send_message(&cmd).await.unwrap();
+        assert_eq!(first.id(), retry.id());
+
+        // The key is unique per sender only
+        let reply = commands::SendMessage {
+            from: contacts[1].id(),
+            to: contacts[0].id(),
+            ..cmd
+        };
+        let reply = service.send_message(&reply).await.unwrap();
+        assert_ne!(reply.id(), first.id());
+
+        let messages = service.get_messages(&channel.id()).await.unwrap();
+        assert_eq!(messages.len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn publishes_sent_messages_once() {
+        let mut repo = mock_message_repo();
+        let mut contact_repo = mock_contact_repo();
+        let mut channel_repo = mock_channel_repo();
+
+        let contacts = add_test_contacts(&mut contact_repo).await;
+        let events = EventBus::default();
+        let sent = record::<MessageSent>(&events);
+        let created = record::<ChannelCreated>(&events);
+
+        let mut service = MessageService::new(&mut repo, &mut channel_repo, &mut contact_repo)
+            .with_events(events);
+        let cmd = commands::SendMessage {
+            channel_id: None,
+            from: contacts[0].id(),
+            to: contacts[1].id(),
+            content: MessageContent::text("Hold the door!"),
+            client_message_id: Some("hodor-1".to_string()),
+        };
+        service.send_message(&cmd).await.unwrap();
+        service.send_message(&cmd).await.unwrap();
+
+        assert_eq!(created.send(Recorded).await.unwrap(), 1);
+        // The channel creation notice and the message, but not the retry
+        assert_eq!(sent.send(Recorded).await.unwrap(), 2);
+    }
+
+    #[actix_web::test]
+    async fn blocked_contacts_cannot_send_direct_messages() {
+        let mut repo = mock_message_repo();
+        let mut contact_repo = mock_contact_repo();
+        let mut channel_repo = mock_channel_repo();
+
+        let contacts = add_test_contacts(&mut contact_repo).await;
+        let (sansa, eddard) = (contacts[0].id(), contacts[1].id());
+        let private = add_test_channel(&mut channel_repo, &contacts).await;
+        let group = channel_repo
+            .create(&Channel::new(
+                "Winterfell",
+                ChannelType::Group,
+                &[sansa.clone(), eddard.clone()],
+            ))
+            .await
+            .unwrap();
+        let mut blocker = contacts[1].clone();
+        blocker.blocked.push(BlockedContact {
+            contact_id: sansa.clone(),
+            hide_messages: true,
+            blocked_at: Utc::now(),
+        });
+        contact_repo.update(&blocker).await.unwrap();
+
+        let events = EventBus::default();
+        let sent = record::<MessageSent>(&events);
+        let mut service = MessageService::new(&mut repo, &mut channel_repo, &mut contact_repo)
+            .with_events(events);
+        let send = |channel_id: Option<IdType>, from: &IdType, to: &IdType| commands::SendMessage {
+            channel_id,
+            from: from.clone(),
+            to: to.clone(),
+            content: MessageContent::text("Winter is coming, @Sansa Stark"),
+            client_message_id: None,
+        };
+        assert!(service
+            .send_message(&send(None, &sansa, &eddard))
+            .await
+            .is_err());
+        let direct = send(Some(private.id()), &sansa, &eddard);
+        assert!(service.send_message(&direct).await.is_err());
+        let reply = send(Some(private.id()), &eddard, &sansa);
+        assert!(
+            service.send_message(&reply).await.is_ok(),
+            "The blocker can still write"
+        );
+
+        for (from, to) in [(&sansa, &eddard), (&eddard, &sansa)] {
+            let cmd = send(Some(group.id()), from, to);
+            service.send_message(&cmd).await.unwrap();
+        }
+        let seen_by_sansa = service
+            .get_messages_after(&group.id(), &sansa, 0, 10)
+            .await
+            .unwrap();
+        assert_eq!(
+            seen_by_sansa.len(),
+            1,
+            "Eddard's message is hidden from Sansa"
+        );
+        assert_eq!(seen_by_sansa[0].from, sansa);
+        let seen_by_eddard = service
+            .get_messages_after(&group.id(), &eddard, 0, 10)
+            .await
+            .unwrap();
+        assert_eq!(seen_by_eddard.len(), 2);
+        let direct_history = service
+            .get_messages_after(&private.id(), &sansa, 0, 10)
+            .await
+            .unwrap();
+        assert!(
+            direct_history.iter().any(|m| m.from == eddard),
+            "Hiding only applies to shared groups"
+        );
+
+        let group_sends: Vec<MessageSent> = sent
+            .send(Replay::default())
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.channel.id() == group.id())
+            .collect();
+        assert_eq!(group_sends.len(), 2);
+        assert!(group_sends[0].hidden_from.is_empty());
+        assert_eq!(group_sends[1].hidden_from, vec![sansa.clone()]);
+
+        // Eddard's mention and pin do not reveal his message to Sansa either
+        let hidden = &seen_by_eddard[1];
+        assert_eq!(hidden.mentions, vec![sansa.clone()]);
+        let pin = commands::PinMessage {
+            channel_id: group.id(),
+            message_id: hidden.id(),
+            pinned_by: eddard.clone(),
+        };
+        service.pin_message(&pin).await.unwrap();
+        let mentions = service.get_mentions(&sansa, 10, 0).await.unwrap();
+        assert_eq!(mentions.len(), 1, "Only the direct reply");
+        assert_eq!(mentions[0].channel_id, private.id());
+        assert!(service
+            .get_pins(&group.id(), &sansa)
+            .await
+            .unwrap()
+            .is_empty());
+        let pins = service.get_pins(&group.id(), &eddard).await.unwrap();
+        assert_eq!(pins.len(), 1);
+    }
+
+    struct Echo;
+
+    #[async_trait::async_trait]
+    impl SlashCommandHandler for Echo {
+        async fn handle(
+            &self,
+            invocation: &SlashCommandInvoked,
+        ) -> Result<Option<MessageContent>, String> {
+            Ok(Some(MessageContent::text(&invocation.args)))
+        }
+    }
+
+    #[actix_web::test]
+    async fn answers_slash_commands() {
+        let mut repo = mock_message_repo();
+        let mut contact_repo = mock_contact_repo();
+        let mut channel_repo = mock_channel_repo();
+
+        let contacts = add_test_contacts(&mut contact_repo).await;
+        let channel = add_test_channel(&mut channel_repo, &contacts).await;
+        let events = EventBus::default();
+        let invoked = record::<SlashCommandInvoked>(&events);
+        let registry = CommandRegistry::default();
+        registry.register("echo", Echo);
+        let mut service = MessageService::new(&mut repo, &mut channel_repo, &mut contact_repo)
+            .with_events(events)
+            .with_commands(registry);
+
+        for text in ["/echo Winter is coming", "/weather Winterfell"] {
+            let cmd = commands::SendMessage {
+                channel_id: Some(channel.id()),
+                from: contacts[0].id(),
+                to: contacts[1].id(),
+                content: MessageContent::text(text),
+                client_message_id: None,
+            };
+            service.send_message(&cmd).await.unwrap();
+        }
+
+        let messages = service.get_messages(&channel.id()).await.unwrap();
+        assert_eq!(messages.len(), 3, "Only the in-process command is answered");
+        let reply = &messages[1];
+        assert_eq!(reply.bot_name, Some("/echo".to_string()));
+        assert_eq!(reply.content, MessageContent::text("Winter is coming"));
+        // The other command is left to the bots registered over HTTP
+        assert_eq!(invoked.send(Recorded).await.unwrap(), 1);
+    }
+
+    #[actix_web::test]
+    async fn processes_the_sent_messages() {
+        let mut repo = mock_message_repo();
+        let mut contact_repo = mock_contact_repo();
+        let mut channel_repo = mock_channel_repo();
+
+        let contacts = add_test_contacts(&mut contact_repo).await;
+        let channel = add_test_channel(&mut channel_repo, &contacts).await;
+        let rule = |pattern: &str, action| FilterRule {
+            pattern: pattern.to_string(),
+            regex: false,
+            action,
+        };
+        let filter = ContentFilter::new(vec![
+            rule("red wedding", FilterAction::Reject),
+            rule("bastard", FilterAction::Mask),
+            rule("wildfire", FilterAction::Flag),
+        ])
+        .unwrap();
+        let pipeline = Pipeline::standard();
+        pipeline.register(filter);
+        let events = EventBus::default();
+        let flagged = record::<MessageFlagged>(&events);
+        let mut banned = contacts[1].clone();
+        banned.banned = true;
+        contact_repo.update(&banned).await.unwrap();
+        let mut service = MessageService::new(&mut repo, &mut channel_repo, &mut contact_repo)
+            .with_events(events)
+            .with_pipeline(pipeline);
+
+        let send = |from: &Contact, to: &Contact, text: &str| commands::SendMessage {
+            channel_id: Some(channel.id()),
+            from: from.id(),
+            to: to.id(),
+            content: MessageContent::text(text),
+            client_message_id: None,
+        };
+        let (sansa, eddard) = (&contacts[0], &contacts[1]);
+        assert!(service
+            .send_message(&send(sansa, eddard, "See you at the Red Wedding"))
+            .await
+            .is_err());
+        let masked = service
+            .send_message(&send(sansa, eddard, "Jon is no Bastard"))
+            .await
+            .unwrap();
+        assert_eq!(masked.content, MessageContent::text("Jon is no *******"));
+        let flagged_message = service
+            .send_message(&send(
+                sansa,
+                eddard,
+                " The wildfire is under https://sept.example ",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            flagged_message.links,
+            vec!["https://sept.example".to_string()]
+        );
+        assert_eq!(flagged.send(Recorded).await.unwrap(), 1);
+
+        let post = |text: &str| commands::PostBotMessage {
+            channel_id: channel.id(),
+            bot_id: IdType::String("raven".to_string()),
+            bot_name: "Raven".to_string(),
+            content: MessageContent::text(text),
+        };
+        assert!(service
+            .post_bot_message(&post("Remember the Red Wedding"))
+            .await
+            .is_err());
+        service
+            .post_bot_message(&post("Wildfire under the sept"))
+            .await
+            .unwrap();
+        assert_eq!(flagged.send(Recorded).await.unwrap(), 2);
+        let masked = service
+            .send_bot_message(sansa, &channel.id(), &MessageContent::text("Bastard"))
+            .await
+            .unwrap();
+        assert_eq!(masked.content, MessageContent::text("*******"));
+        assert!(
+            service
+                .send_message(&send(eddard, sansa, "Winter is coming"))
+                .await
+                .is_err(),
+            "Banned contacts cannot send"
+        );
+    }
+
+    #[actix_web::test]
+    async fn attaches_link_previews() {
+        let mut repo = mock_message_repo();
+        let mut contact_repo = mock_contact_repo();
+        let mut channel_repo = mock_channel_repo();
+
+        let contacts = add_test_contacts(&mut contact_repo).await;
+        let channel = add_test_channel(&mut channel_repo, &contacts).await;
+        let events = EventBus::default();
+        let updated = record::<MessageUpdated>(&events);
+        let mut service = MessageService::new(&mut repo, &mut channel_repo, &mut contact_repo)
+            .with_events(events)
+            .with_pipeline(Pipeline::standard());
+        let cmd = commands::SendMessage {
+            channel_id: Some(channel.id()),
+            from: contacts[0].id(),
+            to: contacts[1].id(),
+            content: MessageContent::text("Read https://citadel.example/maesters"),
+            client_message_id: None,
+        };
+        let message = service.send_message(&cmd).await.unwrap();
+        let preview = LinkPreview {
+            url: message.links[0].clone(),
+            title: Some("The Citadel".to_string()),
+            description: None,
+            image: None,
+        };
+
+        service
+            .attach_previews(&message.id(), vec![preview.clone()])
+            .await
+            .unwrap();
+
+        let messages = service.get_messages(&channel.id()).await.unwrap();
+        assert_eq!(messages[0].previews, vec![preview]);
+        assert_eq!(updated.send(Recorded).await.unwrap(), 1);
+    }
+
+    #[actix_web::test]
+    async fn can_catch_up_after_a_sequence_number() {
+        let mut repo = mock_message_repo();
+        let mut contact_repo = mock_contact_repo();
+        let mut channel_repo = mock_channel_repo();
+
+        let contacts = add_test_contacts(&mut contact_repo).await;
+        let channel = add_test_channel(&mut channel_repo, &contacts).await;
+        let outsider = contact_repo
+            .create(&Contact::new("Theon Greyjoy", "theon@pyke.com"))
+            .await
+            .unwrap();
+
+        let mut service = MessageService::new(&mut repo, &mut channel_repo, &mut contact_repo);
+        let mut seqs = vec![];
+        for text in ["Winter", "is", "coming"] {
+            let cmd = commands::SendMessage {
+                channel_id: Some(channel.id()),
+                from: contacts[0].id(),
+                to: contacts[1].id(),
+                content: MessageContent::text(text),
+                client_message_id: None,
+            };
+            seqs.push(service.send_message(&cmd).await.unwrap().seq);
+        }
+        assert_eq!(seqs, vec![1, 2, 3]);
+
+        let messages = service
+            .get_messages_after(&channel.id(), &contacts[1].id(), 1, 10)
+            .await
+            .unwrap();
+        let texts: Vec<_> = messages.iter().filter_map(|m| m.content.body()).collect();
+        assert_eq!(texts, vec!["is", "coming"]);
+
+        let res = service
+            .get_messages_after(&channel.id(), &outsider.id(), 0, 10)
+            .await;
+        assert!(res.is_err(), "Only members can read the channel");
+
+        // A client that synced the deleted message must still get the next one
+        service.repository.delete(&messages[1].id()).await.unwrap();
+        let cmd = commands::SendMessage {
+            channel_id: Some(channel.id()),
+            from: contacts[0].id(),
+            to: contacts[1].id(),
+            content: MessageContent::text("again"),
+            client_message_id: None,
+        };
+        assert_eq!(service.send_message(&cmd).await.unwrap().seq, 4);
+    }
+
+    #[actix_web::test]
+    async fn resolves_mentions_of_channel_members() {
+        let mut repo = mock_message_repo();
+        let mut contact_repo = mock_contact_repo();
+        let mut channel_repo = mock_channel_repo();
+
+        let contacts = add_test_contacts(&mut contact_repo).await;
+        let outsider = contact_repo
+            .create(&Contact::new("Cersei Lannister", "cersei@kingslanding.com"))
+            .await
+            .unwrap();
+        let channel = add_test_channel(&mut channel_repo, &contacts).await;
+
+        let mut service = MessageService::new(&mut repo, &mut channel_repo, &mut contact_repo);
+
+        let cmd = commands::SendMessage {
+            channel_id: Some(channel.id()),
+            from: contacts[0].id(),
+            to: contacts[1].id(),
+            content: MessageContent::text("@Eddard Stark, do not trust @cersei@kingslanding.com"),
+            client_message_id: None,
+        };
+        service.send_message(&cmd).await.unwrap();
+
+        let mentions = service
+            .get_mentions(&contacts[1].id(), 20, 0)
+            .await
+            .unwrap();
+        assert_eq!(mentions.len(), 1);
+        assert_eq!(mentions[0].mentions, vec![contacts[1].id()]);
+
+        let cmd = commands::SendMessage {
+            content: MessageContent::text("@Eddard Stark, winter is coming"),
+            ..cmd
+        };
+        let latest = service.send_message(&cmd).await.unwrap();
+        let page = service.get_mentions(&contacts[1].id(), 1, 0).await.unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].id(), latest.id());
+        let page = service.get_mentions(&contacts[1].id(), 1, 1).await.unwrap();
+        assert_eq!(page[0].id(), mentions[0].id());
+
+        let mentions = service.get_mentions(&outsider.id(), 20, 0).await.unwrap();
+        assert!(mentions.is_empty(), "Only channel members can be mentioned");
+    }
+
+    #[actix_web::test]
+    async fn messages_disappear_after_the_channel_ttl() {
+        let mut repo = mock_message_repo();
+        let mut contact_repo = mock_contact_repo();
+        let mut channel_repo = mock_channel_repo();
+
+        let contacts = add_test_contacts(&mut contact_repo).await;
+        let mut channel = add_test_channel(&mut channel_repo, &contacts).await;
+        channel.message_ttl = Some(60);
+        channel_repo.update(&channel).await.unwrap();
+
+        let mut service = MessageService::new(&mut repo, &mut channel_repo, &mut contact_repo);
+        let cmd = commands::SendMessage {
+            channel_id: Some(channel.id()),
+            from: contacts[0].id(),
+            to: contacts[1].id(),
+            content: MessageContent::text("This message will self-destruct"),
+            client_message_id: None,
+        };
+        service.send_message(&cmd).await.unwrap();
+
+        let messages = service.get_messages(&channel.id()).await.unwrap();
+        assert_eq!(messages.len(), 1);
+        let expires_at = messages[0].expires_at.unwrap();
+        assert_eq!(expires_at, messages[0].created_at + Duration::seconds(60));
+
+        let pin = commands::PinMessage {
+            channel_id: channel.id(),
+            message_id: messages[0].id(),
+            pinned_by: contacts[0].id(),
+        };
+        let pinned = service.pin_message(&pin).await.unwrap();
+        let expires_at = pinned.expires_at.unwrap();
+        assert_eq!(expires_at, pinned.created_at + Duration::seconds(60));
+
+        // Expired messages are hidden before the sweeper deletes them
+        let mut expired = messages[0].clone();
+        expired.expires_at = Some(Utc::now() - Duration::seconds(1));
+        service.repository.update(&expired).await.unwrap();
+        let messages = service.get_messages(&channel.id()).await.unwrap();
+        assert!(messages.iter().all(|m| m.id() != expired.id()));
+        assert!(service.repository.get(&expired.id()).await.is_none());
+        let pins = service.get_pins(&channel.id(), &contacts[0].id()).await;
+        assert!(pins.unwrap().is_empty());
+
+        let purged = service.purge_expired(Utc::now()).await.unwrap();
+        assert_eq!(purged, 1);
+
+        // A lifetime stored before the bound was enforced fails instead of overflowing
+        let mut channel = service.get_channel(&channel.id()).await.unwrap();
+        channel.message_ttl = Some(i64::MAX);
+        service.channel_repository.update(&channel).await.unwrap();
+        assert!(service.send_message(&cmd).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn can_pin_and_unpin_messages() {
+        let mut repo = mock_message_repo();
+        let mut contact_repo = mock_contact_repo();
+        let mut channel_repo = mock_channel_repo();
+
+        let contacts = add_test_contacts(&mut contact_repo).await;
+        let channel = add_test_channel(&mut channel_repo, &contacts).await;
+        let message = repo
+            .create(&Message::new(
+                &channel.id(),
+                &contacts[0].id(),
+                &contacts[1].id(),
+                &MessageContent::text("Winter is coming"),
+            ))
+            .await
+            .unwrap();
+        let outsider = contact_repo
+            .create(&Contact::new("Theon Greyjoy", "theon@pyke.com"))
+            .await
+            .unwrap();
+
+        let mut service = MessageService::new(&mut repo, &mut channel_repo, &mut contact_repo);
+
+        let pin = commands::PinMessage {
+            channel_id: channel.id(),
+            message_id: message.id(),
+            pinned_by: contacts[1].id(),
+        };
+        let event = service.pin_message(&pin).await.unwrap();
+        assert_eq!(
+            event.content,
+            MessageContent::System {
+                event: SystemEvent::MessagePinned {
+                    message_id: message.id()
+                }
+            }
+        );
+        assert!(service.pin_message(&pin).await.is_err(), "Already pinned");
+
+        let pins = service
+            .get_pins(&channel.id(), &contacts[0].id())
+            .await
+            .unwrap();
+        assert_eq!(pins.len(), 1);
+        assert_eq!(pins[0].id(), message.id());
+        assert!(
+            service
+                .get_pins(&channel.id(), &outsider.id())
+                .await
+                .is_err(),
+            "Not a member"
+        );
+
+        let unpin = commands::UnpinMessage {
+            channel_id: channel.id(),
+            message_id: message.id(),
+            unpinned_by: contacts[0].id(),
+        };
+        service.unpin_message(&unpin).await.unwrap();
+        let pins = service.get_pins(&channel.id(), &contacts[0].id()).await;
+        assert!(pins.unwrap().is_empty());
+        assert!(service.unpin_message(&unpin).await.is_err(), "Not pinned");
+    }
+
+    #[actix_web::test]
+    async fn archived_channels_are_read_only() {
+        let mut repo = mock_message_repo();
+        let mut contact_repo = mock_contact_repo();
+        let mut channel_repo = mock_channel_repo();
+
+        let contacts = add_test_contacts(&mut contact_repo).await;
+        let mut channel = add_test_channel(&mut channel_repo, &contacts).await;
+        let mut message = Message::new(
+            &channel.id(),
+            &contacts[0].id(),
+            &contacts[1].id(),
+            &MessageContent::text("The Lannisters send their regards"),
+        );
+        message.seq = 1;
+        let message = repo.create(&message).await.unwrap();
+        channel.archived_at = Some(Utc::now());
+        channel_repo.update(&channel).await.unwrap();
+
+        let mut service = MessageService::new(&mut repo, &mut channel_repo, &mut contact_repo);
+        let cmd = commands::SendMessage {
+            channel_id: None,
+            from: contacts[1].id(),
+            to: contacts[0].id(),
+            content: MessageContent::text("Not today"),
+            client_message_id: None,
+        };
+        assert!(service.send_message(&cmd).await.is_err());
+        let bot = commands::PostBotMessage {
+            channel_id: channel.id(),
+            bot_id: channel.id(),
+            bot_name: "Maester".to_string(),
+            content: MessageContent::text("A raven arrived"),
+        };
+        assert!(service.post_bot_message(&bot).await.is_err());
+        let pin = commands::PinMessage {
+            channel_id: channel.id(),
+            message_id: message.id(),
+            pinned_by: contacts[0].id(),
+        };
+        assert!(service.pin_message(&pin).await.is_err());
+
+        let history = service
+            .get_messages_after(&channel.id(), &contacts[0].id(), 0, 10)
+            .await
+            .unwrap();
+        assert_eq!(history.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn cannot_pin_more_than_the_channel_cap() {
+        let mut repo = mock_message_repo();
+        let mut contact_repo = mock_contact_repo();
+        let mut channel_repo = mock_channel_repo();
+
+        let contacts = add_test_contacts(&mut contact_repo).await;
+        let channel = add_test_channel(&mut channel_repo, &contacts).await;
+        let mut messages = Vec::new();
+        for i in 0..=MAX_PINNED_MESSAGES {
+            let message = repo
+                .create(&Message::new(
+                    &channel.id(),
+                    &contacts[0].id(),
+                    &contacts[1].id(),
+                    &MessageContent::text(&format!("Raven number {i}")),
+                ))
+                .await
+                .unwrap();
+            messages.push(message);
+        }
+
+        let mut service = MessageService::new(&mut repo, &mut channel_repo, &mut contact_repo);
+        let pin = |message: &Message| commands::PinMessage {
+            channel_id: channel.id(),
+            message_id: message.id(),
+            pinned_by: contacts[0].id(),
+        };
+        for message in messages[..MAX_PINNED_MESSAGES].iter() {
+            service.pin_message(&pin(message)).await.unwrap();
+        }
+        let one_too_many = pin(&messages[MAX_PINNED_MESSAGES]);
+        assert!(service.pin_message(&one_too_many).await.is_err());
+
+        let pins = service
+            .get_pins(&channel.id(), &contacts[1].id())
+            .await
+            .unwrap();
+        assert_eq!(pins.len(), MAX_PINNED_MESSAGES);
+        assert_eq!(pins[0].id(), messages[0].id());
+    }
+
+    #[actix_web::test]
+    async fn pins_are_checked_by_the_update() {
+        let mut contact_repo = mock_contact_repo();
+        let mut channel_repo = mock_channel_repo();
+
+        let contacts = add_test_contacts(&mut contact_repo).await;
+        let channel = add_test_channel(&mut channel_repo, &contacts).await;
+        let ids: Vec<IdType> = (0..=MAX_PINNED_MESSAGES)
+            .map(|_| IdType::ObjectId(mongodb::bson::oid::ObjectId::new()))
+            .collect();
+        for id in ids[..MAX_PINNED_MESSAGES].iter() {
+            assert!(channel_repo.pin_message(&channel.id(), id).await.unwrap());
+        }
+        let pin = channel_repo.pin_message(&channel.id(), &ids[0]).await;
+        assert!(!pin.unwrap(), "Already pinned");
+        let pin = channel_repo
+            .pin_message(&channel.id(), &ids[MAX_PINNED_MESSAGES])
+            .await;
+        assert!(!pin.unwrap(), "Full");
+
+        let unpin = channel_repo.unpin_message(&channel.id(), &ids[0]).await;
+        assert!(unpin.unwrap());
+        let unpin = channel_repo.unpin_message(&channel.id(), &ids[0]).await;
+        assert!(!unpin.unwrap(), "Not pinned");
+        let channel = channel_repo.get(&channel.id()).await.unwrap();
+        assert_eq!(channel.pinned_message_ids, ids[1..MAX_PINNED_MESSAGES]);
+    }
 }
 
 #[cfg(test)]
@@ -181,6 +1493,7 @@ mod tests_mongo {
     use crate::adapters::mongo::repository::MongoRepository;
     use crate::adapters::{Model, Repository};
     use crate::commands;
+    use crate::models::MessageContent;
     use crate::services::message_handlers::{add_test_channel, add_test_contacts, MessageService};
 
     #[actix_web::test]
@@ -199,7 +1512,8 @@ mod tests_mongo {
             channel_id: None,
             from: contacts[0].id(),
             to: contacts[1].id(),
-            content: "The north remembers!".to_string(),
+            content: MessageContent::text("The north remembers!"),
+            client_message_id: None,
         };
         let res = service.send_message(&cmd).await;
         assert!(res.is_ok());
@@ -227,7 +1541,7 @@ mod tests_mongo {
         let test_channel = add_test_channel(&mut repo, &contacts).await;
 
         let channel = repo
-            .get_by_contact_ids(&vec![contacts[0].id(), contacts[1].id()])
+            .get_by_contact_ids(&[contacts[0].id(), contacts[1].id()])
             .await
             .unwrap();
         assert_eq!(channel.id(), test_channel.id());
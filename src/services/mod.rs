@@ -1,6 +1,28 @@
 mod channel_handlers;
 mod contact_handlers;
+mod content_filter;
+mod export_handlers;
+mod incoming_webhook_handlers;
+mod mentions;
 mod message_handlers;
+mod moderation_handlers;
+mod notification_handlers;
+mod pipeline;
+mod scheduled_message_handlers;
+mod slash_command_handlers;
+mod slash_commands;
+mod webhook_handlers;
 
 pub use channel_handlers::ChannelService;
 pub use contact_handlers::ContactService;
+pub use content_filter::{ContentFilter, FilterRule};
+pub use export_handlers::{ExportFormat, ExportRecord, ExportService};
+pub use incoming_webhook_handlers::IncomingWebhookService;
+pub use message_handlers::{MessageError, MessageService, MAX_SYNC_BATCH};
+pub use moderation_handlers::ModerationService;
+pub use notification_handlers::{Digest, NotificationService, Notifier};
+pub use pipeline::Pipeline;
+pub use scheduled_message_handlers::ScheduledMessageService;
+pub use slash_command_handlers::SlashCommandService;
+pub use slash_commands::CommandRegistry;
+pub use webhook_handlers::{sign, WebhookRequest, WebhookSender, WebhookService};
@@ -0,0 +1,433 @@
+use crate::adapters::channel_repository::ChannelRepository;
+use crate::adapters::contact_repository::ContactRepository;
+use crate::adapters::message_repository::MessageRepository;
+use crate::adapters::report_repository::{ModerationActionRepository, ReportRepository};
+use crate::adapters::{IdType, Model};
+use crate::commands;
+use crate::events::{ChannelUpdated, ContactUpdated, EventBus};
+use crate::models::{
+    Channel, Contact, Message, ModerationAction, ModerationActionType, Report, ReportStatus,
+};
+use chrono::Utc;
+use std::fmt::{Display, Formatter};
+
+pub struct ModerationService<'a> {
+    repository: &'a mut dyn ReportRepository,
+    action_repository: &'a mut dyn ModerationActionRepository,
+    message_repository: &'a mut dyn MessageRepository,
+    channel_repository: &'a mut dyn ChannelRepository,
+    contact_repository: &'a mut dyn ContactRepository,
+    events: EventBus,
+}
+
+impl<'a> ModerationService<'a> {
+    pub fn new(
+        repo: &'a mut dyn ReportRepository,
+        action_repository: &'a mut dyn ModerationActionRepository,
+        message_repository: &'a mut dyn MessageRepository,
+        channel_repository: &'a mut dyn ChannelRepository,
+        contact_repository: &'a mut dyn ContactRepository,
+    ) -> Self {
+        ModerationService {
+            repository: repo,
+            action_repository,
+            message_repository,
+            channel_repository,
+            contact_repository,
+            events: EventBus::default(),
+        }
+    }
+
+    /// Publishes the changes to the given bus
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Queues a report of the message, made by a member of its channel
+    pub async fn report_message(
+        &mut self,
+        cmd: &commands::ReportMessage,
+    ) -> Result<Report, ModerationError> {
+        let reason = cmd.reason.trim();
+        if reason.is_empty() {
+            return Err(ModerationError {
+                message: "Reports need a reason".to_string(),
+            });
+        }
+        let message = self.get_message(&cmd.message_id).await?;
+        let channel = self.get_channel(&message.channel_id).await?;
+        if !channel.contact_ids.contains(&cmd.reported_by) {
+            return Err(ModerationError {
+                message: format!(
+                    "Contact with id {} is not a channel member",
+                    cmd.reported_by
+                ),
+            });
+        }
+        let report = Report::new(
+            &message.id(),
+            &channel.id(),
+            &message.from,
+            Some(cmd.reported_by.clone()),
+            reason,
+        );
+        self.create_report(&report).await
+    }
+
+    /// Queues a report of a message that matched flag rules of the content filter
+    pub async fn flag_message(
+        &mut self,
+        message: &Message,
+        rules: &[String],
+    ) -> Result<Report, ModerationError> {
+        let reason = format!("Matched the filter rules {}", rules.join(", "));
+        let report = Report::new(
+            &message.id(),
+            &message.channel_id,
+            &message.from,
+            None,
+            &reason,
+        );
+        self.create_report(&report).await
+    }
+
+    /// Returns the reports in the given status, oldest first
+    pub async fn list_reports(
+        &self,
+        moderator_id: &IdType,
+        status: &ReportStatus,
+        limit: i64,
+        offset: u64,
+    ) -> Result<Vec<Report>, ModerationError> {
+        self.get_moderator(moderator_id).await?;
+        match self.repository.find_by_status(status, limit, offset).await {
+            Ok(r) => Ok(r),
+            Err(e) => Err(ModerationError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    /// Applies the action to an open report, closes it and records the action
+    pub async fn act(&mut self, cmd: &commands::ModerateReport) -> Result<Report, ModerationError> {
+        self.get_moderator(&cmd.moderator_id).await?;
+        let mut report = self.get_report(&cmd.report_id).await?;
+        if report.status != ReportStatus::Open {
+            return Err(ModerationError {
+                message: "The report is already closed".to_string(),
+            });
+        }
+        match cmd.action {
+            ModerationActionType::Resolve => {}
+            ModerationActionType::Dismiss => {}
+            ModerationActionType::DeleteMessage => self.delete_message(&report).await?,
+            ModerationActionType::BanAuthor => self.ban(&report.author_id).await?,
+        }
+        report.status = match cmd.action {
+            ModerationActionType::Dismiss => ReportStatus::Dismissed,
+            _ => ReportStatus::Resolved,
+        };
+        report.updated_at = Utc::now();
+        if let Err(e) = self.repository.update(&report).await {
+            return Err(ModerationError {
+                message: e.to_string(),
+            });
+        }
+        let action = ModerationAction::new(
+            &report.id(),
+            &cmd.moderator_id,
+            &cmd.action,
+            cmd.note.clone(),
+        );
+        match self.action_repository.create(&action).await {
+            Ok(_) => Ok(report),
+            Err(e) => Err(ModerationError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    /// Returns the actions taken on the report, oldest first
+    pub async fn list_actions(
+        &self,
+        moderator_id: &IdType,
+        report_id: &IdType,
+    ) -> Result<Vec<ModerationAction>, ModerationError> {
+        self.get_moderator(moderator_id).await?;
+        self.get_report(report_id).await?;
+        match self.action_repository.find_by_report_id(report_id).await {
+            Ok(a) => Ok(a),
+            Err(e) => Err(ModerationError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    /// Appoints or removes a moderator. Only moderators can change moderators.
+    pub async fn set_moderator(
+        &mut self,
+        changed_by: &IdType,
+        contact_id: &IdType,
+        moderator: bool,
+    ) -> Result<Contact, ModerationError> {
+        self.get_moderator(changed_by).await?;
+        let mut contact = self.get_contact(contact_id).await?;
+        contact.moderator = moderator;
+        self.update_contact(&mut contact).await?;
+        Ok(contact)
+    }
+
+    async fn create_report(&mut self, report: &Report) -> Result<Report, ModerationError> {
+        match self.repository.create(report).await {
+            Ok(r) => Ok(r),
+            Err(e) => Err(ModerationError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    /// Deletes the reported message and unpins it from its channel
+    async fn delete_message(&mut self, report: &Report) -> Result<(), ModerationError> {
+        if let Err(e) = self.message_repository.delete(&report.message_id).await {
+            return Err(ModerationError {
+                message: e.to_string(),
+            });
+        }
+        let unpinned = self
+            .channel_repository
+            .unpin_message(&report.channel_id, &report.message_id)
+            .await;
+        match unpinned {
+            Ok(true) => (),
+            Ok(false) => return Ok(()),
+            Err(e) => {
+                return Err(ModerationError {
+                    message: e.to_string(),
+                })
+            }
+        }
+        if let Some(channel) = self.channel_repository.get(&report.channel_id).await {
+            self.events.publish(ChannelUpdated { channel });
+        }
+        Ok(())
+    }
+
+    async fn ban(&mut self, contact_id: &IdType) -> Result<(), ModerationError> {
+        let mut contact = self.get_contact(contact_id).await?;
+        contact.banned = true;
+        self.update_contact(&mut contact).await
+    }
+
+    async fn update_contact(&mut self, contact: &mut Contact) -> Result<(), ModerationError> {
+        contact.updated_at = Utc::now();
+        match self.contact_repository.update(contact).await {
+            Ok(_) => {
+                self.events.publish(ContactUpdated {
+                    contact: contact.clone(),
+                });
+                Ok(())
+            }
+            Err(e) => Err(ModerationError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    async fn get_moderator(&self, id: &IdType) -> Result<Contact, ModerationError> {
+        match self.get_contact(id).await? {
+            c if c.moderator => Ok(c),
+            _ => Err(ModerationError {
+                message: "Only moderators can review reports".to_string(),
+            }),
+        }
+    }
+
+    async fn get_contact(&self, id: &IdType) -> Result<Contact, ModerationError> {
+        match self.contact_repository.get(id).await {
+            Some(c) => Ok(c),
+            None => Err(ModerationError {
+                message: format!("Contact with id {id} not found"),
+            }),
+        }
+    }
+
+    async fn get_report(&self, id: &IdType) -> Result<Report, ModerationError> {
+        match self.repository.get(id).await {
+            Some(r) => Ok(r),
+            None => Err(ModerationError {
+                message: format!("Report with id {id} not found"),
+            }),
+        }
+    }
+
+    async fn get_message(&self, id: &IdType) -> Result<Message, ModerationError> {
+        match self.message_repository.get(id).await {
+            Some(m) => Ok(m),
+            None => Err(ModerationError {
+                message: format!("Message with id {id} not found"),
+            }),
+        }
+    }
+
+    async fn get_channel(&self, id: &IdType) -> Result<Channel, ModerationError> {
+        match self.channel_repository.get(id).await {
+            Some(c) => Ok(c),
+            None => Err(ModerationError {
+                message: format!("Channel with id {id} not found"),
+            }),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ModerationError {
+    pub message: String,
+}
+
+impl Display for ModerationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::adapters::{
+        mock_channel_repo, mock_contact_repo, mock_message_repo, mock_moderation_action_repo,
+        mock_report_repo, IdType, Model, Repository,
+    };
+    use crate::commands;
+    use crate::models::{
+        Channel, ChannelType, Contact, Message, MessageContent, ModerationActionType, ReportStatus,
+    };
+    use crate::services::moderation_handlers::ModerationService;
+
+    #[actix_web::test]
+    async fn moderators_work_the_report_queue() {
+        let mut repo = mock_report_repo();
+        let mut action_repo = mock_moderation_action_repo();
+        let mut message_repo = mock_message_repo();
+        let mut channel_repo = mock_channel_repo();
+        let mut contact_repo = mock_contact_repo();
+
+        let mut moderator = Contact::new("Varys", "varys@kingslanding.com");
+        moderator.moderator = true;
+        let varys = contact_repo.create(&moderator).await.unwrap().id();
+        let joffrey = contact_repo
+            .create(&Contact::new(
+                "Joffrey Baratheon",
+                "joffrey@kingslanding.com",
+            ))
+            .await
+            .unwrap()
+            .id();
+        let sansa = contact_repo
+            .create(&Contact::new("Sansa Stark", "sansa@winterfell.com"))
+            .await
+            .unwrap()
+            .id();
+        let channel = channel_repo
+            .create(&Channel::new(
+                "Small Council",
+                ChannelType::Group,
+                &[joffrey.clone(), sansa.clone()],
+            ))
+            .await
+            .unwrap();
+        let content = MessageContent::text("Bring me her father's head");
+        let insult = message_repo
+            .create(&Message::new(
+                &channel.id(),
+                &joffrey,
+                &channel.id(),
+                &content,
+            ))
+            .await
+            .unwrap();
+        let threat = message_repo
+            .create(&Message::new(
+                &channel.id(),
+                &joffrey,
+                &channel.id(),
+                &content,
+            ))
+            .await
+            .unwrap();
+
+        let mut service = ModerationService::new(
+            &mut repo,
+            &mut action_repo,
+            &mut message_repo,
+            &mut channel_repo,
+            &mut contact_repo,
+        );
+        let report = |message: &Message, reported_by: &IdType| commands::ReportMessage {
+            message_id: message.id(),
+            reported_by: reported_by.clone(),
+            reason: "Threats".to_string(),
+        };
+        assert!(
+            service
+                .report_message(&report(&insult, &varys))
+                .await
+                .is_err(),
+            "Only members can report"
+        );
+        let first = service
+            .report_message(&report(&insult, &sansa))
+            .await
+            .unwrap();
+        let second = service
+            .report_message(&report(&threat, &sansa))
+            .await
+            .unwrap();
+        assert_eq!(first.author_id, joffrey);
+
+        assert!(service
+            .list_reports(&sansa, &ReportStatus::Open, 10, 0)
+            .await
+            .is_err());
+        let open = service
+            .list_reports(&varys, &ReportStatus::Open, 10, 0)
+            .await
+            .unwrap();
+        assert_eq!(open.len(), 2);
+
+        let act = |report_id, action| commands::ModerateReport {
+            report_id,
+            moderator_id: varys.clone(),
+            action,
+            note: None,
+        };
+        let deleted = service
+            .act(&act(first.id(), ModerationActionType::DeleteMessage))
+            .await
+            .unwrap();
+        assert_eq!(deleted.status, ReportStatus::Resolved);
+        let banned = service
+            .act(&act(second.id(), ModerationActionType::BanAuthor))
+            .await
+            .unwrap();
+        assert_eq!(banned.status, ReportStatus::Resolved);
+        assert!(
+            service
+                .act(&act(second.id(), ModerationActionType::Dismiss))
+                .await
+                .is_err(),
+            "Closed reports take no more actions"
+        );
+        let actions = service.list_actions(&varys, &second.id()).await.unwrap();
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0].action, ModerationActionType::BanAuthor);
+        assert!(service
+            .list_reports(&varys, &ReportStatus::Open, 10, 0)
+            .await
+            .unwrap()
+            .is_empty());
+
+        assert!(message_repo.get(&insult.id()).await.is_none());
+        assert!(message_repo.get(&threat.id()).await.is_some());
+        assert!(contact_repo.get(&joffrey).await.unwrap().banned);
+    }
+}
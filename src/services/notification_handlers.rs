@@ -0,0 +1,462 @@
+use crate::adapters::contact_repository::ContactRepository;
+use crate::adapters::notification_repository::NotificationRepository;
+use crate::adapters::{IdType, Model};
+use crate::models::{
+    Channel, Contact, ContactKind, Message, MessageContent, Notification, NotificationStatus,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use std::fmt::{Display, Formatter};
+
+/// How long the first notification of a contact waits before it is sent, so
+/// the messages arriving meanwhile go into the same digest
+pub const BATCH_WINDOW_SECONDS: i64 = 120;
+
+/// Digests still failing after this many attempts are given up
+pub const MAX_NOTIFY_ATTEMPTS: i32 = 5;
+
+/// The wait before the first retry of a failed digest, doubled after each
+/// further failure
+const RETRY_DELAY_SECONDS: i64 = 60;
+
+/// The number of characters of the message text shown in a notification
+const PREVIEW_LENGTH: usize = 140;
+
+/// The pending notifications of one contact, sent together
+pub struct Digest {
+    pub contact: Contact,
+    pub notifications: Vec<Notification>,
+}
+
+impl Digest {
+    pub fn subject(&self) -> String {
+        match self.notifications.as_slice() {
+            [n] => format!("New message from {}", n.sender_name),
+            notifications => format!("{} new messages", notifications.len()),
+        }
+    }
+
+    /// Lists the messages one per line, oldest first
+    pub fn body(&self) -> String {
+        let lines: Vec<String> = self
+            .notifications
+            .iter()
+            .map(|n| match &n.channel_name {
+                Some(channel) => format!("[{channel}] {}: {}", n.sender_name, n.preview),
+                None => format!("{}: {}", n.sender_name, n.preview),
+            })
+            .collect();
+        format!("Hi {},\n\n{}\n", self.contact.name, lines.join("\n"))
+    }
+}
+
+/// Tells the contacts about the messages they missed
+#[async_trait(?Send)]
+pub trait Notifier {
+    async fn notify(&self, digest: &Digest) -> Result<(), String>;
+}
+
+pub struct NotificationService<'a> {
+    repository: &'a mut dyn NotificationRepository,
+    contact_repository: &'a mut dyn ContactRepository,
+}
+
+impl<'a> NotificationService<'a> {
+    pub fn new(
+        repo: &'a mut dyn NotificationRepository,
+        contact_repository: &'a mut dyn ContactRepository,
+    ) -> Self {
+        NotificationService {
+            repository: repo,
+            contact_repository,
+        }
+    }
+
+    /// Queues a notification of the message for the channel members that are
+    /// offline, except its sender, the bots and the members whose channel
+    /// preferences leave the message out
+    pub async fn enqueue(
+        &mut self,
+        channel: &Channel,
+        message: &Message,
+        offline: &[IdType],
+    ) -> Result<Vec<Notification>, NotificationError> {
+        let preview = match preview(&message.content) {
+            Some(p) => p,
+            None => return Ok(vec![]),
+        };
+        let sender_name = match &message.bot_name {
+            Some(name) => name.clone(),
+            None => match self.contact_repository.get(&message.from).await {
+                Some(c) => c.name,
+                None => "Someone".to_string(),
+            },
+        };
+        let mut notifications = Vec::new();
+        for contact_id in channel.contact_ids.iter() {
+            if *contact_id == message.from || !offline.contains(contact_id) {
+                continue;
+            }
+            if !channel
+                .preferences_of(contact_id)
+                .notifies(message, message.created_at)
+            {
+                continue;
+            }
+            match self.contact_repository.get(contact_id).await {
+                Some(c) if c.kind == ContactKind::Person => {}
+                _ => continue,
+            }
+            // Private channels are stored with an empty name
+            let channel_name = channel.name.clone().filter(|n| !n.is_empty());
+            let notification = Notification::new(
+                contact_id,
+                &channel.id(),
+                channel_name,
+                &message.id(),
+                &sender_name,
+                &preview,
+            );
+            match self.repository.create(&notification).await {
+                Ok(n) => notifications.push(n),
+                Err(e) => {
+                    return Err(NotificationError {
+                        message: e.to_string(),
+                    })
+                }
+            }
+        }
+        Ok(notifications)
+    }
+
+    /// Sends one digest per contact whose oldest pending notification waited
+    /// for the batch window, and returns the number of digests sent. The
+    /// digests of contacts in their do not disturb period wait for its end.
+    pub async fn flush_due(
+        &mut self,
+        now: DateTime<Utc>,
+        notifier: &dyn Notifier,
+    ) -> Result<usize, NotificationError> {
+        let pending = match self.repository.find_pending().await {
+            Ok(n) => n,
+            Err(e) => {
+                return Err(NotificationError {
+                    message: e.to_string(),
+                })
+            }
+        };
+        // Pending notifications come oldest first, so each batch keeps that order
+        let mut batches: Vec<(IdType, Vec<Notification>)> = Vec::new();
+        for notification in pending {
+            match batches
+                .iter_mut()
+                .find(|(id, _)| *id == notification.contact_id)
+            {
+                Some((_, batch)) => batch.push(notification),
+                None => batches.push((notification.contact_id.clone(), vec![notification])),
+            }
+        }
+        let due_before = now - Duration::seconds(BATCH_WINDOW_SECONDS);
+        let mut sent = 0;
+        for (contact_id, notifications) in batches {
+            if notifications[0].created_at > due_before {
+                continue;
+            }
+            // A failed digest waits for its retry, with the messages since
+            if notifications
+                .iter()
+                .any(|n| n.next_attempt_at.is_some_and(|t| t > now))
+            {
+                continue;
+            }
+            let contact = match self.contact_repository.get(&contact_id).await {
+                Some(c) if c.do_not_disturb.as_ref().is_some_and(|d| d.is_active(now)) => continue,
+                Some(c) => c,
+                None => {
+                    let res = Err("Contact not found".to_string());
+                    self.record(notifications, res, true, now).await?;
+                    continue;
+                }
+            };
+            let digest = Digest {
+                contact,
+                notifications,
+            };
+            let res = notifier.notify(&digest).await;
+            if res.is_ok() {
+                sent += 1;
+            }
+            self.record(digest.notifications, res, false, now).await?;
+        }
+        Ok(sent)
+    }
+
+    /// Stores the outcome of an attempt, giving up after the last one or when
+    /// retrying cannot help
+    async fn record(
+        &mut self,
+        notifications: Vec<Notification>,
+        res: Result<(), String>,
+        give_up: bool,
+        now: DateTime<Utc>,
+    ) -> Result<(), NotificationError> {
+        for mut notification in notifications {
+            notification.attempts += 1;
+            notification.updated_at = Utc::now();
+            match &res {
+                Ok(_) => {
+                    notification.status = NotificationStatus::Sent;
+                    notification.last_error = None;
+                }
+                Err(e) => {
+                    notification.last_error = Some(e.clone());
+                    notification.next_attempt_at = Some(now + retry_delay(notification.attempts));
+                    if give_up || notification.attempts >= MAX_NOTIFY_ATTEMPTS {
+                        notification.status = NotificationStatus::Failed;
+                    }
+                }
+            }
+            if let Err(e) = self.repository.update(&notification).await {
+                return Err(NotificationError {
+                    message: e.to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The wait after the given number of failed attempts
+fn retry_delay(attempts: i32) -> Duration {
+    Duration::seconds(RETRY_DELAY_SECONDS << (attempts - 1).clamp(0, 16))
+}
+
+/// Describes the content in a line, or `None` for the content nobody is
+/// notified of
+fn preview(content: &MessageContent) -> Option<String> {
+    match content {
+        MessageContent::Text { text } | MessageContent::Markdown { text } => {
+            if text.chars().count() > PREVIEW_LENGTH {
+                let start: String = text.chars().take(PREVIEW_LENGTH).collect();
+                Some(format!("{start}…"))
+            } else {
+                Some(text.clone())
+            }
+        }
+        MessageContent::Attachment { name, .. } => Some(format!(
+            "Sent {}",
+            name.as_deref().unwrap_or("an attachment")
+        )),
+        MessageContent::System { .. } => None,
+    }
+}
+
+#[derive(Debug)]
+pub struct NotificationError {
+    pub message: String,
+}
+
+impl Display for NotificationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapters::{mock_contact_repo, mock_notification_repo, Repository};
+    use crate::models::{ChannelPreferences, ChannelType, DoNotDisturb, NotificationLevel};
+    use chrono::Timelike;
+    use std::cell::RefCell;
+
+    /// Answers with the given results in turn and keeps the digests
+    struct MockNotifier {
+        results: RefCell<Vec<Result<(), String>>>,
+        digests: RefCell<Vec<(String, usize)>>,
+    }
+
+    #[async_trait(?Send)]
+    impl Notifier for MockNotifier {
+        async fn notify(&self, digest: &Digest) -> Result<(), String> {
+            self.digests
+                .borrow_mut()
+                .push((digest.contact.name.clone(), digest.notifications.len()));
+            self.results.borrow_mut().remove(0)
+        }
+    }
+
+    async fn add_test_contacts(repo: &mut impl Repository<Contact>) -> Vec<Contact> {
+        let mut contacts = vec![];
+        for (name, email) in [
+            ("Jon Snow", "jon@winterfell.com"),
+            ("Sansa Stark", "sansa@winterfell.com"),
+            ("Arya Stark", "arya@winterfell.com"),
+        ] {
+            contacts.push(repo.create(&Contact::new(name, email)).await.unwrap());
+        }
+        contacts
+    }
+
+    #[actix_web::test]
+    async fn notifies_offline_members_once_per_burst() {
+        let mut repo = mock_notification_repo();
+        let mut contact_repo = mock_contact_repo();
+        let contacts = add_test_contacts(&mut contact_repo).await;
+        let ids: Vec<IdType> = contacts.iter().map(|c| c.id()).collect();
+        let channel = Channel::new("Winterfell", ChannelType::Group, &ids);
+        let mut service = NotificationService::new(&mut repo, &mut contact_repo);
+
+        // Sansa is online, Jon sends the messages
+        let offline = [ids[0].clone(), ids[2].clone()];
+        for text in ["Winter", "is", "coming"] {
+            let message = Message::new(
+                &channel.id(),
+                &ids[0],
+                &channel.id(),
+                &MessageContent::text(text),
+            );
+            let queued = service.enqueue(&channel, &message, &offline).await.unwrap();
+            assert_eq!(queued.len(), 1, "Only Arya is notified");
+        }
+
+        let notifier = MockNotifier {
+            results: RefCell::new(vec![Err("Connection refused".to_string()), Ok(())]),
+            digests: RefCell::new(vec![]),
+        };
+        let sent = service.flush_due(Utc::now(), &notifier).await.unwrap();
+        assert_eq!(sent, 0, "The burst waits for the batch window");
+        assert!(notifier.digests.borrow().is_empty());
+
+        let later = Utc::now() + Duration::seconds(BATCH_WINDOW_SECONDS);
+        assert_eq!(service.flush_due(later, &notifier).await.unwrap(), 0);
+        assert_eq!(
+            service.flush_due(later, &notifier).await.unwrap(),
+            0,
+            "The failed digest waits for its retry"
+        );
+        let retry = later + retry_delay(1);
+        assert_eq!(service.flush_due(retry, &notifier).await.unwrap(), 1);
+        assert_eq!(service.flush_due(retry, &notifier).await.unwrap(), 0);
+        assert_eq!(
+            *notifier.digests.borrow(),
+            vec![("Arya Stark".to_string(), 3), ("Arya Stark".to_string(), 3)],
+            "The failed digest is retried whole"
+        );
+    }
+
+    #[actix_web::test]
+    async fn follows_the_notification_preferences() {
+        let mut repo = mock_notification_repo();
+        let mut contact_repo = mock_contact_repo();
+        let contacts = add_test_contacts(&mut contact_repo).await;
+        let ids: Vec<IdType> = contacts.iter().map(|c| c.id()).collect();
+        let mut channel = Channel::new("Winterfell", ChannelType::Group, &ids);
+        // Sansa only hears of her mentions, Arya muted the channel for an hour
+        let mut sansa = ChannelPreferences::new(&ids[1]);
+        sansa.level = NotificationLevel::Mentions;
+        let mut arya = ChannelPreferences::new(&ids[2]);
+        arya.muted = true;
+        arya.muted_until = Some(Utc::now() + Duration::hours(1));
+        channel.preferences = vec![sansa, arya];
+        // Jon is not to be disturbed for the coming hour
+        let mut jon = contacts[0].clone();
+        let minute = Utc::now().hour() * 60 + Utc::now().minute();
+        jon.do_not_disturb = Some(DoNotDisturb {
+            start_minute: minute,
+            end_minute: (minute + 60) % (24 * 60),
+            utc_offset_minutes: 0,
+        });
+        contact_repo.update(&jon).await.unwrap();
+        let mut service = NotificationService::new(&mut repo, &mut contact_repo);
+
+        let mut message = Message::new(
+            &channel.id(),
+            &ids[0],
+            &channel.id(),
+            &MessageContent::text("The wolves are back"),
+        );
+        assert!(service
+            .enqueue(&channel, &message, &ids)
+            .await
+            .unwrap()
+            .is_empty());
+        message.mentions = vec![ids[1].clone()];
+        assert_eq!(
+            service
+                .enqueue(&channel, &message, &ids)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        message.from = ids[1].clone();
+        message.mentions = vec![];
+        let queued = service.enqueue(&channel, &message, &ids).await.unwrap();
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].contact_id, ids[0]);
+
+        let notifier = MockNotifier {
+            results: RefCell::new(vec![Ok(())]),
+            digests: RefCell::new(vec![]),
+        };
+        let later = Utc::now() + Duration::seconds(BATCH_WINDOW_SECONDS);
+        assert_eq!(service.flush_due(later, &notifier).await.unwrap(), 1);
+        assert_eq!(
+            *notifier.digests.borrow(),
+            vec![("Sansa Stark".to_string(), 1)],
+            "Jon's digest waits for the end of his do not disturb period"
+        );
+    }
+
+    #[actix_web::test]
+    async fn gives_up_on_digests_failing_every_attempt() {
+        let mut repo = mock_notification_repo();
+        let mut contact_repo = mock_contact_repo();
+        let contacts = add_test_contacts(&mut contact_repo).await;
+        let ids = [contacts[0].id(), contacts[2].id()];
+        let channel = Channel::new("", ChannelType::Private, &ids);
+        let mut service = NotificationService::new(&mut repo, &mut contact_repo);
+        let message = Message::new(
+            &channel.id(),
+            &ids[0],
+            &ids[1],
+            &MessageContent::text("Valar morghulis"),
+        );
+        let queued = service.enqueue(&channel, &message, &ids).await.unwrap();
+        assert_eq!(queued[0].channel_name, None);
+
+        let notifier = MockNotifier {
+            results: RefCell::new(vec![Err("Mailbox full".to_string()); 6]),
+            digests: RefCell::new(vec![]),
+        };
+        let mut now = Utc::now() + Duration::seconds(BATCH_WINDOW_SECONDS);
+        for attempts in 1..=MAX_NOTIFY_ATTEMPTS {
+            service.flush_due(now, &notifier).await.unwrap();
+            assert_eq!(notifier.digests.borrow().len(), attempts as usize);
+            now += retry_delay(attempts);
+        }
+        service.flush_due(now, &notifier).await.unwrap();
+        assert_eq!(
+            notifier.digests.borrow().len(),
+            MAX_NOTIFY_ATTEMPTS as usize
+        );
+        let notification = service.repository.get(&queued[0].id()).await.unwrap();
+        assert_eq!(notification.status, NotificationStatus::Failed);
+
+        let digest = Digest {
+            contact: contacts[2].clone(),
+            notifications: vec![notification],
+        };
+        assert!(digest.body().contains("\nJon Snow: Valar morghulis\n"));
+    }
+
+    #[test]
+    fn previews_the_start_of_long_messages() {
+        let text = "a".repeat(PREVIEW_LENGTH + 10);
+        let preview = preview(&MessageContent::text(&text)).unwrap();
+        assert_eq!(preview.chars().count(), PREVIEW_LENGTH + 1);
+        assert!(preview.ends_with('…'));
+    }
+}
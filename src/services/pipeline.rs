@@ -0,0 +1,199 @@
+use crate::adapters::IdType;
+use crate::models::MessageContent;
+use regex::Regex;
+use std::sync::{Arc, OnceLock, RwLock};
+use unicode_normalization::UnicodeNormalization;
+
+/// The maximum number of characters in the text of a message
+pub const MAX_MESSAGE_LENGTH: usize = 4000;
+
+/// A message on its way to the repository, as the stages see it
+pub struct Draft {
+    /// The channel of the message, `None` for the first direct message
+    pub channel_id: Option<IdType>,
+    pub from: IdType,
+    pub content: MessageContent,
+    /// The URLs found in the text
+    pub links: Vec<String>,
+    /// The patterns of the filter rules the text matched
+    pub flagged_by: Vec<String>,
+}
+
+impl Draft {
+    pub fn new(channel_id: Option<IdType>, from: &IdType, content: &MessageContent) -> Self {
+        Draft {
+            channel_id,
+            from: from.clone(),
+            content: content.clone(),
+            links: vec![],
+            flagged_by: vec![],
+        }
+    }
+
+    /// Returns the text written by the sender, if the content has any
+    pub fn body(&self) -> Option<&str> {
+        self.content.body()
+    }
+
+    pub fn set_body(&mut self, body: &str) {
+        self.content = self.content.with_body(body);
+    }
+}
+
+/// A step of the pipeline, which can transform, annotate or reject the draft
+pub trait MessageProcessor: Send + Sync {
+    /// Returns why the message is refused, to show to its sender
+    fn process(&self, draft: &mut Draft) -> Result<(), String>;
+}
+
+/// The stages every sent message goes through before it is stored, in order.
+///
+/// Clones share the stages, like the command registry, so the stages
+/// registered at startup apply to every transport.
+#[derive(Clone, Default)]
+pub struct Pipeline {
+    stages: Arc<RwLock<Vec<Arc<dyn MessageProcessor>>>>,
+}
+
+impl Pipeline {
+    /// Trims, normalizes, limits the length of and extracts the links of the text
+    pub fn standard() -> Self {
+        let pipeline = Pipeline::default();
+        pipeline.register(Trim);
+        pipeline.register(Normalize);
+        pipeline.register(MaxLength(MAX_MESSAGE_LENGTH));
+        pipeline.register(ExtractLinks);
+        pipeline
+    }
+
+    /// Adds the stage at the end of the pipeline
+    pub fn register(&self, stage: impl MessageProcessor + 'static) {
+        self.stages.write().unwrap().push(Arc::new(stage));
+    }
+
+    /// Runs the stages in order, stopping at the first that rejects the draft
+    pub fn run(&self, draft: &mut Draft) -> Result<(), String> {
+        let stages = self.stages.read().unwrap().clone();
+        for stage in stages.iter() {
+            stage.process(draft)?;
+        }
+        Ok(())
+    }
+}
+
+/// Strips the surrounding whitespace and refuses the texts left empty
+pub struct Trim;
+
+impl MessageProcessor for Trim {
+    fn process(&self, draft: &mut Draft) -> Result<(), String> {
+        let body = match draft.body() {
+            Some(b) => b.trim().to_string(),
+            None => return Ok(()),
+        };
+        if body.is_empty() {
+            return Err("Messages cannot be empty".to_string());
+        }
+        draft.set_body(&body);
+        Ok(())
+    }
+}
+
+/// Rewrites the text in the Unicode normalization form C, so equal texts
+/// compare, search and filter equal
+pub struct Normalize;
+
+impl MessageProcessor for Normalize {
+    fn process(&self, draft: &mut Draft) -> Result<(), String> {
+        if let Some(body) = draft.body() {
+            let body: String = body.nfc().collect();
+            draft.set_body(&body);
+        }
+        Ok(())
+    }
+}
+
+/// Refuses the texts longer than the given number of characters
+pub struct MaxLength(pub usize);
+
+impl MessageProcessor for MaxLength {
+    fn process(&self, draft: &mut Draft) -> Result<(), String> {
+        match draft.body() {
+            Some(b) if b.chars().count() > self.0 => Err(format!(
+                "Messages cannot be longer than {} characters",
+                self.0
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Collects the distinct `http` and `https` URLs of the text
+pub struct ExtractLinks;
+
+impl MessageProcessor for ExtractLinks {
+    fn process(&self, draft: &mut Draft) -> Result<(), String> {
+        static URL: OnceLock<Regex> = OnceLock::new();
+        let url = URL.get_or_init(|| Regex::new(r"(?i)\bhttps?://[^\s<>]+").unwrap());
+        let body = match draft.body() {
+            Some(b) => b.to_string(),
+            None => return Ok(()),
+        };
+        for m in url.find_iter(&body) {
+            // Punctuation closing the sentence is not part of the link
+            let link = m
+                .as_str()
+                .trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '\'', '"']);
+            if !draft.links.iter().any(|l| l == link) {
+                draft.links.push(link.to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Draft, MaxLength, MessageProcessor, Pipeline};
+    use crate::adapters::IdType;
+    use crate::models::MessageContent;
+
+    fn draft(text: &str) -> Draft {
+        let from = IdType::String("arya".to_string());
+        Draft::new(None, &from, &MessageContent::text(text))
+    }
+
+    struct Shout;
+
+    impl MessageProcessor for Shout {
+        fn process(&self, draft: &mut Draft) -> Result<(), String> {
+            let body = draft.body().unwrap_or_default().to_uppercase();
+            draft.set_body(&body);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn runs_the_stages_in_order() {
+        let pipeline = Pipeline::standard();
+        pipeline.register(Shout);
+
+        let mut d = draft("  Cafe\u{301} at https://braavos.example/house-of-black-and-white. ");
+        pipeline.run(&mut d).unwrap();
+        assert_eq!(
+            d.content,
+            MessageContent::text("CAFÉ AT HTTPS://BRAAVOS.EXAMPLE/HOUSE-OF-BLACK-AND-WHITE.")
+        );
+        assert_eq!(
+            d.links,
+            vec!["https://braavos.example/house-of-black-and-white".to_string()]
+        );
+        assert!(pipeline.run(&mut draft("   ")).is_err());
+    }
+
+    #[test]
+    fn limits_the_length() {
+        let stage = MaxLength(5);
+        assert!(stage.process(&mut draft("Needle")).is_err());
+        assert!(stage.process(&mut draft("Nymér")).is_ok());
+    }
+}
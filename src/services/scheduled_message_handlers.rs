@@ -0,0 +1,368 @@
+use crate::adapters::scheduled_message_repository::ScheduledMessageRepository;
+use crate::adapters::IdType;
+use crate::commands;
+use crate::models::{MessageContent, ScheduledMessage, ScheduledMessageStatus};
+use crate::services::MessageService;
+use chrono::{DateTime, Utc};
+use std::fmt::{Display, Formatter};
+
+pub struct ScheduledMessageService<'a> {
+    repository: &'a mut dyn ScheduledMessageRepository,
+}
+
+impl<'a> ScheduledMessageService<'a> {
+    pub fn new(repo: &'a mut dyn ScheduledMessageRepository) -> Self {
+        ScheduledMessageService { repository: repo }
+    }
+
+    pub async fn schedule_message(
+        &mut self,
+        cmd: &commands::ScheduleMessage,
+    ) -> Result<ScheduledMessage, ScheduledMessageError> {
+        validate_content(&cmd.message.content)?;
+        validate_deliver_at(&cmd.deliver_at)?;
+        let scheduled = ScheduledMessage::new(&cmd.message, cmd.deliver_at);
+        match self.repository.create(&scheduled).await {
+            Ok(m) => Ok(m),
+            Err(e) => Err(ScheduledMessageError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    /// Returns the messages the contact is still waiting to send, soonest first
+    pub async fn list_pending(
+        &self,
+        contact_id: &IdType,
+    ) -> Result<Vec<ScheduledMessage>, ScheduledMessageError> {
+        match self.repository.find_pending_by_sender(contact_id).await {
+            Ok(m) => Ok(m),
+            Err(e) => Err(ScheduledMessageError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    pub async fn edit_scheduled_message(
+        &mut self,
+        cmd: &commands::EditScheduledMessage,
+    ) -> Result<ScheduledMessage, ScheduledMessageError> {
+        let mut scheduled = self.get_pending(&cmd.id, &cmd.contact_id).await?;
+        if let Some(content) = &cmd.content {
+            validate_content(content)?;
+            scheduled.content = content.clone();
+        }
+        if let Some(deliver_at) = cmd.deliver_at {
+            validate_deliver_at(&deliver_at)?;
+            scheduled.deliver_at = deliver_at;
+        }
+        self.save_pending(&mut scheduled).await?;
+        Ok(scheduled)
+    }
+
+    pub async fn cancel_scheduled_message(
+        &mut self,
+        cmd: &commands::CancelScheduledMessage,
+    ) -> Result<ScheduledMessage, ScheduledMessageError> {
+        let mut scheduled = self.get_pending(&cmd.id, &cmd.contact_id).await?;
+        scheduled.status = ScheduledMessageStatus::Cancelled;
+        self.save_pending(&mut scheduled).await?;
+        Ok(scheduled)
+    }
+
+    /// Sends every pending message due by the given time and returns the
+    /// messages that were processed, with their final status. Each message is
+    /// claimed before it is sent, so the messages another run claimed or
+    /// their sender changed meanwhile are skipped.
+    pub async fn deliver_due(
+        &mut self,
+        now: DateTime<Utc>,
+        message_service: &mut MessageService<'_>,
+    ) -> Result<Vec<ScheduledMessage>, ScheduledMessageError> {
+        let due = match self.repository.find_due(now).await {
+            Ok(m) => m,
+            Err(e) => {
+                return Err(ScheduledMessageError {
+                    message: e.to_string(),
+                })
+            }
+        };
+        let mut processed = Vec::new();
+        for mut scheduled in due {
+            scheduled.status = ScheduledMessageStatus::Delivering;
+            if !self
+                .save(&mut scheduled, &ScheduledMessageStatus::Pending)
+                .await?
+            {
+                continue;
+            }
+            match message_service.send_message(&scheduled.to_command()).await {
+                Ok(_) => {
+                    scheduled.status = ScheduledMessageStatus::Delivered;
+                    scheduled.error = None;
+                }
+                Err(e) => {
+                    scheduled.status = ScheduledMessageStatus::Failed;
+                    scheduled.error = Some(e.to_string());
+                }
+            }
+            self.save(&mut scheduled, &ScheduledMessageStatus::Delivering)
+                .await?;
+            processed.push(scheduled);
+        }
+        Ok(processed)
+    }
+
+    async fn get_pending(
+        &self,
+        id: &IdType,
+        contact_id: &IdType,
+    ) -> Result<ScheduledMessage, ScheduledMessageError> {
+        match self.repository.get(id).await {
+            Some(m) if &m.from == contact_id => {
+                if m.status == ScheduledMessageStatus::Pending {
+                    Ok(m)
+                } else {
+                    Err(ScheduledMessageError {
+                        message: "Only pending messages can be changed".to_string(),
+                    })
+                }
+            }
+            _ => Err(ScheduledMessageError {
+                message: format!("Scheduled message with id {id} not found"),
+            }),
+        }
+    }
+
+    /// Saves the changes of a pending message, unless it stopped being
+    /// pending since it was read
+    async fn save_pending(
+        &mut self,
+        scheduled: &mut ScheduledMessage,
+    ) -> Result<(), ScheduledMessageError> {
+        if self
+            .save(scheduled, &ScheduledMessageStatus::Pending)
+            .await?
+        {
+            Ok(())
+        } else {
+            Err(ScheduledMessageError {
+                message: "Only pending messages can be changed".to_string(),
+            })
+        }
+    }
+
+    /// Saves the message if its stored status is still the given one, and
+    /// returns whether it was saved
+    async fn save(
+        &mut self,
+        scheduled: &mut ScheduledMessage,
+        status: &ScheduledMessageStatus,
+    ) -> Result<bool, ScheduledMessageError> {
+        scheduled.updated_at = Utc::now();
+        match self.repository.update_if_status(scheduled, status).await {
+            Ok(saved) => Ok(saved),
+            Err(e) => Err(ScheduledMessageError {
+                message: e.to_string(),
+            }),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ScheduledMessageError {
+    pub message: String,
+}
+
+impl Display for ScheduledMessageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+fn validate_content(content: &MessageContent) -> Result<(), ScheduledMessageError> {
+    if let MessageContent::System { .. } = content {
+        return Err(ScheduledMessageError {
+            message: "System messages cannot be scheduled".to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn validate_deliver_at(deliver_at: &DateTime<Utc>) -> Result<(), ScheduledMessageError> {
+    if *deliver_at <= Utc::now() {
+        return Err(ScheduledMessageError {
+            message: "Messages must be scheduled in the future".to_string(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapters::message_repository::MessageRepository;
+    use crate::adapters::{
+        mock_channel_repo, mock_contact_repo, mock_message_repo, mock_scheduled_message_repo,
+        Model, Repository,
+    };
+    use crate::models::Contact;
+    use chrono::Duration;
+
+    async fn add_test_contacts(repo: &mut impl Repository<Contact>) -> Vec<Contact> {
+        let tyrion = repo
+            .create(&Contact::new("Tyrion Lannister", "tyrion@casterlyrock.com"))
+            .await
+            .unwrap();
+        let varys = repo
+            .create(&Contact::new("Varys", "varys@kingslanding.com"))
+            .await
+            .unwrap();
+        vec![tyrion, varys]
+    }
+
+    fn schedule_cmd(contacts: &[Contact], deliver_at: DateTime<Utc>) -> commands::ScheduleMessage {
+        commands::ScheduleMessage {
+            message: commands::SendMessage {
+                channel_id: None,
+                from: contacts[0].id(),
+                to: contacts[1].id(),
+                content: MessageContent::text("I drink and I know things"),
+                client_message_id: None,
+            },
+            deliver_at,
+        }
+    }
+
+    #[actix_web::test]
+    async fn can_edit_and_cancel_pending_messages() {
+        let mut contact_repo = mock_contact_repo();
+        let contacts = add_test_contacts(&mut contact_repo).await;
+        let mut repo = mock_scheduled_message_repo();
+        let mut service = ScheduledMessageService::new(&mut repo);
+
+        let deliver_at = Utc::now() + Duration::hours(1);
+        let scheduled = service
+            .schedule_message(&schedule_cmd(&contacts, deliver_at))
+            .await
+            .unwrap();
+
+        let edit = commands::EditScheduledMessage {
+            id: scheduled.id(),
+            contact_id: contacts[0].id(),
+            content: Some(MessageContent::text("A Lannister always pays his debts")),
+            deliver_at: None,
+        };
+        let edited = service.edit_scheduled_message(&edit).await.unwrap();
+        assert_eq!(edited.content, edit.content.unwrap());
+
+        let cancel = commands::CancelScheduledMessage {
+            id: scheduled.id(),
+            contact_id: contacts[1].id(),
+        };
+        assert!(
+            service.cancel_scheduled_message(&cancel).await.is_err(),
+            "Only the sender can cancel"
+        );
+        let cancel = commands::CancelScheduledMessage {
+            id: scheduled.id(),
+            contact_id: contacts[0].id(),
+        };
+        service.cancel_scheduled_message(&cancel).await.unwrap();
+        let pending = service.list_pending(&contacts[0].id()).await.unwrap();
+        assert!(pending.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn cannot_schedule_in_the_past() {
+        let mut contact_repo = mock_contact_repo();
+        let contacts = add_test_contacts(&mut contact_repo).await;
+        let mut repo = mock_scheduled_message_repo();
+        let mut service = ScheduledMessageService::new(&mut repo);
+        let cmd = schedule_cmd(&contacts, Utc::now() - Duration::minutes(1));
+        assert!(service.schedule_message(&cmd).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn delivers_due_messages() {
+        let mut contact_repo = mock_contact_repo();
+        let mut channel_repo = mock_channel_repo();
+        let mut message_repo = mock_message_repo();
+        let contacts = add_test_contacts(&mut contact_repo).await;
+        let mut repo = mock_scheduled_message_repo();
+        let mut service = ScheduledMessageService::new(&mut repo);
+
+        let soon = service
+            .schedule_message(&schedule_cmd(&contacts, Utc::now() + Duration::minutes(5)))
+            .await
+            .unwrap();
+        let later = service
+            .schedule_message(&schedule_cmd(&contacts, Utc::now() + Duration::days(1)))
+            .await
+            .unwrap();
+
+        let mut message_service =
+            MessageService::new(&mut message_repo, &mut channel_repo, &mut contact_repo);
+        let delivered = service
+            .deliver_due(Utc::now() + Duration::minutes(10), &mut message_service)
+            .await
+            .unwrap();
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0].id(), soon.id());
+        assert_eq!(delivered[0].status, ScheduledMessageStatus::Delivered);
+
+        let pending = service.list_pending(&contacts[0].id()).await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id(), later.id());
+
+        let (_total, channels) = channel_repo.list(None, None).await.unwrap();
+        let messages = message_repo
+            .get_by_channel_id(&channels[0].id(), 100, 0)
+            .await
+            .unwrap();
+        assert!(messages
+            .iter()
+            .any(|m| m.content == MessageContent::text("I drink and I know things")));
+    }
+
+    #[actix_web::test]
+    async fn claimed_messages_are_not_delivered_again() {
+        let mut contact_repo = mock_contact_repo();
+        let mut channel_repo = mock_channel_repo();
+        let mut message_repo = mock_message_repo();
+        let contacts = add_test_contacts(&mut contact_repo).await;
+        let mut repo = mock_scheduled_message_repo();
+        let mut service = ScheduledMessageService::new(&mut repo);
+        let soon = service
+            .schedule_message(&schedule_cmd(&contacts, Utc::now() + Duration::minutes(5)))
+            .await
+            .unwrap();
+
+        let claimed = ScheduledMessage {
+            status: ScheduledMessageStatus::Delivering,
+            ..soon.clone()
+        };
+        let pending = ScheduledMessageStatus::Pending;
+        assert!(repo.update_if_status(&claimed, &pending).await.unwrap());
+        assert!(
+            !repo.update_if_status(&claimed, &pending).await.unwrap(),
+            "Claimed once"
+        );
+
+        let mut service = ScheduledMessageService::new(&mut repo);
+        let cancel = commands::CancelScheduledMessage {
+            id: soon.id(),
+            contact_id: contacts[0].id(),
+        };
+        assert!(service.cancel_scheduled_message(&cancel).await.is_err());
+        let mut message_service =
+            MessageService::new(&mut message_repo, &mut channel_repo, &mut contact_repo);
+        let delivered = service
+            .deliver_due(Utc::now() + Duration::minutes(10), &mut message_service)
+            .await
+            .unwrap();
+        assert!(delivered.is_empty());
+        let stored = repo.get(&soon.id()).await.unwrap();
+        assert_eq!(stored.status, ScheduledMessageStatus::Delivering);
+    }
+}
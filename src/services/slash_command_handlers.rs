@@ -0,0 +1,163 @@
+use crate::adapters::slash_command_repository::SlashCommandRepository;
+use crate::adapters::{IdType, Model};
+use crate::commands;
+use crate::models::SlashCommand;
+use crate::services::slash_commands::{parse_command, CommandRegistry};
+use std::fmt::{Display, Formatter};
+
+pub struct SlashCommandService<'a> {
+    repository: &'a mut dyn SlashCommandRepository,
+    commands: CommandRegistry,
+}
+
+impl<'a> SlashCommandService<'a> {
+    pub fn new(repo: &'a mut dyn SlashCommandRepository) -> Self {
+        SlashCommandService {
+            repository: repo,
+            commands: CommandRegistry::default(),
+        }
+    }
+
+    /// Keeps bots from registering the commands answered in-process
+    pub fn with_commands(mut self, commands: CommandRegistry) -> Self {
+        self.commands = commands;
+        self
+    }
+
+    /// Registers the command for the bot, answered through its callback URL
+    pub async fn register_command(
+        &mut self,
+        cmd: &commands::RegisterSlashCommand,
+    ) -> Result<SlashCommand, SlashCommandError> {
+        let name = match parse_command(&format!("/{}", cmd.name)) {
+            Some((name, args)) if args.is_empty() => name,
+            _ => {
+                return Err(SlashCommandError {
+                    message: "Command names are letters, digits, '-' and '_'".to_string(),
+                })
+            }
+        };
+        if !(cmd.callback_url.starts_with("https://") || cmd.callback_url.starts_with("http://")) {
+            return Err(SlashCommandError {
+                message: "Callback URLs must use http or https".to_string(),
+            });
+        }
+        if self.commands.contains(&name) || self.repository.find_by_name(&name).await.is_some() {
+            return Err(SlashCommandError {
+                message: format!("Command /{name} is already taken"),
+            });
+        }
+        let command = SlashCommand::new(
+            &name,
+            &cmd.bot_id,
+            &cmd.callback_url,
+            cmd.description.clone(),
+        );
+        match self.repository.create(&command).await {
+            Ok(c) => Ok(c),
+            Err(e) => Err(SlashCommandError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    pub async fn list_commands(
+        &self,
+        bot_id: &IdType,
+    ) -> Result<Vec<SlashCommand>, SlashCommandError> {
+        match self.repository.find_by_bot_id(bot_id).await {
+            Ok(c) => Ok(c),
+            Err(e) => Err(SlashCommandError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    /// Unregisters the command, which only the bot owning it can do
+    pub async fn delete_command(
+        &mut self,
+        bot_id: &IdType,
+        name: &str,
+    ) -> Result<(), SlashCommandError> {
+        let command = match self.repository.find_by_name(name).await {
+            Some(c) if &c.bot_id == bot_id => c,
+            _ => {
+                return Err(SlashCommandError {
+                    message: format!("Command /{name} not found"),
+                })
+            }
+        };
+        match self.repository.delete(&command.id()).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(SlashCommandError {
+                message: e.to_string(),
+            }),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SlashCommandError {
+    pub message: String,
+}
+
+impl Display for SlashCommandError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapters::mock_slash_command_repo;
+    use crate::events::SlashCommandInvoked;
+    use crate::models::MessageContent;
+    use crate::services::slash_commands::SlashCommandHandler;
+    use mongodb::bson::oid::ObjectId;
+
+    struct Silent;
+
+    #[async_trait::async_trait]
+    impl SlashCommandHandler for Silent {
+        async fn handle(
+            &self,
+            _invocation: &SlashCommandInvoked,
+        ) -> Result<Option<MessageContent>, String> {
+            Ok(None)
+        }
+    }
+
+    fn register_cmd(bot_id: &IdType, name: &str) -> commands::RegisterSlashCommand {
+        commands::RegisterSlashCommand {
+            bot_id: bot_id.clone(),
+            name: name.to_string(),
+            callback_url: "https://ravens.winterfell.com/commands".to_string(),
+            description: None,
+        }
+    }
+
+    #[actix_web::test]
+    async fn bots_own_the_commands_they_register() {
+        let mut repo = mock_slash_command_repo();
+        let registry = CommandRegistry::default();
+        registry.register("shrug", Silent);
+        let mut service = SlashCommandService::new(&mut repo).with_commands(registry);
+        let raven = IdType::ObjectId(ObjectId::new());
+        let crow = IdType::ObjectId(ObjectId::new());
+
+        let command = service
+            .register_command(&register_cmd(&raven, "Weather"))
+            .await
+            .unwrap();
+        assert_eq!(command.name, "weather");
+        for name in ["weather", "shrug", "two words", ""] {
+            let res = service.register_command(&register_cmd(&crow, name)).await;
+            assert!(res.is_err(), "/{name} cannot be registered");
+        }
+
+        assert!(service.delete_command(&crow, "weather").await.is_err());
+        service.delete_command(&raven, "weather").await.unwrap();
+        assert!(service.list_commands(&raven).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,78 @@
+use crate::events::SlashCommandInvoked;
+use crate::models::MessageContent;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// Splits a message starting with `/name` into the lowercased command name
+/// and the trimmed rest of the text.
+///
+/// Names are ASCII letters, digits, `-` and `_`, so paths like `/usr/bin`
+/// and a lone `/` stay plain messages.
+pub fn parse_command(text: &str) -> Option<(String, String)> {
+    let rest = text.strip_prefix('/')?;
+    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    let name = &rest[..end];
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return None;
+    }
+    Some((name.to_ascii_lowercase(), rest[end..].trim().to_string()))
+}
+
+/// Answers a slash command inside the server
+#[async_trait]
+pub trait SlashCommandHandler: Send + Sync {
+    /// Returns the reply posted into the channel, or `None` to stay silent
+    async fn handle(
+        &self,
+        invocation: &SlashCommandInvoked,
+    ) -> Result<Option<MessageContent>, String>;
+}
+
+/// The in-process slash command handlers, keyed by command name.
+///
+/// Clones share the handlers, like the event bus. Commands without a handler
+/// here are left to the bots registered for them over HTTP.
+#[derive(Clone, Default)]
+pub struct CommandRegistry {
+    handlers: Arc<RwLock<HashMap<String, Arc<dyn SlashCommandHandler>>>>,
+}
+
+impl CommandRegistry {
+    pub fn register(&self, name: &str, handler: impl SlashCommandHandler + 'static) {
+        let mut handlers = self.handlers.write().unwrap();
+        handlers.insert(name.to_ascii_lowercase(), Arc::new(handler));
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn SlashCommandHandler>> {
+        self.handlers.read().unwrap().get(name).cloned()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.handlers.read().unwrap().contains_key(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_command;
+
+    #[test]
+    fn parses_the_command_name_and_arguments() {
+        assert_eq!(
+            parse_command("/Roll  2d6 "),
+            Some(("roll".to_string(), "2d6".to_string()))
+        );
+        assert_eq!(
+            parse_command("/weather"),
+            Some(("weather".to_string(), String::new()))
+        );
+        assert_eq!(parse_command("/"), None);
+        assert_eq!(parse_command("/usr/bin is a path"), None);
+        assert_eq!(parse_command("not a /command"), None);
+    }
+}
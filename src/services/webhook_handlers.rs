@@ -0,0 +1,489 @@
+use crate::adapters::contact_repository::ContactRepository;
+use crate::adapters::webhook_repository::{WebhookDeliveryRepository, WebhookRepository};
+use crate::adapters::{IdType, Model};
+use crate::commands;
+use crate::link_previews::check_public_uri;
+use crate::models::{Webhook, WebhookDelivery, WebhookDeliveryStatus, WebhookEventType};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt::{Display, Formatter};
+
+/// Deliveries still failing after this many attempts are given up
+pub const MAX_DELIVERY_ATTEMPTS: i32 = 8;
+
+/// The wait before the first retry, doubled on every further attempt
+const RETRY_DELAY_SECONDS: i64 = 30;
+
+/// A signed request to a webhook endpoint
+pub struct WebhookRequest<'a> {
+    pub url: &'a str,
+    pub delivery_id: IdType,
+    pub event: &'a WebhookEventType,
+    /// The hex encoded HMAC-SHA256 of the body, keyed by the webhook secret
+    pub signature: String,
+    pub body: &'a str,
+}
+
+/// Sends the webhook requests over the wire
+#[async_trait(?Send)]
+pub trait WebhookSender {
+    /// Succeeds when the endpoint answered with a success status
+    async fn send(&self, request: &WebhookRequest<'_>) -> Result<(), String>;
+}
+
+pub struct WebhookService<'a> {
+    repository: &'a mut dyn WebhookRepository,
+    delivery_repository: &'a mut dyn WebhookDeliveryRepository,
+    contact_repository: &'a mut dyn ContactRepository,
+}
+
+impl<'a> WebhookService<'a> {
+    pub fn new(
+        repo: &'a mut dyn WebhookRepository,
+        delivery_repository: &'a mut dyn WebhookDeliveryRepository,
+        contact_repository: &'a mut dyn ContactRepository,
+    ) -> Self {
+        WebhookService {
+            repository: repo,
+            delivery_repository,
+            contact_repository,
+        }
+    }
+
+    /// Registers the endpoint, which must be reachable on the internet, for a
+    /// moderator
+    pub async fn register_webhook(
+        &mut self,
+        cmd: &commands::RegisterWebhook,
+    ) -> Result<Webhook, WebhookError> {
+        self.check_moderator(&cmd.registered_by).await?;
+        let uri = match cmd.url.parse() {
+            Ok(u) => u,
+            Err(_) => {
+                return Err(WebhookError {
+                    message: format!("Invalid URL {}", cmd.url),
+                })
+            }
+        };
+        if let Err(e) = check_public_uri(&uri) {
+            return Err(WebhookError { message: e });
+        }
+        let webhook = Webhook::new(&cmd.url, cmd.channel_id.clone(), &cmd.events);
+        match self.repository.create(&webhook).await {
+            Ok(w) => Ok(w),
+            Err(e) => Err(WebhookError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    pub async fn list_webhooks(
+        &self,
+        moderator_id: &IdType,
+        skip: Option<u64>,
+        limit: Option<i32>,
+    ) -> Result<(i32, Vec<Webhook>), WebhookError> {
+        self.check_moderator(moderator_id).await?;
+        match self.repository.list(skip, limit).await {
+            Ok(w) => Ok(w),
+            Err(e) => Err(WebhookError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    pub async fn delete_webhook(
+        &mut self,
+        moderator_id: &IdType,
+        id: &IdType,
+    ) -> Result<(), WebhookError> {
+        self.check_moderator(moderator_id).await?;
+        self.get_webhook(id).await?;
+        match self.repository.delete(id).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(WebhookError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    /// Returns the deliveries of the webhook, newest first
+    pub async fn get_deliveries(
+        &self,
+        moderator_id: &IdType,
+        webhook_id: &IdType,
+        limit: i64,
+        offset: u64,
+    ) -> Result<Vec<WebhookDelivery>, WebhookError> {
+        self.check_moderator(moderator_id).await?;
+        self.get_webhook(webhook_id).await?;
+        match self
+            .delivery_repository
+            .find_by_webhook_id(webhook_id, limit, offset)
+            .await
+        {
+            Ok(d) => Ok(d),
+            Err(e) => Err(WebhookError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    /// Queues a delivery of the event for every webhook listening to it
+    pub async fn enqueue(
+        &mut self,
+        event: &WebhookEventType,
+        channel_id: &IdType,
+        data: serde_json::Value,
+    ) -> Result<Vec<WebhookDelivery>, WebhookError> {
+        let webhooks = match self.repository.find_by_channel_id(channel_id).await {
+            Ok(w) => w,
+            Err(e) => {
+                return Err(WebhookError {
+                    message: e.to_string(),
+                })
+            }
+        };
+        let payload = serde_json::json!({
+            "event": event,
+            "created_at": Utc::now(),
+            "data": data,
+        })
+        .to_string();
+        let mut deliveries = Vec::new();
+        for webhook in webhooks.iter().filter(|w| w.accepts(event, channel_id)) {
+            let delivery = WebhookDelivery::new(webhook, event, &payload);
+            match self.delivery_repository.create(&delivery).await {
+                Ok(d) => deliveries.push(d),
+                Err(e) => {
+                    return Err(WebhookError {
+                        message: e.to_string(),
+                    })
+                }
+            }
+        }
+        Ok(deliveries)
+    }
+
+    /// Attempts every delivery due by the given time and returns them with
+    /// the outcome recorded. Failed attempts are retried with exponential
+    /// backoff until [`MAX_DELIVERY_ATTEMPTS`].
+    pub async fn deliver_due(
+        &mut self,
+        now: DateTime<Utc>,
+        sender: &dyn WebhookSender,
+    ) -> Result<Vec<WebhookDelivery>, WebhookError> {
+        let due = match self.delivery_repository.find_due(now).await {
+            Ok(d) => d,
+            Err(e) => {
+                return Err(WebhookError {
+                    message: e.to_string(),
+                })
+            }
+        };
+        let mut processed = Vec::new();
+        for mut delivery in due {
+            let result = match self.repository.get(&delivery.webhook_id).await {
+                Some(webhook) => {
+                    let request = WebhookRequest {
+                        url: &webhook.url,
+                        delivery_id: delivery.id(),
+                        event: &delivery.event,
+                        signature: sign(&webhook.secret, &delivery.payload),
+                        body: &delivery.payload,
+                    };
+                    sender.send(&request).await
+                }
+                None => Err("Webhook was deleted".to_string()),
+            };
+            delivery.attempts += 1;
+            match result {
+                Ok(_) => {
+                    delivery.status = WebhookDeliveryStatus::Delivered;
+                    delivery.last_error = None;
+                }
+                Err(e) => {
+                    if delivery.attempts >= MAX_DELIVERY_ATTEMPTS {
+                        delivery.status = WebhookDeliveryStatus::Failed;
+                    }
+                    delivery.next_attempt_at = now + retry_delay(delivery.attempts);
+                    delivery.last_error = Some(e);
+                }
+            }
+            self.save(&mut delivery).await?;
+            processed.push(delivery);
+        }
+        Ok(processed)
+    }
+
+    async fn check_moderator(&self, id: &IdType) -> Result<(), WebhookError> {
+        match self.contact_repository.get(id).await {
+            Some(c) if c.moderator => Ok(()),
+            _ => Err(WebhookError {
+                message: "Only moderators can manage webhooks".to_string(),
+            }),
+        }
+    }
+
+    async fn get_webhook(&self, id: &IdType) -> Result<Webhook, WebhookError> {
+        match self.repository.get(id).await {
+            None => Err(WebhookError {
+                message: format!("Webhook with id {id} not found"),
+            }),
+            Some(w) => Ok(w),
+        }
+    }
+
+    async fn save(&mut self, delivery: &mut WebhookDelivery) -> Result<(), WebhookError> {
+        delivery.updated_at = Utc::now();
+        match self.delivery_repository.update(delivery).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(WebhookError {
+                message: e.to_string(),
+            }),
+        }
+    }
+}
+
+/// Returns the hex encoded HMAC-SHA256 of the body, keyed by the secret
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(body.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// The wait after the given number of failed attempts
+fn retry_delay(attempts: i32) -> Duration {
+    Duration::seconds(RETRY_DELAY_SECONDS << (attempts - 1).clamp(0, 16))
+}
+
+#[derive(Debug)]
+pub struct WebhookError {
+    pub message: String,
+}
+
+impl Display for WebhookError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapters::{
+        mock_contact_repo, mock_webhook_delivery_repo, mock_webhook_repo, Repository,
+    };
+    use crate::models::Contact;
+    use mongodb::bson::oid::ObjectId;
+    use std::cell::RefCell;
+
+    /// Answers with the given results in turn and keeps the requests
+    struct MockSender {
+        results: RefCell<Vec<Result<(), String>>>,
+        signatures: RefCell<Vec<String>>,
+    }
+
+    #[async_trait(?Send)]
+    impl WebhookSender for MockSender {
+        async fn send(&self, request: &WebhookRequest<'_>) -> Result<(), String> {
+            self.signatures.borrow_mut().push(request.signature.clone());
+            self.results.borrow_mut().remove(0)
+        }
+    }
+
+    async fn create_maester(contact_repo: &mut dyn ContactRepository) -> IdType {
+        let mut maester = Contact::new("Maester Luwin", "luwin@winterfell.com");
+        maester.moderator = true;
+        contact_repo.create(&maester).await.unwrap().id()
+    }
+
+    fn register_cmd(maester_id: &IdType, channel_id: Option<IdType>) -> commands::RegisterWebhook {
+        commands::RegisterWebhook {
+            url: "https://ravens.winterfell.com/hooks".to_string(),
+            registered_by: maester_id.clone(),
+            channel_id,
+            events: vec![],
+        }
+    }
+
+    #[test]
+    fn signs_with_hmac_sha256() {
+        assert_eq!(
+            sign("key", "The quick brown fox jumps over the lazy dog"),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[actix_web::test]
+    async fn only_moderators_register_public_endpoints() {
+        let mut repo = mock_webhook_repo();
+        let mut delivery_repo = mock_webhook_delivery_repo();
+        let mut contact_repo = mock_contact_repo();
+        let maester_id = create_maester(&mut contact_repo).await;
+        let hodor_id = contact_repo
+            .create(&Contact::new("Hodor", "hodor@winterfell.com"))
+            .await
+            .unwrap()
+            .id();
+        let mut service = WebhookService::new(&mut repo, &mut delivery_repo, &mut contact_repo);
+
+        let res = service
+            .register_webhook(&register_cmd(&hodor_id, None))
+            .await;
+        assert!(res.is_err());
+        assert!(service.list_webhooks(&hodor_id, None, None).await.is_err());
+
+        for url in [
+            "http://127.0.0.1:27017/",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/hooks",
+            "ftp://ravens.winterfell.com/hooks",
+        ] {
+            let mut cmd = register_cmd(&maester_id, None);
+            cmd.url = url.to_string();
+            assert!(service.register_webhook(&cmd).await.is_err(), "{url}");
+        }
+
+        service
+            .register_webhook(&register_cmd(&maester_id, None))
+            .await
+            .unwrap();
+        let (total, _) = service
+            .list_webhooks(&maester_id, None, None)
+            .await
+            .unwrap();
+        assert_eq!(total, 1);
+    }
+
+    #[actix_web::test]
+    async fn enqueues_events_for_the_matching_webhooks() {
+        let mut repo = mock_webhook_repo();
+        let mut delivery_repo = mock_webhook_delivery_repo();
+        let mut contact_repo = mock_contact_repo();
+        let maester_id = create_maester(&mut contact_repo).await;
+        let mut service = WebhookService::new(&mut repo, &mut delivery_repo, &mut contact_repo);
+        let channel_id = IdType::ObjectId(ObjectId::new());
+        let other_id = IdType::ObjectId(ObjectId::new());
+
+        let global = service
+            .register_webhook(&register_cmd(&maester_id, None))
+            .await
+            .unwrap();
+        service
+            .register_webhook(&register_cmd(&maester_id, Some(other_id)))
+            .await
+            .unwrap();
+        let mut cmd = register_cmd(&maester_id, Some(channel_id.clone()));
+        cmd.events = vec![WebhookEventType::ChannelCreated];
+        service.register_webhook(&cmd).await.unwrap();
+
+        let deliveries = service
+            .enqueue(
+                &WebhookEventType::MessageSent,
+                &channel_id,
+                serde_json::json!({}),
+            )
+            .await
+            .unwrap();
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].webhook_id, global.id());
+    }
+
+    #[actix_web::test]
+    async fn retries_failed_deliveries_with_backoff() {
+        let mut repo = mock_webhook_repo();
+        let mut delivery_repo = mock_webhook_delivery_repo();
+        let mut contact_repo = mock_contact_repo();
+        let maester_id = create_maester(&mut contact_repo).await;
+        let mut service = WebhookService::new(&mut repo, &mut delivery_repo, &mut contact_repo);
+        let channel_id = IdType::ObjectId(ObjectId::new());
+        let webhook = service
+            .register_webhook(&register_cmd(&maester_id, None))
+            .await
+            .unwrap();
+        service
+            .enqueue(
+                &WebhookEventType::MessageSent,
+                &channel_id,
+                serde_json::json!({ "text": "Winter is coming" }),
+            )
+            .await
+            .unwrap();
+
+        let sender = MockSender {
+            results: RefCell::new(vec![Err("HTTP 503".to_string()), Ok(())]),
+            signatures: RefCell::new(vec![]),
+        };
+        let now = Utc::now();
+        let failed = service.deliver_due(now, &sender).await.unwrap();
+        assert_eq!(failed[0].status, WebhookDeliveryStatus::Pending);
+        assert_eq!(failed[0].last_error, Some("HTTP 503".to_string()));
+        assert_eq!(failed[0].next_attempt_at, now + Duration::seconds(30));
+
+        // Not due before the backoff has passed
+        let processed = service
+            .deliver_due(now + Duration::seconds(10), &sender)
+            .await
+            .unwrap();
+        assert!(processed.is_empty());
+
+        let delivered = service
+            .deliver_due(now + Duration::seconds(30), &sender)
+            .await
+            .unwrap();
+        assert_eq!(delivered[0].status, WebhookDeliveryStatus::Delivered);
+        assert_eq!(delivered[0].attempts, 2);
+        assert_eq!(
+            sender.signatures.borrow()[1],
+            sign(&webhook.secret, &delivered[0].payload)
+        );
+
+        let log = service
+            .get_deliveries(&maester_id, &webhook.id(), 10, 0)
+            .await
+            .unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].status, WebhookDeliveryStatus::Delivered);
+    }
+
+    #[actix_web::test]
+    async fn gives_up_after_the_last_attempt() {
+        let mut repo = mock_webhook_repo();
+        let mut delivery_repo = mock_webhook_delivery_repo();
+        let mut contact_repo = mock_contact_repo();
+        let maester_id = create_maester(&mut contact_repo).await;
+        let mut service = WebhookService::new(&mut repo, &mut delivery_repo, &mut contact_repo);
+        let channel_id = IdType::ObjectId(ObjectId::new());
+        service
+            .register_webhook(&register_cmd(&maester_id, None))
+            .await
+            .unwrap();
+        service
+            .enqueue(
+                &WebhookEventType::ChannelUpdated,
+                &channel_id,
+                serde_json::json!({}),
+            )
+            .await
+            .unwrap();
+
+        let sender = MockSender {
+            results: RefCell::new(vec![
+                Err("Connection refused".to_string());
+                MAX_DELIVERY_ATTEMPTS as usize
+            ]),
+            signatures: RefCell::new(vec![]),
+        };
+        let mut now = Utc::now();
+        let mut last = vec![];
+        for _ in 0..MAX_DELIVERY_ATTEMPTS {
+            last = service.deliver_due(now, &sender).await.unwrap();
+            now = last[0].next_attempt_at;
+        }
+        assert_eq!(last[0].status, WebhookDeliveryStatus::Failed);
+        assert_eq!(last[0].attempts, MAX_DELIVERY_ATTEMPTS);
+    }
+}
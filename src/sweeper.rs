@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use actix::{Actor, ActorFutureExt, AsyncContext, Context, ContextFutureSpawner, WrapFuture};
+use chrono::Utc;
+
+use crate::adapters::mongo::repository::MongoRepository;
+use crate::models::{Channel, Contact, Message};
+use crate::services::MessageService;
+
+/// How often the expired messages are purged
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Periodically deletes the messages whose lifetime has passed.
+///
+/// Backends with native expiry, like the Mongo TTL index, delete them on their
+/// own; the sweeper covers the others and the delay of the native expiry.
+pub struct MessageSweeper {
+    db: mongodb::Database,
+    sweeping: bool,
+}
+
+impl MessageSweeper {
+    pub fn new(db: mongodb::Database) -> Self {
+        MessageSweeper {
+            db,
+            sweeping: false,
+        }
+    }
+
+    fn sweep(&mut self, ctx: &mut Context<Self>) {
+        if self.sweeping {
+            return;
+        }
+        self.sweeping = true;
+        purge_expired(self.db.clone())
+            .into_actor(self)
+            .map(|_, act, _| act.sweeping = false)
+            .spawn(ctx);
+    }
+}
+
+impl Actor for MessageSweeper {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(SWEEP_INTERVAL, |act, ctx| act.sweep(ctx));
+    }
+}
+
+async fn purge_expired(db: mongodb::Database) {
+    let mut message_repo: MongoRepository<Message> = MongoRepository::new(&db, "messages");
+    let mut channel_repo: MongoRepository<Channel> = MongoRepository::new(&db, "channels");
+    let mut contact_repo: MongoRepository<Contact> = MongoRepository::new(&db, "contacts");
+    let mut service = MessageService::new(&mut message_repo, &mut channel_repo, &mut contact_repo);
+    if let Err(e) = service.purge_expired(Utc::now()).await {
+        eprintln!("Failed to purge expired messages: {e}");
+    }
+}
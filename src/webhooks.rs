@@ -0,0 +1,169 @@
+use std::time::Duration;
+
+use actix::{
+    Actor, ActorFutureExt, AsyncContext, Context, ContextFutureSpawner, Handler, WrapFuture,
+};
+use async_trait::async_trait;
+use chrono::Utc;
+use serde_json::json;
+
+use crate::adapters::mongo::repository::MongoRepository;
+use crate::adapters::{IdType, Model};
+use crate::events::{ChannelCreated, ChannelUpdated, MessageSent};
+use crate::link_previews::{check_public_uri, guarded_client};
+use crate::models::{Contact, Webhook, WebhookDelivery, WebhookEventType};
+use crate::services::{WebhookRequest, WebhookSender, WebhookService};
+
+/// How often the deliveries waiting for a retry are checked
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long an endpoint has to answer
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Queues the message and channel events for the registered webhooks and
+/// sends the deliveries once they are due.
+///
+/// The deliveries live in the database, so the retries pending when the
+/// server stops are sent after a restart.
+pub struct WebhookDispatcher {
+    db: mongodb::Database,
+    delivering: bool,
+}
+
+impl WebhookDispatcher {
+    pub fn new(db: mongodb::Database) -> Self {
+        WebhookDispatcher {
+            db,
+            delivering: false,
+        }
+    }
+
+    fn enqueue(
+        &mut self,
+        ctx: &mut Context<Self>,
+        event: WebhookEventType,
+        channel_id: IdType,
+        data: serde_json::Value,
+    ) {
+        enqueue(self.db.clone(), event, channel_id, data)
+            .into_actor(self)
+            .map(|_, act, ctx| act.deliver(ctx))
+            .spawn(ctx);
+    }
+
+    fn deliver(&mut self, ctx: &mut Context<Self>) {
+        // Skip the tick while the previous run is still sending
+        if self.delivering {
+            return;
+        }
+        self.delivering = true;
+        deliver_due(self.db.clone())
+            .into_actor(self)
+            .map(|_, act, _| act.delivering = false)
+            .spawn(ctx);
+    }
+}
+
+impl Actor for WebhookDispatcher {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.deliver(ctx);
+        ctx.run_interval(POLL_INTERVAL, |act, ctx| act.deliver(ctx));
+    }
+}
+
+impl Handler<MessageSent> for WebhookDispatcher {
+    type Result = ();
+
+    fn handle(&mut self, msg: MessageSent, ctx: &mut Self::Context) -> Self::Result {
+        let channel_id = msg.channel.id();
+        let data = json!({ "channel": msg.channel.without_preferences(), "message": msg.message });
+        self.enqueue(ctx, WebhookEventType::MessageSent, channel_id, data);
+    }
+}
+
+impl Handler<ChannelCreated> for WebhookDispatcher {
+    type Result = ();
+
+    fn handle(&mut self, msg: ChannelCreated, ctx: &mut Self::Context) -> Self::Result {
+        let channel_id = msg.channel.id();
+        let data = json!({ "channel": msg.channel.without_preferences() });
+        self.enqueue(ctx, WebhookEventType::ChannelCreated, channel_id, data);
+    }
+}
+
+impl Handler<ChannelUpdated> for WebhookDispatcher {
+    type Result = ();
+
+    fn handle(&mut self, msg: ChannelUpdated, ctx: &mut Self::Context) -> Self::Result {
+        let channel_id = msg.channel.id();
+        let data = json!({ "channel": msg.channel.without_preferences() });
+        self.enqueue(ctx, WebhookEventType::ChannelUpdated, channel_id, data);
+    }
+}
+
+/// Posts the deliveries with the signature and event in the headers, refusing
+/// the endpoints on private networks
+struct HttpSender {
+    client: awc::Client,
+}
+
+#[async_trait(?Send)]
+impl WebhookSender for HttpSender {
+    async fn send(&self, request: &WebhookRequest<'_>) -> Result<(), String> {
+        let uri = request
+            .url
+            .parse()
+            .map_err(|_| format!("Invalid URL {}", request.url))?;
+        check_public_uri(&uri)?;
+        let event = serde_json::to_value(request.event).unwrap_or_default();
+        let res = self
+            .client
+            .post(uri)
+            .insert_header(("Content-Type", "application/json"))
+            .insert_header(("X-Webhook-Event", event.as_str().unwrap_or_default()))
+            .insert_header(("X-Webhook-Delivery", request.delivery_id.to_string()))
+            .insert_header((
+                "X-Webhook-Signature",
+                format!("sha256={}", request.signature),
+            ))
+            .send_body(request.body.to_string())
+            .await;
+        match res {
+            Ok(r) if r.status().is_success() => Ok(()),
+            Ok(r) => Err(format!("HTTP {}", r.status())),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+async fn enqueue(
+    db: mongodb::Database,
+    event: WebhookEventType,
+    channel_id: IdType,
+    data: serde_json::Value,
+) {
+    let mut repo: MongoRepository<Webhook> = MongoRepository::new(&db, "webhooks");
+    let mut delivery_repo: MongoRepository<WebhookDelivery> =
+        MongoRepository::new(&db, "webhook_deliveries");
+    let mut contact_repo: MongoRepository<Contact> = MongoRepository::new(&db, "contacts");
+    let mut service = WebhookService::new(&mut repo, &mut delivery_repo, &mut contact_repo);
+    if let Err(e) = service.enqueue(&event, &channel_id, data).await {
+        eprintln!("Failed to queue webhook deliveries: {e}");
+    }
+}
+
+async fn deliver_due(db: mongodb::Database) {
+    let mut repo: MongoRepository<Webhook> = MongoRepository::new(&db, "webhooks");
+    let mut delivery_repo: MongoRepository<WebhookDelivery> =
+        MongoRepository::new(&db, "webhook_deliveries");
+    let mut contact_repo: MongoRepository<Contact> = MongoRepository::new(&db, "contacts");
+    let mut service = WebhookService::new(&mut repo, &mut delivery_repo, &mut contact_repo);
+    let sender = HttpSender {
+        client: guarded_client(REQUEST_TIMEOUT, false),
+    };
+    if let Err(e) = service.deliver_due(Utc::now(), &sender).await {
+        eprintln!("Failed to send webhook deliveries: {e}");
+    }
+}
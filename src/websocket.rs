@@ -1,12 +1,442 @@
-use actix::{Actor, StreamHandler};
+use std::collections::HashMap;
+
+use actix::{
+    Actor, ActorContext, ActorFutureExt, Addr, AsyncContext, Context, ContextFutureSpawner,
+    Handler, Recipient, StreamHandler, WrapFuture,
+};
 
 use actix_web::{web, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
+use serde::{Deserialize, Serialize};
+
+use crate::adapters::mongo::repository::MongoRepository;
+use crate::adapters::{IdType, Model};
+use crate::api::{parse_id, Repositories};
+use crate::commands::SendMessage;
+use crate::events::{EventBus, MessageSent, MessageUpdated};
+use crate::models::{Contact, Message, MessageContent};
+use crate::rate_limit::{bot_key, ip_key, retry_after_seconds, RateLimiter, RouteClass};
+use crate::services::{CommandRegistry, ContactService, MessageError, Pipeline, MAX_SYNC_BATCH};
+use crate::AppState;
+
+/// Events pushed from the server to connected clients
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerEvent {
+    Message {
+        message: Box<Message>,
+    },
+    /// A message the client received changed, like when its link previews
+    /// were attached
+    MessageUpdated {
+        message: Box<Message>,
+    },
+    /// Confirms that a message sent by the client is stored
+    Ack {
+        client_message_id: Option<String>,
+        message_id: IdType,
+    },
+    Error {
+        client_message_id: Option<String>,
+        message: String,
+    },
+    /// The messages requested by a sync, in sequence order
+    History {
+        channel_id: IdType,
+        messages: Vec<Message>,
+    },
+}
+
+/// Events sent by clients to the server
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientEvent {
+    SendMessage {
+        channel_id: Option<String>,
+        to: String,
+        content: MessageContent,
+        client_message_id: Option<String>,
+    },
+    /// Asks for the messages of the channel after the last sequence number the
+    /// client has seen. Clients send it again with the last `seq` received
+    /// until the history comes back empty.
+    Sync { channel_id: String, after_seq: i64 },
+}
+
+/// Keeps track of the open sessions of every contact
+#[derive(Default)]
+pub struct ChatServer {
+    sessions: HashMap<usize, (IdType, Recipient<Push>)>,
+    /// The key each session counts against for the socket cap
+    cap_keys: HashMap<usize, String>,
+    next_id: usize,
+}
+
+impl Actor for ChatServer {
+    type Context = Context<Self>;
+}
+
+#[derive(actix::Message)]
+#[rtype(result = "()")]
+pub struct Push(pub String);
+
+/// Opens a session, unless `max_sessions` are already open under its cap key
+#[derive(actix::Message)]
+#[rtype(result = "Option<usize>")]
+pub struct Connect {
+    pub contact_id: IdType,
+    /// The verified identity or address the session counts against
+    pub cap_key: String,
+    pub recipient: Recipient<Push>,
+    pub max_sessions: usize,
+}
+
+#[derive(actix::Message)]
+#[rtype(result = "()")]
+pub struct Disconnect {
+    pub session_id: usize,
+}
+
+impl Handler<Connect> for ChatServer {
+    type Result = Option<usize>;
+
+    fn handle(&mut self, msg: Connect, _ctx: &mut Self::Context) -> Self::Result {
+        let open = self
+            .cap_keys
+            .values()
+            .filter(|key| **key == msg.cap_key)
+            .count();
+        if open >= msg.max_sessions {
+            return None;
+        }
+        self.next_id += 1;
+        self.sessions
+            .insert(self.next_id, (msg.contact_id, msg.recipient));
+        self.cap_keys.insert(self.next_id, msg.cap_key);
+        Some(self.next_id)
+    }
+}
+
+impl Handler<Disconnect> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Disconnect, _ctx: &mut Self::Context) -> Self::Result {
+        self.sessions.remove(&msg.session_id);
+        self.cap_keys.remove(&msg.session_id);
+    }
+}
+
+/// Returns the given contacts that have no open session
+#[derive(actix::Message)]
+#[rtype(result = "Vec<IdType>")]
+pub struct FindOffline {
+    pub contact_ids: Vec<IdType>,
+}
+
+impl Handler<FindOffline> for ChatServer {
+    type Result = Vec<IdType>;
+
+    fn handle(&mut self, msg: FindOffline, _ctx: &mut Self::Context) -> Self::Result {
+        msg.contact_ids
+            .into_iter()
+            .filter(|id| {
+                !self
+                    .sessions
+                    .values()
+                    .any(|(contact_id, _)| contact_id == id)
+            })
+            .collect()
+    }
+}
+
+impl ChatServer {
+    /// Sends the event to every open session of the given contacts
+    fn broadcast(&self, contact_ids: &[IdType], event: &ServerEvent) {
+        let payload = match serde_json::to_string(event) {
+            Ok(p) => p,
+            Err(_) => return,
+        };
+        for (contact_id, recipient) in self.sessions.values() {
+            if contact_ids.contains(contact_id) {
+                recipient.do_send(Push(payload.clone()));
+            }
+        }
+    }
+}
+
+/// Pushes the stored messages to the connected members of their channel,
+/// except the ones the sender hides its messages from
+impl Handler<MessageSent> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: MessageSent, _ctx: &mut Self::Context) -> Self::Result {
+        let event = ServerEvent::Message {
+            message: Box::new(msg.message),
+        };
+        let recipients: Vec<IdType> = msg
+            .channel
+            .contact_ids
+            .into_iter()
+            .filter(|id| !msg.hidden_from.contains(id))
+            .collect();
+        self.broadcast(&recipients, &event);
+    }
+}
 
-struct WebSocket;
+/// Pushes the changed messages to the connected members of their channel
+impl Handler<MessageUpdated> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: MessageUpdated, _ctx: &mut Self::Context) -> Self::Result {
+        let event = ServerEvent::MessageUpdated {
+            message: Box::new(msg.message),
+        };
+        let recipients: Vec<IdType> = msg
+            .channel
+            .contact_ids
+            .into_iter()
+            .filter(|id| !msg.hidden_from.contains(id))
+            .collect();
+        self.broadcast(&recipients, &event);
+    }
+}
+
+struct WebSocket {
+    contact_id: IdType,
+    db: mongodb::Database,
+    events: EventBus,
+    commands: CommandRegistry,
+    pipeline: Pipeline,
+    limiter: RateLimiter,
+    /// The rate limit buckets of the address and of the verified bot, the
+    /// first one being the key of the socket cap
+    limit_keys: Vec<String>,
+    server: Addr<ChatServer>,
+    session_id: Option<usize>,
+}
+
+impl WebSocket {
+    fn handle_event(&mut self, text: &str, ctx: &mut ws::WebsocketContext<Self>) {
+        if let Err(message) = self.check_limit(RouteClass::Frame) {
+            return push(
+                ctx,
+                &ServerEvent::Error {
+                    client_message_id: None,
+                    message,
+                },
+            );
+        }
+        let event: ClientEvent = match serde_json::from_str(text) {
+            Ok(e) => e,
+            Err(e) => {
+                return push(
+                    ctx,
+                    &ServerEvent::Error {
+                        client_message_id: None,
+                        message: e.to_string(),
+                    },
+                )
+            }
+        };
+        match event {
+            ClientEvent::SendMessage {
+                channel_id,
+                to,
+                content,
+                client_message_id,
+            } => {
+                let cmd = match self.check_limit(RouteClass::Message).and_then(|_| {
+                    self.parse_send_message(channel_id, to, content, &client_message_id)
+                }) {
+                    Ok(cmd) => cmd,
+                    Err(message) => {
+                        return push(
+                            ctx,
+                            &ServerEvent::Error {
+                                client_message_id,
+                                message,
+                            },
+                        )
+                    }
+                };
+                // Frames are handled one at a time so acks keep the order of the sends
+                send_message(
+                    self.db.clone(),
+                    self.events.clone(),
+                    self.commands.clone(),
+                    self.pipeline.clone(),
+                    cmd,
+                )
+                .into_actor(self)
+                .map(move |res, _act, ctx| match res {
+                    Ok(message) => push(
+                        ctx,
+                        &ServerEvent::Ack {
+                            client_message_id,
+                            message_id: message.id(),
+                        },
+                    ),
+                    Err(e) => push(
+                        ctx,
+                        &ServerEvent::Error {
+                            client_message_id,
+                            message: e.to_string(),
+                        },
+                    ),
+                })
+                .wait(ctx);
+            }
+            ClientEvent::Sync {
+                channel_id,
+                after_seq,
+            } => {
+                let channel_id = match parse_id(&channel_id) {
+                    Ok(id) => id,
+                    Err(e) => {
+                        return push(
+                            ctx,
+                            &ServerEvent::Error {
+                                client_message_id: None,
+                                message: e.to_string(),
+                            },
+                        )
+                    }
+                };
+                sync(
+                    self.db.clone(),
+                    self.events.clone(),
+                    channel_id.clone(),
+                    self.contact_id.clone(),
+                    after_seq,
+                )
+                .into_actor(self)
+                .map(move |res, _act, ctx| match res {
+                    Ok(messages) => push(
+                        ctx,
+                        &ServerEvent::History {
+                            channel_id,
+                            messages,
+                        },
+                    ),
+                    Err(e) => push(
+                        ctx,
+                        &ServerEvent::Error {
+                            client_message_id: None,
+                            message: e.to_string(),
+                        },
+                    ),
+                })
+                .wait(ctx);
+            }
+        }
+    }
+
+    fn check_limit(&self, class: RouteClass) -> Result<(), String> {
+        self.limiter.check(class, &self.limit_keys).map_err(|wait| {
+            format!(
+                "Rate limit exceeded, retry in {} seconds",
+                retry_after_seconds(wait)
+            )
+        })
+    }
+
+    fn parse_send_message(
+        &self,
+        channel_id: Option<String>,
+        to: String,
+        content: MessageContent,
+        client_message_id: &Option<String>,
+    ) -> Result<SendMessage, String> {
+        let channel_id = match channel_id {
+            Some(id) => Some(parse_id(&id).map_err(|e| e.to_string())?),
+            None => None,
+        };
+        Ok(SendMessage {
+            channel_id,
+            from: self.contact_id.clone(),
+            to: parse_id(&to).map_err(|e| e.to_string())?,
+            content,
+            client_message_id: client_message_id.clone(),
+        })
+    }
+}
+
+async fn send_message(
+    db: mongodb::Database,
+    events: EventBus,
+    commands: CommandRegistry,
+    pipeline: Pipeline,
+    cmd: SendMessage,
+) -> Result<Message, MessageError> {
+    let mut repos = Repositories::new(&db, &events)
+        .with_commands(&commands)
+        .with_pipeline(&pipeline);
+    repos.message_service().send_message(&cmd).await
+}
+
+async fn sync(
+    db: mongodb::Database,
+    events: EventBus,
+    channel_id: IdType,
+    contact_id: IdType,
+    after_seq: i64,
+) -> Result<Vec<Message>, MessageError> {
+    let mut repos = Repositories::new(&db, &events);
+    repos
+        .message_service()
+        .get_messages_after(&channel_id, &contact_id, after_seq, MAX_SYNC_BATCH)
+        .await
+}
+
+fn push(ctx: &mut ws::WebsocketContext<WebSocket>, event: &ServerEvent) {
+    if let Ok(payload) = serde_json::to_string(event) {
+        ctx.text(payload);
+    }
+}
 
 impl Actor for WebSocket {
     type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.server
+            .send(Connect {
+                contact_id: self.contact_id.clone(),
+                cap_key: self.limit_keys[0].clone(),
+                recipient: ctx.address().recipient(),
+                max_sessions: self.limiter.max_sockets(),
+            })
+            .into_actor(self)
+            .map(|res, act, ctx| match res {
+                Ok(Some(id)) => act.session_id = Some(id),
+                Ok(None) => {
+                    push(
+                        ctx,
+                        &ServerEvent::Error {
+                            client_message_id: None,
+                            message: "Too many open sessions".to_string(),
+                        },
+                    );
+                    ctx.close(Some(ws::CloseCode::Policy.into()));
+                    ctx.stop();
+                }
+                Err(_) => ctx.stop(),
+            })
+            .wait(ctx);
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        if let Some(session_id) = self.session_id {
+            self.server.do_send(Disconnect { session_id });
+        }
+    }
+}
+
+impl Handler<Push> for WebSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: Push, ctx: &mut Self::Context) -> Self::Result {
+        ctx.text(msg.0);
+    }
 }
 
 /// Handler for ws::Message message
@@ -14,18 +444,124 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebSocket {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
         match msg {
             Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
-            Ok(ws::Message::Text(text)) => ctx.text(text),
-            Ok(ws::Message::Binary(bin)) => ctx.binary(bin),
+            Ok(ws::Message::Text(text)) => self.handle_event(&text, ctx),
+            // Binary frames carry no events, so they are not answered
+            Ok(ws::Message::Binary(_)) => (),
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
             _ => (),
         }
     }
 }
 
+#[derive(Deserialize)]
+pub struct ConnectQuery {
+    contact_id: String,
+}
+
 pub async fn index(
     req: HttpRequest,
     stream: web::Payload,
+    data: web::Data<AppState>,
+    query: web::Query<ConnectQuery>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let resp = ws::start(WebSocket {}, &req, stream);
-    println!("{resp:?}");
-    resp
+    let contact_id = parse_id(&query.contact_id)?;
+    // The contact id is not verified, so only a bot proving its identity with
+    // its API key gets buckets and a socket cap of its own
+    let mut limit_keys = Vec::new();
+    if let Some(api_key) = bearer_token(&req) {
+        let mut repo: MongoRepository<Contact> = MongoRepository::new(&data.db, "contacts");
+        match ContactService::new(&mut repo)
+            .authenticate_bot(api_key)
+            .await
+        {
+            Some(bot) if bot.id() == contact_id => limit_keys.push(bot_key(api_key)),
+            _ => return Err(actix_web::error::ErrorUnauthorized("Invalid API key")),
+        }
+    }
+    match req.peer_addr() {
+        Some(addr) => limit_keys.push(ip_key(addr.ip())),
+        None if limit_keys.is_empty() => {
+            return Err(actix_web::error::ErrorBadRequest("Unknown client address"))
+        }
+        None => (),
+    }
+    let session = WebSocket {
+        contact_id,
+        db: data.db.clone(),
+        events: data.events.clone(),
+        commands: data.commands.clone(),
+        pipeline: data.pipeline.clone(),
+        limiter: data.limiter.clone(),
+        limit_keys,
+        server: data.chat_server.clone(),
+        session_id: None,
+    };
+    ws::start(session, &req, stream)
+}
+
+fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChatServer, Connect, Disconnect, Push};
+    use crate::adapters::IdType;
+    use actix::{Actor, Context, Handler};
+
+    struct Client;
+
+    impl Actor for Client {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<Push> for Client {
+        type Result = ();
+
+        fn handle(&mut self, _msg: Push, _ctx: &mut Self::Context) -> Self::Result {}
+    }
+
+    #[actix_web::test]
+    async fn caps_the_sockets_of_a_key_whatever_contact_they_claim() {
+        let server = ChatServer::default().start();
+        let connect = |contact_id: &str, cap_key: &str| Connect {
+            contact_id: IdType::String(contact_id.to_string()),
+            cap_key: cap_key.to_string(),
+            recipient: Client.start().recipient(),
+            max_sessions: 1,
+        };
+        let jon = server.send(connect("jon", "ip:10.0.0.1")).await.unwrap();
+        assert!(jon.is_some());
+        assert!(
+            server
+                .send(connect("arya", "ip:10.0.0.1"))
+                .await
+                .unwrap()
+                .is_none(),
+            "The address is at its cap"
+        );
+        assert!(server
+            .send(connect("jon", "ip:10.0.0.2"))
+            .await
+            .unwrap()
+            .is_some());
+
+        server
+            .send(Disconnect {
+                session_id: jon.unwrap(),
+            })
+            .await
+            .unwrap();
+        assert!(server
+            .send(connect("arya", "ip:10.0.0.1"))
+            .await
+            .unwrap()
+            .is_some());
+    }
 }